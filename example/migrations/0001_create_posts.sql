@@ -0,0 +1,11 @@
+-- Apply with `wrangler d1 migrations apply example-posts` (add `--local` for `wrangler dev`)
+CREATE TABLE IF NOT EXISTS posts (
+    id INTEGER PRIMARY KEY,
+    title TEXT NOT NULL,
+    content TEXT NOT NULL
+);
+
+INSERT INTO posts (id, title, content) VALUES
+    (0, 'My first post', 'This is my first post'),
+    (1, 'My second post', 'This is my second post'),
+    (2, 'My third post', 'This is my third post');
@@ -1,6 +1,5 @@
 use std::sync::Arc;
 
-use lazy_static::lazy_static;
 use leptos::*;
 use leptos_meta::*;
 use leptos_router::*;
@@ -53,7 +52,11 @@ pub fn App(cx: Scope) -> impl IntoView {
 #[component]
 fn HomePage(cx: Scope) -> impl IntoView {
     // load the posts
-    let posts = create_resource(cx, || (), |_| async { list_post_metadata().await });
+    let posts = create_resource(
+        cx,
+        || (),
+        move |_| async move { list_post_metadata(cx).await },
+    );
     let posts_view = move || {
         posts.with(cx, |posts| posts
             .clone()
@@ -110,7 +113,7 @@ fn Post(cx: Scope) -> impl IntoView {
     let post = create_resource(cx, id, |id| async move {
         match id {
             Err(e) => Err(e),
-            Ok(id) => get_post(id.0)
+            Ok(id) => get_post(cx, id.0)
                 .await
                 .map(|data| data.ok_or(PostError::PostNotFound))
                 .map_err(|_| PostError::ServerError)
@@ -158,27 +161,6 @@ fn Post(cx: Scope) -> impl IntoView {
     }
 }
 
-// Dummy API
-lazy_static! {
-    static ref POSTS: Vec<Post> = vec![
-        Post {
-            id: 0,
-            title: "My first post".to_string(),
-            content: "This is my first post".to_string(),
-        },
-        Post {
-            id: 1,
-            title: "My second post".to_string(),
-            content: "This is my second post".to_string(),
-        },
-        Post {
-            id: 2,
-            title: "My third post".to_string(),
-            content: "This is my third post".to_string(),
-        },
-    ];
-}
-
 #[derive(Error, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PostError {
     #[error("Invalid post ID.")]
@@ -202,20 +184,28 @@ pub struct PostMetadata {
     title: String,
 }
 
+// The posts are stored in the D1 database bound as `DB`, see migrations/
 #[server(ListPostMetadata, "/api")]
-pub async fn list_post_metadata() -> Result<Vec<PostMetadata>, ServerFnError> {
-    Ok(POSTS
-        .iter()
-        .map(|data| PostMetadata {
-            id: data.id,
-            title: data.title.clone(),
-        })
-        .collect())
+pub async fn list_post_metadata(cx: Scope) -> Result<Vec<PostMetadata>, ServerFnError> {
+    use leptos_cloudflare::d1::{use_d1, D1Query};
+
+    let db = use_d1(cx, "DB")?;
+    let posts = D1Query::new("SELECT id, title FROM posts ORDER BY id")
+        .all::<PostMetadata>(&db)
+        .await?;
+    Ok(posts)
 }
 
 #[server(GetPost, "/api")]
-pub async fn get_post(id: usize) -> Result<Option<Post>, ServerFnError> {
-    Ok(POSTS.iter().find(|post| post.id == id).cloned())
+pub async fn get_post(cx: Scope, id: usize) -> Result<Option<Post>, ServerFnError> {
+    use leptos_cloudflare::d1::{use_d1, D1Query};
+
+    let db = use_d1(cx, "DB")?;
+    let post = D1Query::new("SELECT id, title, content FROM posts WHERE id = ?1")
+        .bind(id)
+        .first::<Post>(&db)
+        .await?;
+    Ok(post)
 }
 
 #[component]
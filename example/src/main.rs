@@ -8,16 +8,14 @@ mod utils;
 pub async fn main(
     req: worker::Request,
     env: worker::Env,
-    _ctx: worker::Context,
+    ctx: worker::Context,
 ) -> worker::Result<worker::Response> {
-    use std::collections::HashSet;
     use std::{net::SocketAddr, str::FromStr};
 
     use app::App;
     use leptos::*;
     use leptos_cloudflare::{self, LeptosRoutes};
     use utils::set_panic_hook;
-    use worker::Router;
 
     // Automatic registration of server functions doesn't work for wasm32 server
     utils::handle_register_server_fn(app::GetPost::register_explicit());
@@ -25,8 +23,6 @@ pub async fn main(
 
     set_panic_hook();
 
-    let routes = leptos_cloudflare::generate_route_list(|cx| view! { cx,  <App /> }.into_view(cx));
-
     // Manually specify options, because worker doesn't have access to local fs
     let leptos_options = LeptosOptions {
         output_name: String::from("example"),
@@ -37,24 +33,31 @@ pub async fn main(
         reload_port: 3001,
     };
 
-    let router = Router::with_data(leptos_cloudflare::WorkerRouterData {
-        options: leptos_options.clone(),
-        static_dirs: HashSet::from([String::from("static"), String::from("css")]),
-        app_fn: app::App,
-    });
-
-    worker::console_debug!("Routes: {:?}", routes);
-
-    router
-        .leptos_routes(routes)
-        .get_async(
-            &format!("/{}/:client_asset", &leptos_options.site_pkg_dir),
-            leptos_cloudflare::serve_static_from_kv,
+    let data = leptos_cloudflare::WorkerRouterData::builder()
+        .options(leptos_options)
+        .app(app::App)
+        .server_fn_prefix("/api")
+        .static_dir("static")
+        .static_dir("css")
+        .context(ctx)
+        // Keeps one of the demo posts out of search results, leaving the other pages indexable
+        .route(
+            "/post/partially-blocked/:id",
+            leptos_cloudflare::RouteConfig {
+                robots: Some(leptos_cloudflare::RobotsDirectives::noindex()),
+                no_hydrate: false,
+            },
         )
-        .get_async("/static/:asset", leptos_cloudflare::serve_static_from_kv)
-        .post_async("/api/:fn_name", leptos_cloudflare::handle_server_fns)
-        .run(req, env)
-        .await
+        .build()?;
+
+    // Assets and server functions are served without generating the route list
+    data.serve(req, env, |router| {
+        let routes =
+            leptos_cloudflare::generate_route_list(|cx| view! { cx,  <App /> }.into_view(cx));
+        worker::console_debug!("Routes: {:?}", routes);
+        router.leptos_routes(routes)
+    })
+    .await
 }
 
 #[cfg(feature = "ssr")]
@@ -0,0 +1,137 @@
+//! Leptos signals updated by the live channels of `leptos_cloudflare::live`.
+//!
+//! `leptos-cloudflare` only builds for the worker, so this crate holds the part of live channels that
+//! runs in the browser. It has no dependency on `workers-rs`, and an app depends on it in both builds:
+//!
+//! ```ignore
+//! #[component]
+//! fn Counter(cx: Scope, initial: u64) -> impl IntoView {
+//!     let count = use_live_signal(cx, "/live/counter", initial);
+//!     view! { cx, <p>{move || count.get()} " clicks"</p> }
+//! }
+//! ```
+//!
+//! The signal starts with the value passed to it, which is what the worker renders, and connects to
+//! the channel once the page is hydrated. Each value published to the channel is deserialized from
+//! JSON into the signal; values that don't deserialize are skipped. Dropped connections are reopened
+//! with a growing delay, and the socket is closed when the scope is disposed.
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use leptos::{create_effect, create_signal, on_cleanup, ReadSignal, Scope, SignalSet, WriteSignal};
+use serde::de::DeserializeOwned;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{MessageEvent, WebSocket};
+
+/// The delay before reconnecting after the first failure, in milliseconds. It doubles with each
+/// failure that follows.
+const MIN_RETRY_DELAY: i32 = 1_000;
+const MAX_RETRY_DELAY: i32 = 30_000;
+
+/// A signal holding the last value published to the live channel served at `path`, e.g.
+/// `/live/counter`, or `initial` until there is one.
+pub fn use_live_signal<T>(cx: Scope, path: &str, initial: T) -> ReadSignal<T>
+where
+    T: DeserializeOwned + 'static,
+{
+    let (value, set_value) = create_signal(cx, initial);
+    let path = path.to_string();
+    // Effects only run in the browser, so the worker renders `initial`
+    create_effect(cx, move |_| {
+        let Some(url) = socket_url(&path) else {
+            return;
+        };
+        let subscription = Rc::new(Subscription {
+            url,
+            closed: Cell::new(false),
+            retry_delay: Cell::new(MIN_RETRY_DELAY),
+            socket: RefCell::new(None),
+            handlers: RefCell::new(Vec::new()),
+        });
+        connect(subscription.clone(), set_value);
+        on_cleanup(cx, move || subscription.close());
+    });
+    value
+}
+
+struct Subscription {
+    url: String,
+    closed: Cell<bool>,
+    retry_delay: Cell<i32>,
+    socket: RefCell<Option<WebSocket>>,
+    // The event handlers of the open socket, dropped when it is replaced
+    handlers: RefCell<Vec<Closure<dyn FnMut(JsValue)>>>,
+}
+
+impl Subscription {
+    fn close(&self) {
+        self.closed.set(true);
+        if let Some(socket) = self.socket.borrow_mut().take() {
+            socket.set_onclose(None);
+            let _ = socket.close();
+        }
+        // The handlers hold the subscription
+        self.handlers.borrow_mut().clear();
+    }
+}
+
+/// The `ws:` or `wss:` URL of `path` on the origin of the page.
+fn socket_url(path: &str) -> Option<String> {
+    let location = web_sys::window()?.location();
+    let scheme = match location.protocol().ok()?.as_str() {
+        "https:" => "wss",
+        _ => "ws",
+    };
+    Some(format!("{scheme}://{}{path}", location.host().ok()?))
+}
+
+fn connect<T>(subscription: Rc<Subscription>, set_value: WriteSignal<T>)
+where
+    T: DeserializeOwned + 'static,
+{
+    if subscription.closed.get() {
+        return;
+    }
+    let Ok(socket) = WebSocket::new(&subscription.url) else {
+        return reconnect(subscription, set_value);
+    };
+
+    let on_message = Closure::<dyn FnMut(JsValue)>::new(move |event: JsValue| {
+        let text = event.unchecked_into::<MessageEvent>().data().as_string();
+        if let Some(value) = text.and_then(|text| serde_json::from_str::<T>(&text).ok()) {
+            set_value.set(value);
+        }
+    });
+    let on_open = Closure::<dyn FnMut(JsValue)>::new({
+        let subscription = subscription.clone();
+        move |_| subscription.retry_delay.set(MIN_RETRY_DELAY)
+    });
+    let on_close = Closure::<dyn FnMut(JsValue)>::new({
+        let subscription = subscription.clone();
+        move |_| reconnect(subscription.clone(), set_value)
+    });
+    socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+    socket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+    socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+    *subscription.socket.borrow_mut() = Some(socket);
+    *subscription.handlers.borrow_mut() = vec![on_message, on_open, on_close];
+}
+
+fn reconnect<T>(subscription: Rc<Subscription>, set_value: WriteSignal<T>)
+where
+    T: DeserializeOwned + 'static,
+{
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+    let delay = subscription.retry_delay.get();
+    subscription
+        .retry_delay
+        .set((delay * 2).min(MAX_RETRY_DELAY));
+    let retry = Closure::once_into_js(move || connect(subscription, set_value));
+    let _ =
+        window.set_timeout_with_callback_and_timeout_and_arguments_0(retry.unchecked_ref(), delay);
+}
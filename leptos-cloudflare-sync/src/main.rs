@@ -0,0 +1,327 @@
+//! Uploads the static assets of a Leptos site to Workers KV or R2, for serving with
+//! `leptos_cloudflare::AssetSource` instead of Worker Sites.
+//!
+//! ```console
+//! leptos-cloudflare-sync --dir pkg --kv <namespace id>
+//! leptos-cloudflare-sync --dir pkg --r2 <bucket name>
+//! ```
+//!
+//! Every file is stored under a key derived from its content, so only files that changed since the last
+//! sync are uploaded, and the previous deployment keeps working until the new manifest is uploaded last.
+//! Uploads go through `wrangler`, which must be installed and logged in.
+//!
+//! With `--version`, the manifest is stored as that version and only served once the version pointer in
+//! the KV namespace given with `--pointer-kv` selects it (see `leptos_cloudflare::content_version`):
+//!
+//! ```console
+//! leptos-cloudflare-sync --dir pkg --kv <namespace id> --version 2023-11-02 --pointer-kv <namespace id> --activate
+//! leptos-cloudflare-sync --pointer-kv <namespace id> --switch-to 2023-10-28
+//! ```
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{exit, Command};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Must match `leptos_cloudflare::assets::MANIFEST_KEY`.
+const MANIFEST_KEY: &str = "__manifest.json";
+/// Must match `leptos_cloudflare::content_version::POINTER_KEY`.
+const POINTER_KEY: &str = "__content_version";
+
+/// Same format as `leptos_cloudflare::assets::AssetManifest`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AssetManifest {
+    files: HashMap<String, AssetEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AssetEntry {
+    key: String,
+    hash: String,
+}
+
+/// Same format as `leptos_cloudflare::content_version::VersionPointer`.
+#[derive(Debug, Serialize, Deserialize)]
+struct VersionPointer {
+    active: String,
+    previous: Option<String>,
+    activated_at: u64,
+}
+
+enum Store {
+    Kv(String),
+    R2(String),
+}
+
+enum Args {
+    Sync(SyncArgs),
+    /// Points the version pointer in the namespace at a version.
+    SwitchTo {
+        pointer_kv: String,
+        version: String,
+    },
+}
+
+struct SyncArgs {
+    dir: PathBuf,
+    store: Store,
+    /// Also write the manifest here.
+    manifest_out: Option<PathBuf>,
+    /// Store the manifest as this version.
+    version: Option<String>,
+    /// The namespace of the version pointer, to activate the version once it is synced.
+    activate: Option<String>,
+}
+
+const USAGE: &str = "usage: leptos-cloudflare-sync --dir <site dir> (--kv <namespace id> | --r2 <bucket>) [--manifest-out <file>] [--version <name> [--pointer-kv <namespace id> --activate]]
+       leptos-cloudflare-sync --pointer-kv <namespace id> --switch-to <name>";
+
+fn parse_args() -> Result<Args, String> {
+    let mut dir = None;
+    let mut store = None;
+    let mut manifest_out = None;
+    let mut version = None;
+    let mut pointer_kv = None;
+    let mut activate = false;
+    let mut switch_to = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--activate" {
+            activate = true;
+            continue;
+        }
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--dir" => dir = Some(PathBuf::from(value()?)),
+            "--kv" => store = Some(Store::Kv(value()?)),
+            "--r2" => store = Some(Store::R2(value()?)),
+            "--manifest-out" => manifest_out = Some(PathBuf::from(value()?)),
+            "--version" => version = Some(value()?),
+            "--pointer-kv" => pointer_kv = Some(value()?),
+            "--switch-to" => switch_to = Some(value()?),
+            _ => return Err(format!("unknown argument {arg}")),
+        }
+    }
+
+    if let Some(version) = switch_to {
+        return Ok(Args::SwitchTo {
+            pointer_kv: pointer_kv.ok_or("--switch-to needs --pointer-kv")?,
+            version,
+        });
+    }
+    if activate && (version.is_none() || pointer_kv.is_none()) {
+        return Err("--activate needs --version and --pointer-kv".to_string());
+    }
+
+    Ok(Args::Sync(SyncArgs {
+        dir: dir.ok_or("--dir is required")?,
+        store: store.ok_or("one of --kv or --r2 is required")?,
+        manifest_out,
+        version,
+        activate: pointer_kv.filter(|_| activate),
+    }))
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            exit(2);
+        }
+    };
+
+    let result = match &args {
+        Args::Sync(args) => sync(args),
+        Args::SwitchTo {
+            pointer_kv,
+            version,
+        } => switch_to(pointer_kv, version),
+    };
+    if let Err(err) = result {
+        eprintln!("sync failed: {err}");
+        exit(1);
+    }
+}
+
+fn sync(args: &SyncArgs) -> Result<(), String> {
+    let manifest_key = match &args.version {
+        Some(version) => format!("__manifests/{version}.json"),
+        None => MANIFEST_KEY.to_string(),
+    };
+    // Any earlier manifest tells which files are already uploaded
+    let previous = download(&args.store, &manifest_key)
+        .or_else(|| download(&args.store, MANIFEST_KEY))
+        .and_then(|json| serde_json::from_slice::<AssetManifest>(&json).ok())
+        .unwrap_or_default();
+
+    let mut files = Vec::new();
+    collect_files(&args.dir, &mut files).map_err(|err| err.to_string())?;
+
+    let mut manifest = AssetManifest::default();
+    let mut uploaded = 0;
+    for file in files {
+        let path = file
+            .strip_prefix(&args.dir)
+            .expect("collected files are inside the directory")
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let content = fs::read(&file).map_err(|err| format!("{}: {err}", file.display()))?;
+        let hash = hex::encode(Sha256::digest(&content));
+        let entry = AssetEntry {
+            key: format!("{hash}/{path}"),
+            hash,
+        };
+
+        let unchanged = previous
+            .files
+            .get(&path)
+            .is_some_and(|previous| previous.key == entry.key);
+        if !unchanged {
+            upload(&args.store, &entry.key, &file)?;
+            uploaded += 1;
+        }
+        manifest.files.insert(path, entry);
+    }
+
+    let json = serde_json::to_string_pretty(&manifest).map_err(|err| err.to_string())?;
+    let manifest_file = std::env::temp_dir().join("leptos-cloudflare-manifest.json");
+    fs::write(&manifest_file, &json).map_err(|err| err.to_string())?;
+    // Uploaded last, so that the manifest never points at files that aren't there yet
+    upload(&args.store, &manifest_key, &manifest_file)?;
+    if let Some(manifest_out) = &args.manifest_out {
+        fs::write(manifest_out, &json).map_err(|err| err.to_string())?;
+    }
+
+    println!(
+        "{} files, {uploaded} uploaded, {} unchanged",
+        manifest.files.len(),
+        manifest.files.len() - uploaded
+    );
+
+    if let (Some(pointer_kv), Some(version)) = (&args.activate, &args.version) {
+        switch_to(pointer_kv, version)?;
+    }
+    Ok(())
+}
+
+/// Makes `version` the active version, remembering the current one for rollbacks.
+fn switch_to(pointer_kv: &str, version: &str) -> Result<(), String> {
+    let current = download(&Store::Kv(pointer_kv.to_string()), POINTER_KEY)
+        .and_then(|json| serde_json::from_slice::<VersionPointer>(&json).ok());
+    let previous = match current {
+        Some(current) if current.active != version => Some(current.active),
+        Some(current) => current.previous,
+        None => None,
+    };
+    let activated_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|err| err.to_string())?
+        .as_millis() as u64;
+    let pointer = VersionPointer {
+        active: version.to_string(),
+        previous,
+        activated_at,
+    };
+
+    let json = serde_json::to_string(&pointer).map_err(|err| err.to_string())?;
+    wrangler(&[
+        "kv",
+        "key",
+        "put",
+        POINTER_KEY,
+        &json,
+        "--namespace-id",
+        pointer_kv,
+        "--remote",
+    ])?;
+    match &pointer.previous {
+        Some(previous) => println!("{version} is active, was {previous}"),
+        None => println!("{version} is active"),
+    }
+    Ok(())
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn download(store: &Store, key: &str) -> Option<Vec<u8>> {
+    match store {
+        Store::Kv(namespace_id) => wrangler(&[
+            "kv",
+            "key",
+            "get",
+            key,
+            "--namespace-id",
+            namespace_id,
+            "--remote",
+        ]),
+        Store::R2(bucket) => wrangler(&[
+            "r2",
+            "object",
+            "get",
+            &format!("{bucket}/{key}"),
+            "--pipe",
+            "--remote",
+        ]),
+    }
+    .ok()
+}
+
+fn upload(store: &Store, key: &str, file: &Path) -> Result<(), String> {
+    let file = file.to_string_lossy();
+    match store {
+        Store::Kv(namespace_id) => wrangler(&[
+            "kv",
+            "key",
+            "put",
+            key,
+            "--path",
+            &file,
+            "--namespace-id",
+            namespace_id,
+            "--remote",
+        ]),
+        Store::R2(bucket) => wrangler(&[
+            "r2",
+            "object",
+            "put",
+            &format!("{bucket}/{key}"),
+            "--file",
+            &file,
+            "--remote",
+        ]),
+    }
+    .map(|_| ())
+}
+
+fn wrangler(args: &[&str]) -> Result<Vec<u8>, String> {
+    let output = Command::new("wrangler")
+        .args(args)
+        .output()
+        .map_err(|err| format!("could not run wrangler: {err}"))?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(format!(
+            "wrangler {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        ))
+    }
+}
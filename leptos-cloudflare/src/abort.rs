@@ -0,0 +1,73 @@
+//! Stopping work for clients that went away.
+//!
+//! The `signal` of an incoming request is aborted when the client disconnects (with the
+//! `request_signal_passthrough` compatibility flag). The streaming renderers stop pulling the page as
+//! soon as that happens, so resources that are still pending are no longer awaited and the reactive
+//! runtime of the request is disposed.
+//!
+//! Futures that were already spawned (e.g. by resources) run to completion unless what they wait for is
+//! canceled as well, so server functions and resource loaders should pass the signal to their upstream
+//! requests:
+//!
+//! ```ignore
+//! #[server(Search, "/api")]
+//! pub async fn search(cx: Scope, query: String) -> Result<Vec<Hit>, ServerFnError> {
+//!     let mut init = worker::RequestInit::new();
+//!     if let Some(signal) = use_abort_signal(cx) {
+//!         init.with_signal(Some(&signal.into()));
+//!     }
+//!     // ...
+//! }
+//! ```
+
+use futures::channel::oneshot;
+use futures::{Stream, StreamExt};
+use leptos::{use_context, Scope};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+
+/// The abort signal of the request, provided in its context.
+#[derive(Clone)]
+pub(crate) struct RequestSignal(pub(crate) web_sys::AbortSignal);
+
+impl RequestSignal {
+    pub(crate) fn of(request: &Result<web_sys::Request, wasm_bindgen::JsValue>) -> Option<Self> {
+        request.as_ref().ok().map(|request| Self(request.signal()))
+    }
+}
+
+/// The abort signal of the current request, which is aborted when the client disconnects.
+pub fn use_abort_signal(cx: Scope) -> Option<web_sys::AbortSignal> {
+    use_context::<RequestSignal>(cx).map(|signal| signal.0)
+}
+
+/// Ends `stream` as soon as `signal` is aborted.
+pub(crate) fn until_aborted<S>(
+    stream: S,
+    signal: Option<RequestSignal>,
+) -> impl Stream<Item = S::Item>
+where
+    S: Stream,
+{
+    let (sender, receiver) = oneshot::channel::<()>();
+
+    if let Some(RequestSignal(signal)) = signal {
+        if signal.aborted() {
+            let _ = sender.send(());
+        } else {
+            let on_abort = Closure::once_into_js(move || {
+                let _ = sender.send(());
+            });
+            let _ = signal.add_event_listener_with_callback("abort", on_abort.unchecked_ref());
+        }
+    }
+
+    stream.take_until(async move {
+        if receiver.await.is_ok() {
+            tracing::debug!("the client disconnected, stopping the render");
+        } else {
+            // Without a signal, the sender is dropped right away and the render is never stopped
+            futures::future::pending::<()>().await;
+        }
+    })
+}
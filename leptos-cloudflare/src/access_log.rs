@@ -0,0 +1,267 @@
+//! Access logs written to R2 in batches.
+//!
+//! [AccessLog::wrap] runs the fetch handler and appends a structured line for the request to a buffer of
+//! the isolate. Once the buffer holds [max_lines](AccessLog::max_lines) lines or its oldest line is older
+//! than [max_age](AccessLog::max_age), it is written to R2 as one newline-delimited JSON object under
+//! [Context::wait_until](worker::Context::wait_until), so the response isn't held back:
+//!
+//! ```ignore
+//! static ACCESS_LOG: AccessLog = AccessLog::new("LOGS").max_age(Duration::from_secs(30));
+//!
+//! #[worker::event(fetch)]
+//! pub async fn main(req: Request, env: Env, ctx: Context) -> worker::Result<Response> {
+//!     ACCESS_LOG
+//!         .wrap(req, env, &ctx, |req, env| async move {
+//!             data.into_router().leptos_routes(routes).run(req, env).await
+//!         })
+//!         .await
+//! }
+//! ```
+//!
+//! Objects are keyed `<prefix>/<yyyy>/<mm>/<dd>/<hh>/<timestamp>-<random>.ndjson` in UTC, so a time
+//! range can be listed by prefix. Lines still buffered when an isolate is evicted are lost, and so are
+//! the oldest lines once R2 has failed for long enough that [max_buffered](AccessLog::max_buffered)
+//! lines are waiting; the logs are meant for analysis, not for auditing. Each bucket and prefix has
+//! its own buffer, so several logs can be kept side by side.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// One request, as written to the log.
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessLogLine {
+    /// Milliseconds since the epoch at which the request arrived.
+    pub timestamp: u64,
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    /// `0` if the handler failed.
+    pub status: u16,
+    pub duration_ms: u64,
+    pub colo: String,
+    pub country: Option<String>,
+    pub ray: Option<String>,
+    pub user_agent: Option<String>,
+    pub referer: Option<String>,
+}
+
+#[derive(Default)]
+struct Buffer {
+    lines: Vec<AccessLogLine>,
+    /// When the oldest buffered line was added.
+    started_at: u64,
+}
+
+impl Buffer {
+    /// Drops the oldest lines past `max`, returning how many were dropped.
+    fn keep_newest(&mut self, max: usize) -> usize {
+        let dropped = self.lines.len().saturating_sub(max);
+        self.lines.drain(..dropped);
+        dropped
+    }
+}
+
+thread_local! {
+    /// The buffers by bucket binding and key prefix.
+    static BUFFERS: RefCell<HashMap<(&'static str, &'static str), Buffer>> =
+        RefCell::new(HashMap::new());
+}
+
+pub struct AccessLog {
+    binding: &'static str,
+    prefix: &'static str,
+    max_lines: usize,
+    max_age: Duration,
+    max_buffered: usize,
+}
+
+impl AccessLog {
+    /// Logs to the R2 bucket `binding`, flushing every 100 lines or 60 seconds, and keeping at most
+    /// 10,000 lines while R2 fails.
+    pub const fn new(binding: &'static str) -> Self {
+        Self {
+            binding,
+            prefix: "access-logs",
+            max_lines: 100,
+            max_age: Duration::from_secs(60),
+            max_buffered: 10_000,
+        }
+    }
+
+    /// The prefix of the object keys, `access-logs` by default.
+    pub const fn prefix(mut self, prefix: &'static str) -> Self {
+        self.prefix = prefix;
+        self
+    }
+
+    /// How many lines are buffered before they are flushed.
+    pub const fn max_lines(mut self, max_lines: usize) -> Self {
+        self.max_lines = max_lines;
+        self
+    }
+
+    /// How long the oldest line is buffered before the lines are flushed. Only checked when a request
+    /// is logged, so a quiet isolate may hold lines for longer.
+    pub const fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// How many lines are kept while flushes fail. Past it, the oldest lines are dropped, so an R2
+    /// outage can't grow the memory of the isolate without bound.
+    pub const fn max_buffered(mut self, max_buffered: usize) -> Self {
+        self.max_buffered = max_buffered;
+        self
+    }
+
+    /// Runs `handler` for `req` and logs the request.
+    pub async fn wrap<F, Fut>(
+        &'static self,
+        req: worker::Request,
+        env: worker::Env,
+        ctx: &worker::Context,
+        handler: F,
+    ) -> worker::Result<worker::Response>
+    where
+        F: FnOnce(worker::Request, worker::Env) -> Fut,
+        Fut: Future<Output = worker::Result<worker::Response>>,
+    {
+        let started_at = worker::Date::now().as_millis();
+        let url = req.url()?;
+        let headers = req.headers();
+        let mut line = AccessLogLine {
+            timestamp: started_at,
+            method: req.method().to_string(),
+            path: url.path().to_string(),
+            query: url.query().map(str::to_string),
+            status: 0,
+            duration_ms: 0,
+            colo: req.cf().colo(),
+            country: req.cf().country(),
+            ray: headers.get("CF-Ray")?,
+            user_agent: headers.get("User-Agent")?,
+            referer: headers.get("Referer")?,
+        };
+
+        let response = handler(req, env.clone()).await;
+        line.status = response
+            .as_ref()
+            .map_or(0, |response| response.status_code());
+        line.duration_ms = worker::Date::now().as_millis() - started_at;
+        self.record(line, env, ctx);
+        response
+    }
+
+    /// Buffers `line`, scheduling a flush if the buffer is full or old enough.
+    pub fn record(&'static self, line: AccessLogLine, env: worker::Env, ctx: &worker::Context) {
+        let now = worker::Date::now().as_millis();
+        let full = self.with_buffer(|buffer| {
+            if buffer.lines.is_empty() {
+                buffer.started_at = now;
+            }
+            buffer.lines.push(line);
+            self.drop_oldest(buffer);
+            buffer.lines.len() >= self.max_lines
+                || now.saturating_sub(buffer.started_at) >= self.max_age.as_millis() as u64
+        });
+
+        if full {
+            ctx.wait_until(async move {
+                if let Err(err) = self.flush(&env).await {
+                    tracing::error!("failed to flush the access log: {err}");
+                }
+            });
+        }
+    }
+
+    /// Writes the buffered lines to R2 right away, e.g. from a scheduled handler.
+    pub async fn flush(&self, env: &worker::Env) -> worker::Result<()> {
+        let lines = self.with_buffer(|buffer| std::mem::take(&mut buffer.lines));
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        let mut body = String::new();
+        for line in &lines {
+            body.push_str(&serde_json::to_string(line)?);
+            body.push('\n');
+        }
+
+        let key = self.object_key(lines[0].timestamp);
+        if let Err(err) = env.bucket(self.binding)?.put(&key, body).execute().await {
+            // Kept for the next flush rather than dropped
+            self.with_buffer(|buffer| {
+                let newer = std::mem::replace(&mut buffer.lines, lines);
+                buffer.lines.extend(newer);
+                self.drop_oldest(buffer);
+            });
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    fn with_buffer<R>(&self, f: impl FnOnce(&mut Buffer) -> R) -> R {
+        BUFFERS.with(|buffers| {
+            let mut buffers = buffers.borrow_mut();
+            f(buffers.entry((self.binding, self.prefix)).or_default())
+        })
+    }
+
+    fn drop_oldest(&self, buffer: &mut Buffer) {
+        let dropped = buffer.keep_newest(self.max_buffered);
+        if dropped > 0 {
+            tracing::warn!("dropped {dropped} access log lines that couldn't be flushed");
+        }
+    }
+
+    fn object_key(&self, timestamp: u64) -> String {
+        let date = js_sys::Date::new(&wasm_bindgen::JsValue::from_f64(timestamp as f64));
+        format!(
+            "{}/{:04}/{:02}/{:02}/{:02}/{}-{:08x}.ndjson",
+            self.prefix,
+            date.get_utc_full_year(),
+            date.get_utc_month() + 1,
+            date.get_utc_date(),
+            date.get_utc_hours(),
+            timestamp,
+            (js_sys::Math::random() * u32::MAX as f64) as u32
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(timestamp: u64) -> AccessLogLine {
+        AccessLogLine {
+            timestamp,
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            query: None,
+            status: 200,
+            duration_ms: 1,
+            colo: "AMS".to_string(),
+            country: None,
+            ray: None,
+            user_agent: None,
+            referer: None,
+        }
+    }
+
+    #[test]
+    fn keeps_the_newest_lines() {
+        let mut buffer = Buffer {
+            lines: (0..5).map(line).collect(),
+            started_at: 0,
+        };
+        assert_eq!(buffer.keep_newest(10), 0);
+        assert_eq!(buffer.keep_newest(3), 2);
+        let timestamps: Vec<_> = buffer.lines.iter().map(|line| line.timestamp).collect();
+        assert_eq!(timestamps, [2, 3, 4]);
+    }
+}
@@ -0,0 +1,298 @@
+//! A minimal operator dashboard mounted at `/__admin`.
+//!
+//! It lists the Leptos routes, the most recent errors of the isolate, feature flags stored in KV (with
+//! buttons to turn them on or off) and the registered [jobs](crate::jobs) (with buttons to run them). The
+//! dashboard is protected by HTTP Basic authentication against a password stored as a secret:
+//!
+//! ```ignore
+//! router.admin_routes(AdminConfig {
+//!     routes: routes.clone(),
+//!     jobs: jobs.clone(),
+//!     flags_kv: Some("FLAGS"),
+//!     password_secret: "ADMIN_PASSWORD",
+//! })
+//! ```
+//!
+//! Browsers resend Basic credentials with cross-site form submissions, so the `POST` routes also
+//! require `Sec-Fetch-Site: same-origin` or an `Origin` matching the dashboard; scripts calling them
+//! must send the `Origin` header. Only flags that already exist in KV can be changed.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+
+use base64::Engine;
+use leptos::{view, CollectView, IntoView};
+use leptos_router::RouteListing;
+use serde::Deserialize;
+
+use crate::api::{ApiRequest, ApiRoutes};
+use crate::jobs::{JobContext, JobRegistry, JobTrigger};
+use crate::util::constant_time_eq;
+use crate::{IntoWorkerResponse, LeptosCloudflareError, Redirect};
+
+/// The key of the JSON object (flag name to enabled) in the flags KV namespace.
+pub const FLAGS_KEY: &str = "__flags";
+
+const MAX_RECENT_ERRORS: usize = 50;
+
+thread_local! {
+    static RECENT_ERRORS: RefCell<VecDeque<(u64, String)>> = RefCell::new(VecDeque::new());
+}
+
+/// Records an error to be shown on the dashboard. Only the last 50 errors of the isolate are kept.
+pub fn record_error(message: impl Into<String>) {
+    RECENT_ERRORS.with(|errors| {
+        let mut errors = errors.borrow_mut();
+        if errors.len() == MAX_RECENT_ERRORS {
+            errors.pop_front();
+        }
+        errors.push_back((worker::Date::now().as_millis(), message.into()));
+    });
+}
+
+/// The form of the flag buttons.
+#[derive(Deserialize)]
+struct FlagForm {
+    enabled: bool,
+}
+
+#[derive(Clone)]
+pub struct AdminConfig {
+    pub routes: Vec<RouteListing>,
+    pub jobs: JobRegistry,
+    /// Binding of the KV namespace holding the feature flags under [FLAGS_KEY].
+    pub flags_kv: Option<&'static str>,
+    /// Name of the secret holding the dashboard password. Any user name is accepted.
+    pub password_secret: &'static str,
+}
+
+pub trait AdminRoutes {
+    fn admin_routes(self, config: AdminConfig) -> Self;
+}
+
+impl<'a, D: 'static> AdminRoutes for worker::Router<'a, D> {
+    fn admin_routes(self, config: AdminConfig) -> Self {
+        let dashboard_config = config.clone();
+        let flags_config = config.clone();
+        let jobs_config = config;
+
+        self.api_route(worker::Method::Get, "/__admin", move |req: ApiRequest| {
+            let config = dashboard_config.clone();
+            async move {
+                if let Err(response) = authorize(&req, &config) {
+                    return response;
+                }
+                let flags = match config.flags_kv {
+                    Some(binding) => load_flags(&req.env, binding).await?,
+                    None => BTreeMap::new(),
+                };
+                worker::Response::from_html(render_dashboard(&config, flags))
+            }
+        })
+        .api_route(
+            worker::Method::Post,
+            "/__admin/flags/:name",
+            move |req: ApiRequest| {
+                let config = flags_config.clone();
+                async move {
+                    if let Err(response) = authorize(&req, &config) {
+                        return response;
+                    }
+                    if let Err(response) = same_origin(&req) {
+                        return response;
+                    }
+                    let Some(binding) = config.flags_kv else {
+                        return LeptosCloudflareError::NotFound.into_worker_response();
+                    };
+                    let form = match serde_urlencoded::from_bytes::<FlagForm>(&req.parts.body) {
+                        Ok(form) => form,
+                        Err(err) => {
+                            return LeptosCloudflareError::BadRequest(err.to_string())
+                                .into_worker_response()
+                        }
+                    };
+                    let name = req.path.get("name").cloned().unwrap_or_default();
+                    let mut flags = load_flags(&req.env, binding).await?;
+                    let Some(enabled) = flags.get_mut(&name) else {
+                        return LeptosCloudflareError::NotFound.into_worker_response();
+                    };
+                    *enabled = form.enabled;
+                    req.env
+                        .kv(binding)?
+                        .put(FLAGS_KEY, serde_json::to_string(&flags)?)?
+                        .execute()
+                        .await?;
+                    Redirect::to("/__admin").into_worker_response()
+                }
+            },
+        )
+        .api_route(
+            worker::Method::Post,
+            "/__admin/jobs/:name",
+            move |req: ApiRequest| {
+                let config = jobs_config.clone();
+                async move {
+                    if let Err(response) = authorize(&req, &config) {
+                        return response;
+                    }
+                    if let Err(response) = same_origin(&req) {
+                        return response;
+                    }
+                    let name = req.path.get("name").cloned().unwrap_or_default();
+                    let run = config
+                        .jobs
+                        .run(
+                            &name,
+                            JobContext {
+                                env: req.env,
+                                trigger: JobTrigger::Manual,
+                                payload: serde_json::Value::Null,
+                            },
+                        )
+                        .await;
+                    match run {
+                        Ok(run) => {
+                            if let Some(error) = run.error {
+                                record_error(format!("job {name}: {error}"));
+                            }
+                            Redirect::to("/__admin").into_worker_response()
+                        }
+                        Err(err) => err.into_worker_response(),
+                    }
+                }
+            },
+        )
+    }
+}
+
+/// Checks the Basic credentials, returning the response to send instead if they are wrong.
+fn authorize(
+    req: &ApiRequest,
+    config: &AdminConfig,
+) -> Result<(), worker::Result<worker::Response>> {
+    let unauthorized = || {
+        let mut headers = worker::Headers::new();
+        headers.set("WWW-Authenticate", "Basic realm=\"admin\"")?;
+        Ok(worker::Response::error("Unauthorized", 401)?.with_headers(headers))
+    };
+
+    let password = match req.env.secret(config.password_secret) {
+        Ok(password) => password.to_string(),
+        Err(err) => return Err(Err(err)),
+    };
+    let provided = req
+        .parts
+        .headers
+        .get("Authorization")
+        .ok()
+        .flatten()
+        .and_then(|header| header.strip_prefix("Basic ").map(str::to_string))
+        .and_then(|encoded| {
+            base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .ok()
+        })
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .and_then(|credentials| {
+            credentials
+                .split_once(':')
+                .map(|(_, password)| password.to_string())
+        });
+
+    match provided {
+        Some(provided) if constant_time_eq(provided.as_bytes(), password.as_bytes()) => Ok(()),
+        _ => Err(unauthorized()),
+    }
+}
+
+/// Checks that a state-changing request comes from the dashboard itself, returning the response to
+/// send instead if it may come from another site.
+fn same_origin(req: &ApiRequest) -> Result<(), worker::Result<worker::Response>> {
+    let header = |name| req.parts.headers.get(name).ok().flatten();
+    let allowed = match (header("Sec-Fetch-Site"), header("Origin")) {
+        (Some(site), _) => site == "same-origin",
+        (None, Some(origin)) => origin == req.parts.url.origin().ascii_serialization(),
+        (None, None) => false,
+    };
+    match allowed {
+        true => Ok(()),
+        false => Err(worker::Response::error("Cross-site request", 403)),
+    }
+}
+
+async fn load_flags(env: &worker::Env, binding: &str) -> worker::Result<BTreeMap<String, bool>> {
+    Ok(env
+        .kv(binding)?
+        .get(FLAGS_KEY)
+        .json::<BTreeMap<String, bool>>()
+        .await?
+        .unwrap_or_default())
+}
+
+fn render_dashboard(config: &AdminConfig, flags: BTreeMap<String, bool>) -> String {
+    let routes = config
+        .routes
+        .iter()
+        .map(|listing| (listing.path().to_string(), format!("{:?}", listing.mode())))
+        .collect::<Vec<_>>();
+    let jobs = config
+        .jobs
+        .jobs()
+        .map(|job| (job.name(), job.schedule().unwrap_or("manual")))
+        .collect::<Vec<_>>();
+    let errors =
+        RECENT_ERRORS.with(|errors| errors.borrow().iter().rev().cloned().collect::<Vec<_>>());
+    let has_flags = config.flags_kv.is_some();
+
+    let html = leptos::ssr::render_to_string(move |cx| {
+        view! { cx,
+            <h1>"Admin"</h1>
+            <h2>"Routes"</h2>
+            <table>
+                {routes.into_iter().map(|(path, mode)| view! { cx,
+                    <tr><td><code>{path}</code></td><td>{mode}</td></tr>
+                }).collect_view(cx)}
+            </table>
+            <h2>"Recent errors"</h2>
+            <ul>
+                {errors.into_iter().map(|(at, message)| view! { cx,
+                    <li><time>{worker::Date::new(worker::DateInit::Millis(at)).to_string()}</time>" "{message}</li>
+                }).collect_view(cx)}
+            </ul>
+            {has_flags.then(|| view! { cx,
+                <h2>"Flags"</h2>
+                <table>
+                    {flags.into_iter().map(|(name, enabled)| view! { cx,
+                        <tr>
+                            <td>{name.clone()}</td>
+                            <td>{if enabled { "on" } else { "off" }}</td>
+                            <td>
+                                <form method="post" action=format!("/__admin/flags/{name}")>
+                                    <input type="hidden" name="enabled" value=(!enabled).to_string()/>
+                                    <button type="submit">{if enabled { "Turn off" } else { "Turn on" }}</button>
+                                </form>
+                            </td>
+                        </tr>
+                    }).collect_view(cx)}
+                </table>
+            })}
+            <h2>"Jobs"</h2>
+            <table>
+                {jobs.into_iter().map(|(name, schedule)| view! { cx,
+                    <tr>
+                        <td>{name}</td>
+                        <td><code>{schedule}</code></td>
+                        <td>
+                            <form method="post" action=format!("/__admin/jobs/{name}")>
+                                <button type="submit">"Run now"</button>
+                            </form>
+                        </td>
+                    </tr>
+                }).collect_view(cx)}
+            </table>
+        }
+        .into_view(cx)
+    });
+
+    format!("<!DOCTYPE html><html><head><title>Admin</title></head><body>{html}</body></html>")
+}
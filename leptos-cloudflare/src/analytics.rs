@@ -0,0 +1,210 @@
+//! Writing data points to [Workers Analytics Engine](https://developers.cloudflare.com/analytics/analytics-engine/).
+//!
+//! [use_analytics] gets a dataset from the bindings of the request, for events of the app:
+//!
+//! ```ignore
+//! #[server(AddToCart, "/api")]
+//! pub async fn add_to_cart(cx: Scope, sku: String, quantity: u32) -> Result<(), ServerFnError> {
+//!     use_analytics(cx, "CART_EVENTS")?.write(
+//!         DataPoint::new().index(&sku).blob("add").double(quantity as f64),
+//!     )?;
+//!     // ...
+//! }
+//! ```
+//!
+//! With [WorkerRouterDataBuilder::request_metrics](crate::WorkerRouterDataBuilder::request_metrics),
+//! the render handlers and [handle_server_fns](crate::handle_server_fns) also write a data point for
+//! every page and server function call, laid out as:
+//!
+//! | Field     | Value                                                                      |
+//! |-----------|----------------------------------------------------------------------------|
+//! | `index1`  | the route, e.g. `/post/:id`, or the path of the server function              |
+//! | `blob1`   | `page` or `server_fn`                                                      |
+//! | `blob2`   | the route, as in `index1`                                                  |
+//! | `blob3`   | the [SsrMode](leptos_router::SsrMode) of a page, e.g. `out-of-order`, empty for server functions |
+//! | `blob4`   | the method                                                                 |
+//! | `double1` | the status, `0` if the handler failed                                      |
+//! | `double2` | the duration until the response was returned, in milliseconds              |
+//!
+//! ```sql
+//! SELECT blob2 AS route, quantileWeighted(0.99)(double2, _sample_interval) AS p99
+//! FROM request_metrics WHERE blob1 = 'page' AND timestamp > NOW() - INTERVAL '1' DAY
+//! GROUP BY route
+//! ```
+//!
+//! Streamed pages return their response once the shell is ready, so their duration doesn't include
+//! the rest of the stream. Writes don't wait for the dataset and never fail the request.
+
+use std::cell::RefCell;
+
+use js_sys::{Array, Object, Reflect};
+use leptos::Scope;
+use leptos_router::SsrMode;
+use wasm_bindgen::{JsCast, JsValue};
+use worker::Env;
+
+use crate::route_config::{path_matches, static_segments};
+use crate::{use_env, LeptosCloudflareError};
+
+/// Analytics Engine drops indexes longer than this.
+const MAX_INDEX_BYTES: usize = 96;
+
+thread_local! {
+    // The patterns of the app routes, to record the route of a page rather than its path
+    static ROUTES: RefCell<Vec<String>> = RefCell::new(Vec::new());
+}
+
+/// The dataset bound as `binding`, from the bindings of the current request.
+pub fn use_analytics(cx: Scope, binding: &str) -> Result<AnalyticsDataset, LeptosCloudflareError> {
+    Ok(AnalyticsDataset::from_env(&use_env(cx)?, binding)?)
+}
+
+/// A data point: up to 20 blobs and 20 doubles, and one index that sampling is grouped by.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DataPoint {
+    indexes: Vec<String>,
+    blobs: Vec<String>,
+    doubles: Vec<f64>,
+}
+
+impl DataPoint {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the index, truncated to 96 bytes.
+    pub fn index(mut self, index: &str) -> Self {
+        let mut end = index.len().min(MAX_INDEX_BYTES);
+        while !index.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.indexes = vec![index[..end].to_string()];
+        self
+    }
+
+    /// Adds the next blob, `blob1` first.
+    pub fn blob(mut self, blob: impl Into<String>) -> Self {
+        self.blobs.push(blob.into());
+        self
+    }
+
+    /// Adds the next double, `double1` first.
+    pub fn double(mut self, double: f64) -> Self {
+        self.doubles.push(double);
+        self
+    }
+
+    fn to_js(&self) -> Result<JsValue, JsValue> {
+        let point = Object::new();
+        let strings = |values: &[String]| {
+            values
+                .iter()
+                .map(|value| JsValue::from_str(value))
+                .collect::<Array>()
+        };
+        Reflect::set(&point, &"indexes".into(), &strings(&self.indexes))?;
+        Reflect::set(&point, &"blobs".into(), &strings(&self.blobs))?;
+        let doubles = self
+            .doubles
+            .iter()
+            .map(|double| JsValue::from_f64(*double))
+            .collect::<Array>();
+        Reflect::set(&point, &"doubles".into(), &doubles)?;
+        Ok(point.into())
+    }
+}
+
+/// An Analytics Engine dataset.
+#[derive(Clone)]
+pub struct AnalyticsDataset(JsValue);
+
+impl AnalyticsDataset {
+    /// The dataset bound as `binding` in `env`.
+    pub fn from_env(env: &Env, binding: &str) -> worker::Result<Self> {
+        let dataset = Reflect::get(env, &JsValue::from_str(binding))?;
+        if dataset.is_undefined() {
+            return Err(worker::Error::RustError(format!(
+                "no Analytics Engine dataset is bound as {binding}"
+            )));
+        }
+        Ok(Self(dataset))
+    }
+
+    /// Writes `point`. The write is sent in the background after the response.
+    pub fn write(&self, point: DataPoint) -> worker::Result<()> {
+        let write: js_sys::Function =
+            Reflect::get(&self.0, &"writeDataPoint".into())?.unchecked_into();
+        write.call1(&self.0, &point.to_js()?)?;
+        Ok(())
+    }
+}
+
+/// Records a data point for every page and server function call to the dataset bound as `binding`.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestMetrics {
+    binding: &'static str,
+}
+
+impl RequestMetrics {
+    pub const fn new(binding: &'static str) -> Self {
+        Self { binding }
+    }
+
+    /// Records a request that started at `started_at`, in milliseconds since the epoch: a page
+    /// rendered with `mode`, or a server function call without.
+    pub(crate) fn record(
+        &self,
+        env: &Env,
+        route: &str,
+        mode: Option<SsrMode>,
+        method: &worker::Method,
+        status: Option<u16>,
+        started_at: u64,
+    ) {
+        let duration = worker::Date::now().as_millis().saturating_sub(started_at);
+        let point = DataPoint::new()
+            .index(route)
+            .blob(if mode.is_some() { "page" } else { "server_fn" })
+            .blob(route)
+            .blob(mode.map(ssr_mode_name).unwrap_or_default())
+            .blob(method.to_string())
+            .double(status.unwrap_or_default() as f64)
+            .double(duration as f64);
+        let written =
+            AnalyticsDataset::from_env(env, self.binding).and_then(|dataset| dataset.write(point));
+        if let Err(err) = written {
+            tracing::warn!("failed to record request metrics: {err}");
+        }
+    }
+}
+
+/// Remembers the patterns of the app routes for [route_of].
+pub(crate) fn register_routes<'a>(patterns: impl Iterator<Item = &'a str>) {
+    ROUTES.with(|routes| {
+        let mut routes = routes.borrow_mut();
+        routes.clear();
+        routes.extend(patterns.map(str::to_string));
+    });
+}
+
+/// The most specific route pattern matching `path`, or `path` itself if none does.
+pub(crate) fn route_of(path: &str) -> String {
+    ROUTES.with(|routes| {
+        routes
+            .borrow()
+            .iter()
+            .filter(|pattern| path_matches(pattern, path))
+            .max_by_key(|pattern| static_segments(pattern))
+            .cloned()
+            .unwrap_or_else(|| path.to_string())
+    })
+}
+
+fn ssr_mode_name(mode: SsrMode) -> &'static str {
+    match mode {
+        SsrMode::OutOfOrder => "out-of-order",
+        SsrMode::PartiallyBlocked => "partially-blocked",
+        SsrMode::InOrder => "in-order",
+        SsrMode::Async => "async",
+    }
+}
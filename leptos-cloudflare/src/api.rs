@@ -0,0 +1,253 @@
+//! A small layer for endpoints that are neither Leptos routes nor server functions, e.g. a public
+//! REST API or a health check living in the same worker.
+//!
+//! ```ignore
+//! #[derive(Deserialize)]
+//! struct PostPath {
+//!     id: usize,
+//! }
+//!
+//! async fn get_post(req: ApiRequest<PostPath>) -> worker::Result<worker::Response> {
+//!     worker::Response::from_json(&find_post(req.path.id))
+//! }
+//!
+//! router
+//!     .leptos_routes(routes)
+//!     .api_route(worker::Method::Get, "/v1/posts/:id", get_post)
+//! ```
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::rc::Rc;
+
+use futures::future::LocalBoxFuture;
+use http::StatusCode;
+use serde::de::DeserializeOwned;
+
+use crate::memo_cache::{memo_cache, MemoCache};
+use crate::normalize::{decode_param, PathError};
+use crate::query;
+use crate::response::IntoWorkerResponse;
+use crate::route_config::{extract_params, path_matches, static_segments};
+use crate::{generate_request_parts, ErrorRenderer, RequestParts};
+
+/// Everything an API handler receives: the request, its typed path parameters and query string,
+/// and the worker environment.
+pub struct ApiRequest<Path = HashMap<String, String>, Query = HashMap<String, String>> {
+    pub parts: RequestParts,
+    pub path: Path,
+    pub query: Query,
+    pub env: worker::Env,
+}
+
+type BoxedApiHandler = Rc<
+    dyn Fn(
+        RequestParts,
+        HashMap<String, String>,
+        worker::Env,
+    ) -> LocalBoxFuture<'static, worker::Result<worker::Response>>,
+>;
+
+thread_local! {
+    static API_ROUTES: RouteRegistry<BoxedApiHandler> = RouteRegistry::new();
+}
+
+/// Handlers for the routes of a [worker::Router](worker::Router), which only accepts function
+/// pointers: handlers that may capture state are registered here, and a dispatcher function added
+/// to the router looks them up. Every kind of route keeps its own registry in a `thread_local!`.
+pub(crate) struct RouteRegistry<H> {
+    routes: RefCell<Vec<RegisteredRoute<H>>>,
+    // Method and path -> index of the matching route
+    matches: MemoCache<(String, String), Option<usize>>,
+}
+
+struct RegisteredRoute<H> {
+    method: worker::Method,
+    pattern: String,
+    handler: H,
+}
+
+impl<H: Clone> RouteRegistry<H> {
+    pub(crate) fn new() -> Self {
+        Self {
+            routes: RefCell::new(Vec::new()),
+            matches: memo_cache(1024),
+        }
+    }
+
+    /// Registers `handler` for requests with `method` whose path matches `pattern`.
+    pub(crate) fn register(&self, method: worker::Method, pattern: &str, handler: H) {
+        let mut routes = self.routes.borrow_mut();
+        // The router is rebuilt for every request, so replace instead of piling up duplicates
+        let existing = routes
+            .iter_mut()
+            .find(|route| route.method == method && route.pattern == pattern);
+        match existing {
+            Some(route) => route.handler = handler,
+            None => {
+                routes.push(RegisteredRoute {
+                    method,
+                    pattern: pattern.to_string(),
+                    handler,
+                });
+                // The new route may be a better match for memoized paths
+                self.matches.clear();
+            }
+        }
+    }
+
+    /// The handler of the most specific route for `method` and `path`, with the `:param` and
+    /// `*wildcard` values of the path, still percent-encoded.
+    pub(crate) fn find(
+        &self,
+        method: &worker::Method,
+        path: &str,
+    ) -> Option<(H, HashMap<String, String>)> {
+        let key = (method.to_string(), path.to_string());
+        let index = self.matches.get_or_insert_with(key, || {
+            self.routes
+                .borrow()
+                .iter()
+                .enumerate()
+                .filter(|(_, route)| route.method == *method)
+                .filter(|(_, route)| path_matches(&route.pattern, path))
+                .max_by_key(|(_, route)| static_segments(&route.pattern))
+                .map(|(index, _)| index)
+        })?;
+        let routes = self.routes.borrow();
+        let route = routes.get(index)?;
+        let params = extract_params(&route.pattern, path)?;
+        Some((route.handler.clone(), params))
+    }
+}
+
+/// Decodes the values found by [RouteRegistry::find] (see [decode_param]).
+pub(crate) fn decode_params(
+    params: HashMap<String, String>,
+) -> Result<HashMap<String, String>, PathError> {
+    params
+        .into_iter()
+        .map(|(name, value)| Ok((name, decode_param(&value)?)))
+        .collect()
+}
+
+/// Adds `dispatch` to `router` for requests with `method` whose path matches `path`.
+pub(crate) fn route<'a, D, T>(
+    router: worker::Router<'a, D>,
+    method: &worker::Method,
+    path: &str,
+    dispatch: fn(worker::Request, worker::RouteContext<D>) -> T,
+) -> worker::Router<'a, D>
+where
+    T: Future<Output = worker::Result<worker::Response>> + 'a,
+{
+    match method {
+        worker::Method::Get => router.get_async(path, dispatch),
+        worker::Method::Post => router.post_async(path, dispatch),
+        worker::Method::Put => router.put_async(path, dispatch),
+        worker::Method::Patch => router.patch_async(path, dispatch),
+        worker::Method::Delete => router.delete_async(path, dispatch),
+        worker::Method::Head => router.head_async(path, dispatch),
+        worker::Method::Options => router.options_async(path, dispatch),
+        _ => router.on_async(path, dispatch),
+    }
+}
+
+pub trait ApiRoutes {
+    /// Registers `handler` for requests with `method` whose path matches `path`, which uses the
+    /// same `:param` and `*wildcard` syntax as the worker router. Path parameters are
+    /// [decoded](crate::normalize::decode_param) and deserialized into `Path`, and the query string
+    /// into `Query`, whose `Vec` fields take repeated parameters (see [Query](crate::Query)); a
+    /// mismatch for either responds with 400.
+    fn api_route<H, Fut, R, Path, Query>(
+        self,
+        method: worker::Method,
+        path: &str,
+        handler: H,
+    ) -> Self
+    where
+        H: Fn(ApiRequest<Path, Query>) -> Fut + 'static,
+        Fut: Future<Output = R> + 'static,
+        R: IntoWorkerResponse + 'static,
+        Path: DeserializeOwned + 'static,
+        Query: DeserializeOwned + 'static;
+}
+
+impl<'a, D: 'static> ApiRoutes for worker::Router<'a, D> {
+    fn api_route<H, Fut, R, Path, Query>(
+        self,
+        method: worker::Method,
+        path: &str,
+        handler: H,
+    ) -> Self
+    where
+        H: Fn(ApiRequest<Path, Query>) -> Fut + 'static,
+        Fut: Future<Output = R> + 'static,
+        R: IntoWorkerResponse + 'static,
+        Path: DeserializeOwned + 'static,
+        Query: DeserializeOwned + 'static,
+    {
+        let handler: BoxedApiHandler = Rc::new(move |parts, params, env| {
+            let accept = parts.headers.get("Accept").ok().flatten();
+            let request = extract(parts, params, env);
+            match request {
+                Ok(request) => {
+                    let response = handler(request);
+                    Box::pin(async move { response.await.into_worker_response() })
+                }
+                Err(err) => Box::pin(async move {
+                    ErrorRenderer::installed().render(
+                        accept.as_deref(),
+                        StatusCode::BAD_REQUEST,
+                        &err,
+                    )
+                }),
+            }
+        });
+
+        API_ROUTES.with(|routes| routes.register(method.clone(), path, handler));
+        route(self, &method, path, dispatch_api_route::<D>)
+    }
+}
+
+fn extract<Path, Query>(
+    parts: RequestParts,
+    params: HashMap<String, String>,
+    env: worker::Env,
+) -> Result<ApiRequest<Path, Query>, String>
+where
+    Path: DeserializeOwned,
+    Query: DeserializeOwned,
+{
+    let params = decode_params(params).map_err(|err| format!("Invalid path parameters: {err}"))?;
+    // Route parameters are re-encoded so that they go through the same string-to-value
+    // conversions as the query string
+    let encoded_params = serde_urlencoded::to_string(&params).map_err(|err| err.to_string())?;
+    let path = serde_urlencoded::from_str::<Path>(&encoded_params)
+        .map_err(|err| format!("Invalid path parameters: {err}"))?;
+    let query::Query(query) = query::Query::<Query>::parse(parts.url.query().unwrap_or(""))
+        .map_err(|err| err.to_string())?;
+
+    Ok(ApiRequest {
+        parts,
+        path,
+        query,
+        env,
+    })
+}
+
+async fn dispatch_api_route<D>(
+    mut req: worker::Request,
+    ctx: worker::RouteContext<D>,
+) -> worker::Result<worker::Response> {
+    let matched = API_ROUTES.with(|routes| routes.find(&req.method(), &req.path()));
+
+    match matched {
+        Some((handler, params)) => {
+            let parts = generate_request_parts(&mut req).await?;
+            handler(parts, params, ctx.env).await
+        }
+        None => ErrorRenderer::installed().render_for(&req, StatusCode::NOT_FOUND, "Not found"),
+    }
+}
@@ -0,0 +1,192 @@
+//! API keys for third-party integrations, stored hashed in KV.
+//!
+//! Each key is stored under `apikey:<sha256 of the key>` with its scopes and an optional rate limit, so a
+//! leaked KV namespace doesn't leak usable keys. Clients send the key as `Authorization: Bearer <key>` or
+//! `X-Api-Key: <key>`. With [WorkerRouterDataBuilder::api_keys](crate::WorkerRouterDataBuilder::api_keys),
+//! the server functions marked with [protect](ApiKeyAuth::protect) require a key with their scope, and
+//! every server function can read the key it was called with through [use_api_key]:
+//!
+//! ```ignore
+//! let api_keys = ApiKeyAuth::new("API_KEYS").protect("/api/list_orders", "orders:read");
+//!
+//! WorkerRouterData::builder()
+//!     .api_keys(api_keys.clone())
+//!     // ...
+//!
+//! // from an admin server function, the plaintext key is only returned here
+//! let (key, record) = api_keys.create(&env, "Acme ERP", &["orders:read"], Some(60)).await?;
+//! ```
+//!
+//! `Authorization: Bearer` may carry other credentials, e.g. the app's own session tokens, so a bearer
+//! token that isn't a known key is only rejected where a scope is required. An unknown `X-Api-Key` is
+//! always rejected.
+//!
+//! [api_route](crate::ApiRoutes::api_route) handlers check keys with [authenticate](ApiKeyAuth::authenticate).
+//! Rate limits are counted per isolate, so they bound the load a key causes rather than enforce an exact
+//! quota.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use leptos::{use_context, Scope};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::util::random_bytes;
+use crate::LeptosCloudflareError;
+
+/// What is stored for a key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyRecord {
+    /// The first characters of the key, to tell keys apart in listings.
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    /// Requests per minute, unlimited if `None`.
+    pub rate_limit_per_minute: Option<u32>,
+    /// Milliseconds since the epoch.
+    pub created_at: u64,
+}
+
+impl ApiKeyRecord {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes
+            .iter()
+            .any(|granted| granted == scope || granted == "*")
+    }
+}
+
+/// The key a server function was called with, provided in its context.
+#[derive(Debug, Clone)]
+pub struct ApiKey(pub Rc<ApiKeyRecord>);
+
+/// Validates API keys against a KV namespace, and guards the server functions marked as protected.
+#[derive(Debug, Clone)]
+pub struct ApiKeyAuth {
+    binding: &'static str,
+    /// Server function path -> required scope.
+    protected: HashMap<String, String>,
+}
+
+thread_local! {
+    // Key id -> start of its current one-minute window and the requests made in it
+    static USAGE: RefCell<HashMap<String, (u64, u32)>> = RefCell::new(HashMap::new());
+}
+
+impl ApiKeyAuth {
+    /// Keys are stored in the KV namespace `binding`.
+    pub fn new(binding: &'static str) -> Self {
+        Self {
+            binding,
+            protected: HashMap::new(),
+        }
+    }
+
+    /// Requires a key with `scope` to call the server function at `path`, e.g. `/api/list_orders`.
+    pub fn protect(mut self, path: &str, scope: &str) -> Self {
+        self.protected.insert(path.to_string(), scope.to_string());
+        self
+    }
+
+    /// The scope required for the server function at `path`, if it is protected.
+    pub(crate) fn required_scope(&self, path: &str) -> Option<&str> {
+        self.protected.get(path).map(String::as_str)
+    }
+
+    /// Finds the key sent with `headers`, returning `None` if there is none and an error if it is
+    /// unknown, lacks `scope` or exceeded its rate limit. Without `scope`, an unknown bearer token
+    /// gives `None`, since it may not be meant as an API key.
+    pub async fn authenticate(
+        &self,
+        env: &worker::Env,
+        headers: &worker::Headers,
+        scope: Option<&str>,
+    ) -> Result<Option<ApiKey>, LeptosCloudflareError> {
+        let Some((key, bearer)) = provided_key(headers)? else {
+            return Ok(None);
+        };
+
+        let record = env
+            .kv(self.binding)?
+            .get(&storage_key(&key))
+            .json::<ApiKeyRecord>()
+            .await
+            .map_err(worker::Error::from)?;
+        let record = match record {
+            Some(record) => record,
+            None if bearer && scope.is_none() => return Ok(None),
+            None => return Err(LeptosCloudflareError::Unauthorized),
+        };
+        if scope.is_some_and(|scope| !record.has_scope(scope)) {
+            return Err(LeptosCloudflareError::Forbidden);
+        }
+        if let Some(limit) = record.rate_limit_per_minute {
+            if !within_rate_limit(&record.id, limit) {
+                return Err(LeptosCloudflareError::TooManyRequests);
+            }
+        }
+        Ok(Some(ApiKey(Rc::new(record))))
+    }
+
+    /// Creates a key and returns it with its record. Only its hash is stored, so the key can't be
+    /// shown again.
+    pub async fn create(
+        &self,
+        env: &worker::Env,
+        name: &str,
+        scopes: &[&str],
+        rate_limit_per_minute: Option<u32>,
+    ) -> worker::Result<(String, ApiKeyRecord)> {
+        let key = format!("lck_{}", hex::encode(random_bytes(24)?));
+        let record = ApiKeyRecord {
+            id: key[..12].to_string(),
+            name: name.to_string(),
+            scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+            rate_limit_per_minute,
+            created_at: worker::Date::now().as_millis(),
+        };
+        env.kv(self.binding)?
+            .put(&storage_key(&key), &record)?
+            .execute()
+            .await?;
+        Ok((key, record))
+    }
+
+    /// Revokes `key`. KV takes up to a minute to stop serving it everywhere.
+    pub async fn revoke(&self, env: &worker::Env, key: &str) -> worker::Result<()> {
+        env.kv(self.binding)?.delete(&storage_key(key)).await?;
+        Ok(())
+    }
+}
+
+/// The API key the current server function was called with, if any.
+pub fn use_api_key(cx: Scope) -> Option<ApiKey> {
+    use_context::<ApiKey>(cx)
+}
+
+/// The key sent with `headers`, and whether it was sent as a bearer token.
+fn provided_key(headers: &worker::Headers) -> worker::Result<Option<(String, bool)>> {
+    if let Some(key) = headers.get("X-Api-Key")? {
+        return Ok(Some((key, false)));
+    }
+    Ok(headers
+        .get("Authorization")?
+        .and_then(|header| header.strip_prefix("Bearer ").map(str::to_string))
+        .map(|key| (key, true)))
+}
+
+fn storage_key(key: &str) -> String {
+    format!("apikey:{}", hex::encode(Sha256::digest(key.as_bytes())))
+}
+
+fn within_rate_limit(id: &str, limit: u32) -> bool {
+    let minute = worker::Date::now().as_millis() / 60_000;
+    USAGE.with(|usage| {
+        let mut usage = usage.borrow_mut();
+        usage.retain(|_, (window, _)| *window == minute);
+        let (_, count) = usage.entry(id.to_string()).or_insert((minute, 0));
+        *count += 1;
+        *count <= limit
+    })
+}
@@ -0,0 +1,61 @@
+//! Root views that depend on the bindings of the worker or on the request.
+//!
+//! [WorkerRouterData::app_fn](crate::WorkerRouterData::app_fn) only gets a `Scope`. Wrap a function that
+//! also takes the [Env] and the [RequestParts] with [app_with_env] to branch on them without statics:
+//!
+//! ```ignore
+//! let data = WorkerRouterData::builder()
+//!     .options(leptos_options)
+//!     .app_with_env(|cx, env, _req| {
+//!         let maintenance = env.var("MAINTENANCE").is_ok_and(|var| var.to_string() == "1");
+//!         view! { cx, <App maintenance/> }
+//!     })
+//!     .build()?;
+//! ```
+//!
+//! The wrapped function only works while handling a request, so keep passing the plain app to
+//! [generate_route_list](crate::generate_route_list). The client hydrates the same view without the
+//! bindings, so whatever the server branches on has to reach the client as well, e.g. as a prop.
+//!
+//! Server functions and components rendered on the server get the bindings with [use_env]:
+//!
+//! ```ignore
+//! #[server(CountVisits, "/api")]
+//! pub async fn count_visits(cx: Scope) -> Result<u64, ServerFnError> {
+//!     let kv = use_env(cx)?.kv("COUNTERS")?;
+//!     // ...
+//! }
+//! ```
+
+use leptos::{use_context, Scope};
+use worker::Env;
+
+use crate::{LeptosCloudflareError, RequestParts};
+
+/// The bindings of the worker, provided in the context of every rendered request.
+#[derive(Clone)]
+pub(crate) struct RequestEnv(pub(crate) Env);
+
+/// The bindings of the worker, for KV, D1, R2, secrets and so on. Fails outside of a request handled
+/// by the crate, e.g. when the component renders in the browser.
+pub fn use_env(cx: Scope) -> Result<Env, LeptosCloudflareError> {
+    use_context::<RequestEnv>(cx)
+        .map(|env| env.0)
+        .ok_or_else(|| {
+            LeptosCloudflareError::Internal("use_env called outside of a request".to_string())
+        })
+}
+
+/// Turns `app_fn` into a root component that gets the bindings and the request of the render.
+pub fn app_with_env<IV, F>(app_fn: F) -> impl Fn(Scope) -> IV + Clone + 'static
+where
+    F: Fn(Scope, &Env, &RequestParts) -> IV + Clone + 'static,
+{
+    move |cx| {
+        let env = use_context::<RequestEnv>(cx)
+            .expect("app_with_env renders outside of a request handled by leptos-cloudflare");
+        let req = use_context::<RequestParts>(cx)
+            .expect("app_with_env renders outside of a request handled by leptos-cloudflare");
+        app_fn(cx, &env.0, &req)
+    }
+}
@@ -0,0 +1,155 @@
+//! Serving static assets uploaded by `leptos-cloudflare-sync` instead of Worker Sites.
+//!
+//! The sync tool uploads every file of the site directory to a KV namespace or an R2 bucket under a
+//! content-hashed key, skipping files that haven't changed since the last sync, and stores an
+//! [AssetManifest] mapping paths to keys under [MANIFEST_KEY]. Set
+//! [WorkerRouterData::assets](crate::WorkerRouterData::assets) to the same store to serve from it:
+//!
+//! ```sh
+//! leptos-cloudflare-sync --dir pkg --kv <namespace id>
+//! ```
+//!
+//! ```ignore
+//! WorkerRouterData::builder()
+//!     .assets(AssetSource::Kv("ASSETS"))
+//!     // ...
+//! ```
+//!
+//! The manifest is read once per isolate. Sync before deploying, so that the isolates of the new
+//! deployment read the new manifest. Manifests synced as a version are served once a pointer in KV
+//! selects them, see [content_version](crate::content_version).
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use worker::Env;
+
+use crate::memo_cache::{memo_cache, MemoCache};
+use crate::ErrorRenderer;
+
+/// The key the manifest is stored under, next to the assets.
+pub const MANIFEST_KEY: &str = "__manifest.json";
+
+/// The key the manifest of `version` is stored under, or [MANIFEST_KEY] without a version.
+pub fn manifest_key(version: Option<&str>) -> String {
+    match version {
+        Some(version) => format!("__manifests/{version}.json"),
+        None => MANIFEST_KEY.to_string(),
+    }
+}
+
+/// The total number of files of the manifests kept in memory.
+const MAX_MANIFEST_FILES: usize = 100_000;
+
+/// Where static assets are served from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum AssetSource {
+    /// The `__STATIC_CONTENT` namespace and manifest of Worker Sites (`[site]` in `wrangler.toml`).
+    #[default]
+    WorkerSites,
+    /// A KV namespace synced by `leptos-cloudflare-sync`, by binding.
+    Kv(&'static str),
+    /// An R2 bucket synced by `leptos-cloudflare-sync`, by binding.
+    R2(&'static str),
+}
+
+/// Maps the path of every asset, relative to the site directory, to where it is stored.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AssetManifest {
+    pub files: HashMap<String, AssetEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssetEntry {
+    /// The key of the file in the store.
+    pub key: String,
+    /// SHA-256 of the content, hex encoded.
+    pub hash: String,
+}
+
+thread_local! {
+    // Weighed by their number of files, so a runaway manifest can't take the memory of the isolate
+    static MANIFESTS: MemoCache<(AssetSource, String), Rc<AssetManifest>> =
+        memo_cache(MAX_MANIFEST_FILES).weigher(|_, manifest| manifest.files.len().max(1));
+}
+
+async fn manifest(
+    env: &Env,
+    source: AssetSource,
+    version: Option<&str>,
+) -> worker::Result<Rc<AssetManifest>> {
+    let key = (source, manifest_key(version));
+    if let Some(manifest) = MANIFESTS.with(|manifests| manifests.get(&key)) {
+        return Ok(manifest);
+    }
+
+    let manifest = match read(env, source, &key.1).await? {
+        Some(bytes) => serde_json::from_slice::<AssetManifest>(&bytes)?,
+        None => {
+            tracing::warn!("no asset manifest found in {source:?} under {}", key.1);
+            AssetManifest::default()
+        }
+    };
+    let manifest = Rc::new(manifest);
+    MANIFESTS.with(|manifests| manifests.insert(key, manifest.clone()));
+    Ok(manifest)
+}
+
+async fn read(env: &Env, source: AssetSource, key: &str) -> worker::Result<Option<Vec<u8>>> {
+    match source {
+        AssetSource::WorkerSites => Ok(None),
+        AssetSource::Kv(binding) => Ok(env.kv(binding)?.get(key).bytes().await?),
+        AssetSource::R2(binding) => {
+            let Some(object) = env.bucket(binding)?.get(key).execute().await? else {
+                return Ok(None);
+            };
+            match object.body() {
+                Some(body) => Ok(Some(body.bytes().await?)),
+                None => Ok(None),
+            }
+        }
+    }
+}
+
+/// Serves the asset at `path`, relative to the site directory, from the manifest of `version` in a
+/// synced store.
+pub(crate) async fn serve_synced_asset(
+    env: &Env,
+    source: AssetSource,
+    version: Option<&str>,
+    path: &str,
+    req: &worker::Request,
+    errors: &ErrorRenderer,
+) -> worker::Result<worker::Response> {
+    let manifest = manifest(env, source, version).await?;
+    let Some(entry) = manifest.files.get(path) else {
+        return errors.render_for(req, StatusCode::NOT_FOUND, "Not found");
+    };
+    let Some(content_type) = mime_guess::from_path(path).first() else {
+        return errors.render_for(
+            req,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Unsupported file type",
+        );
+    };
+    let if_none_match = req.headers().get("If-None-Match")?;
+
+    let etag = format!("\"{}\"", entry.hash);
+    if if_none_match.as_deref() == Some(etag.as_str()) {
+        let mut response = worker::Response::empty()?.with_status(304);
+        response.headers_mut().set("ETag", &etag)?;
+        return Ok(response);
+    }
+
+    let Some(bytes) = read(env, source, &entry.key).await? else {
+        return errors.render_for(req, StatusCode::NOT_FOUND, "Not found");
+    };
+    let mut response = worker::Response::from_bytes(bytes)?;
+    response
+        .headers_mut()
+        .set("Content-Type", content_type.essence_str())?;
+    response.headers_mut().set("ETag", &etag)?;
+    Ok(response)
+}
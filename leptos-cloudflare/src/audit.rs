@@ -0,0 +1,82 @@
+//! Progressive enhancement audit.
+//!
+//! In development (`LeptosOptions::env` is `DEV`), adding `?__nojs` to any URL serves the page the way a
+//! browser without JavaScript would get it: without the wasm bundle. The rendered HTML is also checked
+//! for elements that only work once the app is hydrated, and each one is logged as a warning:
+//!
+//! - `<form>`s without an `action`, which have nowhere to submit to
+//! - `<a>`s without an `href`, which are only clickable through event handlers
+//! - `<button>`s outside of any form, which do nothing without event handlers
+//!
+//! An `ActionForm` posting to a server function passes, as it renders a regular form.
+
+use leptos::leptos_config::Env;
+use leptos::LeptosOptions;
+
+/// The query parameter that turns the audit on.
+pub const AUDIT_QUERY_PARAM: &str = "__nojs";
+
+/// Provided in the context of requests that are audited.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct NoJsAudit;
+
+/// Whether `url` asks for an audit, which is only honored in development.
+pub(crate) fn audit_requested(options: &LeptosOptions, url: &worker::Url) -> bool {
+    options.env == Env::DEV && url.query_pairs().any(|(key, _)| key == AUDIT_QUERY_PARAM)
+}
+
+/// Logs every element of `html` that wouldn't work without JavaScript.
+pub(crate) fn audit_html(html: &str) {
+    let mut form_depth = 0usize;
+    let mut issues = 0;
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        let end = rest.find('>').map(|end| end + 1).unwrap_or(rest.len());
+        let tag = &rest[..end];
+        let name = tag[1..]
+            .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        let issue = match name.as_str() {
+            "form" => {
+                form_depth += 1;
+                (!has_attribute(tag, "action")).then_some("form without an action")
+            }
+            "/form" => {
+                form_depth = form_depth.saturating_sub(1);
+                None
+            }
+            "a" => (!has_attribute(tag, "href")).then_some("link without an href"),
+            "button" if form_depth == 0 => Some("button outside of a form"),
+            _ => None,
+        };
+        if let Some(issue) = issue {
+            issues += 1;
+            tracing::warn!("no-JS audit: {issue}: {}", truncate(tag, 120));
+        }
+
+        rest = &rest[end..];
+    }
+
+    tracing::info!("no-JS audit finished with {issues} issue(s)");
+}
+
+fn has_attribute(tag: &str, attribute: &str) -> bool {
+    tag.split_whitespace().skip(1).any(|part| {
+        part.trim_end_matches(['>', '/'])
+            .split('=')
+            .next()
+            .is_some_and(|name| name.eq_ignore_ascii_case(attribute))
+    })
+}
+
+fn truncate(text: &str, max: usize) -> &str {
+    match text.char_indices().nth(max) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
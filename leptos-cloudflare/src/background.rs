@@ -0,0 +1,65 @@
+//! Background work that outlives the response, e.g. cache writes, analytics or webhooks.
+//!
+//! The runtime may stop a worker as soon as its response is sent, so work that isn't awaited before
+//! has to be registered with [Context::wait_until](worker::Context::wait_until). Pass the context of
+//! the fetch handler to [WorkerRouterDataBuilder::context](crate::WorkerRouterDataBuilder::context),
+//! and components and server functions can schedule work through [use_wait_until]:
+//!
+//! ```ignore
+//! #[worker::event(fetch)]
+//! pub async fn main(req: Request, env: Env, ctx: Context) -> worker::Result<Response> {
+//!     let data = WorkerRouterData::builder()
+//!         .options(leptos_options)
+//!         .app(App)
+//!         .context(ctx)
+//!         .build()?;
+//!     data.into_router().leptos_routes(routes).run(req, env).await
+//! }
+//!
+//! #[server(PlaceOrder, "/api")]
+//! pub async fn place_order(cx: Scope, order: Order) -> Result<(), ServerFnError> {
+//!     save(cx, &order).await?;
+//!     let env = use_env(cx)?;
+//!     use_wait_until(cx).spawn(async move {
+//!         if let Err(err) = notify_fulfillment(&env, &order).await {
+//!             tracing::error!("failed to notify fulfillment: {err}");
+//!         }
+//!     });
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Streamed pages keep rendering while the response is sent, so work scheduled while rendering doesn't
+//! hold back the first bytes either.
+
+use std::future::Future;
+use std::rc::Rc;
+
+use leptos::{use_context, Scope};
+
+/// Schedules futures to run after the response, provided in the context of every request.
+#[derive(Clone, Default)]
+pub struct WaitUntil(pub(crate) Option<Rc<worker::Context>>);
+
+impl WaitUntil {
+    /// Runs `future` to completion even if the response is sent before it finishes.
+    ///
+    /// Without the [Context](worker::Context) of the request, e.g. when it wasn't passed to the
+    /// builder, `future` is only spawned, and may be cancelled once the response is sent.
+    pub fn spawn(&self, future: impl Future<Output = ()> + 'static) {
+        match &self.0 {
+            Some(ctx) => ctx.wait_until(future),
+            None => {
+                tracing::warn!(
+                    "no worker::Context for wait_until, background work may be cancelled: pass it to WorkerRouterDataBuilder::context"
+                );
+                wasm_bindgen_futures::spawn_local(future);
+            }
+        }
+    }
+}
+
+/// The [WaitUntil] of the current request.
+pub fn use_wait_until(cx: Scope) -> WaitUntil {
+    use_context::<WaitUntil>(cx).unwrap_or_default()
+}
@@ -0,0 +1,505 @@
+//! Building [WorkerRouterData] and the router around it.
+//!
+//! The builder fills in the defaults and checks that the settings fit together before the first request
+//! comes in, instead of requests failing (or being routed to the wrong handler) at runtime:
+//!
+//! ```ignore
+//! let data = WorkerRouterData::builder()
+//!     .options(leptos_options)
+//!     .app(App)
+//!     .server_fn_prefix("/api")
+//!     .static_dir("assets")
+//!     .build()?;
+//!
+//! data.into_router()
+//!     .leptos_routes(routes)
+//!     .run(req, env)
+//!     .await
+//! ```
+//!
+//! [into_router](WorkerRouterData::into_router) registers the routes for the wasm bundle, the static
+//! directories and the server functions, so only the app routes are left to add.
+//! [serve](WorkerRouterData::serve) adds them only for requests that aren't for an asset or a server
+//! function, which are then answered without setting up the app.
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use leptos::leptos_server::server_fn_by_path;
+use leptos::{IntoView, LeptosOptions};
+use thiserror::Error;
+
+use crate::analytics::RequestMetrics;
+#[cfg(feature = "api-keys")]
+use crate::api_keys::ApiKeyAuth;
+use crate::content_version::ContentVersions;
+use crate::normalize::normalize_request;
+use crate::page_cache::PageCache;
+use crate::redirects::RedirectQuery;
+use crate::request_headers::RequestHeaderPolicy;
+#[cfg(feature = "flash")]
+use crate::seal::Sealer;
+use crate::streaming::DEFAULT_STREAM_BUFFER;
+#[cfg(feature = "preview")]
+use crate::PreviewConfig;
+use crate::{
+    app_with_env, handle_server_fns, serve_static_from_kv, AssetSource, CacheSegments,
+    Dependencies, ErrorRenderer, IntoWorkerResponse, ProvideIsolateState, RequestParts,
+    RouteConfig, ShellHooks, WorkerRouterData,
+};
+
+/// The server function prefix used when none is set.
+pub const DEFAULT_SERVER_FN_PREFIX: &str = "/api";
+
+#[derive(Error, Debug)]
+pub enum BuildError {
+    #[error("No LeptosOptions were given")]
+    MissingOptions,
+    #[error("The pkg directory of the LeptosOptions is empty")]
+    EmptyPkgDir,
+    #[error("Invalid static directory {0:?}: expected a single path segment")]
+    InvalidStaticDir(String),
+    #[error("The static directory {0:?} is the pkg directory")]
+    StaticDirIsPkgDir(String),
+    #[error(
+        "Invalid server function prefix {0:?}: expected a path starting with / below the root"
+    )]
+    InvalidServerFnPrefix(String),
+    #[error("The server function prefix {0:?} is inside the static directory {1:?}")]
+    ServerFnPrefixCollision(String, String),
+    #[error("Invalid route {0:?}: expected a path starting with /")]
+    InvalidRoute(String),
+}
+
+impl From<BuildError> for worker::Error {
+    fn from(err: BuildError) -> Self {
+        worker::Error::RustError(err.to_string())
+    }
+}
+
+/// Builder for [WorkerRouterData], created with [WorkerRouterData::builder].
+#[derive(Clone)]
+pub struct WorkerRouterDataBuilder<AppFn> {
+    options: Option<LeptosOptions>,
+    app_fn: AppFn,
+    server_fn_prefix: String,
+    static_dirs: Vec<String>,
+    assets: AssetSource,
+    shell: ShellHooks,
+    route_config: HashMap<String, RouteConfig>,
+    isolate_states: Vec<&'static dyn ProvideIsolateState>,
+    deps: Dependencies,
+    cache_segments: CacheSegments,
+    stream_buffer: usize,
+    error_renderer: ErrorRenderer,
+    context: Option<Rc<worker::Context>>,
+    content_versions: Option<ContentVersions>,
+    request_metrics: Option<RequestMetrics>,
+    request_headers: RequestHeaderPolicy,
+    page_cache: Option<PageCache>,
+    redirect_query: RedirectQuery,
+    #[cfg(feature = "preview")]
+    preview: Option<PreviewConfig>,
+    #[cfg(feature = "api-keys")]
+    api_keys: Option<ApiKeyAuth>,
+    #[cfg(feature = "flash")]
+    flash: Option<Sealer>,
+}
+
+impl WorkerRouterData<(), fn(leptos::Scope)> {
+    /// Starts building a [WorkerRouterData]. [options](WorkerRouterDataBuilder::options) and
+    /// [app](WorkerRouterDataBuilder::app) are required, everything else has a default. Without an app,
+    /// there is no `build` to call.
+    pub fn builder() -> WorkerRouterDataBuilder<()> {
+        WorkerRouterDataBuilder {
+            options: None,
+            app_fn: (),
+            server_fn_prefix: DEFAULT_SERVER_FN_PREFIX.to_string(),
+            static_dirs: Vec::new(),
+            assets: AssetSource::default(),
+            shell: ShellHooks::default(),
+            route_config: HashMap::new(),
+            isolate_states: Vec::new(),
+            deps: Dependencies::new(),
+            cache_segments: CacheSegments::default(),
+            stream_buffer: DEFAULT_STREAM_BUFFER,
+            error_renderer: ErrorRenderer::default(),
+            context: None,
+            content_versions: None,
+            request_metrics: None,
+            request_headers: RequestHeaderPolicy::new(),
+            page_cache: None,
+            redirect_query: RedirectQuery::preserve(),
+            #[cfg(feature = "preview")]
+            preview: None,
+            #[cfg(feature = "api-keys")]
+            api_keys: None,
+            #[cfg(feature = "flash")]
+            flash: None,
+        }
+    }
+}
+
+impl<AppFn> WorkerRouterDataBuilder<AppFn> {
+    pub fn options(mut self, options: LeptosOptions) -> Self {
+        self.options = Some(options);
+        self
+    }
+
+    /// The root component of the app.
+    pub fn app<IV, F>(self, app_fn: F) -> WorkerRouterDataBuilder<F>
+    where
+        IV: IntoView + 'static,
+        F: Fn(leptos::Scope) -> IV + Clone + 'static,
+    {
+        WorkerRouterDataBuilder {
+            options: self.options,
+            app_fn,
+            server_fn_prefix: self.server_fn_prefix,
+            static_dirs: self.static_dirs,
+            assets: self.assets,
+            shell: self.shell,
+            route_config: self.route_config,
+            isolate_states: self.isolate_states,
+            deps: self.deps,
+            cache_segments: self.cache_segments,
+            stream_buffer: self.stream_buffer,
+            error_renderer: self.error_renderer,
+            context: self.context,
+            content_versions: self.content_versions,
+            request_metrics: self.request_metrics,
+            request_headers: self.request_headers,
+            page_cache: self.page_cache,
+            redirect_query: self.redirect_query,
+            #[cfg(feature = "preview")]
+            preview: self.preview,
+            #[cfg(feature = "api-keys")]
+            api_keys: self.api_keys,
+            #[cfg(feature = "flash")]
+            flash: self.flash,
+        }
+    }
+
+    /// The root component, as a function that also gets the bindings and the request. See
+    /// [app_with_env](crate::app_with_env).
+    pub fn app_with_env<IV, F>(
+        self,
+        app_fn: F,
+    ) -> WorkerRouterDataBuilder<impl Fn(leptos::Scope) -> IV + Clone + 'static>
+    where
+        IV: IntoView + 'static,
+        F: Fn(leptos::Scope, &worker::Env, &RequestParts) -> IV + Clone + 'static,
+    {
+        self.app(app_with_env(app_fn))
+    }
+
+    /// The path server functions are posted to, which must match the prefix given to `#[server]`.
+    /// Defaults to [DEFAULT_SERVER_FN_PREFIX].
+    pub fn server_fn_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.server_fn_prefix = prefix.into();
+        self
+    }
+
+    /// Adds a directory, e.g. `assets` for `/assets/logo.svg`, that serves static assets.
+    pub fn static_dir(mut self, dir: impl Into<String>) -> Self {
+        self.static_dirs.push(dir.into());
+        self
+    }
+
+    pub fn assets(mut self, assets: AssetSource) -> Self {
+        self.assets = assets;
+        self
+    }
+
+    pub fn shell(mut self, shell: ShellHooks) -> Self {
+        self.shell = shell;
+        self
+    }
+
+    /// Sets the configuration of the routes matching `path`, e.g. `/post/:id`.
+    pub fn route(mut self, path: impl Into<String>, config: RouteConfig) -> Self {
+        self.route_config.insert(path.into(), config);
+        self
+    }
+
+    pub fn isolate_state(mut self, state: &'static dyn ProvideIsolateState) -> Self {
+        self.isolate_states.push(state);
+        self
+    }
+
+    pub fn deps(mut self, deps: Dependencies) -> Self {
+        self.deps = deps;
+        self
+    }
+
+    pub fn cache_segments(mut self, segments: CacheSegments) -> Self {
+        self.cache_segments = segments;
+        self
+    }
+
+    /// How many chunks of a streamed page are rendered ahead of the client. Defaults to
+    /// [DEFAULT_STREAM_BUFFER].
+    pub fn stream_buffer(mut self, chunks: usize) -> Self {
+        self.stream_buffer = chunks;
+        self
+    }
+
+    /// Renders the error responses of the crate. See [ErrorRenderer].
+    pub fn error_renderer(mut self, renderer: ErrorRenderer) -> Self {
+        self.error_renderer = renderer;
+        self
+    }
+
+    /// The context of the fetch handler, for [use_wait_until](crate::background::use_wait_until).
+    pub fn context(mut self, ctx: worker::Context) -> Self {
+        self.context = Some(Rc::new(ctx));
+        self
+    }
+
+    /// Serves the version of the synced assets selected by `versions`. See
+    /// [content_version](crate::content_version).
+    pub fn content_versions(mut self, versions: ContentVersions) -> Self {
+        self.content_versions = Some(versions);
+        self
+    }
+
+    /// Records every page and server function call to Analytics Engine. See
+    /// [analytics](crate::analytics).
+    pub fn request_metrics(mut self, metrics: RequestMetrics) -> Self {
+        self.request_metrics = Some(metrics);
+        self
+    }
+
+    /// Filters the request headers exposed in the context, [RequestHeaderPolicy::new] by default. See
+    /// [request_headers](crate::request_headers).
+    pub fn request_headers(mut self, policy: RequestHeaderPolicy) -> Self {
+        self.request_headers = policy;
+        self
+    }
+
+    /// Caches the pages rendered with `SsrMode::Async` in the Cache API. See
+    /// [page_cache](crate::page_cache).
+    pub fn page_cache(mut self, cache: PageCache) -> Self {
+        self.page_cache = Some(cache);
+        self
+    }
+
+    /// What [redirect](crate::redirect) from a page does with the query string of the request,
+    /// [RedirectQuery::preserve] by default. See [redirects](crate::redirects#query-strings).
+    pub fn redirect_query(mut self, query: RedirectQuery) -> Self {
+        self.redirect_query = query;
+        self
+    }
+
+    #[cfg(feature = "preview")]
+    pub fn preview(mut self, config: PreviewConfig) -> Self {
+        self.preview = Some(config);
+        self
+    }
+
+    /// Checks the API keys sent to server functions. See [ApiKeyAuth].
+    #[cfg(feature = "api-keys")]
+    pub fn api_keys(mut self, api_keys: ApiKeyAuth) -> Self {
+        self.api_keys = Some(api_keys);
+        self
+    }
+
+    /// Enables flash messages, sealed with `sealer`. See [flash](crate::flash).
+    #[cfg(feature = "flash")]
+    pub fn flash(mut self, sealer: Sealer) -> Self {
+        self.flash = Some(sealer);
+        self
+    }
+}
+
+impl<AppFn> WorkerRouterDataBuilder<AppFn> {
+    /// Checks the settings and builds the [WorkerRouterData].
+    pub fn build<IV>(self) -> Result<WorkerRouterData<IV, AppFn>, BuildError>
+    where
+        IV: IntoView + 'static,
+        AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+    {
+        let options = self.options.ok_or(BuildError::MissingOptions)?;
+
+        let pkg_dir = options.site_pkg_dir.trim_matches('/');
+        if pkg_dir.is_empty() {
+            return Err(BuildError::EmptyPkgDir);
+        }
+
+        let mut static_dirs = HashSet::new();
+        for dir in self.static_dirs {
+            let trimmed = dir.trim_matches('/');
+            if trimmed.is_empty() || trimmed.contains('/') {
+                return Err(BuildError::InvalidStaticDir(dir));
+            }
+            if trimmed == pkg_dir {
+                return Err(BuildError::StaticDirIsPkgDir(dir));
+            }
+            static_dirs.insert(trimmed.to_string());
+        }
+
+        let server_fn_prefix = self.server_fn_prefix.trim_end_matches('/').to_string();
+        if !server_fn_prefix.starts_with('/') {
+            return Err(BuildError::InvalidServerFnPrefix(self.server_fn_prefix));
+        }
+        // Requests under the pkg or a static directory are routed to the asset handler
+        let first_segment = server_fn_prefix[1..].split('/').next().unwrap_or_default();
+        if let Some(dir) = std::iter::once(pkg_dir)
+            .chain(static_dirs.iter().map(String::as_str))
+            .find(|dir| *dir == first_segment)
+        {
+            return Err(BuildError::ServerFnPrefixCollision(
+                server_fn_prefix,
+                dir.to_string(),
+            ));
+        }
+
+        if let Some(path) = self.route_config.keys().find(|path| !path.starts_with('/')) {
+            return Err(BuildError::InvalidRoute(path.clone()));
+        }
+
+        Ok(WorkerRouterData {
+            options,
+            static_dirs,
+            assets: self.assets,
+            shell: self.shell,
+            app_fn: self.app_fn,
+            server_fn_prefix,
+            route_config: self.route_config,
+            isolate_states: self.isolate_states,
+            deps: self.deps,
+            cache_segments: self.cache_segments,
+            stream_buffer: self.stream_buffer,
+            error_renderer: self.error_renderer,
+            context: self.context,
+            content_versions: self.content_versions,
+            request_metrics: self.request_metrics,
+            request_headers: self.request_headers,
+            page_cache: self.page_cache,
+            redirect_query: self.redirect_query,
+            #[cfg(feature = "preview")]
+            preview: self.preview,
+            #[cfg(feature = "api-keys")]
+            api_keys: self.api_keys,
+            #[cfg(feature = "flash")]
+            flash: self.flash,
+        })
+    }
+}
+
+impl<IV, AppFn> WorkerRouterData<IV, AppFn>
+where
+    IV: IntoView + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+{
+    /// Creates a router with routes for the wasm bundle, the static directories and the server
+    /// functions. The app routes are added with [leptos_routes](crate::LeptosRoutes::leptos_routes).
+    pub fn into_router<'a>(self) -> worker::Router<'a, Self> {
+        // Synced assets keep the directory structure, Worker Sites only serves the top level
+        let asset = match self.assets {
+            AssetSource::WorkerSites => ":asset",
+            _ => "*asset",
+        };
+        let asset_routes: Vec<String> =
+            std::iter::once(self.options.site_pkg_dir.trim_matches('/'))
+                .chain(self.static_dirs.iter().map(String::as_str))
+                .map(|dir| format!("/{dir}/{asset}"))
+                .collect();
+        let server_fn_route = format!("{}/:fn_name", self.server_fn_prefix);
+        self.error_renderer.install();
+
+        let mut router = worker::Router::with_data(self);
+        for path in asset_routes {
+            router = router.get_async(&path, serve_static_from_kv);
+        }
+        // `GetJSON` and `GetCBOR` server functions are called with GET
+        router
+            .get_async(&server_fn_route, handle_server_fns)
+            .post_async(&server_fn_route, handle_server_fns)
+    }
+
+    /// Runs `req` through the router, adding the app routes with `app_routes` only for requests that
+    /// can reach them.
+    ///
+    /// The path of `req` is [normalized](crate::normalize) first, and paths that can't be normalized
+    /// are answered with `400 Bad Request`. Requests for the wasm bundle, the static directories and
+    /// registered server functions are handled by the routes of
+    /// [into_router](WorkerRouterData::into_router) alone, so they never pay for what the app routes
+    /// cost to set up, e.g. [generate_route_list](crate::generate_route_list) rendering the app in a
+    /// Leptos runtime. Other paths under the server function prefix, like `/api/health` below, reach
+    /// the app routes. Pass everything that needs a runtime through `app_routes`:
+    ///
+    /// ```ignore
+    /// data.serve(req, env, |router| {
+    ///     router
+    ///         .api_route(Method::Get, "/api/health", health)
+    ///         .leptos_routes(generate_route_list(App))
+    /// })
+    /// .await
+    /// ```
+    pub async fn serve<'a, F>(
+        self,
+        req: worker::Request,
+        env: worker::Env,
+        app_routes: F,
+    ) -> worker::Result<worker::Response>
+    where
+        F: FnOnce(worker::Router<'a, Self>) -> worker::Router<'a, Self>,
+    {
+        let req = match normalize_request(req) {
+            Ok(req) => req,
+            Err(err) => return err.into_worker_response(),
+        };
+        if self.is_fast_path(&req) {
+            self.into_router().run(req, env).await
+        } else {
+            app_routes(self.into_router()).run(req, env).await
+        }
+    }
+
+    /// Whether `req` is for an asset or a registered server function.
+    fn is_fast_path(&self, req: &worker::Request) -> bool {
+        let path = req.path();
+        let Some(first_segment) = path.trim_start_matches('/').split('/').next() else {
+            return false;
+        };
+        let in_static_dir = matches!(req.method(), worker::Method::Get | worker::Method::Head)
+            && (first_segment == self.options.site_pkg_dir.trim_matches('/')
+                || self.static_dirs.contains(first_segment));
+        in_static_dir
+            || server_fn_name(&self.server_fn_prefix, &req.method(), &path)
+                .is_some_and(|name| server_fn_by_path(name).is_some())
+    }
+}
+
+/// The name of the server function a request with `method` for `path` would call, if it is under
+/// `prefix`. As in [handle_server_fns](crate::handle_server_fns), that is the last path segment.
+fn server_fn_name<'p>(prefix: &str, method: &worker::Method, path: &'p str) -> Option<&'p str> {
+    if !matches!(method, worker::Method::Get | worker::Method::Post) {
+        return None;
+    }
+    let rest = path.strip_prefix(prefix)?.strip_prefix('/')?;
+    rest.rsplit('/').next().filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_server_fn_names_under_the_prefix() {
+        let name = |method, path| server_fn_name("/api", &method, path);
+        assert_eq!(
+            name(worker::Method::Post, "/api/add_todo"),
+            Some("add_todo")
+        );
+        assert_eq!(
+            name(worker::Method::Get, "/api/get_todos"),
+            Some("get_todos")
+        );
+        assert_eq!(name(worker::Method::Put, "/api/add_todo"), None);
+        assert_eq!(name(worker::Method::Get, "/apis/get_todos"), None);
+        assert_eq!(name(worker::Method::Get, "/api"), None);
+        assert_eq!(name(worker::Method::Get, "/api/"), None);
+    }
+}
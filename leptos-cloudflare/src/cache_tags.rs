@@ -0,0 +1,306 @@
+//! Cached responses that can be purged by tag or URL prefix.
+//!
+//! Pages declare what they show with [set_cache_tags], which adds a `Cache-Tag` header (the same header
+//! Cloudflare's zone cache understands). [TaggedCache] stores responses in the Cache API or in KV and
+//! indexes them by those tags and by URL in a KV namespace, so that [purge_tag](TaggedCache::purge_tag)
+//! can remove every page showing e.g. posts as soon as a post changes:
+//!
+//! ```ignore
+//! #[component]
+//! fn PostList(cx: Scope) -> impl IntoView {
+//!     set_cache_tags(cx, &["posts"]);
+//!     // ...
+//! }
+//!
+//! let cache = TaggedCache::new(CacheStore::Kv("PAGES"), "CACHE_INDEX");
+//! router.cache_purge_route(cache.clone(), "PURGE_TOKEN");
+//!
+//! // after saving a post
+//! cache.purge_tag(&env, "posts").await?;
+//! ```
+
+use std::time::Duration;
+
+use leptos::{use_context, Scope};
+use serde::{Deserialize, Serialize};
+use worker::Env;
+
+use crate::api::{ApiRequest, ApiRoutes};
+use crate::segments;
+use crate::util::authorize_bearer;
+use crate::zone_purge::ZonePurge;
+use crate::{Json, LeptosCloudflareError, ResponseOptions};
+
+/// The response header listing the tags of a response, separated by commas.
+pub const CACHE_TAG_HEADER: &str = "Cache-Tag";
+
+/// Tags the current response with `tags`.
+pub fn set_cache_tags(cx: Scope, tags: &[&str]) {
+    if let Some(mut res_options) = use_context::<ResponseOptions>(cx) {
+        res_options
+            .append_header(CACHE_TAG_HEADER, &tags.join(","))
+            .expect("failed to append header value");
+    }
+}
+
+/// Where [TaggedCache] keeps the responses themselves.
+#[derive(Debug, Clone, Copy)]
+pub enum CacheStore {
+    /// The Cache API of the data center. Purges only reach the data center they run in.
+    CacheApi,
+    /// A KV namespace, by binding. Purges are global, but take up to a minute to propagate.
+    Kv(&'static str),
+}
+
+#[derive(Debug, Clone)]
+pub struct TaggedCache {
+    store: CacheStore,
+    index: &'static str,
+    zone: Option<ZonePurge>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+}
+
+/// Summary of a purge.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct PurgeReport {
+    pub purged: u32,
+}
+
+impl TaggedCache {
+    /// `index` is the binding of the KV namespace that maps tags and URLs to cached entries.
+    pub fn new(store: CacheStore, index: &'static str) -> Self {
+        Self {
+            store,
+            index,
+            zone: None,
+        }
+    }
+
+    /// Also purges the zone cache for every URL this cache purges.
+    pub fn with_zone_purge(mut self, zone: ZonePurge) -> Self {
+        self.zone = Some(zone);
+        self
+    }
+
+    /// Returns the response cached for `url`, if any.
+    pub async fn get(&self, env: &Env, url: &str) -> worker::Result<Option<worker::Response>> {
+        match self.store {
+            CacheStore::CacheApi => worker::Cache::default().get(url, false).await,
+            CacheStore::Kv(binding) => {
+                let (body, stored) = env
+                    .kv(binding)?
+                    .get(&page_key(url))
+                    .bytes_with_metadata::<StoredResponse>()
+                    .await?;
+                let (Some(body), Some(stored)) = (body, stored) else {
+                    return Ok(None);
+                };
+
+                let headers = worker::Headers::new();
+                for (key, value) in &stored.headers {
+                    headers.append(key, value)?;
+                }
+                Ok(Some(
+                    worker::Response::from_bytes(body)?
+                        .with_status(stored.status)
+                        .with_headers(headers),
+                ))
+            }
+        }
+    }
+
+    /// Caches `response` for `url` for `ttl`, indexed by the tags in its `Cache-Tag` header, and returns
+    /// it for sending. KV requires a `ttl` of at least 60 seconds.
+    pub async fn put(
+        &self,
+        env: &Env,
+        url: &str,
+        mut response: worker::Response,
+        ttl: Duration,
+    ) -> worker::Result<worker::Response> {
+        let tags = response
+            .headers()
+            .get(CACHE_TAG_HEADER)?
+            .map(|tags| {
+                tags.split(',')
+                    .map(|tag| tag.trim().to_string())
+                    .filter(|tag| !tag.is_empty())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        let response = match self.store {
+            CacheStore::CacheApi => {
+                let mut cached = response.cloned()?;
+                cached
+                    .headers_mut()
+                    .set("Cache-Control", &format!("max-age={}", ttl.as_secs()))?;
+                worker::Cache::default().put(url, cached).await?;
+                response
+            }
+            CacheStore::Kv(binding) => {
+                let status = response.status_code();
+                let headers = response.headers().clone();
+                let body = response.bytes().await?;
+                env.kv(binding)?
+                    .put_bytes(&page_key(url), &body)?
+                    .metadata(StoredResponse {
+                        status,
+                        headers: headers.entries().collect(),
+                    })?
+                    .expiration_ttl(ttl.as_secs())
+                    .execute()
+                    .await?;
+                worker::Response::from_bytes(body)?
+                    .with_status(status)
+                    .with_headers(headers)
+            }
+        };
+
+        let index = env.kv(self.index)?;
+        for key in tags
+            .iter()
+            .map(|tag| tag_key(tag, url))
+            .chain([url_key(url)])
+        {
+            index
+                .put(&key, url)?
+                .expiration_ttl(ttl.as_secs())
+                .execute()
+                .await?;
+        }
+
+        Ok(response)
+    }
+
+    /// Removes every cached response tagged with `tag`.
+    pub async fn purge_tag(
+        &self,
+        env: &Env,
+        tag: &str,
+    ) -> Result<PurgeReport, LeptosCloudflareError> {
+        self.purge_indexed(env, &format!("tag:{tag}:")).await
+    }
+
+    /// Removes every cached response whose URL starts with `prefix`, e.g. `https://example.com/blog/`.
+    pub async fn purge_prefix(
+        &self,
+        env: &Env,
+        prefix: &str,
+    ) -> Result<PurgeReport, LeptosCloudflareError> {
+        self.purge_indexed(env, &url_key(prefix)).await
+    }
+
+    async fn purge_indexed(
+        &self,
+        env: &Env,
+        prefix: &str,
+    ) -> Result<PurgeReport, LeptosCloudflareError> {
+        let index = env.kv(self.index)?;
+        let mut report = PurgeReport::default();
+        let mut purged_urls = Vec::new();
+        let mut cursor = None;
+
+        loop {
+            let mut list = index.list().prefix(prefix.to_string());
+            if let Some(cursor) = cursor {
+                list = list.cursor(cursor);
+            }
+            let page = list.execute().await.map_err(worker::Error::from)?;
+
+            for key in page.keys {
+                let url = index
+                    .get(&key.name)
+                    .text()
+                    .await
+                    .map_err(worker::Error::from)?;
+                if let Some(url) = url {
+                    self.delete(env, &url).await?;
+                    report.purged += 1;
+                    purged_urls.push(segments::public_url(&url));
+                }
+                index.delete(&key.name).await.map_err(worker::Error::from)?;
+            }
+
+            if page.list_complete {
+                break;
+            }
+            cursor = page.cursor;
+        }
+
+        if let Some(zone) = &self.zone {
+            purged_urls.sort();
+            purged_urls.dedup();
+            zone.purge_urls(env, &purged_urls).await?;
+        }
+        Ok(report)
+    }
+
+    async fn delete(&self, env: &Env, url: &str) -> worker::Result<()> {
+        match self.store {
+            CacheStore::CacheApi => {
+                worker::Cache::default().delete(url, false).await?;
+            }
+            CacheStore::Kv(binding) => env.kv(binding)?.delete(&page_key(url)).await?,
+        }
+        Ok(())
+    }
+}
+
+fn page_key(url: &str) -> String {
+    format!("page:{url}")
+}
+
+fn tag_key(tag: &str, url: &str) -> String {
+    format!("tag:{tag}:{url}")
+}
+
+fn url_key(url: &str) -> String {
+    format!("url:{url}")
+}
+
+#[derive(Deserialize)]
+struct PurgeRequest {
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    prefixes: Vec<String>,
+}
+
+pub trait CachePurgeRoutes {
+    /// Mounts `POST /__cache/purge`, taking `{"tags": [...], "prefixes": [...]}` and authorized with
+    /// `Authorization: Bearer <token>`, where the token is stored in the secret `token_secret`.
+    fn cache_purge_route(self, cache: TaggedCache, token_secret: &'static str) -> Self;
+}
+
+impl<'a, D: 'static> CachePurgeRoutes for worker::Router<'a, D> {
+    fn cache_purge_route(self, cache: TaggedCache, token_secret: &'static str) -> Self {
+        self.api_route(
+            worker::Method::Post,
+            "/__cache/purge",
+            move |req: ApiRequest| {
+                let cache = cache.clone();
+                async move {
+                    authorize_bearer(&req.env, &req.parts.headers, token_secret)?;
+                    let purge = serde_json::from_slice::<PurgeRequest>(&req.parts.body)
+                        .map_err(|err| LeptosCloudflareError::BadRequest(err.to_string()))?;
+
+                    let mut report = PurgeReport::default();
+                    for tag in &purge.tags {
+                        report.purged += cache.purge_tag(&req.env, tag).await?.purged;
+                    }
+                    for prefix in &purge.prefixes {
+                        report.purged += cache.purge_prefix(&req.env, prefix).await?.purged;
+                    }
+
+                    Ok::<_, LeptosCloudflareError>(Json(report))
+                }
+            },
+        )
+    }
+}
@@ -0,0 +1,176 @@
+//! Canary releases: a sticky slice of the traffic served by a new version of the app.
+//!
+//! [Canary::route] picks the [Release] of every request and passes it to the fetch handler, which can
+//! run a router built from either version of the app. With [service](Canary::service), the canary
+//! slice is forwarded to another worker instead, e.g. a separately deployed `my-app-canary`:
+//!
+//! ```ignore
+//! static CANARY: Canary = Canary::new("v2", 5).percent_var("CANARY_PERCENT");
+//!
+//! #[worker::event(fetch)]
+//! pub async fn main(req: Request, env: Env, _ctx: Context) -> worker::Result<Response> {
+//!     CANARY
+//!         .route(req, env, |release, req, env| async move {
+//!             match release {
+//!                 Release::Stable => stable::router_data()?.into_router().leptos_routes(stable::routes()),
+//!                 Release::Canary => canary::router_data()?.into_router().leptos_routes(canary::routes()),
+//!             }
+//!             .run(req, env)
+//!             .await
+//!         })
+//!         .await
+//! }
+//! ```
+//!
+//! A visitor stays on the same release across requests: the choice is derived from the visitor ID of
+//! [experiments](crate::experiments), so raising the percentage only moves stable visitors to the
+//! canary. The `X-Canary` header or `__canary` cookie set to `1` or `0` forces a release, e.g. for
+//! testing the canary before any traffic goes to it. The percentage can be read from a variable with
+//! [percent_var](Canary::percent_var), so a rollout is widened or rolled back without a deployment.
+//!
+//! Responses differ between the releases, so pages must not be cached across them: the page cache of
+//! each release is given its [release](crate::PageCache::release), which keeps its pages apart even
+//! when the canary is another worker on the same zone:
+//!
+//! ```ignore
+//! // in stable::router_data(), and with Release::Canary in canary::router_data()
+//! WorkerRouterData::builder()
+//!     .page_cache(PageCache::new(Duration::from_secs(300)).release(Release::Stable.as_str()))
+//!     // ...
+//! ```
+
+use std::future::Future;
+
+use crate::experiments::{bucket_hash, new_id, BUCKET_COOKIE};
+use crate::util::cookie;
+
+const OVERRIDE_COOKIE: &str = "__canary";
+
+/// The version of the app serving a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Release {
+    Stable,
+    Canary,
+}
+
+impl Release {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Release::Stable => "stable",
+            Release::Canary => "canary",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Canary {
+    name: &'static str,
+    percent: u8,
+    percent_var: Option<&'static str>,
+    header: &'static str,
+    service: Option<&'static str>,
+}
+
+impl Canary {
+    /// Sends `percent` out of 100 visitors to the canary. `name` identifies the rollout: visitors are
+    /// shuffled anew for each name, so a new one should be used for every release.
+    pub const fn new(name: &'static str, percent: u8) -> Self {
+        Self {
+            name,
+            percent,
+            percent_var: None,
+            header: "X-Canary",
+            service: None,
+        }
+    }
+
+    /// Reads the percentage from the variable `var` when it is set, falling back to the one given to
+    /// [new](Canary::new).
+    pub const fn percent_var(mut self, var: &'static str) -> Self {
+        self.percent_var = Some(var);
+        self
+    }
+
+    /// The request header forcing a release, `X-Canary` by default.
+    pub const fn header(mut self, header: &'static str) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Forwards the canary slice to the worker bound as the service `binding` rather than passing it
+    /// to the handler.
+    pub const fn service(mut self, binding: &'static str) -> Self {
+        self.service = Some(binding);
+        self
+    }
+
+    /// The release of a visitor with the bucket ID `id` and no override.
+    pub fn release_for(&self, env: &worker::Env, id: &str) -> Release {
+        let percent = self
+            .percent_var
+            .and_then(|var| env.var(var).ok())
+            .and_then(|var| var.to_string().trim().parse::<u8>().ok())
+            .unwrap_or(self.percent)
+            .min(100);
+        if bucket_hash(self.name, id) % 100 < percent as u64 {
+            Release::Canary
+        } else {
+            Release::Stable
+        }
+    }
+
+    /// Runs `handler` with the release of `req`, or forwards `req` to the canary service.
+    pub async fn route<F, Fut>(
+        &self,
+        req: worker::Request,
+        env: worker::Env,
+        handler: F,
+    ) -> worker::Result<worker::Response>
+    where
+        F: FnOnce(Release, worker::Request, worker::Env) -> Fut,
+        Fut: Future<Output = worker::Result<worker::Response>>,
+    {
+        let headers = req.headers();
+        let forced = headers
+            .get(self.header)?
+            .or_else(|| cookie(headers, OVERRIDE_COOKIE))
+            .and_then(|value| match value.trim() {
+                "1" => Some(Release::Canary),
+                "0" => Some(Release::Stable),
+                _ => None,
+            });
+        let (release, assigned_id) = match forced {
+            Some(release) => (release, None),
+            None => match cookie(headers, BUCKET_COOKIE) {
+                Some(id) => (self.release_for(&env, &id), None),
+                None => {
+                    let id = new_id();
+                    (self.release_for(&env, &id), Some(id))
+                }
+            },
+        };
+
+        let mut response = match (release, self.service) {
+            (Release::Canary, Some(binding)) => {
+                // Fetched responses have immutable headers, so they are copied to add the cookie
+                let response = env.service(binding)?.fetch_request(req).await?;
+                let headers = worker::Headers::new();
+                for (name, value) in response.headers().entries() {
+                    headers.append(&name, &value)?;
+                }
+                response.with_headers(headers)
+            }
+            _ => handler(release, req, env).await?,
+        };
+
+        if let Some(id) = assigned_id {
+            response.headers_mut().append(
+                "Set-Cookie",
+                &format!(
+                    "{BUCKET_COOKIE}={id}; Path=/; Max-Age=31536000; HttpOnly; Secure; SameSite=Lax"
+                ),
+            )?;
+        }
+        Ok(response)
+    }
+}
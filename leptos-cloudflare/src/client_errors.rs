@@ -0,0 +1,316 @@
+//! Collecting the errors of the hydrated app on the server.
+//!
+//! Panics of the wasm app and uncaught JS errors only show up in the console of the visitor. [script],
+//! added to the document with [ShellHooks](crate::ShellHooks), sends them to the route mounted by
+//! [client_errors_route](ClientErrorRoutes::client_errors_route), which hands them to a
+//! [ClientErrorSink]:
+//!
+//! ```ignore
+//! const SHELL: ShellHooks = ShellHooks {
+//!     head: Some(client_errors::script),
+//!     ..ShellHooks::NONE
+//! };
+//!
+//! let errors = ClientErrors::new(SentrySink::new("SENTRY_DSN"))
+//!     .symbolicate(|env, error| async move { symbolicate_with_source_maps(&env, error).await });
+//!
+//! router.client_errors_route(errors)
+//! ```
+//!
+//! Reports larger than [max_bytes](ClientErrors::max_bytes) are rejected, and each client can send at
+//! most [per_minute](ClientErrors::per_minute) reports to an isolate. Stack traces point into the
+//! minified bundle, so [symbolicate](ClientErrors::symbolicate) can map them back to the sources (e.g.
+//! with source maps stored in R2) before they reach the sink.
+
+use std::future::Future;
+use std::rc::Rc;
+
+use futures::future::LocalBoxFuture;
+use futures::FutureExt;
+use http::StatusCode;
+use leptos::Scope;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+use worker::Env;
+
+use crate::api::{ApiRequest, ApiRoutes};
+use crate::util::{allow_per_minute, client_windows, ClientWindows};
+use crate::LeptosCloudflareError;
+
+/// The path the script posts errors to.
+pub const CLIENT_ERRORS_PATH: &str = "/__leptos/client-errors";
+
+const DEFAULT_MAX_BYTES: usize = 16 * 1024;
+const DEFAULT_PER_MINUTE: u32 = 10;
+
+/// An error reported by a browser.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientError {
+    /// `panic` for wasm panics, `error` for uncaught errors and `rejection` for unhandled rejections.
+    pub kind: String,
+    pub message: String,
+    #[serde(default)]
+    pub stack: Option<String>,
+    /// The URL of the page.
+    pub url: String,
+    /// Set by the server from the request headers.
+    #[serde(skip_deserializing)]
+    pub user_agent: Option<String>,
+}
+
+/// Where collected errors are stored or forwarded to.
+pub trait ClientErrorSink: 'static {
+    fn report(
+        &self,
+        env: Env,
+        error: ClientError,
+    ) -> LocalBoxFuture<'static, Result<(), LeptosCloudflareError>>;
+}
+
+type Symbolicate = Rc<dyn Fn(Env, ClientError) -> LocalBoxFuture<'static, ClientError>>;
+
+/// The configuration of the collector route.
+#[derive(Clone)]
+pub struct ClientErrors {
+    sink: Rc<dyn ClientErrorSink>,
+    symbolicate: Option<Symbolicate>,
+    max_bytes: usize,
+    per_minute: u32,
+}
+
+impl ClientErrors {
+    pub fn new(sink: impl ClientErrorSink) -> Self {
+        Self {
+            sink: Rc::new(sink),
+            symbolicate: None,
+            max_bytes: DEFAULT_MAX_BYTES,
+            per_minute: DEFAULT_PER_MINUTE,
+        }
+    }
+
+    /// Rewrites each error before it is reported, e.g. to resolve its stack trace with source maps.
+    pub fn symbolicate<F, Fut>(mut self, symbolicate: F) -> Self
+    where
+        F: Fn(Env, ClientError) -> Fut + 'static,
+        Fut: Future<Output = ClientError> + 'static,
+    {
+        self.symbolicate = Some(Rc::new(move |env, error| {
+            symbolicate(env, error).boxed_local()
+        }));
+        self
+    }
+
+    /// The largest accepted report, 16 KiB by default.
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// How many reports a client may send per minute, 10 by default.
+    pub fn per_minute(mut self, per_minute: u32) -> Self {
+        self.per_minute = per_minute;
+        self
+    }
+}
+
+thread_local! {
+    static RATE_LIMITS: ClientWindows = client_windows();
+}
+
+/// The script that reports panics and uncaught errors, for [ShellHooks::head](crate::ShellHooks::head).
+/// At most ten reports are sent per page view.
+pub fn script(cx: Scope) -> String {
+    #[cfg(feature = "nonce")]
+    let nonce = leptos::nonce::use_nonce(cx)
+        .map(|nonce| format!(r#" nonce="{nonce}""#))
+        .unwrap_or_default();
+    #[cfg(not(feature = "nonce"))]
+    let nonce = {
+        let _ = cx;
+        String::new()
+    };
+
+    format!(
+        r#"<script{nonce}>(function(){{var sent=0;function report(kind,message,stack){{if(sent>=10)return;sent++;navigator.sendBeacon("{CLIENT_ERRORS_PATH}",JSON.stringify({{kind:kind,message:String(message).slice(0,4000),stack:stack?String(stack).slice(0,8000):null,url:location.href}}));}}addEventListener("error",function(e){{report("error",e.message,e.error&&e.error.stack);}});addEventListener("unhandledrejection",function(e){{var r=e.reason||{{}};report("rejection",r.message||r,r.stack);}});var original=console.error;console.error=function(){{var message=Array.prototype.join.call(arguments," ");if(/panicked at/.test(message))report("panic",message,new Error().stack);return original.apply(console,arguments);}};}})();</script>"#
+    )
+}
+
+pub trait ClientErrorRoutes {
+    /// Mounts `POST /__leptos/client-errors`, which receives the reports of [script].
+    fn client_errors_route(self, errors: ClientErrors) -> Self;
+}
+
+impl<'a, D: 'static> ClientErrorRoutes for worker::Router<'a, D> {
+    fn client_errors_route(self, errors: ClientErrors) -> Self {
+        self.api_route(
+            worker::Method::Post,
+            CLIENT_ERRORS_PATH,
+            move |req: ApiRequest| {
+                let errors = errors.clone();
+                async move {
+                    if req.parts.body.len() > errors.max_bytes {
+                        return Ok((StatusCode::PAYLOAD_TOO_LARGE, ()));
+                    }
+                    let client = req
+                        .parts
+                        .headers
+                        .get("CF-Connecting-IP")?
+                        .unwrap_or_default();
+                    let allowed = RATE_LIMITS
+                        .with(|limits| allow_per_minute(limits, &client, errors.per_minute));
+                    if !allowed {
+                        return Ok((StatusCode::TOO_MANY_REQUESTS, ()));
+                    }
+
+                    let mut error = serde_json::from_slice::<ClientError>(&req.parts.body)
+                        .map_err(|err| LeptosCloudflareError::BadRequest(err.to_string()))?;
+                    error.user_agent = req.parts.headers.get("User-Agent")?;
+                    if let Some(symbolicate) = &errors.symbolicate {
+                        error = symbolicate(req.env.clone(), error).await;
+                    }
+
+                    tracing::debug!("client {}: {}", error.kind, error.message);
+                    if let Err(err) = errors.sink.report(req.env, error).await {
+                        tracing::error!("failed to report a client error: {err}");
+                    }
+                    Ok::<_, LeptosCloudflareError>((StatusCode::NO_CONTENT, ()))
+                }
+            },
+        )
+    }
+}
+
+/// Stores each error in a KV namespace under `client-errors/<timestamp>-<random>`.
+pub struct KvSink {
+    pub binding: &'static str,
+    /// How long the errors are kept.
+    pub ttl_seconds: u64,
+}
+
+impl ClientErrorSink for KvSink {
+    fn report(
+        &self,
+        env: Env,
+        error: ClientError,
+    ) -> LocalBoxFuture<'static, Result<(), LeptosCloudflareError>> {
+        let binding = self.binding;
+        let ttl_seconds = self.ttl_seconds;
+        async move {
+            let key = format!(
+                "client-errors/{}-{:08x}",
+                js_sys::Date::now() as u64,
+                (js_sys::Math::random() * u32::MAX as f64) as u32
+            );
+            env.kv(binding)?
+                .put(&key, serde_json::to_string(&error)?)?
+                .expiration_ttl(ttl_seconds)
+                .execute()
+                .await?;
+            Ok::<_, worker::Error>(())
+        }
+        .map(|result| result.map_err(LeptosCloudflareError::from))
+        .boxed_local()
+    }
+}
+
+/// Writes each error as a data point of an Analytics Engine dataset, with the blobs
+/// `[kind, message, url]` and the kind as index.
+pub struct AnalyticsEngineSink {
+    pub binding: &'static str,
+}
+
+impl ClientErrorSink for AnalyticsEngineSink {
+    fn report(
+        &self,
+        env: Env,
+        error: ClientError,
+    ) -> LocalBoxFuture<'static, Result<(), LeptosCloudflareError>> {
+        let result = (|| {
+            let dataset = js_sys::Reflect::get(&env, &JsValue::from_str(self.binding))?;
+            let write = js_sys::Reflect::get(&dataset, &JsValue::from_str("writeDataPoint"))?;
+            let point = js_sys::Object::new();
+            // Blobs are limited in size, the message is truncated
+            let message: String = error.message.chars().take(1024).collect();
+            let blobs = js_sys::Array::of3(
+                &JsValue::from_str(&error.kind),
+                &JsValue::from_str(&message),
+                &JsValue::from_str(&error.url),
+            );
+            js_sys::Reflect::set(&point, &JsValue::from_str("blobs"), &blobs)?;
+            js_sys::Reflect::set(
+                &point,
+                &JsValue::from_str("indexes"),
+                &js_sys::Array::of1(&JsValue::from_str(&error.kind)),
+            )?;
+            js_sys::Function::from(write).call1(&dataset, &point)
+        })();
+        let result = result
+            .map(|_| ())
+            .map_err(|err| LeptosCloudflareError::Internal(format!("{err:?}")));
+        futures::future::ready(result).boxed_local()
+    }
+}
+
+/// Sends each error to Sentry as an event.
+pub struct SentrySink {
+    /// Name of the secret with the DSN of the project.
+    pub dsn_secret: &'static str,
+}
+
+impl SentrySink {
+    pub fn new(dsn_secret: &'static str) -> Self {
+        Self { dsn_secret }
+    }
+}
+
+impl ClientErrorSink for SentrySink {
+    fn report(
+        &self,
+        env: Env,
+        error: ClientError,
+    ) -> LocalBoxFuture<'static, Result<(), LeptosCloudflareError>> {
+        let dsn_secret = self.dsn_secret;
+        async move {
+            // https://<key>@<host>/<project>
+            let dsn = worker::Url::parse(&env.secret(dsn_secret)?.to_string())
+                .map_err(|err| LeptosCloudflareError::Internal(err.to_string()))?;
+            let project = dsn.path().trim_start_matches('/');
+            let host = dsn.host_str().unwrap_or_default();
+            let endpoint = format!("{}://{host}/api/{project}/store/", dsn.scheme());
+
+            let event = serde_json::json!({
+                "platform": "javascript",
+                "level": "error",
+                "logger": error.kind,
+                "request": {
+                    "url": error.url,
+                    "headers": { "User-Agent": error.user_agent },
+                },
+                "exception": {
+                    "values": [{ "type": error.kind, "value": error.message }],
+                },
+                "extra": { "stack": error.stack },
+            });
+
+            let mut headers = worker::Headers::new();
+            headers.set("Content-Type", "application/json")?;
+            headers.set(
+                "X-Sentry-Auth",
+                &format!("Sentry sentry_version=7, sentry_key={}", dsn.username()),
+            )?;
+            let mut init = worker::RequestInit::new();
+            init.with_method(worker::Method::Post)
+                .with_headers(headers)
+                .with_body(Some(JsValue::from_str(&event.to_string())));
+            let request = worker::Request::new_with_init(&endpoint, &init)?;
+            let response = worker::Fetch::Request(request).send().await?;
+            match response.status_code() {
+                200..=299 => Ok(()),
+                status => Err(LeptosCloudflareError::Internal(format!(
+                    "Sentry responded with {status}"
+                ))),
+            }
+        }
+        .boxed_local()
+    }
+}
@@ -0,0 +1,425 @@
+//! Headless CMS adapters.
+//!
+//! A [ContentSource] fetches entries from a CMS. [CachedContent] memoizes them, in the Cache API unless
+//! another [KeyValueCache] is chosen, so that SSR doesn't hit the CMS on every request, and [CmsRoutes::cms_routes] mounts
+//! `POST /__cms/invalidate/:source` for the CMS webhook to drop changed entries from the cache.
+//! Two sources are included: [RestCms] for CMSes that serve entries as JSON, and [GithubMarkdown] for
+//! markdown files in a GitHub repository.
+//!
+//! ```ignore
+//! let posts = CachedContent::new(
+//!     GithubMarkdown::new("acme", "site-content", "main", "posts", "GITHUB_WEBHOOK_SECRET"),
+//!     Duration::from_secs(3600),
+//! );
+//! router.cms_routes(vec![posts.clone()]);
+//!
+//! // in a server function
+//! let post = posts.get(&env, &slug).await?;
+//! ```
+//!
+//! The Cache API is local to each data center, so an invalidation only reaches the data center that
+//! received the webhook; elsewhere entries expire after the TTL. A [KvCache](crate::kv_cache::KvCache)
+//! given to [with_cache](CachedContent::with_cache) makes invalidations global.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+use worker::Env;
+
+use crate::api::{ApiRequest, ApiRoutes};
+use crate::kv_cache::{CacheApiCache, KeyValueCache};
+use crate::util::authorize_bearer;
+use crate::webhooks::verify_github;
+use crate::{Json, LeptosCloudflareError, RequestParts};
+
+/// A piece of content as returned by a [ContentSource].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContentEntry {
+    pub slug: String,
+    pub title: Option<String>,
+    /// The main content, e.g. markdown or HTML, as stored in the CMS.
+    pub body: String,
+    /// Every other field of the entry.
+    pub fields: serde_json::Value,
+}
+
+pub trait ContentSource {
+    /// Identifies the source in cache keys and in its invalidation route.
+    fn name(&self) -> &str;
+
+    /// Fetches the entry `slug`, or `None` if it doesn't exist.
+    fn fetch(
+        &self,
+        env: &Env,
+        slug: &str,
+    ) -> LocalBoxFuture<'static, Result<Option<ContentEntry>, LeptosCloudflareError>>;
+
+    /// Authenticates a webhook request from the CMS and returns the slugs it reports as changed.
+    fn invalidated_slugs(
+        &self,
+        env: &Env,
+        req: &RequestParts,
+    ) -> Result<Vec<String>, LeptosCloudflareError>;
+}
+
+/// A [ContentSource] whose entries are memoized for `ttl`.
+#[derive(Clone)]
+pub struct CachedContent {
+    source: Rc<dyn ContentSource>,
+    cache: Rc<dyn KeyValueCache>,
+    ttl: Duration,
+}
+
+impl CachedContent {
+    /// Memoizes the entries of `source` in the Cache API.
+    pub fn new(source: impl ContentSource + 'static, ttl: Duration) -> Self {
+        Self {
+            source: Rc::new(source),
+            cache: Rc::new(CacheApiCache::new("cms")),
+            ttl,
+        }
+    }
+
+    /// Memoizes the entries in `cache` instead.
+    pub fn with_cache(mut self, cache: impl KeyValueCache) -> Self {
+        self.cache = Rc::new(cache);
+        self
+    }
+
+    pub async fn get(
+        &self,
+        env: &Env,
+        slug: &str,
+    ) -> Result<Option<ContentEntry>, LeptosCloudflareError> {
+        let key = self.cache_key(slug);
+
+        if let Some(cached) = self.cache.get(env, &key).await? {
+            return Ok(Some(
+                serde_json::from_slice(&cached).map_err(worker::Error::from)?,
+            ));
+        }
+
+        let entry = self.source.fetch(env, slug).await?;
+        if let Some(entry) = &entry {
+            let value = serde_json::to_vec(entry).map_err(worker::Error::from)?;
+            self.cache.put(env, &key, value, self.ttl).await?;
+        }
+        Ok(entry)
+    }
+
+    /// Drops `slugs` from the cache. With the Cache API, only this data center's copy is dropped.
+    pub async fn invalidate(&self, env: &Env, slugs: &[String]) -> worker::Result<()> {
+        for slug in slugs {
+            self.cache.delete(env, &self.cache_key(slug)).await?;
+        }
+        Ok(())
+    }
+
+    fn cache_key(&self, slug: &str) -> String {
+        format!("{}/{slug}", self.source.name())
+    }
+}
+
+pub trait CmsRoutes {
+    fn cms_routes(self, sources: Vec<CachedContent>) -> Self;
+}
+
+#[derive(Serialize)]
+struct Invalidated {
+    slugs: Vec<String>,
+}
+
+impl<'a, D: 'static> CmsRoutes for worker::Router<'a, D> {
+    fn cms_routes(self, sources: Vec<CachedContent>) -> Self {
+        let sources = Rc::new(
+            sources
+                .into_iter()
+                .map(|source| (source.source.name().to_string(), source))
+                .collect::<HashMap<_, _>>(),
+        );
+
+        self.api_route(
+            worker::Method::Post,
+            "/__cms/invalidate/:source",
+            move |req: ApiRequest| {
+                let sources = sources.clone();
+                async move {
+                    let name = req.path.get("source").cloned().unwrap_or_default();
+                    let content = sources.get(&name).ok_or(LeptosCloudflareError::NotFound)?;
+
+                    let slugs = content.source.invalidated_slugs(&req.env, &req.parts)?;
+                    content.invalidate(&req.env, &slugs).await?;
+                    tracing::info!("invalidated {} {name} entries", slugs.len());
+
+                    Ok::<_, LeptosCloudflareError>(Json(Invalidated { slugs }))
+                }
+            },
+        )
+    }
+}
+
+/// A CMS that serves each entry as a JSON object at `{base_url}/{slug}`. The `title` and `body` keys
+/// of the object become [ContentEntry::title] and [ContentEntry::body].
+///
+/// Its webhook must send `Authorization: Bearer <webhook secret>` and a body of the form
+/// `{"slugs": ["about", "pricing"]}`.
+pub struct RestCms {
+    pub name: String,
+    pub base_url: String,
+    /// Name of the secret with the API token, sent as a Bearer token.
+    pub token_secret: Option<&'static str>,
+    pub webhook_secret: &'static str,
+}
+
+#[derive(Deserialize)]
+struct RestWebhook {
+    slugs: Vec<String>,
+}
+
+impl ContentSource for RestCms {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn fetch(
+        &self,
+        env: &Env,
+        slug: &str,
+    ) -> LocalBoxFuture<'static, Result<Option<ContentEntry>, LeptosCloudflareError>> {
+        let url = encode_slug(slug)
+            .map(|encoded| format!("{}/{encoded}", self.base_url.trim_end_matches('/')));
+        let token = self
+            .token_secret
+            .map(|secret| env.secret(secret).map(|token| token.to_string()))
+            .transpose();
+        let slug = slug.to_string();
+
+        Box::pin(async move {
+            let mut headers = worker::Headers::new();
+            headers.set("Accept", "application/json")?;
+            if let Some(token) = token? {
+                headers.set("Authorization", &format!("Bearer {token}"))?;
+            }
+            let Some(mut response) = fetch(&url?, headers).await? else {
+                return Ok(None);
+            };
+
+            let fields = response.json::<serde_json::Value>().await?;
+            Ok(Some(ContentEntry {
+                slug,
+                title: fields["title"].as_str().map(str::to_string),
+                body: fields["body"].as_str().unwrap_or_default().to_string(),
+                fields,
+            }))
+        })
+    }
+
+    fn invalidated_slugs(
+        &self,
+        env: &Env,
+        req: &RequestParts,
+    ) -> Result<Vec<String>, LeptosCloudflareError> {
+        authorize_bearer(env, &req.headers, self.webhook_secret)?;
+
+        let webhook = serde_json::from_slice::<RestWebhook>(&req.body)
+            .map_err(|err| LeptosCloudflareError::BadRequest(err.to_string()))?;
+        Ok(webhook.slugs)
+    }
+}
+
+/// Markdown files in a GitHub repository, one entry per `{dir}/{slug}.md` on `branch`.
+///
+/// A leading `---` front matter block of `key: value` lines becomes [ContentEntry::fields]; the title is
+/// its `title`, or else the first `# ` heading. Invalidation is driven by the repository's push webhook,
+/// verified with the webhook secret.
+pub struct GithubMarkdown {
+    pub owner: String,
+    pub repo: String,
+    pub branch: String,
+    pub dir: String,
+    /// Name of the secret with a token, needed for private repositories.
+    pub token_secret: Option<&'static str>,
+    pub webhook_secret: &'static str,
+    name: String,
+}
+
+impl GithubMarkdown {
+    pub fn new(
+        owner: &str,
+        repo: &str,
+        branch: &str,
+        dir: &str,
+        webhook_secret: &'static str,
+    ) -> Self {
+        Self {
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            branch: branch.to_string(),
+            dir: dir.trim_matches('/').to_string(),
+            token_secret: None,
+            webhook_secret,
+            name: format!("{owner}/{repo}/{}", dir.trim_matches('/')).replace('/', "-"),
+        }
+    }
+
+    pub fn with_token(mut self, token_secret: &'static str) -> Self {
+        self.token_secret = Some(token_secret);
+        self
+    }
+}
+
+#[derive(Deserialize)]
+struct PushEvent {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    #[serde(default)]
+    commits: Vec<PushCommit>,
+}
+
+#[derive(Deserialize)]
+struct PushCommit {
+    #[serde(default)]
+    added: Vec<String>,
+    #[serde(default)]
+    modified: Vec<String>,
+    #[serde(default)]
+    removed: Vec<String>,
+}
+
+impl ContentSource for GithubMarkdown {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn fetch(
+        &self,
+        env: &Env,
+        slug: &str,
+    ) -> LocalBoxFuture<'static, Result<Option<ContentEntry>, LeptosCloudflareError>> {
+        let url = encode_slug(slug).map(|encoded| {
+            format!(
+                "https://raw.githubusercontent.com/{}/{}/{}/{}/{encoded}.md",
+                self.owner, self.repo, self.branch, self.dir
+            )
+        });
+        let token = self
+            .token_secret
+            .map(|secret| env.secret(secret).map(|token| token.to_string()))
+            .transpose();
+        let slug = slug.to_string();
+
+        Box::pin(async move {
+            let mut headers = worker::Headers::new();
+            headers.set("User-Agent", "leptos-cloudflare")?;
+            if let Some(token) = token? {
+                headers.set("Authorization", &format!("token {token}"))?;
+            }
+            let Some(mut response) = fetch(&url?, headers).await? else {
+                return Ok(None);
+            };
+
+            let (fields, body) = parse_front_matter(&response.text().await?);
+            let title = fields.get("title").cloned().or_else(|| {
+                body.lines()
+                    .find_map(|line| line.strip_prefix("# "))
+                    .map(|title| title.trim().to_string())
+            });
+            Ok(Some(ContentEntry {
+                slug,
+                title,
+                body,
+                fields: serde_json::to_value(fields).map_err(worker::Error::from)?,
+            }))
+        })
+    }
+
+    fn invalidated_slugs(
+        &self,
+        env: &Env,
+        req: &RequestParts,
+    ) -> Result<Vec<String>, LeptosCloudflareError> {
+        let secret = env.secret(self.webhook_secret)?.to_string();
+        verify_github(secret.as_bytes(), req)?;
+
+        // GitHub sends a ping when the webhook is created, and other events if the webhook subscribes to them
+        if req.headers.get("X-GitHub-Event")?.as_deref() != Some("push") {
+            return Ok(Vec::new());
+        }
+        let event = serde_json::from_slice::<PushEvent>(&req.body)
+            .map_err(|err| LeptosCloudflareError::BadRequest(err.to_string()))?;
+        if event.git_ref != format!("refs/heads/{}", self.branch) {
+            return Ok(Vec::new());
+        }
+
+        let prefix = format!("{}/", self.dir);
+        let mut slugs = event
+            .commits
+            .iter()
+            .flat_map(|commit| {
+                commit
+                    .added
+                    .iter()
+                    .chain(&commit.modified)
+                    .chain(&commit.removed)
+            })
+            .filter_map(|path| path.strip_prefix(&prefix)?.strip_suffix(".md"))
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        slugs.sort();
+        slugs.dedup();
+        Ok(slugs)
+    }
+}
+
+/// `slug` percent-encoded as a single path segment. Slugs often come from route parameters, so
+/// separators and dot segments, which could reach other content with the CMS token, are rejected.
+fn encode_slug(slug: &str) -> Result<String, LeptosCloudflareError> {
+    if matches!(slug, "" | "." | "..") || slug.contains(['/', '\\']) {
+        return Err(LeptosCloudflareError::BadRequest(format!(
+            "Invalid content slug {slug:?}"
+        )));
+    }
+    Ok(String::from(js_sys::encode_uri_component(slug)))
+}
+
+/// GETs `url`, returning `None` on a 404.
+async fn fetch(
+    url: &str,
+    headers: worker::Headers,
+) -> Result<Option<worker::Response>, LeptosCloudflareError> {
+    let mut init = worker::RequestInit::new();
+    init.with_headers(headers);
+    let request = worker::Request::new_with_init(url, &init)?;
+    let response = worker::Fetch::Request(request).send().await?;
+
+    match response.status_code() {
+        404 => Ok(None),
+        status if status >= 400 => Err(LeptosCloudflareError::Internal(format!(
+            "{url} responded with {status}"
+        ))),
+        _ => Ok(Some(response)),
+    }
+}
+
+fn parse_front_matter(text: &str) -> (HashMap<String, String>, String) {
+    let mut fields = HashMap::new();
+    let Some(rest) = text.strip_prefix("---\n") else {
+        return (fields, text.to_string());
+    };
+    let Some((front_matter, body)) = rest.split_once("\n---\n") else {
+        return (fields, text.to_string());
+    };
+
+    for line in front_matter.lines() {
+        if let Some((key, value)) = line.split_once(':') {
+            fields.insert(
+                key.trim().to_string(),
+                value.trim().trim_matches('"').to_string(),
+            );
+        }
+    }
+    (fields, body.trim_start().to_string())
+}
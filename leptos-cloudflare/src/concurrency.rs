@@ -0,0 +1,182 @@
+//! Optimistic concurrency control for server functions.
+//!
+//! Reading server functions return the data together with a [VersionToken]. The client sends the
+//! token back with its mutation, and the mutating server function only applies the change if the stored
+//! version still matches; otherwise it fails with [ConcurrencyError::Conflict] and a `409 Conflict`
+//! status, and the client can reload and retry.
+//!
+//! ```ignore
+//! #[server(SaveNote, "/api")]
+//! pub async fn save_note(cx: Scope, id: String, text: String, version: VersionToken) -> Result<VersionToken, ServerFnError> {
+//!     let kv = notes_kv(cx)?;
+//!     kv_put_versioned(&kv, &id, &text, Some(&version))
+//!         .await
+//!         .map_err(|err| err.respond(cx))
+//! }
+//! ```
+
+use leptos::{use_context, Scope, ServerFnError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use worker::kv::KvStore;
+
+use crate::{LeptosCloudflareError, ResponseOptions};
+
+/// An opaque version of a stored value, handed to the client by read operations and checked by writes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct VersionToken(pub String);
+
+/// A value together with the version it was read at.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Versioned<T> {
+    pub value: T,
+    pub version: VersionToken,
+}
+
+#[derive(Error, Debug)]
+pub enum ConcurrencyError {
+    #[error("The value was modified by someone else since it was read")]
+    Conflict,
+    #[error(transparent)]
+    Worker(#[from] worker::Error),
+}
+
+impl ConcurrencyError {
+    /// Sets the status of the current response to match the error (409 for a conflict) and converts it
+    /// into a [ServerFnError], for use at the end of a server function.
+    pub fn respond(self, cx: Scope) -> ServerFnError {
+        if matches!(self, ConcurrencyError::Conflict) {
+            if let Some(response_options) = use_context::<ResponseOptions>(cx) {
+                response_options.set_status(409);
+            }
+        }
+        ServerFnError::ServerError(self.to_string())
+    }
+}
+
+impl From<ConcurrencyError> for LeptosCloudflareError {
+    fn from(err: ConcurrencyError) -> Self {
+        match err {
+            ConcurrencyError::Conflict => LeptosCloudflareError::Conflict(err.to_string()),
+            ConcurrencyError::Worker(err) => LeptosCloudflareError::Worker(err),
+        }
+    }
+}
+
+/// Fails with [ConcurrencyError::Conflict] unless `expected` is the `current` version.
+pub fn check_version(
+    expected: &VersionToken,
+    current: &VersionToken,
+) -> Result<(), ConcurrencyError> {
+    if expected == current {
+        Ok(())
+    } else {
+        Err(ConcurrencyError::Conflict)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct KvVersionMetadata {
+    version: u64,
+}
+
+/// Reads a JSON value written by [kv_put_versioned], along with its version.
+pub async fn kv_get_versioned<T: DeserializeOwned>(
+    kv: &KvStore,
+    key: &str,
+) -> Result<Option<Versioned<T>>, ConcurrencyError> {
+    let (value, metadata) = kv
+        .get(key)
+        .text_with_metadata::<KvVersionMetadata>()
+        .await
+        .map_err(worker::Error::from)?;
+
+    match value {
+        Some(value) => Ok(Some(Versioned {
+            value: serde_json::from_str(&value).map_err(worker::Error::from)?,
+            version: VersionToken(metadata.map(|m| m.version).unwrap_or(0).to_string()),
+        })),
+        None => Ok(None),
+    }
+}
+
+/// Writes `value` as JSON with an incremented version kept in the KV metadata, and returns the new version.
+///
+/// With `expected` set, the write fails with a conflict if the stored version differs from it. KV has no
+/// compare-and-swap, so two writes racing within the same few milliseconds can both pass the check; use
+/// [d1_update_versioned] when that matters.
+pub async fn kv_put_versioned<T: Serialize>(
+    kv: &KvStore,
+    key: &str,
+    value: &T,
+    expected: Option<&VersionToken>,
+) -> Result<VersionToken, ConcurrencyError> {
+    let (_, metadata) = kv
+        .get(key)
+        .text_with_metadata::<KvVersionMetadata>()
+        .await
+        .map_err(worker::Error::from)?;
+    let current = metadata.map(|m| m.version).unwrap_or(0);
+
+    if let Some(expected) = expected {
+        check_version(expected, &VersionToken(current.to_string()))?;
+    }
+
+    let version = current + 1;
+    kv.put(
+        key,
+        serde_json::to_string(value).map_err(worker::Error::from)?,
+    )
+    .and_then(|put| put.metadata(KvVersionMetadata { version }))
+    .map_err(worker::Error::from)?
+    .execute()
+    .await
+    .map_err(worker::Error::from)?;
+
+    Ok(VersionToken(version.to_string()))
+}
+
+#[cfg(feature = "d1")]
+#[derive(Deserialize)]
+struct D1VersionRow {
+    version: i64,
+}
+
+/// Runs an `UPDATE` that is guarded by an integer `version` column and returns the new version.
+///
+/// The expected version is bound after `params`, and the query has to compare and increment the column
+/// and return it, e.g. with two params:
+///
+/// ```ignore
+/// d1_update_versioned(
+///     &db,
+///     "UPDATE notes SET text = ?1, version = version + 1 WHERE id = ?2 AND version = ?3 RETURNING version",
+///     &[text.into(), id.into()],
+///     &version,
+/// )
+/// ```
+///
+/// If no row was updated, the row was changed (or deleted) in the meantime and a conflict is returned.
+#[cfg(feature = "d1")]
+pub async fn d1_update_versioned(
+    db: &worker::d1::D1Database,
+    query: &str,
+    params: &[wasm_bindgen::JsValue],
+    expected: &VersionToken,
+) -> Result<VersionToken, ConcurrencyError> {
+    let expected = expected
+        .0
+        .parse::<i64>()
+        .map_err(|_| ConcurrencyError::Conflict)?;
+    let params = params
+        .iter()
+        .cloned()
+        .chain([wasm_bindgen::JsValue::from(expected as f64)])
+        .collect::<Vec<_>>();
+
+    match crate::d1::d1_query_one::<D1VersionRow>(db, query, &params).await? {
+        Some(row) => Ok(VersionToken(row.version.to_string())),
+        None => Err(ConcurrencyError::Conflict),
+    }
+}
@@ -0,0 +1,141 @@
+//! Blue/green static content: the version served is picked by a pointer in KV.
+//!
+//! `leptos-cloudflare-sync --version <name>` uploads the assets of a build and stores its manifest as a
+//! version instead of replacing the current one. A [VersionPointer] in KV names the active version,
+//! and switching it serves another version of the assets without deploying the worker, so a bad
+//! release of the static content is rolled back in seconds:
+//!
+//! ```sh
+//! leptos-cloudflare-sync --dir pkg --kv <assets id> --version 2023-11-02 --pointer-kv <pointer id> --activate
+//! # and back
+//! leptos-cloudflare-sync --pointer-kv <pointer id> --switch-to 2023-10-28
+//! ```
+//!
+//! ```ignore
+//! WorkerRouterData::builder()
+//!     .assets(AssetSource::Kv("ASSETS"))
+//!     .content_versions(ContentVersions::new("DEPLOYMENTS"))
+//!     // ...
+//! ```
+//!
+//! Other content built along with the assets, e.g. pre-rendered pages stored under a prefix per
+//! version, reads the active version with [use_content_version]. Isolates check the pointer every 30
+//! seconds, and KV may serve the previous value for up to a minute after a switch. Without a pointer,
+//! the manifest synced without a version is served.
+
+use std::time::Duration;
+
+use leptos::{use_context, Scope};
+use serde::{Deserialize, Serialize};
+use worker::Env;
+
+use crate::memo_cache::{memo_cache, MemoCache};
+use crate::LeptosCloudflareError;
+
+/// The key of the pointer in its namespace, also used by `leptos-cloudflare-sync`.
+pub const POINTER_KEY: &str = "__content_version";
+
+thread_local! {
+    // Binding -> the active version
+    static ACTIVE: MemoCache<&'static str, Option<String>> =
+        memo_cache(16).ttl(Duration::from_secs(30));
+}
+
+/// The value of the pointer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionPointer {
+    pub active: String,
+    /// The version active before, which [ContentVersions::rollback] goes back to.
+    pub previous: Option<String>,
+    /// Milliseconds since the epoch.
+    pub activated_at: u64,
+}
+
+/// The version pointer stored in the KV namespace bound as `binding`.
+#[derive(Debug, Clone, Copy)]
+pub struct ContentVersions {
+    binding: &'static str,
+}
+
+impl ContentVersions {
+    pub const fn new(binding: &'static str) -> Self {
+        Self { binding }
+    }
+
+    /// The active version, as last read by this isolate.
+    pub async fn active(&self, env: &Env) -> worker::Result<Option<String>> {
+        if let Some(active) = ACTIVE.with(|cache| cache.get(&self.binding)) {
+            return Ok(active);
+        }
+        let active = self.pointer(env).await?.map(|pointer| pointer.active);
+        ACTIVE.with(|cache| cache.insert(self.binding, active.clone()));
+        Ok(active)
+    }
+
+    /// The pointer as stored in KV.
+    pub async fn pointer(&self, env: &Env) -> worker::Result<Option<VersionPointer>> {
+        Ok(env
+            .kv(self.binding)?
+            .get(POINTER_KEY)
+            .json::<VersionPointer>()
+            .await?)
+    }
+
+    /// Makes `version` the active version. Its manifest must have been synced before.
+    pub async fn activate(&self, env: &Env, version: &str) -> worker::Result<VersionPointer> {
+        let current = self.pointer(env).await?;
+        let previous = match current {
+            Some(current) if current.active != version => Some(current.active),
+            Some(current) => current.previous,
+            None => None,
+        };
+        let pointer = VersionPointer {
+            active: version.to_string(),
+            previous,
+            activated_at: worker::Date::now().as_millis(),
+        };
+        env.kv(self.binding)?
+            .put(POINTER_KEY, serde_json::to_string(&pointer)?)?
+            .execute()
+            .await?;
+        ACTIVE.with(|cache| cache.insert(self.binding, Some(pointer.active.clone())));
+        Ok(pointer)
+    }
+
+    /// Makes the previous version active again. Fails with `409 Conflict` if there is none.
+    pub async fn rollback(&self, env: &Env) -> Result<VersionPointer, LeptosCloudflareError> {
+        let previous = self
+            .pointer(env)
+            .await?
+            .and_then(|pointer| pointer.previous)
+            .ok_or_else(|| {
+                LeptosCloudflareError::Conflict("There is no previous version".to_string())
+            })?;
+        Ok(self.activate(env, &previous).await?)
+    }
+}
+
+/// The active version of the request, provided in its context.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ContentVersion(pub(crate) Option<String>);
+
+/// The content version serving the current request, if [ContentVersions] are enabled and one is
+/// active.
+pub fn use_content_version(cx: Scope) -> Option<String> {
+    use_context::<ContentVersion>(cx).and_then(|version| version.0)
+}
+
+/// The active version, or `None` when versions are disabled or can't be read.
+pub(crate) async fn active_version(
+    versions: Option<&ContentVersions>,
+    env: &Env,
+) -> Option<String> {
+    match versions?.active(env).await {
+        Ok(active) => active,
+        Err(err) => {
+            // Serving the unversioned content beats failing every request
+            tracing::error!("failed to read the active content version: {err}");
+            None
+        }
+    }
+}
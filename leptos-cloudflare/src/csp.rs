@@ -0,0 +1,224 @@
+//! Collecting Content Security Policy violation reports.
+//!
+//! With the `nonce` feature, scripts rendered by Leptos carry the nonce of the request, so the app can send
+//! a strict `Content-Security-Policy`. Violations of that policy are only visible to the browser unless it
+//! reports them: [csp_report_route](CspReportRoutes::csp_report_route) mounts `POST /__csp-report`,
+//! which accepts both the legacy `report-uri` format and the Reporting API, and logs or stores them.
+//!
+//! ```ignore
+//! fn csp(cx: Scope) -> String {
+//!     let nonce = use_nonce(cx).unwrap_or_default();
+//!     format!("script-src 'nonce-{nonce}' 'strict-dynamic'; {CSP_REPORT_DIRECTIVES}")
+//! }
+//!
+//! router.csp_report_route(CspReportStore::Kv { binding: "CSP_REPORTS", ttl_seconds: 7 * 86400 })
+//! ```
+//!
+//! Responses using the Reporting API also need `Reporting-Endpoints: csp-endpoint="/__csp-report"`.
+//!
+//! Each client may send [REPORTS_PER_MINUTE] reports per minute to an isolate, so a page that
+//! violates its policy in a loop, or a script posting fake reports, can't flood the logs and KV.
+
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::api::{ApiRequest, ApiRoutes};
+use crate::util::{allow_per_minute, client_windows, ClientWindows};
+use crate::LeptosCloudflareError;
+
+/// The path of the report route.
+pub const CSP_REPORT_PATH: &str = "/__csp-report";
+
+/// The directives that send violations to [CSP_REPORT_PATH], for both `report-uri` and the Reporting API.
+pub const CSP_REPORT_DIRECTIVES: &str = "report-uri /__csp-report; report-to csp-endpoint";
+
+/// Reports larger than this are rejected.
+const MAX_REPORT_BYTES: usize = 16 * 1024;
+
+/// How many reports a client may send per minute. Browsers send one per violation, or batch them
+/// with the Reporting API.
+pub const REPORTS_PER_MINUTE: u32 = 30;
+
+thread_local! {
+    static RATE_LIMITS: ClientWindows = client_windows();
+}
+
+/// A violation, normalized from either report format.
+#[derive(Debug, Clone, Serialize)]
+pub struct CspViolation {
+    pub document_uri: String,
+    pub blocked_uri: String,
+    pub effective_directive: String,
+    pub disposition: String,
+    pub source_file: Option<String>,
+    pub line_number: Option<u32>,
+    pub user_agent: Option<String>,
+}
+
+/// What to do with the received violations.
+#[derive(Debug, Clone, Copy)]
+pub enum CspReportStore {
+    /// Only logs them.
+    Log,
+    /// Logs them and stores each in a KV namespace under `csp/<timestamp>-<random>`.
+    Kv {
+        binding: &'static str,
+        ttl_seconds: u64,
+    },
+}
+
+/// `application/csp-report`, sent for `report-uri`.
+#[derive(Deserialize)]
+struct LegacyReport {
+    #[serde(rename = "csp-report")]
+    report: LegacyBody,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct LegacyBody {
+    document_uri: String,
+    #[serde(default)]
+    blocked_uri: String,
+    #[serde(default, alias = "violated-directive")]
+    effective_directive: String,
+    #[serde(default)]
+    disposition: Option<String>,
+    #[serde(default)]
+    source_file: Option<String>,
+    #[serde(default)]
+    line_number: Option<u32>,
+}
+
+/// An entry of `application/reports+json`, sent for `report-to`.
+#[derive(Deserialize)]
+struct ReportingApiReport {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    user_agent: Option<String>,
+    body: ReportingApiBody,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReportingApiBody {
+    #[serde(rename = "documentURL")]
+    document_url: String,
+    #[serde(default, rename = "blockedURL")]
+    blocked_url: String,
+    #[serde(default)]
+    effective_directive: String,
+    #[serde(default)]
+    disposition: Option<String>,
+    #[serde(default)]
+    source_file: Option<String>,
+    #[serde(default)]
+    line_number: Option<u32>,
+}
+
+/// Parses the body of a report request, returning `None` if it is in neither format.
+fn parse_reports(body: &[u8], user_agent: Option<String>) -> Option<Vec<CspViolation>> {
+    if let Ok(LegacyReport { report }) = serde_json::from_slice::<LegacyReport>(body) {
+        return Some(vec![CspViolation {
+            document_uri: report.document_uri,
+            blocked_uri: report.blocked_uri,
+            effective_directive: report.effective_directive,
+            disposition: report.disposition.unwrap_or_else(|| "enforce".to_string()),
+            source_file: report.source_file,
+            line_number: report.line_number,
+            user_agent,
+        }]);
+    }
+
+    let reports = serde_json::from_slice::<Vec<ReportingApiReport>>(body).ok()?;
+    Some(
+        reports
+            .into_iter()
+            .filter(|report| report.kind == "csp-violation")
+            .map(|report| CspViolation {
+                document_uri: report.body.document_url,
+                blocked_uri: report.body.blocked_url,
+                effective_directive: report.body.effective_directive,
+                disposition: report
+                    .body
+                    .disposition
+                    .unwrap_or_else(|| "enforce".to_string()),
+                source_file: report.body.source_file,
+                line_number: report.body.line_number,
+                user_agent: report.user_agent.or_else(|| user_agent.clone()),
+            })
+            .collect(),
+    )
+}
+
+pub trait CspReportRoutes {
+    /// Mounts `POST /__csp-report`, which receives the violation reports of browsers.
+    fn csp_report_route(self, store: CspReportStore) -> Self;
+}
+
+impl<'a, D: 'static> CspReportRoutes for worker::Router<'a, D> {
+    fn csp_report_route(self, store: CspReportStore) -> Self {
+        self.api_route(
+            worker::Method::Post,
+            CSP_REPORT_PATH,
+            move |req: ApiRequest| async move {
+                if req.parts.body.len() > MAX_REPORT_BYTES {
+                    return Ok((StatusCode::PAYLOAD_TOO_LARGE, ()));
+                }
+                let client = req
+                    .parts
+                    .headers
+                    .get("CF-Connecting-IP")?
+                    .unwrap_or_default();
+                let allowed = RATE_LIMITS
+                    .with(|limits| allow_per_minute(limits, &client, REPORTS_PER_MINUTE));
+                if !allowed {
+                    return Ok((StatusCode::TOO_MANY_REQUESTS, ()));
+                }
+                let user_agent = req.parts.headers.get("User-Agent")?;
+                let Some(violations) = parse_reports(&req.parts.body, user_agent) else {
+                    return Err(LeptosCloudflareError::BadRequest(
+                        "Not a CSP violation report".to_string(),
+                    ));
+                };
+
+                for violation in violations {
+                    tracing::warn!(
+                        "CSP violation on {}: {} blocked {}",
+                        violation.document_uri,
+                        violation.effective_directive,
+                        violation.blocked_uri
+                    );
+                    if let CspReportStore::Kv {
+                        binding,
+                        ttl_seconds,
+                    } = store
+                    {
+                        store_violation(&req.env, binding, ttl_seconds, &violation).await?;
+                    }
+                }
+                Ok((StatusCode::NO_CONTENT, ()))
+            },
+        )
+    }
+}
+
+async fn store_violation(
+    env: &worker::Env,
+    binding: &str,
+    ttl_seconds: u64,
+    violation: &CspViolation,
+) -> worker::Result<()> {
+    let key = format!(
+        "csp/{}-{:08x}",
+        js_sys::Date::now() as u64,
+        (js_sys::Math::random() * u32::MAX as f64) as u32
+    );
+    env.kv(binding)?
+        .put(&key, serde_json::to_string(violation)?)?
+        .expiration_ttl(ttl_seconds)
+        .execute()
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,350 @@
+//! Typed queries and pagination on top of [D1](worker::d1::D1Database).
+//!
+//! Rows are deserialized with serde, using the column names as field names, so a struct with the same
+//! shape as the `SELECT` list can be used directly:
+//!
+//! ```ignore
+//! #[derive(Deserialize)]
+//! struct Post {
+//!     id: u32,
+//!     title: String,
+//! }
+//!
+//! let posts = d1_query_as::<Post>(&db, "SELECT id, title FROM posts WHERE author = ?1", &[author.into()]).await?;
+//! ```
+//!
+//! Server functions get the database from the bindings of the request with [use_d1], and can bind
+//! parameters one by one with [D1Query]:
+//!
+//! ```ignore
+//! #[server(GetPost, "/api")]
+//! pub async fn get_post(cx: Scope, id: u32) -> Result<Option<Post>, ServerFnError> {
+//!     let db = use_d1(cx, "DB")?;
+//!     let post = D1Query::new("SELECT id, title FROM posts WHERE id = ?1")
+//!         .bind(id)
+//!         .first::<Post>(&db)
+//!         .await?;
+//!     Ok(post)
+//! }
+//! ```
+
+use leptos::Scope;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use wasm_bindgen::JsValue;
+use worker::d1::{D1Database, D1PreparedStatement, D1Result};
+
+use crate::{use_env, LeptosCloudflareError};
+
+#[doc(hidden)]
+pub use wasm_bindgen::JsValue as __JsValue;
+
+/// A page of results that can be returned from a server function as is.
+///
+/// `next_cursor` is opaque to the client: it is passed back unchanged to fetch the following page,
+/// and is `None` on the last page.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerPaginated<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> ServerPaginated<T> {
+    pub fn has_more(&self) -> bool {
+        self.next_cursor.is_some()
+    }
+}
+
+/// The D1 database bound as `binding`, from the bindings of the current request.
+pub fn use_d1(cx: Scope, binding: &str) -> Result<D1Database, LeptosCloudflareError> {
+    Ok(use_env(cx)?.d1(binding)?)
+}
+
+/// A query with its parameters, bound to `?1`, `?2`, ... in the order of the [bind](D1Query::bind) calls.
+#[derive(Debug, Clone)]
+pub struct D1Query {
+    query: String,
+    params: Vec<JsValue>,
+}
+
+impl D1Query {
+    pub fn new(query: impl Into<String>) -> Self {
+        Self {
+            query: query.into(),
+            params: Vec::new(),
+        }
+    }
+
+    /// Binds the next parameter. Use `JsValue::NULL` for `NULL`.
+    pub fn bind(mut self, param: impl Into<JsValue>) -> Self {
+        self.params.push(param.into());
+        self
+    }
+
+    /// Deserializes every row into `T`.
+    pub async fn all<T: DeserializeOwned>(&self, db: &D1Database) -> worker::Result<Vec<T>> {
+        d1_query_as(db, &self.query, &self.params).await
+    }
+
+    /// Deserializes the first row into `T`, if any.
+    pub async fn first<T: DeserializeOwned>(&self, db: &D1Database) -> worker::Result<Option<T>> {
+        d1_query_one(db, &self.query, &self.params).await
+    }
+
+    /// Runs a statement that returns no rows, e.g. an `INSERT` or an `UPDATE`.
+    pub async fn run(&self, db: &D1Database) -> Result<D1Result, D1Error> {
+        let result = prepare(db, &self.query, &self.params)?.run().await?;
+        match result.error() {
+            Some(error) => Err(D1Error::from(worker::Error::RustError(error))),
+            None => Ok(result),
+        }
+    }
+}
+
+/// Runs `query` with `params` bound to `?1`, `?2`, ... and deserializes every row into `T`.
+pub async fn d1_query_as<T: DeserializeOwned>(
+    db: &D1Database,
+    query: &str,
+    params: &[JsValue],
+) -> worker::Result<Vec<T>> {
+    prepare(db, query, params)?.all().await?.results::<T>()
+}
+
+/// Like [d1_query_as], but only returns the first row, if any.
+pub async fn d1_query_one<T: DeserializeOwned>(
+    db: &D1Database,
+    query: &str,
+    params: &[JsValue],
+) -> worker::Result<Option<T>> {
+    prepare(db, query, params)?.first::<T>(None).await
+}
+
+/// Fetches a page of `limit` rows starting at the offset encoded in `cursor` (the first page if `None`).
+///
+/// The query must not have a `LIMIT` clause of its own; it is appended here. Offset pagination is simple
+/// but gets slower the deeper the page and can skip or repeat rows when the table changes between
+/// requests. Prefer [d1_paginate_keyset] for large or frequently changing tables.
+///
+/// Cursors come from clients, so one that isn't an offset, or whose next page would be past the
+/// largest offset, fails with `400 Bad Request`.
+pub async fn d1_paginate_offset<T: DeserializeOwned>(
+    db: &D1Database,
+    query: &str,
+    params: &[JsValue],
+    cursor: Option<&str>,
+    limit: u32,
+) -> Result<ServerPaginated<T>, LeptosCloudflareError> {
+    let invalid_cursor = || LeptosCloudflareError::BadRequest("Invalid page cursor".to_string());
+    let offset = match cursor {
+        Some(cursor) => cursor.parse::<u32>().map_err(|_| invalid_cursor())?,
+        None => 0,
+    };
+    let next_offset = offset.checked_add(limit).ok_or_else(invalid_cursor)?;
+    let fetched = fetch_limit(limit)?;
+
+    let query = format!(
+        "{query} LIMIT ?{} OFFSET ?{}",
+        params.len() + 1,
+        params.len() + 2
+    );
+    // One extra row tells whether there is a next page without a separate COUNT query
+    let params = params
+        .iter()
+        .cloned()
+        .chain([JsValue::from(fetched), JsValue::from(offset)])
+        .collect::<Vec<_>>();
+
+    let mut items = d1_query_as::<T>(db, &query, &params).await?;
+    let next_cursor = if items.len() > limit as usize {
+        items.truncate(limit as usize);
+        Some(next_offset.to_string())
+    } else {
+        None
+    };
+
+    Ok(ServerPaginated { items, next_cursor })
+}
+
+/// Fetches a page of rows that come after the row encoded in `cursor`, using the value returned by
+/// `key_of` for the last row of a page as the cursor of the next one.
+///
+/// `params` are bound first, followed by the cursor (`NULL` on the first page) and the page size, so the
+/// query has to reference them by position and order by the key column:
+///
+/// ```ignore
+/// d1_paginate_keyset::<Post>(
+///     &db,
+///     "SELECT id, title FROM posts WHERE author = ?1 AND (?2 IS NULL OR id > ?2) ORDER BY id LIMIT ?3",
+///     &[author.into()],
+///     cursor.as_deref(),
+///     20,
+///     |post| post.id.to_string(),
+/// )
+/// ```
+///
+/// Cursors that look like integers are bound as numbers, everything else as text, since SQLite never
+/// considers an integer column equal to or smaller than a text value.
+pub async fn d1_paginate_keyset<T: DeserializeOwned>(
+    db: &D1Database,
+    query: &str,
+    params: &[JsValue],
+    cursor: Option<&str>,
+    limit: u32,
+    key_of: impl Fn(&T) -> String,
+) -> Result<ServerPaginated<T>, LeptosCloudflareError> {
+    let cursor = match cursor {
+        Some(cursor) => match cursor.parse::<i64>() {
+            Ok(number) => JsValue::from(number as f64),
+            Err(_) => JsValue::from_str(cursor),
+        },
+        None => JsValue::NULL,
+    };
+    let params = params
+        .iter()
+        .cloned()
+        .chain([cursor, JsValue::from(fetch_limit(limit)?)])
+        .collect::<Vec<_>>();
+
+    let mut items = d1_query_as::<T>(db, query, &params).await?;
+    let next_cursor = if items.len() > limit as usize {
+        items.truncate(limit as usize);
+        items.last().map(key_of)
+    } else {
+        None
+    };
+
+    Ok(ServerPaginated { items, next_cursor })
+}
+
+/// How many rows to fetch for a page of `limit`: one extra row tells whether there is a next page.
+fn fetch_limit(limit: u32) -> Result<u32, LeptosCloudflareError> {
+    limit
+        .checked_add(1)
+        .ok_or_else(|| LeptosCloudflareError::BadRequest("Invalid page size".to_string()))
+}
+
+/// Errors of a failed [with_transaction] or [d1_batch!](crate::d1_batch), classified from the SQLite
+/// error message so that callers can react to constraint violations without string matching.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum D1Error {
+    #[error("Unique constraint violated: {0}")]
+    UniqueViolation(String),
+    #[error("Foreign key constraint violated: {0}")]
+    ForeignKeyViolation(String),
+    #[error("Check or not-null constraint violated: {0}")]
+    ConstraintViolation(String),
+    #[error("D1 error: {0}")]
+    Other(String),
+}
+
+impl From<worker::Error> for D1Error {
+    fn from(err: worker::Error) -> Self {
+        let message = err.to_string();
+        if message.contains("UNIQUE constraint failed") {
+            D1Error::UniqueViolation(message)
+        } else if message.contains("FOREIGN KEY constraint failed") {
+            D1Error::ForeignKeyViolation(message)
+        } else if message.contains("constraint failed") {
+            D1Error::ConstraintViolation(message)
+        } else {
+            D1Error::Other(message)
+        }
+    }
+}
+
+impl From<D1Error> for LeptosCloudflareError {
+    fn from(err: D1Error) -> Self {
+        match err {
+            D1Error::UniqueViolation(_) | D1Error::ForeignKeyViolation(_) => {
+                LeptosCloudflareError::Conflict(err.to_string())
+            }
+            D1Error::ConstraintViolation(_) => LeptosCloudflareError::BadRequest(err.to_string()),
+            D1Error::Other(_) => LeptosCloudflareError::Internal(err.to_string()),
+        }
+    }
+}
+
+/// Statements collected by [with_transaction], executed together once the closure returns.
+pub struct D1Transaction<'a> {
+    db: &'a D1Database,
+    statements: Vec<D1PreparedStatement>,
+}
+
+impl<'a> D1Transaction<'a> {
+    /// Adds a statement to the transaction. Nothing is executed until the transaction commits, so results
+    /// of earlier statements cannot be read here; use subqueries or `RETURNING` instead.
+    pub fn execute(&mut self, query: &str, params: &[JsValue]) -> Result<&mut Self, D1Error> {
+        self.statements.push(prepare(self.db, query, params)?);
+        Ok(self)
+    }
+}
+
+/// Runs the statements added by `build` as a single D1 batch.
+///
+/// D1 executes a batch as one SQL transaction: if any statement fails, none of them is applied. If
+/// `build` itself returns an error, nothing is sent to the database at all. The results are returned in
+/// the order the statements were added.
+///
+/// ```ignore
+/// with_transaction(&db, |tx| {
+///     tx.execute("UPDATE accounts SET balance = balance - ?1 WHERE id = ?2", &[amount.into(), from.into()])?;
+///     tx.execute("UPDATE accounts SET balance = balance + ?1 WHERE id = ?2", &[amount.into(), to.into()])?;
+///     Ok(())
+/// })
+/// .await?;
+/// ```
+pub async fn with_transaction<F>(db: &D1Database, build: F) -> Result<Vec<D1Result>, D1Error>
+where
+    F: FnOnce(&mut D1Transaction) -> Result<(), D1Error>,
+{
+    let mut transaction = D1Transaction {
+        db,
+        statements: Vec::new(),
+    };
+    build(&mut transaction)?;
+
+    run_batch(db, transaction.statements).await
+}
+
+/// Runs `(query, params)` pairs as a single D1 batch. See [with_transaction] for the semantics,
+/// and [d1_batch!](crate::d1_batch) for a more compact syntax.
+pub async fn d1_batch(
+    db: &D1Database,
+    statements: Vec<(&str, Vec<JsValue>)>,
+) -> Result<Vec<D1Result>, D1Error> {
+    let statements = statements
+        .into_iter()
+        .map(|(query, params)| prepare(db, query, &params))
+        .collect::<worker::Result<Vec<_>>>()?;
+
+    run_batch(db, statements).await
+}
+
+async fn run_batch(
+    db: &D1Database,
+    statements: Vec<D1PreparedStatement>,
+) -> Result<Vec<D1Result>, D1Error> {
+    if statements.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let results = db.batch(statements).await?;
+    match results.iter().find_map(|result| result.error()) {
+        Some(error) => Err(D1Error::from(worker::Error::RustError(error))),
+        None => Ok(results),
+    }
+}
+
+fn prepare(
+    db: &D1Database,
+    query: &str,
+    params: &[JsValue],
+) -> worker::Result<D1PreparedStatement> {
+    let statement = db.prepare(query);
+    if params.is_empty() {
+        Ok(statement)
+    } else {
+        statement.bind(params)
+    }
+}
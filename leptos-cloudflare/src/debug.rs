@@ -0,0 +1,35 @@
+//! Development-only overrides for debugging rendering.
+//!
+//! In development (`LeptosOptions::env` is `DEV`), `?__ssr=<mode>` renders any route with another
+//! [SsrMode] than the one it declares, e.g. `?__ssr=async` to see whether a hydration bug is related
+//! to streaming. The modes are `async`, `in_order`, `out_of_order` and `partially_blocked`. In
+//! production, the parameter is ignored.
+
+use leptos::leptos_config::Env;
+use leptos::LeptosOptions;
+use leptos_router::SsrMode;
+
+/// The query parameter that overrides the [SsrMode].
+pub const SSR_QUERY_PARAM: &str = "__ssr";
+
+/// The mode requested by `req`, if any, which is only honored in development.
+pub(crate) fn ssr_mode_override(options: &LeptosOptions, req: &worker::Request) -> Option<SsrMode> {
+    if options.env != Env::DEV {
+        return None;
+    }
+
+    let url = req.url().ok()?;
+    let (_, mode) = url.query_pairs().find(|(key, _)| key == SSR_QUERY_PARAM)?;
+    let mode = match mode.replace('-', "_").as_str() {
+        "async" => SsrMode::Async,
+        "in_order" => SsrMode::InOrder,
+        "out_of_order" => SsrMode::OutOfOrder,
+        "partially_blocked" => SsrMode::PartiallyBlocked,
+        other => {
+            tracing::warn!("ignoring unknown {SSR_QUERY_PARAM} mode {other}");
+            return None;
+        }
+    };
+    tracing::info!("rendering {} with {mode:?}", url.path());
+    Some(mode)
+}
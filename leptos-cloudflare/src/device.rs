@@ -0,0 +1,124 @@
+//! The kind of device a request comes from, for adaptive layouts rendered on the server.
+//!
+//! The device is derived from the `Sec-CH-UA-Mobile` client hint when the browser sends it, and from the
+//! `User-Agent` otherwise:
+//!
+//! ```ignore
+//! #[component]
+//! fn Navigation(cx: Scope) -> impl IntoView {
+//!     match use_device(cx) {
+//!         Device::Mobile => view! { cx, <BottomBar/> }.into_view(cx),
+//!         _ => view! { cx, <Sidebar/> }.into_view(cx),
+//!     }
+//! }
+//! ```
+//!
+//! [use_device] marks the response as varying by those headers and asks the browser for the client
+//! hint on later requests. Pages cached with [CacheSegments](crate::CacheSegments) need
+//! [by_device](crate::CacheSegments::by_device), so that e.g. the mobile layout isn't served to desktops.
+
+use leptos::{use_context, Scope};
+
+use crate::ResponseOptions;
+
+/// The headers the device is derived from.
+pub const DEVICE_HEADERS: &str = "Sec-CH-UA-Mobile, User-Agent";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Device {
+    Mobile,
+    Tablet,
+    #[default]
+    Desktop,
+    /// A crawler or another automated client.
+    Bot,
+}
+
+impl Device {
+    pub fn from_headers(headers: &worker::Headers) -> Self {
+        let user_agent = headers
+            .get("User-Agent")
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if is_bot(&user_agent) {
+            return Device::Bot;
+        }
+
+        match headers.get("Sec-CH-UA-Mobile").ok().flatten().as_deref() {
+            Some("?1") => Device::Mobile,
+            // Tablets report themselves as not mobile, so only the user agent can tell them apart
+            Some(_) if !is_tablet(&user_agent) => Device::Desktop,
+            _ => Self::from_user_agent(&user_agent),
+        }
+    }
+
+    fn from_user_agent(user_agent: &str) -> Self {
+        if is_tablet(user_agent) {
+            Device::Tablet
+        } else if ["mobi", "iphone", "ipod", "android", "windows phone"]
+            .iter()
+            .any(|needle| user_agent.contains(needle))
+        {
+            Device::Mobile
+        } else {
+            Device::Desktop
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Device::Mobile => "mobile",
+            Device::Tablet => "tablet",
+            Device::Desktop => "desktop",
+            Device::Bot => "bot",
+        }
+    }
+}
+
+fn is_bot(user_agent: &str) -> bool {
+    user_agent.is_empty()
+        || [
+            "bot",
+            "crawl",
+            "spider",
+            "slurp",
+            "facebookexternalhit",
+            "headless",
+            "lighthouse",
+            "curl/",
+            "wget/",
+        ]
+        .iter()
+        .any(|needle| user_agent.contains(needle))
+}
+
+fn is_tablet(user_agent: &str) -> bool {
+    user_agent.contains("ipad")
+        || user_agent.contains("tablet")
+        || (user_agent.contains("android") && !user_agent.contains("mobile"))
+}
+
+/// The device of the current request, or [Device::Desktop] outside of a request.
+///
+/// Adds `Vary` and `Accept-CH` headers to the response, since its content now depends on the device.
+pub fn use_device(cx: Scope) -> Device {
+    let Some(device) = use_context::<Device>(cx) else {
+        return Device::default();
+    };
+
+    if let Some(mut res_options) = use_context::<ResponseOptions>(cx) {
+        let varies = res_options
+            .headers
+            .get("Vary")
+            .ok()
+            .flatten()
+            .is_some_and(|vary| vary.contains("Sec-CH-UA-Mobile"));
+        if !varies {
+            let _ = res_options.append_header("Vary", DEVICE_HEADERS);
+            let _ = res_options.insert_header("Accept-CH", "Sec-CH-UA-Mobile");
+        }
+    }
+    device
+}
@@ -0,0 +1,142 @@
+//! Request-scoped dependencies.
+//!
+//! Components and server functions get their services (a mailer, a repository backed by D1, an API
+//! client, ...) with [use_dep] instead of constructing them from bindings or pulling them out of statics.
+//! The services are registered once, in [WorkerRouterData::deps](crate::WorkerRouterData::deps), by
+//! giving a function (or a [Provide] implementation) that constructs each type:
+//!
+//! ```ignore
+//! let deps = Dependencies::new()
+//!     .provide(|env, _req| Ok(PostRepository::new(env.d1("DB")?)))
+//!     .value(Mailer::new("noreply@example.com"));
+//!
+//! #[server(CreatePost, "/api")]
+//! pub async fn create_post(cx: Scope, title: String) -> Result<(), ServerFnError> {
+//!     use_dep::<PostRepository>(cx)?.insert(&title).await?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Each dependency is constructed the first time it is used in a request and shared for the rest of it.
+//! A later registration for the same type replaces an earlier one, so tests can swap in a fake:
+//!
+//! ```ignore
+//! let deps = app_dependencies().value(Mailer::fake());
+//! ```
+
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use leptos::{use_context, Scope};
+use worker::Env;
+
+use crate::{LeptosCloudflareError, RequestParts};
+
+/// Constructs a `T` for a request.
+pub trait Provide<T> {
+    fn provide(&self, env: &Env, req: &RequestParts) -> Result<T, LeptosCloudflareError>;
+}
+
+impl<T, F> Provide<T> for F
+where
+    F: Fn(&Env, &RequestParts) -> Result<T, LeptosCloudflareError>,
+{
+    fn provide(&self, env: &Env, req: &RequestParts) -> Result<T, LeptosCloudflareError> {
+        self(env, req)
+    }
+}
+
+type ErasedProvider = Rc<dyn Fn(&Env, &RequestParts) -> Result<Rc<dyn Any>, LeptosCloudflareError>>;
+
+/// The registered providers, by the type they provide.
+#[derive(Clone, Default)]
+pub struct Dependencies {
+    providers: HashMap<TypeId, ErasedProvider>,
+}
+
+impl Dependencies {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `provide` as the way to construct a `T`.
+    pub fn provide<T, F>(self, provide: F) -> Self
+    where
+        T: 'static,
+        F: Fn(&Env, &RequestParts) -> Result<T, LeptosCloudflareError> + 'static,
+    {
+        self.provider(provide)
+    }
+
+    /// Registers a [Provide] implementation as the way to construct a `T`.
+    pub fn provider<T: 'static>(mut self, provider: impl Provide<T> + 'static) -> Self {
+        let provider: ErasedProvider = Rc::new(move |env: &Env, req: &RequestParts| {
+            provider
+                .provide(env, req)
+                .map(|dep| Rc::new(dep) as Rc<dyn Any>)
+        });
+        self.providers.insert(TypeId::of::<T>(), provider);
+        self
+    }
+
+    /// Registers a value that is cloned into every request.
+    pub fn value<T: Clone + 'static>(self, value: T) -> Self {
+        self.provide(move |_, _| Ok(value.clone()))
+    }
+}
+
+/// The dependencies of a single request, provided in its context.
+#[derive(Clone)]
+pub(crate) struct DepContainer {
+    dependencies: Dependencies,
+    env: Env,
+    req: RequestParts,
+    constructed: Rc<RefCell<HashMap<TypeId, Rc<dyn Any>>>>,
+}
+
+impl DepContainer {
+    pub(crate) fn new(dependencies: Dependencies, env: Env, req: RequestParts) -> Self {
+        Self {
+            dependencies,
+            env,
+            req,
+            constructed: Rc::default(),
+        }
+    }
+
+    fn get<T: 'static>(&self) -> Result<Rc<T>, LeptosCloudflareError> {
+        let type_id = TypeId::of::<T>();
+        let constructed = self.constructed.borrow().get(&type_id).cloned();
+        let dep = match constructed {
+            Some(dep) => dep,
+            None => {
+                let provider = self.dependencies.providers.get(&type_id).ok_or_else(|| {
+                    LeptosCloudflareError::Internal(format!(
+                        "No provider registered for {}",
+                        std::any::type_name::<T>()
+                    ))
+                })?;
+                let dep = provider(&self.env, &self.req)?;
+                self.constructed.borrow_mut().insert(type_id, dep.clone());
+                dep
+            }
+        };
+
+        Ok(dep
+            .downcast::<T>()
+            .expect("dependencies are stored under the TypeId of their type"))
+    }
+}
+
+/// Returns the `T` of the current request, constructing it on first use.
+///
+/// Fails if no provider for `T` was registered, or if the provider fails.
+pub fn use_dep<T: 'static>(cx: Scope) -> Result<Rc<T>, LeptosCloudflareError> {
+    use_context::<DepContainer>(cx)
+        .ok_or_else(|| {
+            LeptosCloudflareError::Internal("use_dep called outside of a request".to_string())
+        })?
+        .get::<T>()
+}
@@ -0,0 +1,103 @@
+use std::pin::Pin;
+
+use futures::{Stream, StreamExt};
+
+/// Body of a [download_response]. Build it from bytes with `into()`, or from a stream with
+/// [DownloadBody::stream] for exports that should not be buffered in memory.
+pub enum DownloadBody {
+    Bytes(Vec<u8>),
+    Stream(Pin<Box<dyn Stream<Item = worker::Result<Vec<u8>>>>>),
+}
+
+impl DownloadBody {
+    pub fn stream(stream: impl Stream<Item = worker::Result<Vec<u8>>> + 'static) -> Self {
+        DownloadBody::Stream(Box::pin(stream))
+    }
+}
+
+impl From<Vec<u8>> for DownloadBody {
+    fn from(bytes: Vec<u8>) -> Self {
+        DownloadBody::Bytes(bytes)
+    }
+}
+
+impl From<String> for DownloadBody {
+    fn from(text: String) -> Self {
+        DownloadBody::Bytes(text.into_bytes())
+    }
+}
+
+/// Builds a response that browsers save as `filename` instead of displaying it.
+/// The `Content-Type` is guessed from the extension of `filename`.
+pub fn download_response(
+    body: impl Into<DownloadBody>,
+    filename: &str,
+) -> worker::Result<worker::Response> {
+    let mut response = match body.into() {
+        DownloadBody::Bytes(bytes) => worker::Response::from_bytes(bytes)?,
+        DownloadBody::Stream(stream) => {
+            worker::Response::from_stream(stream.map(|chunk| chunk.map_err(|err| err.to_string())))?
+        }
+    };
+
+    let content_type = mime_guess::from_path(filename).first_or_octet_stream();
+    response
+        .headers_mut()
+        .set("Content-Type", content_type.essence_str())?;
+    response
+        .headers_mut()
+        .set("Content-Disposition", &content_disposition(filename))?;
+
+    Ok(response)
+}
+
+/// Builds an `attachment` `Content-Disposition` value for `filename`.
+///
+/// Non-ASCII names are sent in the RFC 5987 `filename*` parameter, with an ASCII approximation
+/// in `filename` for clients that do not support it.
+pub fn content_disposition(filename: &str) -> String {
+    let fallback = filename
+        .chars()
+        .map(|c| {
+            if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+
+    if fallback == filename {
+        format!("attachment; filename=\"{filename}\"")
+    } else {
+        format!(
+            "attachment; filename=\"{fallback}\"; filename*=UTF-8''{}",
+            encode_rfc5987(filename)
+        )
+    }
+}
+
+/// Percent-encodes everything except the `attr-char` set of RFC 5987.
+fn encode_rfc5987(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z'
+            | b'A'..=b'Z'
+            | b'0'..=b'9'
+            | b'!'
+            | b'#'
+            | b'$'
+            | b'&'
+            | b'+'
+            | b'-'
+            | b'.'
+            | b'^'
+            | b'_'
+            | b'`'
+            | b'|'
+            | b'~' => (byte as char).to_string(),
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
@@ -0,0 +1,90 @@
+//! Rendering and server functions inside a Durable Object.
+//!
+//! A Durable Object that owns some state (a chat room, a document) can serve its own pages and server
+//! functions, so reads and writes happen next to its storage instead of through a round trip from the
+//! worker. [durable_fetch] runs the same router as the worker from the `fetch` of the object, with the
+//! object's state, id, storage and bindings available to components and server functions through
+//! [use_durable_object]:
+//!
+//! ```ignore
+//! #[durable_object]
+//! pub struct Room {
+//!     state: Rc<State>,
+//!     env: Env,
+//! }
+//!
+//! #[durable_object]
+//! impl DurableObject for Room {
+//!     fn new(state: State, env: Env) -> Self {
+//!         Self { state: Rc::new(state), env }
+//!     }
+//!
+//!     async fn fetch(&mut self, req: Request) -> worker::Result<Response> {
+//!         durable_fetch(self.state.clone(), self.env.clone(), req, router_data()?, routes()).await
+//!     }
+//! }
+//!
+//! #[server(PostMessage, "/api")]
+//! pub async fn post_message(cx: Scope, text: String) -> Result<(), ServerFnError> {
+//!     let room = use_durable_object(cx)?;
+//!     room.storage.lock().await.put(&format!("message:{}", now()), text).await?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Input gates only hold back the other requests of the object: the resources and server functions of
+//! the same request may still use the storage at the same time, so it is shared behind an async
+//! [Mutex](futures::lock::Mutex) whose guard can be held across the `await` of an operation.
+//!
+//! The worker forwards the requests for a room to its object, e.g. with
+//! `env.durable_object("ROOMS")?.id_from_name(&room)?.get_stub()?.fetch_with_request(req)`.
+
+use std::rc::Rc;
+
+use futures::lock::Mutex;
+use leptos::{IntoView, Scope};
+use leptos_router::RouteListing;
+use worker::{Env, Request, Response, State, Storage};
+
+use crate::{use_dep, LeptosCloudflareError, LeptosRoutes, WorkerRouterData};
+
+/// The Durable Object handling the current request.
+#[derive(Clone)]
+pub struct DurableObjectParts {
+    /// The state of the object, e.g. for its alarms or WebSockets. The object keeps it in an [Rc] to
+    /// share it with its requests.
+    pub state: Rc<State>,
+    /// The id of the object, as a hex string.
+    pub id: String,
+    /// The storage of the object. Futures of the same request that use it wait for each other's
+    /// operations instead of conflicting.
+    pub storage: Rc<Mutex<Storage>>,
+    pub env: Env,
+}
+
+/// Handles `req` inside a Durable Object with the router built from `data` and the app `routes`.
+pub async fn durable_fetch<IV, AppFn>(
+    state: Rc<State>,
+    env: Env,
+    req: Request,
+    mut data: WorkerRouterData<IV, AppFn>,
+    routes: Vec<RouteListing>,
+) -> worker::Result<Response>
+where
+    IV: IntoView + 'static,
+    AppFn: Fn(Scope) -> IV + Clone + 'static,
+{
+    let parts = DurableObjectParts {
+        id: state.id().to_string(),
+        storage: Rc::new(Mutex::new(state.storage())),
+        state,
+        env: env.clone(),
+    };
+    data.deps = data.deps.value(parts);
+    data.into_router().leptos_routes(routes).run(req, env).await
+}
+
+/// The Durable Object handling the current request, when called under [durable_fetch].
+pub fn use_durable_object(cx: Scope) -> Result<Rc<DurableObjectParts>, LeptosCloudflareError> {
+    use_dep::<DurableObjectParts>(cx)
+}
@@ -0,0 +1,158 @@
+//! Inbound email with Email Workers.
+//!
+//! `worker::event` has no `email` event, so the worker exports the handler itself and passes the
+//! message to [handle_email], which runs the handler inside a Leptos runtime like a server function:
+//!
+//! ```ignore
+//! #[wasm_bindgen]
+//! pub async fn email(message: JsValue, env: Env, _ctx: JsValue) -> Result<(), JsValue> {
+//!     handle_email(message, env, |cx, message| async move {
+//!         if !message.from().ends_with("@acme.com") {
+//!             message.set_reject("Unknown sender")?;
+//!             return Ok(());
+//!         }
+//!         let raw = message.raw().await?;
+//!         store_ticket(cx, &raw).await?;
+//!         message.forward("support@acme.com", None).await?;
+//!         Ok(())
+//!     })
+//!     .await
+//!     .map_err(|err| JsValue::from_str(&err.to_string()))
+//! }
+//! ```
+//!
+//! and `worker-build`'s generated entry point has to call it, e.g. with a `shim.mjs` adding
+//! `async email(message, env, ctx) { await wasm.email(message, env, ctx) }` to the exported handlers.
+
+use std::future::Future;
+
+use leptos::{create_runtime, provide_context, raw_scope_and_disposer, Scope};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use worker::Env;
+
+use crate::app_env::RequestEnv;
+use crate::LeptosCloudflareError;
+
+/// A `ForwardableEmailMessage` received by the worker.
+pub struct EmailMessage {
+    inner: JsValue,
+}
+
+/// The envelope of the message being handled, provided in the context of [handle_email].
+#[derive(Debug, Clone)]
+pub struct EmailEventParts {
+    pub from: String,
+    pub to: String,
+    /// The size of the raw message in bytes.
+    pub raw_size: u64,
+}
+
+impl EmailMessage {
+    /// Wraps the message passed to the `email` handler.
+    pub fn from_js(inner: JsValue) -> Self {
+        Self { inner }
+    }
+
+    /// The envelope sender.
+    pub fn from(&self) -> String {
+        self.string("from")
+    }
+
+    /// The envelope recipient.
+    pub fn to(&self) -> String {
+        self.string("to")
+    }
+
+    pub fn headers(&self) -> worker::Result<worker::Headers> {
+        let headers = js_sys::Reflect::get(&self.inner, &"headers".into())?;
+        Ok(worker::Headers(headers.unchecked_into()))
+    }
+
+    pub fn raw_size(&self) -> u64 {
+        js_sys::Reflect::get(&self.inner, &"rawSize".into())
+            .ok()
+            .and_then(|size| size.as_f64())
+            .unwrap_or_default() as u64
+    }
+
+    /// Reads the whole raw message. The stream can only be read once.
+    pub async fn raw(&self) -> worker::Result<Vec<u8>> {
+        let stream = js_sys::Reflect::get(&self.inner, &"raw".into())?;
+        let mut response =
+            worker::Response::from_body(worker::ResponseBody::Stream(stream.unchecked_into()))?;
+        response.bytes().await
+    }
+
+    /// Rejects the message with `reason`, which the sending server receives.
+    pub fn set_reject(&self, reason: &str) -> worker::Result<()> {
+        self.call("setReject", &[JsValue::from_str(reason)])?;
+        Ok(())
+    }
+
+    /// Forwards the message to `recipient`, a verified address of the account, optionally adding
+    /// `headers` (only `X-*` headers are allowed).
+    pub async fn forward(
+        &self,
+        recipient: &str,
+        headers: Option<worker::Headers>,
+    ) -> worker::Result<()> {
+        let mut args = vec![JsValue::from_str(recipient)];
+        if let Some(headers) = headers {
+            args.push(headers.0.into());
+        }
+        let promise = self.call("forward", &args)?;
+        JsFuture::from(js_sys::Promise::from(promise)).await?;
+        Ok(())
+    }
+
+    fn string(&self, key: &str) -> String {
+        js_sys::Reflect::get(&self.inner, &key.into())
+            .ok()
+            .and_then(|value| value.as_string())
+            .unwrap_or_default()
+    }
+
+    fn call(&self, method: &str, args: &[JsValue]) -> worker::Result<JsValue> {
+        let function: js_sys::Function =
+            js_sys::Reflect::get(&self.inner, &method.into())?.unchecked_into();
+        let args = args.iter().collect::<js_sys::Array>();
+        Ok(function.apply(&self.inner, &args)?)
+    }
+}
+
+/// Runs `handler` for `message` in a new Leptos runtime with [EmailEventParts] and the bindings of the
+/// worker in its context. There is no request, so helpers that read it, like
+/// [use_dep](crate::use_dep), aren't available.
+#[tracing::instrument(level = "trace", skip_all)]
+pub async fn handle_email<F, Fut>(
+    message: JsValue,
+    env: Env,
+    handler: F,
+) -> Result<(), LeptosCloudflareError>
+where
+    F: FnOnce(Scope, EmailMessage) -> Fut,
+    Fut: Future<Output = Result<(), LeptosCloudflareError>>,
+{
+    let message = EmailMessage::from_js(message);
+    let parts = EmailEventParts {
+        from: message.from(),
+        to: message.to(),
+        raw_size: message.raw_size(),
+    };
+    tracing::debug!("email from {} to {}", parts.from, parts.to);
+
+    let runtime = create_runtime();
+    let (cx, disposer) = raw_scope_and_disposer(runtime);
+
+    provide_context(cx, RequestEnv(env));
+    provide_context(cx, parts);
+    let result = handler(cx, message).await;
+    if let Err(err) = &result {
+        tracing::error!("email handler failed: {err}");
+    }
+
+    disposer.dispose();
+    runtime.dispose();
+    result
+}
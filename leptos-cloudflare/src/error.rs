@@ -0,0 +1,258 @@
+use std::cell::Cell;
+
+use http::StatusCode;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::response::IntoWorkerResponse;
+
+/// Errors produced by the crate's handlers and helpers. Returning it from a route handler or an
+/// [api_route](crate::ApiRoutes::api_route) responds with the matching status code and the error message,
+/// or with [ProblemDetails] if [ErrorRenderer::problem_details] is set.
+#[derive(Error, Debug)]
+pub enum LeptosCloudflareError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("Unauthorized")]
+    Unauthorized,
+    #[error("Forbidden")]
+    Forbidden,
+    #[error("Not found")]
+    NotFound,
+    #[error("{0}")]
+    Conflict(String),
+    #[error("Too many requests")]
+    TooManyRequests,
+    #[error("{0}")]
+    Internal(String),
+    #[error(transparent)]
+    Worker(#[from] worker::Error),
+}
+
+impl LeptosCloudflareError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            LeptosCloudflareError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            LeptosCloudflareError::Unauthorized => StatusCode::UNAUTHORIZED,
+            LeptosCloudflareError::Forbidden => StatusCode::FORBIDDEN,
+            LeptosCloudflareError::NotFound => StatusCode::NOT_FOUND,
+            LeptosCloudflareError::Conflict(_) => StatusCode::CONFLICT,
+            LeptosCloudflareError::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            LeptosCloudflareError::Internal(_) | LeptosCloudflareError::Worker(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// A stable identifier of the kind of error, e.g. `not_found`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            LeptosCloudflareError::BadRequest(_) => "bad_request",
+            LeptosCloudflareError::Unauthorized => "unauthorized",
+            LeptosCloudflareError::Forbidden => "forbidden",
+            LeptosCloudflareError::NotFound => "not_found",
+            LeptosCloudflareError::Conflict(_) => "conflict",
+            LeptosCloudflareError::TooManyRequests => "too_many_requests",
+            LeptosCloudflareError::Internal(_) => "internal",
+            LeptosCloudflareError::Worker(_) => "worker",
+        }
+    }
+
+    /// The error as Problem Details, with its [code](LeptosCloudflareError::code) as an extension
+    /// member. The messages of internal errors are left out, they may reveal internals.
+    pub fn problem_details(&self) -> ProblemDetails {
+        let detail = match self {
+            LeptosCloudflareError::BadRequest(message)
+            | LeptosCloudflareError::Conflict(message) => Some(message.clone()),
+            _ => None,
+        };
+        let mut problem = ProblemDetails::new(self.status(), detail);
+        problem
+            .extensions
+            .insert("code".to_string(), self.code().into());
+        problem
+    }
+}
+
+impl IntoWorkerResponse for LeptosCloudflareError {
+    fn into_worker_response(self) -> worker::Result<worker::Response> {
+        if ErrorRenderer::installed().problem_details {
+            return self.problem_details().into_worker_response();
+        }
+        worker::Response::error(self.to_string(), self.status().as_u16())
+    }
+}
+
+/// An error body as defined by [RFC 9457](https://www.rfc-editor.org/rfc/rfc9457), sent as
+/// `application/problem+json`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProblemDetails {
+    /// A URI identifying the kind of problem, `about:blank` when the status says it all.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub title: String,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// A URI identifying this occurrence, e.g. the path of the request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// Additional members, serialized next to the standard ones.
+    #[serde(flatten)]
+    pub extensions: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ProblemDetails {
+    /// A problem of type `about:blank`, titled with the reason phrase of `status`.
+    pub fn new(status: StatusCode, detail: Option<String>) -> Self {
+        Self {
+            kind: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or("Error").to_string(),
+            status: status.as_u16(),
+            detail,
+            instance: None,
+            extensions: serde_json::Map::new(),
+        }
+    }
+}
+
+impl IntoWorkerResponse for ProblemDetails {
+    fn into_worker_response(self) -> worker::Result<worker::Response> {
+        let mut response = worker::Response::from_json(&self)?.with_status(self.status);
+        response
+            .headers_mut()
+            .set("Content-Type", "application/problem+json")?;
+        Ok(response)
+    }
+}
+
+/// Renders the error responses produced by the crate itself, such as an unknown server function, a
+/// missing asset or a page that failed to render. Clients that prefer JSON in their `Accept` header get
+/// `{"status": 404, "error": "Not found"}`, everyone else an HTML page made by `html`.
+///
+/// Set it with [WorkerRouterDataBuilder::error_renderer](crate::WorkerRouterDataBuilder::error_renderer)
+/// to show the error pages in the style of the app.
+#[derive(Debug, Clone, Copy)]
+pub struct ErrorRenderer {
+    /// Renders the HTML page for a status and message. The message is plain text and must be escaped.
+    pub html: fn(StatusCode, &str) -> String,
+    /// Sends JSON errors as [ProblemDetails] instead, including the [LeptosCloudflareError]s returned
+    /// by [api_route](crate::ApiRoutes::api_route) handlers.
+    pub problem_details: bool,
+}
+
+impl Default for ErrorRenderer {
+    fn default() -> Self {
+        Self {
+            html: default_error_page,
+            problem_details: false,
+        }
+    }
+}
+
+thread_local! {
+    // For the handlers that are generic over the router data and can't reach WorkerRouterData
+    static ISOLATE_ERROR_RENDERER: Cell<Option<ErrorRenderer>> = Cell::new(None);
+}
+
+impl ErrorRenderer {
+    /// Renders the response for `status` with `message`, in the format preferred by `accept`.
+    pub fn render(
+        &self,
+        accept: Option<&str>,
+        status: StatusCode,
+        message: &str,
+    ) -> worker::Result<worker::Response> {
+        self.render_at(accept, status, message, None)
+    }
+
+    /// Renders the response for `status` with `message` for `req`. Its path is the `instance` of
+    /// Problem Details.
+    pub fn render_for(
+        &self,
+        req: &worker::Request,
+        status: StatusCode,
+        message: &str,
+    ) -> worker::Result<worker::Response> {
+        self.render_at(
+            req.headers().get("Accept")?.as_deref(),
+            status,
+            message,
+            Some(req.path()),
+        )
+    }
+
+    fn render_at(
+        &self,
+        accept: Option<&str>,
+        status: StatusCode,
+        message: &str,
+        instance: Option<String>,
+    ) -> worker::Result<worker::Response> {
+        if !prefers_json(accept) {
+            return Ok(worker::Response::from_html((self.html)(status, message))?
+                .with_status(status.as_u16()));
+        }
+        if self.problem_details {
+            let mut problem = ProblemDetails::new(status, Some(message.to_string()));
+            problem.instance = instance;
+            return problem.into_worker_response();
+        }
+        Ok(worker::Response::from_json(&serde_json::json!({
+            "status": status.as_u16(),
+            "error": message,
+        }))?
+        .with_status(status.as_u16()))
+    }
+
+    /// Makes this the renderer of the handlers that aren't given one by [WorkerRouterData](crate::WorkerRouterData),
+    /// such as [api_route](crate::ApiRoutes::api_route). Done by [into_router](crate::WorkerRouterData::into_router).
+    pub(crate) fn install(self) {
+        ISOLATE_ERROR_RENDERER.with(|renderer| renderer.set(Some(self)));
+    }
+
+    /// The renderer installed in the isolate, or the default one.
+    pub(crate) fn installed() -> Self {
+        ISOLATE_ERROR_RENDERER.with(Cell::get).unwrap_or_default()
+    }
+}
+
+/// Whether `accept` ranks JSON at least as high as HTML. Wildcards count for neither.
+pub(crate) fn prefers_json(accept: Option<&str>) -> bool {
+    let mut json = 0.0_f32;
+    let mut html = 0.0_f32;
+    for range in accept.unwrap_or_default().split(',') {
+        let mut params = range.split(';');
+        let media_type = params
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if media_type == "application/json" || media_type.ends_with("+json") {
+            json = json.max(quality);
+        } else if media_type == "text/html" || media_type == "application/xhtml+xml" {
+            html = html.max(quality);
+        }
+    }
+    json > 0.0 && json >= html
+}
+
+fn default_error_page(status: StatusCode, message: &str) -> String {
+    let title = format!(
+        "{} {}",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or("Error")
+    );
+    let message = message
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{title}</title></head>\
+         <body><h1>{title}</h1><p>{message}</p></body></html>"
+    )
+}
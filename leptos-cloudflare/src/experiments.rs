@@ -0,0 +1,192 @@
+//! Sticky experiment buckets for A/B tests and percentage rollouts.
+//!
+//! Every visitor gets a random, stable ID in the [BUCKET_COOKIE] cookie the first time an experiment
+//! is evaluated for them. The variant is derived from a hash of the experiment name and that ID, so it
+//! is the same in every isolate and for every request of the visitor, without storing assignments:
+//!
+//! ```ignore
+//! static CHECKOUT: Experiment =
+//!     Experiment::new("checkout", &[("control", 50), ("one_page", 50)]).log_exposures("EXPOSURES");
+//!
+//! // a rollout of a feature flag to 10% of the visitors
+//! static NEW_SEARCH: Experiment = Experiment::new("new_search", &[("on", 10), ("off", 90)]);
+//!
+//! #[component]
+//! fn Checkout(cx: Scope) -> impl IntoView {
+//!     match use_variant(cx, &CHECKOUT) {
+//!         "one_page" => view! { cx, <OnePageCheckout/> }.into_view(cx),
+//!         _ => view! { cx, <Steps/> }.into_view(cx),
+//!     }
+//! }
+//! ```
+//!
+//! With [log_exposures](Experiment::log_exposures), the first evaluation of an experiment in a request
+//! writes a data point to the Workers Analytics Engine dataset bound under that name, with the
+//! experiment, the variant and the visitor ID as blobs and the experiment as index. Pages that show
+//! variants must not be cached across visitors, or only per variant (see
+//! [CacheSegments](crate::CacheSegments)).
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use leptos::{use_context, Scope};
+use wasm_bindgen::JsValue;
+
+use crate::app_env::RequestEnv;
+use crate::request_headers::use_full_request_parts;
+use crate::util::cookie;
+use crate::ResponseOptions;
+
+/// The cookie holding the visitor ID buckets are derived from.
+pub const BUCKET_COOKIE: &str = "__bucket";
+
+/// An experiment with weighted variants.
+#[derive(Debug, Clone, Copy)]
+pub struct Experiment {
+    pub name: &'static str,
+    /// Variant names and their relative weights.
+    pub variants: &'static [(&'static str, u32)],
+    /// The Analytics Engine dataset exposures are logged to.
+    pub exposures: Option<&'static str>,
+}
+
+impl Experiment {
+    pub const fn new(name: &'static str, variants: &'static [(&'static str, u32)]) -> Self {
+        Self {
+            name,
+            variants,
+            exposures: None,
+        }
+    }
+
+    /// Logs exposures to the Analytics Engine dataset bound as `binding`.
+    pub const fn log_exposures(mut self, binding: &'static str) -> Self {
+        self.exposures = Some(binding);
+        self
+    }
+
+    /// The variant of the visitor with `id`. Returns `""` if the experiment has no variants.
+    pub fn variant_for(&self, id: &str) -> &'static str {
+        let total: u64 = self.variants.iter().map(|(_, weight)| *weight as u64).sum();
+        if total == 0 {
+            return self
+                .variants
+                .first()
+                .map(|(name, _)| *name)
+                .unwrap_or_default();
+        }
+
+        let mut point = bucket_hash(self.name, id) % total;
+        for (name, weight) in self.variants {
+            if point < *weight as u64 {
+                return name;
+            }
+            point -= *weight as u64;
+        }
+        unreachable!("the point is below the total weight")
+    }
+}
+
+/// The visitor ID of a request and the experiments it was exposed to, provided in its context.
+#[derive(Clone, Default)]
+pub(crate) struct Buckets(Rc<RefCell<BucketsState>>);
+
+#[derive(Default)]
+struct BucketsState {
+    id: Option<String>,
+    exposed: HashSet<&'static str>,
+}
+
+/// The variant of `experiment` for the current visitor, assigning them an ID if they don't have one.
+///
+/// Outside of a request, the first variant is returned.
+pub fn use_variant(cx: Scope, experiment: &Experiment) -> &'static str {
+    let (Some(buckets), Some(req)) = (use_context::<Buckets>(cx), use_full_request_parts(cx))
+    else {
+        return experiment
+            .variants
+            .first()
+            .map(|(name, _)| *name)
+            .unwrap_or_default();
+    };
+
+    let mut state = buckets.0.borrow_mut();
+    let id = match &state.id {
+        Some(id) => id.clone(),
+        None => {
+            let id = match cookie(&req.headers, BUCKET_COOKIE) {
+                Some(id) => id,
+                None => {
+                    let id = new_id();
+                    if let Some(mut res_options) = use_context::<ResponseOptions>(cx) {
+                        let _ = res_options.append_header(
+                            "Set-Cookie",
+                            &format!(
+                                "{BUCKET_COOKIE}={id}; Path=/; Max-Age=31536000; HttpOnly; Secure; SameSite=Lax"
+                            ),
+                        );
+                    }
+                    id
+                }
+            };
+            state.id = Some(id.clone());
+            id
+        }
+    };
+
+    let variant = experiment.variant_for(&id);
+    if let Some(binding) = experiment.exposures {
+        if state.exposed.insert(experiment.name) {
+            if let Some(env) = use_context::<RequestEnv>(cx) {
+                log_exposure(&env.0, binding, experiment.name, variant, &id);
+            }
+        }
+    }
+    variant
+}
+
+/// Writes a data point without waiting for it, as Analytics Engine does.
+fn log_exposure(env: &worker::Env, binding: &str, experiment: &str, variant: &str, id: &str) {
+    let result = (|| {
+        let dataset = js_sys::Reflect::get(env, &JsValue::from_str(binding))?;
+        let write = js_sys::Reflect::get(&dataset, &JsValue::from_str("writeDataPoint"))?;
+        let point = js_sys::Object::new();
+        let blobs = js_sys::Array::of3(
+            &JsValue::from_str(experiment),
+            &JsValue::from_str(variant),
+            &JsValue::from_str(id),
+        );
+        js_sys::Reflect::set(&point, &JsValue::from_str("blobs"), &blobs)?;
+        js_sys::Reflect::set(
+            &point,
+            &JsValue::from_str("indexes"),
+            &js_sys::Array::of1(&JsValue::from_str(experiment)),
+        )?;
+        js_sys::Function::from(write).call1(&dataset, &point)
+    })();
+
+    if let Err(err) = result {
+        tracing::warn!("failed to log the exposure to {experiment}: {err:?}");
+    }
+}
+
+pub(crate) fn new_id() -> String {
+    (0..4)
+        .map(|_| format!("{:08x}", (js_sys::Math::random() * u32::MAX as f64) as u32))
+        .collect()
+}
+
+/// 64-bit FNV-1a of `experiment:id`, which is stable across isolates and deployments.
+pub(crate) fn bucket_hash(experiment: &str, id: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in experiment
+        .bytes()
+        .chain(std::iter::once(b':'))
+        .chain(id.bytes())
+    {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
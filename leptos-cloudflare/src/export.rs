@@ -0,0 +1,27 @@
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+
+use crate::download::{download_response, DownloadBody};
+
+/// Streams `rows` as a CSV download named `filename`, serializing one row at a time so that
+/// exports of large tables stay within the worker's memory limit.
+///
+/// The header line is derived from the field names of the first row. An error in the stream aborts
+/// the response midway, since the status has already been sent by then.
+pub fn stream_csv<S, T>(rows: S, filename: &str) -> worker::Result<worker::Response>
+where
+    S: Stream<Item = worker::Result<T>> + 'static,
+    T: Serialize,
+{
+    let mut writer = csv::Writer::from_writer(Vec::new());
+
+    let chunks = rows.map(move |row| {
+        writer
+            .serialize(row?)
+            .and_then(|_| writer.flush().map_err(csv::Error::from))
+            .map_err(|err| worker::Error::RustError(err.to_string()))?;
+        Ok(std::mem::take(writer.get_mut()))
+    });
+
+    download_response(DownloadBody::stream(chunks), filename)
+}
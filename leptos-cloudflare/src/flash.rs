@@ -0,0 +1,160 @@
+//! One-shot messages that survive a redirect, for post/redirect/get flows.
+//!
+//! A server function queues messages with [success], [info], [warning] or [error]. They are
+//! [sealed](crate::seal) into a cookie of its response, and the next page rendered on the server takes
+//! them out of the cookie, clears it and provides them to the app through [use_flash]:
+//!
+//! ```ignore
+//! WorkerRouterData::builder()
+//!     .flash(Sealer::new(&["FLASH_KEY"]))
+//!     // ...
+//!
+//! #[server(SaveProfile, "/api")]
+//! pub async fn save_profile(cx: Scope, name: String) -> Result<(), ServerFnError> {
+//!     save(cx, &name).await?;
+//!     flash::success(cx, "Saved");
+//!     Ok(())
+//! }
+//!
+//! #[component]
+//! fn Flashes(cx: Scope) -> impl IntoView {
+//!     use_flash(cx)
+//!         .into_iter()
+//!         .map(|message| view! { cx, <p class=message.level.as_str()>{message.text}</p> })
+//!         .collect_view(cx)
+//! }
+//! ```
+//!
+//! A form submitted without JavaScript is redirected back to its page, which renders the messages.
+//! With JavaScript the cookie is set all the same, and the messages show up on the next page the server
+//! renders rather than after client-side navigations.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use leptos::{use_context, Scope};
+use serde::{Deserialize, Serialize};
+
+use crate::seal::Sealer;
+use crate::util::cookie;
+use crate::{RequestParts, ResponseOptions};
+
+pub(crate) const COOKIE_NAME: &str = "__flash";
+/// How long unconsumed messages are kept, in seconds.
+const MAX_AGE: u64 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlashLevel {
+    Success,
+    Info,
+    Warning,
+    Error,
+}
+
+impl FlashLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FlashLevel::Success => "success",
+            FlashLevel::Info => "info",
+            FlashLevel::Warning => "warning",
+            FlashLevel::Error => "error",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlashMessage {
+    pub level: FlashLevel,
+    pub text: String,
+}
+
+/// The messages taken from the request, provided in the context of every render.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Flash(Vec<FlashMessage>);
+
+/// The messages queued while handling a server function, provided in its context.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FlashOutbox(Rc<RefCell<Vec<FlashMessage>>>);
+
+/// The flash messages of the current render.
+pub fn use_flash(cx: Scope) -> Vec<FlashMessage> {
+    use_context::<Flash>(cx).unwrap_or_default().0
+}
+
+/// Queues `text` as a success message for the next page.
+pub fn success(cx: Scope, text: impl Into<String>) {
+    push(cx, FlashLevel::Success, text.into());
+}
+
+/// Queues `text` as an informational message for the next page.
+pub fn info(cx: Scope, text: impl Into<String>) {
+    push(cx, FlashLevel::Info, text.into());
+}
+
+/// Queues `text` as a warning for the next page.
+pub fn warning(cx: Scope, text: impl Into<String>) {
+    push(cx, FlashLevel::Warning, text.into());
+}
+
+/// Queues `text` as an error message for the next page.
+pub fn error(cx: Scope, text: impl Into<String>) {
+    push(cx, FlashLevel::Error, text.into());
+}
+
+fn push(cx: Scope, level: FlashLevel, text: String) {
+    match use_context::<FlashOutbox>(cx) {
+        Some(outbox) => outbox.0.borrow_mut().push(FlashMessage { level, text }),
+        None => tracing::warn!(
+            "flash message dropped: only server functions can set them, and only with WorkerRouterData::flash"
+        ),
+    }
+}
+
+/// Seals the messages queued in the context of `cx` into the cookie of its [ResponseOptions].
+pub(crate) async fn commit(cx: Scope, sealer: &Sealer, env: &worker::Env) -> worker::Result<()> {
+    let Some(outbox) = use_context::<FlashOutbox>(cx) else {
+        return Ok(());
+    };
+    let messages = std::mem::take(&mut *outbox.0.borrow_mut());
+    if messages.is_empty() {
+        return Ok(());
+    }
+
+    let sealed = sealer.seal(env, &serde_json::to_string(&messages)?).await?;
+    if let Some(mut res_options) = use_context::<ResponseOptions>(cx) {
+        res_options.append_header(
+            "Set-Cookie",
+            &format!(
+                "{COOKIE_NAME}={sealed}; Path=/; Max-Age={MAX_AGE}; HttpOnly; Secure; SameSite=Lax"
+            ),
+        )?;
+    }
+    Ok(())
+}
+
+/// Takes the messages out of the cookie of `req`, clearing it. A page showing messages is only meant
+/// for this visitor, so it is marked as uncacheable.
+pub(crate) async fn take(
+    sealer: &Sealer,
+    env: &worker::Env,
+    req: &RequestParts,
+    res_options: &mut ResponseOptions,
+) -> worker::Result<Flash> {
+    let Some(sealed) = cookie(&req.headers, COOKIE_NAME) else {
+        return Ok(Flash::default());
+    };
+
+    res_options.append_header(
+        "Set-Cookie",
+        &format!("{COOKIE_NAME}=; Path=/; Max-Age=0; HttpOnly; Secure; SameSite=Lax"),
+    )?;
+    res_options.insert_header("Cache-Control", "private, no-store")?;
+
+    let messages = sealer
+        .unseal(env, &sealed)
+        .await?
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default();
+    Ok(Flash(messages))
+}
@@ -0,0 +1,178 @@
+//! Re-populating forms after a failed validation, for forms that also work without JavaScript.
+//!
+//! When validation fails, a server function hands the submitted values and the errors per field to
+//! [reject]. Like [flash messages](crate::flash), they are sealed into a cookie, and the page the form
+//! is redirected back to reads them with [use_form_state] to fill the inputs and show the errors:
+//!
+//! ```ignore
+//! #[server(SignUp, "/api")]
+//! pub async fn sign_up(cx: Scope, email: String, name: String) -> Result<(), ServerFnError> {
+//!     let mut errors = FieldErrors::new();
+//!     if !email.contains('@') {
+//!         errors.add("email", "Enter a valid email address");
+//!     }
+//!     if !errors.is_empty() {
+//!         forms::reject(cx, "sign-up", &[("email", &email), ("name", &name)], errors);
+//!         return Ok(());
+//!     }
+//!     // ...
+//! }
+//!
+//! #[component]
+//! fn SignUpForm(cx: Scope) -> impl IntoView {
+//!     let state = use_form_state(cx, "sign-up").unwrap_or_default();
+//!     view! { cx,
+//!         <ActionForm action=sign_up>
+//!             <input name="email" value=state.value("email").unwrap_or_default()/>
+//!             <p class="error">{state.error("email").map(str::to_string)}</p>
+//!             // ...
+//!         </ActionForm>
+//!     }
+//! }
+//! ```
+//!
+//! This uses the sealer of [WorkerRouterData::flash](crate::WorkerRouterData::flash). Fields whose name
+//! contains `password` are never stored, and the values are dropped (keeping the errors) if they don't
+//! fit in a cookie.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use leptos::{use_context, Scope};
+use serde::{Deserialize, Serialize};
+
+use crate::seal::Sealer;
+use crate::util::cookie;
+use crate::{RequestParts, ResponseOptions};
+
+pub(crate) const COOKIE_NAME: &str = "__form";
+/// How long a rejected submission is kept, in seconds.
+const MAX_AGE: u64 = 300;
+/// Browsers drop cookies over 4 KB, with some room left for the name and attributes.
+const MAX_SEALED_LEN: usize = 3800;
+
+/// Error messages by field name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldErrors(HashMap<String, String>);
+
+impl FieldErrors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the error of `field`, replacing an earlier one.
+    pub fn add(&mut self, field: &str, message: impl Into<String>) -> &mut Self {
+        self.0.insert(field.to_string(), message.into());
+        self
+    }
+
+    pub fn get(&self, field: &str) -> Option<&str> {
+        self.0.get(field).map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// A rejected submission of a form.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FormState {
+    pub form: String,
+    pub values: HashMap<String, String>,
+    pub errors: FieldErrors,
+}
+
+impl FormState {
+    /// The submitted value of `field`.
+    pub fn value(&self, field: &str) -> Option<String> {
+        self.values.get(field).cloned()
+    }
+
+    pub fn error(&self, field: &str) -> Option<&str> {
+        self.errors.get(field)
+    }
+}
+
+/// The submission taken from the request, provided in the context of every render.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RejectedForm(Option<FormState>);
+
+/// The submission rejected while handling a server function, provided in its context.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FormOutbox(Rc<RefCell<Option<FormState>>>);
+
+/// The rejected submission of the form `form` for the current render, if there is one.
+pub fn use_form_state(cx: Scope, form: &str) -> Option<FormState> {
+    use_context::<RejectedForm>(cx)
+        .and_then(|rejected| rejected.0)
+        .filter(|state| state.form == form)
+}
+
+/// Sends `values` and `errors` back to the page of the form `form`. Only works in server functions.
+pub fn reject(cx: Scope, form: &str, values: &[(&str, &str)], errors: FieldErrors) {
+    let values = values
+        .iter()
+        .filter(|(name, _)| !name.to_ascii_lowercase().contains("password"))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    let state = FormState {
+        form: form.to_string(),
+        values,
+        errors,
+    };
+
+    match use_context::<FormOutbox>(cx) {
+        Some(outbox) => *outbox.0.borrow_mut() = Some(state),
+        None => tracing::warn!(
+            "rejected form dropped: only server functions can reject forms, and only with WorkerRouterData::flash"
+        ),
+    }
+}
+
+/// Seals the submission rejected in the context of `cx` into the cookie of its [ResponseOptions].
+pub(crate) async fn commit(cx: Scope, sealer: &Sealer, env: &worker::Env) -> worker::Result<()> {
+    let Some(mut state) = use_context::<FormOutbox>(cx).and_then(|outbox| outbox.0.take()) else {
+        return Ok(());
+    };
+
+    let mut sealed = sealer.seal(env, &serde_json::to_string(&state)?).await?;
+    if sealed.len() > MAX_SEALED_LEN {
+        state.values.clear();
+        sealed = sealer.seal(env, &serde_json::to_string(&state)?).await?;
+    }
+    if let Some(mut res_options) = use_context::<ResponseOptions>(cx) {
+        res_options.append_header(
+            "Set-Cookie",
+            &format!(
+                "{COOKIE_NAME}={sealed}; Path=/; Max-Age={MAX_AGE}; HttpOnly; Secure; SameSite=Lax"
+            ),
+        )?;
+    }
+    Ok(())
+}
+
+/// Takes the rejected submission out of the cookie of `req`, clearing it.
+pub(crate) async fn take(
+    sealer: &Sealer,
+    env: &worker::Env,
+    req: &RequestParts,
+    res_options: &mut ResponseOptions,
+) -> worker::Result<RejectedForm> {
+    let Some(sealed) = cookie(&req.headers, COOKIE_NAME) else {
+        return Ok(RejectedForm::default());
+    };
+
+    res_options.append_header(
+        "Set-Cookie",
+        &format!("{COOKIE_NAME}=; Path=/; Max-Age=0; HttpOnly; Secure; SameSite=Lax"),
+    )?;
+    res_options.insert_header("Cache-Control", "private, no-store")?;
+
+    let state = sealer
+        .unseal(env, &sealed)
+        .await?
+        .and_then(|json| serde_json::from_str(&json).ok());
+    Ok(RejectedForm(state))
+}
@@ -0,0 +1,98 @@
+//! Serves an [async-graphql](async_graphql) schema next to the Leptos app.
+//!
+//! Resolvers run inside a Leptos runtime with the same contexts that server functions get, e.g.
+//! [RequestParts](crate::RequestParts) filtered by the
+//! [request header policy](crate::RequestHeaderPolicy), [ResponseOptions](crate::ResponseOptions),
+//! the bindings of [use_env](crate::use_env) and the registered [isolate states](crate::IsolateState).
+//! The [Scope](leptos::Scope) of that runtime is inserted into the GraphQL context data, because the
+//! worker types themselves are not `Send`:
+//!
+//! ```ignore
+//! #[Object]
+//! impl Query {
+//!     async fn user_agent(&self, ctx: &async_graphql::Context<'_>) -> Option<String> {
+//!         let cx = *ctx.data_unchecked::<leptos::Scope>();
+//!         let req = leptos::use_context::<leptos_cloudflare::RequestParts>(cx)?;
+//!         req.headers.get("User-Agent").ok().flatten()
+//!     }
+//! }
+//! ```
+
+use async_graphql::http::{parse_query_string, playground_source, GraphQLPlaygroundConfig};
+use async_graphql::{BatchRequest, ObjectType, Schema, SubscriptionType};
+use leptos::{use_context, IntoView};
+
+use crate::{
+    commit_request_contexts, generate_request_parts, locale, provide_request_contexts,
+    ResponseOptions, ScopedRuntime, WorkerRouterData,
+};
+
+/// Executes a GraphQL request against `schema`.
+///
+/// `GET` requests read the query from the query string, `POST` requests accept a single or a batched
+/// JSON request body. Mount it from a route handler, since the schema cannot be part of the router data:
+///
+/// ```ignore
+/// static SCHEMA: IsolateState<AppSchema> = IsolateState::new(build_schema);
+///
+/// router.on_async("/graphql", |req, ctx| async move {
+///     leptos_cloudflare::graphql::handle_graphql(&SCHEMA.get(), req, ctx).await
+/// })
+/// ```
+#[tracing::instrument(level = "trace", fields(error), skip_all)]
+pub async fn handle_graphql<Query, Mutation, Subscription, IV, AppFn>(
+    schema: &Schema<Query, Mutation, Subscription>,
+    mut req: worker::Request,
+    ctx: worker::RouteContext<WorkerRouterData<IV, AppFn>>,
+) -> worker::Result<worker::Response>
+where
+    Query: ObjectType + 'static,
+    Mutation: ObjectType + 'static,
+    Subscription: SubscriptionType + 'static,
+    IV: IntoView + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+{
+    let time = locale::RequestTime::new(Some(req.cf().timezone_name()));
+    let req_parts = generate_request_parts(&mut req).await?;
+
+    let batch = match req_parts.method {
+        worker::Method::Get => {
+            parse_query_string(req_parts.url.query().unwrap_or("")).map(BatchRequest::Single)
+        }
+        worker::Method::Post => serde_json::from_slice::<BatchRequest>(&req_parts.body)
+            .map_err(|err| async_graphql::ParseRequestError::InvalidRequest(Box::new(err))),
+        _ => return worker::Response::error("Method not allowed", 405),
+    };
+    let batch = match batch {
+        Ok(batch) => batch,
+        Err(err) => return worker::Response::error(err.to_string(), 400),
+    };
+
+    let runtime = ScopedRuntime::new();
+    let cx = runtime.cx;
+    provide_request_contexts(cx, &ctx.data, &ctx.env, &req_parts, time).await?;
+
+    let response = schema.execute_batch(batch.data(cx)).await;
+    commit_request_contexts(cx, &ctx.data, &ctx.env).await?;
+    let res_options = use_context::<ResponseOptions>(cx).unwrap_or_default();
+    drop(runtime);
+
+    let status = res_options.status().unwrap_or(200);
+    let mut res = worker::Response::from_json(&response)?;
+
+    for (key, value) in res_options.headers.into_iter() {
+        res.headers_mut().append(&key, &value)?;
+    }
+    for (name, value) in response.http_headers_iter() {
+        if let Ok(value) = value.to_str() {
+            res.headers_mut().append(name.as_str(), value)?;
+        }
+    }
+
+    Ok(res.with_status(status))
+}
+
+/// Serves the GraphQL Playground IDE, pointed at `endpoint`.
+pub fn graphql_playground(endpoint: &str) -> worker::Result<worker::Response> {
+    worker::Response::from_html(playground_source(GraphQLPlaygroundConfig::new(endpoint)))
+}
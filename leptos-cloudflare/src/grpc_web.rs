@@ -0,0 +1,404 @@
+//! Serves gRPC-web methods next to the Leptos app.
+//!
+//! Methods are registered by their path, `/<package>.<Service>/<Method>`, with handlers that take and
+//! return [prost] messages, e.g. those generated by `prost-build` or `tonic-build` for the client.
+//! Handlers run inside a Leptos runtime with the same contexts that server functions get, so they can
+//! use [use_env](crate::use_env), [use_dep](crate::use_dep) or the API key of the caller:
+//!
+//! ```ignore
+//! async fn say_hello(cx: Scope, request: HelloRequest) -> Result<HelloReply, Status> {
+//!     let greeting = use_dep::<Greeter>(cx)?.greet(&request.name).await?;
+//!     Ok(HelloReply { message: greeting })
+//! }
+//!
+//! async fn watch_prices(cx: Scope, request: WatchRequest) -> Result<PriceStream, Status> {
+//!     Ok(use_dep::<Prices>(cx)?.subscribe(&request.symbol).map(Ok).boxed_local())
+//! }
+//!
+//! router
+//!     .leptos_routes(routes)
+//!     .grpc_unary("/helloworld.Greeter/SayHello", say_hello)
+//!     .grpc_server_streaming("/prices.Prices/Watch", watch_prices)
+//! ```
+//!
+//! Both `application/grpc-web` and the base64 `application/grpc-web-text` used by browsers without
+//! binary streams are accepted, and the response uses the format of the request. gRPC-web has no
+//! client streaming, and compressed messages are refused with `UNIMPLEMENTED`.
+//!
+//! Errors are sent as trailers, a final frame of the body, so a stream that fails halfway still ends
+//! with its status. [LeptosCloudflareError]s convert into the closest [Code], so `?` works on the
+//! helpers of this crate.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::{ready, Future};
+use std::rc::Rc;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures::future::LocalBoxFuture;
+use futures::stream::LocalBoxStream;
+use futures::{Stream, StreamExt};
+use http::StatusCode;
+use leptos::{create_runtime, provide_context, raw_scope_and_disposer, IntoView, Scope};
+use prost::Message;
+use thiserror::Error;
+
+use crate::{
+    app_env, background, di, generate_request_parts, isolate, request_headers, streaming,
+    LeptosCloudflareError, ResponseOptions, WorkerRouterData,
+};
+
+const DATA_FRAME: u8 = 0x00;
+const TRAILERS_FRAME: u8 = 0x80;
+const COMPRESSED_FLAG: u8 = 0x01;
+
+/// The status codes of gRPC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Code {
+    Ok = 0,
+    Cancelled = 1,
+    Unknown = 2,
+    InvalidArgument = 3,
+    DeadlineExceeded = 4,
+    NotFound = 5,
+    AlreadyExists = 6,
+    PermissionDenied = 7,
+    ResourceExhausted = 8,
+    FailedPrecondition = 9,
+    Aborted = 10,
+    OutOfRange = 11,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+    DataLoss = 15,
+    Unauthenticated = 16,
+}
+
+/// The outcome of a call, sent to the client as the `grpc-status` and `grpc-message` trailers.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{code:?}: {message}")]
+pub struct Status {
+    pub code: Code,
+    pub message: String,
+}
+
+impl Status {
+    pub fn new(code: Code, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn ok() -> Self {
+        Self::new(Code::Ok, "")
+    }
+
+    /// The trailers block, with the message percent-encoded as the protocol requires.
+    fn trailers(&self) -> Vec<u8> {
+        let mut message = String::new();
+        for byte in self.message.bytes() {
+            if (0x20..=0x7e).contains(&byte) && byte != b'%' {
+                message.push(byte as char);
+            } else {
+                message.push_str(&format!("%{byte:02X}"));
+            }
+        }
+        format!(
+            "grpc-status:{}\r\ngrpc-message:{message}\r\n",
+            self.code as i32
+        )
+        .into_bytes()
+    }
+}
+
+impl From<LeptosCloudflareError> for Status {
+    fn from(err: LeptosCloudflareError) -> Self {
+        let code = match &err {
+            LeptosCloudflareError::BadRequest(_) => Code::InvalidArgument,
+            LeptosCloudflareError::Unauthorized => Code::Unauthenticated,
+            LeptosCloudflareError::Forbidden => Code::PermissionDenied,
+            LeptosCloudflareError::NotFound => Code::NotFound,
+            LeptosCloudflareError::Conflict(_) => Code::Aborted,
+            LeptosCloudflareError::TooManyRequests => Code::ResourceExhausted,
+            LeptosCloudflareError::Internal(_) | LeptosCloudflareError::Worker(_) => {
+                // The message may reveal internals
+                tracing::error!("gRPC-web handler failed: {err}");
+                return Status::new(Code::Internal, "Internal error");
+            }
+        };
+        Status::new(code, err.to_string())
+    }
+}
+
+impl From<worker::Error> for Status {
+    fn from(err: worker::Error) -> Self {
+        LeptosCloudflareError::from(err).into()
+    }
+}
+
+/// The wire format of a request, which the response uses too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Binary,
+    Text,
+}
+
+impl Format {
+    fn of(content_type: &str) -> Option<Self> {
+        let media_type = content_type.split(';').next().unwrap_or_default().trim();
+        match media_type {
+            "application/grpc-web" | "application/grpc-web+proto" => Some(Format::Binary),
+            "application/grpc-web-text" | "application/grpc-web-text+proto" => Some(Format::Text),
+            _ => None,
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Binary => "application/grpc-web+proto",
+            Format::Text => "application/grpc-web-text+proto",
+        }
+    }
+
+    fn decode(self, body: &[u8]) -> Result<Vec<u8>, Status> {
+        match self {
+            Format::Binary => Ok(body.to_vec()),
+            Format::Text => STANDARD.decode(body).map_err(|err| {
+                Status::new(Code::InvalidArgument, format!("Invalid base64 body: {err}"))
+            }),
+        }
+    }
+
+    /// Encodes one frame. Text frames are base64-encoded one by one, which clients concatenate.
+    fn encode(self, frame: Vec<u8>) -> Vec<u8> {
+        match self {
+            Format::Binary => frame,
+            Format::Text => STANDARD.encode(frame).into_bytes(),
+        }
+    }
+}
+
+fn frame(flag: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 5);
+    frame.push(flag);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// The message of a unary or server streaming request, which has exactly one.
+fn request_message(body: &[u8]) -> Result<&[u8], Status> {
+    let Some((&[flag, a, b, c, d], payload)) = body.split_first_chunk::<5>() else {
+        return Err(Status::new(
+            Code::InvalidArgument,
+            "Missing request message",
+        ));
+    };
+    if flag & COMPRESSED_FLAG != 0 {
+        return Err(Status::new(
+            Code::Unimplemented,
+            "Compressed messages are not supported",
+        ));
+    }
+    let length = u32::from_be_bytes([a, b, c, d]) as usize;
+    payload
+        .get(..length)
+        .ok_or_else(|| Status::new(Code::InvalidArgument, "Truncated request message"))
+}
+
+type Replies = LocalBoxStream<'static, Result<Vec<u8>, Status>>;
+
+type BoxedGrpcHandler =
+    Rc<dyn Fn(Scope, &[u8]) -> LocalBoxFuture<'static, Result<Replies, Status>>>;
+
+thread_local! {
+    // Method path -> handler, since the router only accepts function pointers
+    static GRPC_METHODS: RefCell<HashMap<String, BoxedGrpcHandler>> = RefCell::new(HashMap::new());
+}
+
+pub trait GrpcWebRoutes {
+    /// Serves the unary method at `path`, e.g. `/helloworld.Greeter/SayHello`.
+    fn grpc_unary<Req, Res, H, Fut>(self, path: &str, handler: H) -> Self
+    where
+        Req: Message + Default + 'static,
+        Res: Message + 'static,
+        H: Fn(Scope, Req) -> Fut + 'static,
+        Fut: Future<Output = Result<Res, Status>> + 'static;
+
+    /// Serves the server streaming method at `path`. Each item of the stream is sent as it is
+    /// produced, and an error ends the call with its status.
+    fn grpc_server_streaming<Req, Res, H, Fut, S>(self, path: &str, handler: H) -> Self
+    where
+        Req: Message + Default + 'static,
+        Res: Message + 'static,
+        H: Fn(Scope, Req) -> Fut + 'static,
+        Fut: Future<Output = Result<S, Status>> + 'static,
+        S: Stream<Item = Result<Res, Status>> + 'static;
+}
+
+impl<'a, IV, AppFn> GrpcWebRoutes for worker::Router<'a, WorkerRouterData<IV, AppFn>>
+where
+    IV: IntoView + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+{
+    fn grpc_unary<Req, Res, H, Fut>(self, path: &str, handler: H) -> Self
+    where
+        Req: Message + Default + 'static,
+        Res: Message + 'static,
+        H: Fn(Scope, Req) -> Fut + 'static,
+        Fut: Future<Output = Result<Res, Status>> + 'static,
+    {
+        self.grpc_server_streaming(path, move |cx, request: Req| {
+            let reply = handler(cx, request);
+            async move {
+                let reply = reply.await?;
+                Ok(futures::stream::once(ready(Ok::<_, Status>(reply))))
+            }
+        })
+    }
+
+    fn grpc_server_streaming<Req, Res, H, Fut, S>(self, path: &str, handler: H) -> Self
+    where
+        Req: Message + Default + 'static,
+        Res: Message + 'static,
+        H: Fn(Scope, Req) -> Fut + 'static,
+        Fut: Future<Output = Result<S, Status>> + 'static,
+        S: Stream<Item = Result<Res, Status>> + 'static,
+    {
+        let handler: BoxedGrpcHandler = Rc::new(move |cx, message| {
+            let request = match Req::decode(message) {
+                Ok(request) => request,
+                Err(err) => {
+                    let status =
+                        Status::new(Code::InvalidArgument, format!("Invalid request: {err}"));
+                    return Box::pin(ready(Err(status)));
+                }
+            };
+            let replies = handler(cx, request);
+            Box::pin(async move {
+                let replies = replies.await?;
+                let replies: Replies = replies
+                    .map(|reply| reply.map(|reply| reply.encode_to_vec()))
+                    .boxed_local();
+                Ok(replies)
+            })
+        });
+        GRPC_METHODS.with(|methods| methods.borrow_mut().insert(path.to_string(), handler));
+
+        self.post_async(path, dispatch_grpc_web::<IV, AppFn>)
+    }
+}
+
+/// A response that only holds the trailers, for calls that fail before they start.
+fn status_response(format: Format, status: &Status) -> worker::Result<worker::Response> {
+    let headers = worker::Headers::new();
+    headers.set("Content-Type", format.content_type())?;
+    let body = format.encode(frame(TRAILERS_FRAME, &status.trailers()));
+    Ok(worker::Response::from_bytes(body)?.with_headers(headers))
+}
+
+async fn dispatch_grpc_web<IV, AppFn>(
+    mut req: worker::Request,
+    ctx: worker::RouteContext<WorkerRouterData<IV, AppFn>>,
+) -> worker::Result<worker::Response>
+where
+    IV: IntoView + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+{
+    let path = req.path();
+    let Some(handler) = GRPC_METHODS.with(|methods| methods.borrow().get(&path).cloned()) else {
+        return ctx
+            .data
+            .error_renderer
+            .render_for(&req, StatusCode::NOT_FOUND, "Not found");
+    };
+    let content_type = req.headers().get("Content-Type")?.unwrap_or_default();
+    let Some(format) = Format::of(&content_type) else {
+        return ctx.data.error_renderer.render_for(
+            &req,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Expected a gRPC-web request",
+        );
+    };
+
+    #[cfg(feature = "api-keys")]
+    let api_key = match &ctx.data.api_keys {
+        Some(api_keys) => {
+            let scope = api_keys.required_scope(&path);
+            match api_keys.authenticate(&ctx.env, req.headers(), scope).await {
+                Ok(None) if scope.is_some() => {
+                    return status_response(format, &LeptosCloudflareError::Unauthorized.into())
+                }
+                Ok(api_key) => api_key,
+                Err(err) => return status_response(format, &err.into()),
+            }
+        }
+        None => None,
+    };
+
+    let req_parts = generate_request_parts(&mut req).await?;
+    let message = format
+        .decode(&req_parts.body)
+        .and_then(|body| request_message(&body).map(<[u8]>::to_vec));
+
+    let runtime = create_runtime();
+    let (cx, disposer) = raw_scope_and_disposer(runtime);
+    provide_context(cx, ctx.data.request_headers.apply(&req_parts));
+    provide_context(cx, request_headers::FullRequestParts(req_parts.clone()));
+    isolate::provide_isolate_states(cx, &ctx.data.isolate_states);
+    provide_context(cx, app_env::RequestEnv(ctx.env.clone()));
+    provide_context(cx, background::WaitUntil(ctx.data.context.clone()));
+    provide_context(
+        cx,
+        di::DepContainer::new(ctx.data.deps.clone(), ctx.env.clone(), req_parts),
+    );
+    #[cfg(feature = "api-keys")]
+    if let Some(api_key) = api_key {
+        provide_context(cx, api_key);
+    }
+    let res_options = ResponseOptions::default();
+    provide_context(cx, res_options.clone());
+
+    let replies = match message {
+        Ok(message) => handler(cx, &message).await,
+        Err(status) => Err(status),
+    };
+    let replies =
+        replies.unwrap_or_else(|status| futures::stream::once(ready(Err(status))).boxed_local());
+
+    // The first error ends the call, and its status is sent in the trailers
+    let status = Rc::new(RefCell::new(Status::ok()));
+    let messages = replies
+        .take_while({
+            let status = status.clone();
+            move |reply| {
+                if let Err(err) = reply {
+                    *status.borrow_mut() = err.clone();
+                }
+                ready(reply.is_ok())
+            }
+        })
+        .filter_map(|reply| ready(reply.ok()))
+        .map(|message| frame(DATA_FRAME, &message));
+    let trailers = futures::stream::once(async move {
+        disposer.dispose();
+        runtime.dispose();
+        let trailers = status.borrow().trailers();
+        frame(TRAILERS_FRAME, &trailers)
+    });
+    let body = messages
+        .chain(trailers)
+        .map(move |frame| worker::Result::Ok(format.encode(frame)));
+
+    let mut response = streaming::response_from_stream(body, streaming::DEFAULT_STREAM_BUFFER)?;
+    response
+        .headers_mut()
+        .set("Content-Type", format.content_type())?;
+    // Headers set by the handler, e.g. cookies, as for server functions
+    for (key, value) in res_options.headers.into_iter() {
+        response.headers_mut().append(&key, &value)?;
+    }
+    Ok(response)
+}
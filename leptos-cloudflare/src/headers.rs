@@ -0,0 +1,172 @@
+//! Response headers by path, like the `_headers` file of Cloudflare Pages.
+//!
+//! Each rule is a path pattern followed by indented `Name: value` lines. `! Name` removes a header that
+//! a handler set:
+//!
+//! ```text
+//! /*
+//!   X-Frame-Options: DENY
+//!   Referrer-Policy: strict-origin-when-cross-origin
+//!
+//! /pkg/*
+//!   Cache-Control: public, max-age=31536000, immutable
+//!
+//! /embed/:id
+//!   ! X-Frame-Options
+//! ```
+//!
+//! Patterns use `:param` segments and a trailing `*`, and every matching rule applies, in order. The rules
+//! can also be built in Rust with [rule](HeaderRules::rule). They are applied to the response of the
+//! router, so they cover rendered pages, assets and API routes alike:
+//!
+//! ```ignore
+//! static HEADERS: IsolateState<HeaderRules> =
+//!     IsolateState::new(|| HeaderRules::parse(include_str!("../_headers")));
+//!
+//! #[worker::event(fetch)]
+//! pub async fn main(req: Request, env: Env, _ctx: Context) -> worker::Result<Response> {
+//!     let path = req.path();
+//!     let response = router.run(req, env).await?;
+//!     HEADERS.get().apply(&path, response)
+//! }
+//! ```
+
+/// The headers of the responses for paths matching `pattern`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderRule {
+    /// The path pattern, in the syntax of [path_matches](crate::route_config::path_matches).
+    pub pattern: String,
+    pub set: Vec<(String, String)>,
+    pub remove: Vec<String>,
+}
+
+/// A table of [HeaderRule]s. See the [module documentation](self).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderRules {
+    rules: Vec<HeaderRule>,
+}
+
+impl HeaderRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a `_headers` file. Invalid lines are logged and skipped.
+    pub fn parse(file: &str) -> Self {
+        let mut rules: Vec<HeaderRule> = Vec::new();
+
+        for (number, line) in file.lines().enumerate() {
+            let indented = line.starts_with(char::is_whitespace);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if !indented {
+                if line.starts_with('/') {
+                    rules.push(HeaderRule {
+                        pattern: pattern(line),
+                        ..HeaderRule::default()
+                    });
+                } else {
+                    tracing::warn!(
+                        "skipping invalid header rule on line {}: {line}",
+                        number + 1
+                    );
+                }
+                continue;
+            }
+
+            let Some(rule) = rules.last_mut() else {
+                tracing::warn!(
+                    "skipping header without a path on line {}: {line}",
+                    number + 1
+                );
+                continue;
+            };
+            if let Some(name) = line.strip_prefix('!') {
+                rule.remove.push(name.trim().to_string());
+            } else if let Some((name, value)) = line.split_once(':') {
+                rule.set
+                    .push((name.trim().to_string(), value.trim().to_string()));
+            } else {
+                tracing::warn!("skipping invalid header on line {}: {line}", number + 1);
+            }
+        }
+
+        Self { rules }
+    }
+
+    /// Adds a rule setting `headers` on the responses for paths matching `pattern`.
+    pub fn rule(mut self, pattern: &str, headers: &[(&str, &str)]) -> Self {
+        self.rules.push(HeaderRule {
+            pattern: self::pattern(pattern),
+            set: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            remove: Vec::new(),
+        });
+        self
+    }
+
+    /// Adds a rule removing `headers` from the responses for paths matching `pattern`.
+    pub fn remove(mut self, pattern: &str, headers: &[&str]) -> Self {
+        self.rules.push(HeaderRule {
+            pattern: self::pattern(pattern),
+            set: Vec::new(),
+            remove: headers.iter().map(|name| name.to_string()).collect(),
+        });
+        self
+    }
+
+    /// Applies the rules matching `path` to `response`. A header set by several rules gets their values
+    /// joined with commas, like on Pages.
+    pub fn apply(
+        &self,
+        path: &str,
+        mut response: worker::Response,
+    ) -> worker::Result<worker::Response> {
+        let matching: Vec<&HeaderRule> = self
+            .rules
+            .iter()
+            .filter(|rule| crate::route_config::path_matches(&rule.pattern, path))
+            .collect();
+        if matching.is_empty() {
+            return Ok(response);
+        }
+
+        let mut set: Vec<(&str, String)> = Vec::new();
+        for rule in &matching {
+            for (name, value) in &rule.set {
+                match set
+                    .iter_mut()
+                    .find(|(existing, _)| existing.eq_ignore_ascii_case(name))
+                {
+                    Some((_, existing)) => {
+                        existing.push_str(", ");
+                        existing.push_str(value);
+                    }
+                    None => set.push((name, value.clone())),
+                }
+            }
+        }
+
+        let headers = response.headers_mut();
+        for (name, value) in set {
+            headers.set(name, &value)?;
+        }
+        for name in matching.iter().flat_map(|rule| &rule.remove) {
+            headers.delete(name)?;
+        }
+        Ok(response)
+    }
+}
+
+/// Turns a trailing `*` into a named wildcard segment.
+fn pattern(path: &str) -> String {
+    match path.strip_suffix('*') {
+        Some(prefix) => format!("{prefix}*splat"),
+        None => path.to_string(),
+    }
+}
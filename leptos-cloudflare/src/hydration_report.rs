@@ -0,0 +1,126 @@
+//! Reports of hydration mismatches from the browsers of real visitors.
+//!
+//! When the HTML rendered on the server and the view built by the client diverge, Leptos logs a warning
+//! in the browser, where nobody sees it in production. [script] is a small inline script, added to the
+//! document with [ShellHooks](crate::ShellHooks), that forwards those warnings to the route mounted by
+//! [hydration_report_route](HydrationReportRoutes::hydration_report_route), which logs them and counts
+//! them per page and message:
+//!
+//! ```ignore
+//! const SHELL: ShellHooks = ShellHooks {
+//!     head: Some(hydration_report::script),
+//!     ..ShellHooks::NONE
+//! };
+//!
+//! router.hydration_report_route()
+//! ```
+//!
+//! The counts of the isolate are available with [hydration_reports]. The route takes no more than
+//! [REPORTS_PER_MINUTE] reports per client, and the isolate keeps the counts of the reports that
+//! were most recently received, up to [MAX_REPORTS_BYTES] of paths and messages.
+
+use http::StatusCode;
+use leptos::Scope;
+use serde::{Deserialize, Serialize};
+
+use crate::api::{ApiRequest, ApiRoutes};
+use crate::memo_cache::{memo_cache, MemoCache};
+use crate::util::{allow_per_minute, client_windows, ClientWindows};
+use crate::LeptosCloudflareError;
+
+/// The path the script posts reports to.
+pub const HYDRATION_REPORT_PATH: &str = "/__leptos/hydration-report";
+
+/// Reports larger than this are rejected.
+const MAX_REPORT_BYTES: usize = 8 * 1024;
+
+/// How many reports a client may send per minute.
+pub const REPORTS_PER_MINUTE: u32 = 10;
+
+/// The total size of the paths and messages whose counts are kept.
+pub const MAX_REPORTS_BYTES: usize = 1024 * 1024;
+
+/// A mismatch reported by a browser.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HydrationReport {
+    /// The path of the page.
+    pub path: String,
+    pub message: String,
+}
+
+thread_local! {
+    static REPORTS: MemoCache<(String, String), u32> = memo_cache(MAX_REPORTS_BYTES)
+        .weigher(|(path, message), _| path.len() + message.len());
+    static RATE_LIMITS: ClientWindows = client_windows();
+}
+
+/// The reports received by this isolate, with how often each was received.
+pub fn hydration_reports() -> Vec<(HydrationReport, u32)> {
+    REPORTS.with(|reports| {
+        reports
+            .entries()
+            .into_iter()
+            .map(|((path, message), count)| (HydrationReport { path, message }, count))
+            .collect()
+    })
+}
+
+/// The script that reports hydration warnings, for [ShellHooks::head](crate::ShellHooks::head).
+/// At most five reports are sent per page view.
+pub fn script(cx: Scope) -> String {
+    #[cfg(feature = "nonce")]
+    let nonce = leptos::nonce::use_nonce(cx)
+        .map(|nonce| format!(r#" nonce="{nonce}""#))
+        .unwrap_or_default();
+    #[cfg(not(feature = "nonce"))]
+    let nonce = {
+        let _ = cx;
+        String::new()
+    };
+
+    format!(
+        r#"<script{nonce}>(function(){{var sent=0;["warn","error"].forEach(function(level){{var original=console[level];console[level]=function(){{var message=Array.prototype.join.call(arguments," ");if(sent<5&&/hydrat/i.test(message)){{sent++;navigator.sendBeacon("{HYDRATION_REPORT_PATH}",JSON.stringify({{path:location.pathname,message:message.slice(0,2000)}}));}}return original.apply(console,arguments);}};}});}})();</script>"#
+    )
+}
+
+pub trait HydrationReportRoutes {
+    /// Mounts `POST /__leptos/hydration-report`, which receives the reports of [script].
+    fn hydration_report_route(self) -> Self;
+}
+
+impl<'a, D: 'static> HydrationReportRoutes for worker::Router<'a, D> {
+    fn hydration_report_route(self) -> Self {
+        self.api_route(
+            worker::Method::Post,
+            HYDRATION_REPORT_PATH,
+            |req: ApiRequest| async move {
+                if req.parts.body.len() > MAX_REPORT_BYTES {
+                    return Err(LeptosCloudflareError::BadRequest(
+                        "Report too large".to_string(),
+                    ));
+                }
+                let client = req
+                    .parts
+                    .headers
+                    .get("CF-Connecting-IP")?
+                    .unwrap_or_default();
+                let allowed = RATE_LIMITS
+                    .with(|limits| allow_per_minute(limits, &client, REPORTS_PER_MINUTE));
+                if !allowed {
+                    return Ok((StatusCode::TOO_MANY_REQUESTS, ()));
+                }
+                let report = serde_json::from_slice::<HydrationReport>(&req.parts.body)
+                    .map_err(|err| LeptosCloudflareError::BadRequest(err.to_string()))?;
+
+                tracing::warn!("hydration mismatch on {}: {}", report.path, report.message);
+                REPORTS.with(|reports| {
+                    let key = (report.path, report.message);
+                    let count = reports.get(&key).unwrap_or_default();
+                    reports.insert(key, count + 1);
+                });
+
+                Ok((StatusCode::NO_CONTENT, ()))
+            },
+        )
+    }
+}
@@ -0,0 +1,151 @@
+//! External Postgres databases through [Hyperdrive](https://developers.cloudflare.com/hyperdrive/).
+//!
+//! Hyperdrive pools connections to the database close to it and hands the worker the address of a
+//! local proxy. [use_hyperdrive] reads the binding of the current request, and
+//! [connect](Hyperdrive::connect) opens a [tokio_postgres] client to it over a TCP socket from the
+//! `connect()` API of the runtime:
+//!
+//! ```ignore
+//! #[server(ListOrders, "/api")]
+//! pub async fn list_orders(cx: Scope, customer: i64) -> Result<Vec<Order>, ServerFnError> {
+//!     let client = use_hyperdrive(cx, "DB")?.connect().await?;
+//!     let rows = client
+//!         .query("SELECT id, total FROM orders WHERE customer_id = $1", &[&customer])
+//!         .await
+//!         .map_err(|err| ServerFnError::ServerError(err.to_string()))?;
+//!     Ok(rows.iter().map(|row| Order { id: row.get(0), total: row.get(1) }).collect())
+//! }
+//! ```
+//!
+//! ```toml
+//! [[hyperdrive]]
+//! binding = "DB"
+//! id = "<the id printed by `wrangler hyperdrive create`>"
+//! ```
+//!
+//! Connections are cheap with Hyperdrive, so a client is opened per request and closed when it is
+//! dropped. Other drivers can connect to [host](Hyperdrive::host) and [port](Hyperdrive::port) with the
+//! credentials of the binding, or use [connection_string](Hyperdrive::connection_string).
+
+use leptos::Scope;
+use tokio_postgres::tls::{ChannelBinding, TlsConnect, TlsStream};
+use wasm_bindgen::JsValue;
+use worker::Env;
+
+use crate::socket::{SecureTransport, Socket};
+use crate::{use_env, LeptosCloudflareError};
+
+/// The Hyperdrive configuration bound as `binding`, from the bindings of the current request.
+pub fn use_hyperdrive(cx: Scope, binding: &str) -> Result<Hyperdrive, LeptosCloudflareError> {
+    Ok(Hyperdrive::from_env(&use_env(cx)?, binding)?)
+}
+
+/// A Hyperdrive binding: where to reach the database through Hyperdrive, and the credentials.
+#[derive(Clone)]
+pub struct Hyperdrive {
+    connection_string: String,
+    host: String,
+    port: u16,
+    user: String,
+    password: String,
+    database: String,
+}
+
+impl Hyperdrive {
+    /// The Hyperdrive configuration bound as `binding` in `env`.
+    pub fn from_env(env: &Env, binding: &str) -> worker::Result<Self> {
+        let hyperdrive = js_sys::Reflect::get(env, &JsValue::from_str(binding))?;
+        if hyperdrive.is_undefined() {
+            return Err(worker::Error::RustError(format!(
+                "no Hyperdrive configuration is bound as {binding}"
+            )));
+        }
+        let string = |key: &str| -> worker::Result<String> {
+            Ok(js_sys::Reflect::get(&hyperdrive, &JsValue::from_str(key))?
+                .as_string()
+                .unwrap_or_default())
+        };
+        let port = js_sys::Reflect::get(&hyperdrive, &JsValue::from_str("port"))?
+            .as_f64()
+            .unwrap_or(5432.0) as u16;
+        Ok(Self {
+            connection_string: string("connectionString")?,
+            host: string("host")?,
+            port,
+            user: string("user")?,
+            password: string("password")?,
+            database: string("database")?,
+        })
+    }
+
+    /// A `postgres://` URL with the credentials, for drivers configured with one.
+    pub fn connection_string(&self) -> &str {
+        &self.connection_string
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+
+    pub fn database(&self) -> &str {
+        &self.database
+    }
+
+    /// Opens a Postgres client. The connection is driven in the background until the client is
+    /// dropped.
+    pub async fn connect(&self) -> Result<tokio_postgres::Client, LeptosCloudflareError> {
+        let config = self
+            .connection_string
+            .parse::<tokio_postgres::Config>()
+            .map_err(|err| {
+                LeptosCloudflareError::Internal(format!(
+                    "invalid Hyperdrive connection string: {err}"
+                ))
+            })?;
+        let socket = Socket::connect(&self.host, self.port, SecureTransport::StartTls)?;
+        let (client, connection) = config.connect_raw(socket, StartTls).await.map_err(|err| {
+            LeptosCloudflareError::Internal(format!("failed to connect to Postgres: {err}"))
+        })?;
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Err(err) = connection.await {
+                tracing::error!("Postgres connection failed: {err}");
+            }
+        });
+        Ok(client)
+    }
+}
+
+/// Negotiates TLS with the `startTls()` of the socket, which the runtime encrypts.
+struct StartTls;
+
+impl TlsConnect<Socket> for StartTls {
+    type Stream = Socket;
+    type Error = std::io::Error;
+    type Future = futures::future::Ready<Result<Socket, std::io::Error>>;
+
+    fn connect(self, socket: Socket) -> Self::Future {
+        futures::future::ready(
+            socket
+                .start_tls()
+                .map_err(|err| std::io::Error::other(err.to_string())),
+        )
+    }
+}
+
+impl TlsStream for Socket {
+    fn channel_binding(&self) -> ChannelBinding {
+        ChannelBinding::none()
+    }
+}
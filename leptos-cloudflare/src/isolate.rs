@@ -0,0 +1,183 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Duration;
+
+use futures::future::LocalBoxFuture;
+use leptos::{provide_context, use_context, Scope};
+
+/// A lazily-initialized value shared by every request that is handled by the same Workers isolate.
+///
+/// Cloudflare keeps an isolate alive for an unspecified amount of time and reuses it for many requests,
+/// so anything that is expensive to build but never changes (parsed templates, compiled regexes, static
+/// configuration) can be built once per isolate instead of once per request. There are no guarantees
+/// about how long an isolate lives or how many of them exist at once, so the value must be something
+/// that can be rebuilt at any time.
+///
+/// Declare it as a `static` and register it in
+/// [WorkerRouterData::isolate_states](crate::WorkerRouterData::isolate_states) so that it is provided
+/// into the context of every request:
+///
+/// ```ignore
+/// static TEMPLATES: IsolateState<Templates> = IsolateState::new(Templates::parse);
+///
+/// // inside a component or a server function
+/// let templates = use_isolate_state::<Templates>(cx).expect("templates are provided");
+/// ```
+pub struct IsolateState<T: Send + Sync + 'static> {
+    value: OnceLock<Arc<T>>,
+    init: fn() -> T,
+}
+
+impl<T: Send + Sync + 'static> IsolateState<T> {
+    pub const fn new(init: fn() -> T) -> Self {
+        Self {
+            value: OnceLock::new(),
+            init,
+        }
+    }
+
+    /// Returns the value, initializing it first if this is the first access in the isolate.
+    pub fn get(&self) -> Arc<T> {
+        self.value.get_or_init(|| Arc::new((self.init)())).clone()
+    }
+}
+
+/// An [IsolateState](IsolateState) whose value is loaded asynchronously (e.g. feature flags or a
+/// navigation menu stored in KV) and refreshed once it is older than `ttl`.
+///
+/// Refreshing follows stale-while-revalidate: a request that finds a stale value is served with it
+/// right away, and the reload runs in the background under
+/// [Context::wait_until](worker::Context::wait_until). Only the very first request of an isolate
+/// waits for the loader. If a reload fails, the stale value is kept and the next request retries.
+///
+/// Call [get](RevalidatingIsolateState::get) in the `fetch` handler, where `Env` and `Context` are at
+/// hand, and register the state in [WorkerRouterData::isolate_states](crate::WorkerRouterData::isolate_states)
+/// so that the latest value is provided into the context of every request:
+///
+/// ```ignore
+/// static FLAGS: RevalidatingIsolateState<Flags> =
+///     RevalidatingIsolateState::new(Duration::from_secs(60), |env| {
+///         Box::pin(async move { load_flags_from_kv(&env).await })
+///     });
+///
+/// #[worker::event(fetch)]
+/// pub async fn main(req: Request, env: Env, ctx: Context) -> worker::Result<Response> {
+///     FLAGS.get(&env, &ctx).await?;
+///     // ...
+/// }
+/// ```
+pub struct RevalidatingIsolateState<T: Send + Sync + 'static> {
+    value: RwLock<Option<Loaded<T>>>,
+    revalidating: AtomicBool,
+    ttl: Duration,
+    load: fn(worker::Env) -> LocalBoxFuture<'static, worker::Result<T>>,
+}
+
+struct Loaded<T> {
+    value: Arc<T>,
+    /// Milliseconds since the epoch, as reported by [worker::Date](worker::Date).
+    loaded_at: u64,
+}
+
+impl<T: Send + Sync + 'static> RevalidatingIsolateState<T> {
+    pub const fn new(
+        ttl: Duration,
+        load: fn(worker::Env) -> LocalBoxFuture<'static, worker::Result<T>>,
+    ) -> Self {
+        Self {
+            value: RwLock::new(None),
+            revalidating: AtomicBool::new(false),
+            ttl,
+            load,
+        }
+    }
+
+    /// Returns the value, loading it if the isolate has none yet and scheduling a background reload
+    /// if it is stale.
+    pub async fn get(
+        &'static self,
+        env: &worker::Env,
+        ctx: &worker::Context,
+    ) -> worker::Result<Arc<T>> {
+        let now = worker::Date::now().as_millis();
+        let cached = self.cached();
+
+        match cached {
+            Some(Loaded { value, loaded_at }) => {
+                if now.saturating_sub(loaded_at) >= self.ttl.as_millis() as u64
+                    && !self.revalidating.swap(true, Ordering::AcqRel)
+                {
+                    let env = env.clone();
+                    ctx.wait_until(async move {
+                        if let Err(err) = self.reload(env).await {
+                            tracing::error!("failed to revalidate isolate state: {err}");
+                        }
+                        self.revalidating.store(false, Ordering::Release);
+                    });
+                }
+                Ok(value)
+            }
+            None => self.reload(env.clone()).await,
+        }
+    }
+
+    /// Returns the value loaded last, regardless of its age.
+    pub fn peek(&self) -> Option<Arc<T>> {
+        self.cached().map(|loaded| loaded.value)
+    }
+
+    fn cached(&self) -> Option<Loaded<T>> {
+        self.value
+            .read()
+            .expect("isolate state lock poisoned")
+            .as_ref()
+            .map(|loaded| Loaded {
+                value: loaded.value.clone(),
+                loaded_at: loaded.loaded_at,
+            })
+    }
+
+    async fn reload(&self, env: worker::Env) -> worker::Result<Arc<T>> {
+        let value = Arc::new((self.load)(env).await?);
+        *self.value.write().expect("isolate state lock poisoned") = Some(Loaded {
+            value: value.clone(),
+            loaded_at: worker::Date::now().as_millis(),
+        });
+        Ok(value)
+    }
+}
+
+/// Type-erased [IsolateState](IsolateState) so that states of different types can be registered in
+/// [WorkerRouterData](crate::WorkerRouterData) together.
+pub trait ProvideIsolateState: Sync {
+    /// Provides the value into the context of `cx` as an `Arc<T>`.
+    fn provide(&'static self, cx: Scope);
+}
+
+impl<T: Send + Sync + 'static> ProvideIsolateState for IsolateState<T> {
+    fn provide(&'static self, cx: Scope) {
+        provide_context(cx, self.get());
+    }
+}
+
+impl<T: Send + Sync + 'static> ProvideIsolateState for RevalidatingIsolateState<T> {
+    /// Provides the value loaded last. Nothing is provided until [get](RevalidatingIsolateState::get)
+    /// has loaded it once.
+    fn provide(&'static self, cx: Scope) {
+        if let Some(value) = self.peek() {
+            provide_context(cx, value);
+        }
+    }
+}
+
+/// Reads a value registered as an [IsolateState](IsolateState) or a
+/// [RevalidatingIsolateState](RevalidatingIsolateState) from the context.
+pub fn use_isolate_state<T: Send + Sync + 'static>(cx: Scope) -> Option<Arc<T>> {
+    use_context::<Arc<T>>(cx)
+}
+
+pub(crate) fn provide_isolate_states(cx: Scope, states: &[&'static dyn ProvideIsolateState]) {
+    for state in states {
+        state.provide(cx);
+    }
+}
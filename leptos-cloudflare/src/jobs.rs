@@ -0,0 +1,211 @@
+//! Background jobs that are defined once and can be triggered by a cron schedule, a queue message or
+//! an HTTP request to `/__jobs/run/:name`.
+//!
+//! ```ignore
+//! struct RebuildSitemap;
+//!
+//! impl Job for RebuildSitemap {
+//!     fn name(&self) -> &'static str {
+//!         "rebuild-sitemap"
+//!     }
+//!
+//!     fn schedule(&self) -> Option<&'static str> {
+//!         Some("0 * * * *")
+//!     }
+//!
+//!     fn run(&self, ctx: JobContext) -> LocalBoxFuture<'static, Result<(), LeptosCloudflareError>> {
+//!         Box::pin(async move { rebuild_sitemap(&ctx.env).await })
+//!     }
+//! }
+//!
+//! let jobs = JobRegistry::new().register(RebuildSitemap);
+//! router.job_routes(jobs.clone(), "JOBS_TOKEN");
+//! // and in the scheduled handler
+//! jobs.cron_router().run(&event, env).await?;
+//! ```
+
+use std::rc::Rc;
+
+use futures::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+use worker::Env;
+
+use crate::api::{ApiRequest, ApiRoutes};
+use crate::scheduled::CronRouter;
+use crate::util::authorize_bearer;
+use crate::{Json, LeptosCloudflareError};
+
+/// How a job run was started.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JobTrigger {
+    /// By the cron expression it was scheduled with.
+    Cron(String),
+    Queue,
+    Manual,
+}
+
+/// What a job receives when it runs.
+#[derive(Clone)]
+pub struct JobContext {
+    pub env: Env,
+    pub trigger: JobTrigger,
+    /// Arbitrary input sent along with a queue message or an HTTP trigger.
+    pub payload: serde_json::Value,
+}
+
+pub trait Job: 'static {
+    /// Unique name of the job, used in logs, queue messages and the HTTP trigger route.
+    fn name(&self) -> &'static str;
+
+    /// Cron expression to run the job on, which must also be listed in the `crons` of `wrangler.toml`.
+    fn schedule(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn run(&self, ctx: JobContext) -> LocalBoxFuture<'static, Result<(), LeptosCloudflareError>>;
+}
+
+/// A queue message that triggers the job `job`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobMessage {
+    pub job: String,
+    #[serde(default)]
+    pub payload: serde_json::Value,
+}
+
+/// The result of a job run, as returned by the HTTP trigger.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRun {
+    pub job: String,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Default)]
+pub struct JobRegistry {
+    jobs: Vec<Rc<dyn Job>>,
+}
+
+impl JobRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(mut self, job: impl Job) -> Self {
+        self.jobs.push(Rc::new(job));
+        self
+    }
+
+    pub fn jobs(&self) -> impl Iterator<Item = &dyn Job> {
+        self.jobs.iter().map(|job| job.as_ref())
+    }
+
+    /// Runs the job `name`, logging its outcome and duration.
+    pub async fn run(&self, name: &str, ctx: JobContext) -> Result<JobRun, LeptosCloudflareError> {
+        let job = self
+            .jobs
+            .iter()
+            .find(|job| job.name() == name)
+            .ok_or(LeptosCloudflareError::NotFound)?;
+
+        let trigger = ctx.trigger.clone();
+        let started_at = worker::Date::now().as_millis();
+        let result = job.run(ctx).await;
+        let duration_ms = worker::Date::now().as_millis() - started_at;
+
+        match &result {
+            Ok(()) => tracing::info!("job {name} ({trigger:?}) finished in {duration_ms}ms"),
+            Err(err) => {
+                tracing::error!("job {name} ({trigger:?}) failed after {duration_ms}ms: {err}")
+            }
+        }
+
+        Ok(JobRun {
+            job: name.to_string(),
+            duration_ms,
+            error: result.err().map(|err| err.to_string()),
+        })
+    }
+
+    /// Runs the job a queue message asks for. A failed run is returned as an error so that the message
+    /// can be retried.
+    pub async fn run_message(
+        &self,
+        message: JobMessage,
+        env: Env,
+    ) -> Result<(), LeptosCloudflareError> {
+        let run = self
+            .run(
+                &message.job,
+                JobContext {
+                    env,
+                    trigger: JobTrigger::Queue,
+                    payload: message.payload,
+                },
+            )
+            .await?;
+
+        match run.error {
+            Some(error) => Err(LeptosCloudflareError::Internal(error)),
+            None => Ok(()),
+        }
+    }
+
+    /// A [CronRouter] that runs every job on its [schedule](Job::schedule).
+    pub fn cron_router(&self) -> CronRouter {
+        self.jobs
+            .iter()
+            .filter_map(|job| job.schedule().map(|cron| (job.clone(), cron)))
+            .fold(CronRouter::new(), |router, (job, cron)| {
+                router.named(job.name(), cron, move |invocation| {
+                    job.run(JobContext {
+                        env: invocation.env,
+                        trigger: JobTrigger::Cron(invocation.cron),
+                        payload: serde_json::Value::Null,
+                    })
+                })
+            })
+    }
+}
+
+pub trait JobRoutes {
+    /// Mounts `POST /__jobs/run/:name`, which runs a job right away and responds with its [JobRun].
+    /// Requests must carry `Authorization: Bearer <token>`, where the token is the secret `token_secret`.
+    /// An optional JSON body is passed to the job as its payload.
+    fn job_routes(self, registry: JobRegistry, token_secret: &'static str) -> Self;
+}
+
+impl<'a, D: 'static> JobRoutes for worker::Router<'a, D> {
+    fn job_routes(self, registry: JobRegistry, token_secret: &'static str) -> Self {
+        self.api_route(
+            worker::Method::Post,
+            "/__jobs/run/:name",
+            move |req: ApiRequest| {
+                let registry = registry.clone();
+                async move {
+                    authorize_bearer(&req.env, &req.parts.headers, token_secret)?;
+
+                    let name = req.path.get("name").cloned().unwrap_or_default();
+                    let payload = if req.parts.body.is_empty() {
+                        serde_json::Value::Null
+                    } else {
+                        serde_json::from_slice(&req.parts.body)
+                            .map_err(|err| LeptosCloudflareError::BadRequest(err.to_string()))?
+                    };
+                    let run = registry
+                        .run(
+                            &name,
+                            JobContext {
+                                env: req.env,
+                                trigger: JobTrigger::Manual,
+                                payload,
+                            },
+                        )
+                        .await?;
+
+                    Ok::<_, LeptosCloudflareError>(Json(run))
+                }
+            },
+        )
+    }
+}
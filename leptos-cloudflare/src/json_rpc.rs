@@ -0,0 +1,310 @@
+//! A JSON-RPC 2.0 endpoint for the registered server functions.
+//!
+//! Clients that aren't Leptos apps, like scripts or other services, can call server functions
+//! through a single endpoint instead of their encoded URLs. The method of a call is the path a server
+//! function is registered at, without the prefix, and its params are the arguments by name:
+//!
+//! ```ignore
+//! router
+//!     .leptos_routes(routes)
+//!     .json_rpc_route("/rpc")
+//! ```
+//!
+//! ```json
+//! [
+//!     {"jsonrpc": "2.0", "method": "add_todo", "params": {"title": "Write docs"}, "id": 1},
+//!     {"jsonrpc": "2.0", "method": "get_todos", "params": {"done": false}, "id": 2}
+//! ]
+//! ```
+//!
+//! Each call is handled as a request to the server function with the headers of the JSON-RPC
+//! request, so it gets the same contexts and checks, e.g. the API key, as a direct call. Batches are
+//! run in order, up to [MAX_BATCH] calls, and cookies set by any call are sent with the response.
+//! Server functions with a CBOR encoding can't be called this way.
+//!
+//! Errors use the codes of the specification: `-32700` for invalid JSON, `-32600` for an invalid
+//! call, `-32601` for an unknown method and `-32602` for arguments the server function rejected.
+//! Other failures are `-32000`, with the HTTP status of the call in `data.status` when it got one.
+//! A call that fails doesn't fail the rest of its batch.
+
+use leptos::leptos_server::server_fn_by_path;
+use leptos::server_fn::Encoding;
+use leptos::IntoView;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use wasm_bindgen::JsValue;
+
+use crate::{call_server_fn, locale, WorkerRouterData};
+
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+const SERVER_ERROR: i32 = -32000;
+
+/// The most calls in a batch. Larger batches are rejected as a whole, since each call runs a server
+/// function.
+pub const MAX_BATCH: usize = 100;
+
+#[derive(Debug, Serialize)]
+struct RpcResponse {
+    jsonrpc: &'static str,
+    #[serde(flatten)]
+    outcome: Outcome,
+    id: Value,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Outcome {
+    Result(Value),
+    Error(RpcError),
+}
+
+#[derive(Debug, Serialize)]
+struct RpcError {
+    code: i32,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+pub trait JsonRpcRoutes {
+    /// Serves the registered server functions over JSON-RPC 2.0 at `path`.
+    fn json_rpc_route(self, path: &str) -> Self;
+}
+
+impl<'a, IV, AppFn> JsonRpcRoutes for worker::Router<'a, WorkerRouterData<IV, AppFn>>
+where
+    IV: IntoView + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+{
+    fn json_rpc_route(self, path: &str) -> Self {
+        self.post_async(path, handle_json_rpc::<IV, AppFn>)
+    }
+}
+
+/// What a call answers: its response unless it is a notification, and the cookies it set.
+struct Handled {
+    response: Option<RpcResponse>,
+    cookies: Vec<String>,
+}
+
+impl Handled {
+    /// An invalid call, which is answered even without an id.
+    fn invalid(id: Option<Value>, message: &str) -> Self {
+        Self {
+            response: Some(RpcResponse {
+                jsonrpc: "2.0",
+                outcome: Outcome::Error(RpcError::new(INVALID_REQUEST, message)),
+                id: id.unwrap_or(Value::Null),
+            }),
+            cookies: Vec::new(),
+        }
+    }
+}
+
+async fn handle_json_rpc<IV, AppFn>(
+    mut req: worker::Request,
+    ctx: worker::RouteContext<WorkerRouterData<IV, AppFn>>,
+) -> worker::Result<worker::Response>
+where
+    IV: IntoView + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+{
+    let body = req.text().await?;
+    let (calls, batch) = match serde_json::from_str::<Value>(&body) {
+        Ok(Value::Array(calls)) if calls.is_empty() => {
+            return worker::Response::from_json(&Handled::invalid(None, "Empty batch").response)
+        }
+        Ok(Value::Array(calls)) if calls.len() > MAX_BATCH => {
+            let message = format!("A batch can have at most {MAX_BATCH} calls");
+            return worker::Response::from_json(&Handled::invalid(None, &message).response);
+        }
+        Ok(Value::Array(calls)) => (calls, true),
+        Ok(call) => (vec![call], false),
+        Err(err) => {
+            let response = RpcResponse {
+                jsonrpc: "2.0",
+                outcome: Outcome::Error(RpcError::new(PARSE_ERROR, err.to_string())),
+                id: Value::Null,
+            };
+            return worker::Response::from_json(&response);
+        }
+    };
+
+    let mut responses = Vec::new();
+    let mut cookies = Vec::new();
+    for call in calls {
+        let handled = call_method(&req, &ctx, call).await;
+        responses.extend(handled.response);
+        cookies.extend(handled.cookies);
+    }
+    let mut response = match responses.as_slice() {
+        // Only notifications, which get no response
+        [] => worker::Response::empty()?.with_status(204),
+        [response] if !batch => worker::Response::from_json(response)?,
+        responses => worker::Response::from_json(&responses)?,
+    };
+    for cookie in cookies {
+        response.headers_mut().append("Set-Cookie", &cookie)?;
+    }
+    Ok(response)
+}
+
+async fn call_method<IV, AppFn>(
+    req: &worker::Request,
+    ctx: &worker::RouteContext<WorkerRouterData<IV, AppFn>>,
+    call: Value,
+) -> Handled
+where
+    IV: IntoView + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+{
+    let Value::Object(mut call) = call else {
+        return Handled::invalid(None, "A call must be an object");
+    };
+    let id = call.remove("id");
+    if call.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+        return Handled::invalid(id, "Expected \"jsonrpc\": \"2.0\"");
+    }
+    let Some(Value::String(method)) = call.remove("method") else {
+        return Handled::invalid(id, "Expected a method name");
+    };
+    let invoked = match call.remove("params") {
+        None | Some(Value::Null) => invoke(req, ctx, &method, &Map::new()).await,
+        Some(Value::Object(params)) => invoke(req, ctx, &method, &params).await,
+        Some(_) => {
+            let error = RpcError::new(INVALID_PARAMS, "Expected the arguments by name");
+            Ok((Outcome::Error(error), Vec::new()))
+        }
+    };
+    // A call that couldn't be made, e.g. with a body the runtime rejected, fails on its own
+    let (outcome, cookies) = invoked.unwrap_or_else(|err| {
+        let error = RpcError::new(SERVER_ERROR, err.to_string());
+        (Outcome::Error(error), Vec::new())
+    });
+    Handled {
+        response: id.map(|id| RpcResponse {
+            jsonrpc: "2.0",
+            outcome,
+            id,
+        }),
+        cookies,
+    }
+}
+
+/// Calls the server function registered at `method` with a request like `req`.
+async fn invoke<IV, AppFn>(
+    req: &worker::Request,
+    ctx: &worker::RouteContext<WorkerRouterData<IV, AppFn>>,
+    method: &str,
+    params: &Map<String, Value>,
+) -> worker::Result<(Outcome, Vec<String>)>
+where
+    IV: IntoView + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+{
+    let Some(server_fn) = server_fn_by_path(method) else {
+        let error = RpcError::new(METHOD_NOT_FOUND, format!("No server function at {method}"));
+        return Ok((Outcome::Error(error), Vec::new()));
+    };
+    let args = to_query(params);
+    let mut url = req.url()?;
+    url.set_path(&format!("{}/{method}", ctx.data.server_fn_prefix));
+    url.set_query(None);
+    let body = match server_fn.encoding() {
+        Encoding::Url => args,
+        Encoding::GetJSON => {
+            url.set_query(Some(&args));
+            String::new()
+        }
+        Encoding::Cbor | Encoding::GetCBOR => {
+            let error = RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("{method} uses CBOR and can't be called over JSON-RPC"),
+            );
+            return Ok((Outcome::Error(error), Vec::new()));
+        }
+    };
+
+    let headers = req.headers().clone();
+    // Without it, the server function would redirect back as for a <form> submit
+    headers.set("Accept", "application/json")?;
+    headers.set("Content-Type", "application/x-www-form-urlencoded")?;
+    let mut init = worker::RequestInit::new();
+    init.with_method(worker::Method::Post)
+        .with_headers(headers)
+        .with_body(Some(JsValue::from_str(&body)));
+    let call = worker::Request::new_with_init(url.as_str(), &init)?;
+    let time = locale::RequestTime::new(Some(req.cf().timezone_name()));
+    let mut response = call_server_fn(call, &ctx.data, &ctx.env, time).await?;
+
+    let cookies = response
+        .headers()
+        .entries()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("set-cookie"))
+        .map(|(_, value)| value)
+        .collect();
+    let status = response.status_code();
+    let text = response.text().await?;
+    let outcome = if (200..300).contains(&status) {
+        // Server functions answer with JSON, anything else is passed on as a string
+        Outcome::Result(serde_json::from_str(&text).unwrap_or(Value::String(text)))
+    } else {
+        let code = match status {
+            400 => INVALID_PARAMS,
+            _ => SERVER_ERROR,
+        };
+        Outcome::Error(RpcError {
+            code,
+            message: text,
+            data: Some(serde_json::json!({ "status": status })),
+        })
+    };
+    Ok((outcome, cookies))
+}
+
+/// `params` as a query string in the bracket notation server functions decode their arguments
+/// from, e.g. `tags[0]=a&filter[done]=false`.
+fn to_query(params: &Map<String, Value>) -> String {
+    let mut pairs = Vec::new();
+    for (name, value) in params {
+        push_pairs(&mut pairs, encode(name), value);
+    }
+    pairs.join("&")
+}
+
+fn push_pairs(pairs: &mut Vec<String>, name: String, value: &Value) {
+    match value {
+        // Leaving the argument out deserializes it as `None`
+        Value::Null => {}
+        Value::Bool(value) => pairs.push(format!("{name}={value}")),
+        Value::Number(value) => pairs.push(format!("{name}={value}")),
+        Value::String(value) => pairs.push(format!("{name}={}", encode(value))),
+        Value::Array(values) => {
+            for (index, value) in values.iter().enumerate() {
+                push_pairs(pairs, format!("{name}[{index}]"), value);
+            }
+        }
+        Value::Object(fields) => {
+            for (field, value) in fields {
+                push_pairs(pairs, format!("{name}[{}]", encode(field)), value);
+            }
+        }
+    }
+}
+
+fn encode(value: &str) -> String {
+    String::from(js_sys::encode_uri_component(value))
+}
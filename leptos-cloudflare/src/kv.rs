@@ -0,0 +1,117 @@
+//! JSON values in [Workers KV](worker::kv::KvStore) from server functions and components.
+//!
+//! [use_kv] gets a namespace from the bindings of the request, wrapped in a [Kv] that serializes values
+//! with serde and takes the expiration as a [KvExpiry]:
+//!
+//! ```ignore
+//! #[server(SavePreferences, "/api")]
+//! pub async fn save_preferences(cx: Scope, user: String, prefs: Preferences) -> Result<(), ServerFnError> {
+//!     let kv = use_kv(cx, "PREFERENCES")?;
+//!     kv.put_json(&format!("prefs:{user}"), &prefs, KvExpiry::Ttl(Duration::from_secs(30 * 86_400)))
+//!         .await?;
+//!     Ok(())
+//! }
+//!
+//! let prefs = kv.get_json::<Preferences>(&format!("prefs:{user}")).await?.unwrap_or_default();
+//! ```
+//!
+//! KV is eventually consistent: a value written in one location can take up to a minute to be read
+//! elsewhere. Prefer D1 or a Durable Object for data that is read right after it is written.
+
+use std::time::Duration;
+
+use leptos::Scope;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use worker::kv::{KvStore, PutOptionsBuilder};
+
+use crate::{use_env, LeptosCloudflareError};
+
+/// The KV namespace bound as `binding`, from the bindings of the current request.
+pub fn use_kv(cx: Scope, binding: &str) -> Result<Kv, LeptosCloudflareError> {
+    Ok(Kv(use_env(cx)?.kv(binding)?))
+}
+
+/// When a value written to KV expires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KvExpiry {
+    Never,
+    /// After a duration, of at least a minute.
+    Ttl(Duration),
+    /// At a time, in seconds since the epoch, at least a minute from now.
+    At(u64),
+}
+
+/// A KV namespace storing JSON values.
+#[derive(Clone)]
+pub struct Kv(KvStore);
+
+impl Kv {
+    pub fn new(store: KvStore) -> Self {
+        Self(store)
+    }
+
+    /// The underlying namespace, e.g. to list keys or store raw bytes.
+    pub fn store(&self) -> &KvStore {
+        &self.0
+    }
+
+    /// The value of `key`, or `None` if there is none.
+    pub async fn get_json<T: DeserializeOwned>(&self, key: &str) -> worker::Result<Option<T>> {
+        Ok(self.0.get(key).json::<T>().await?)
+    }
+
+    /// The value of `key` and the metadata it was written with.
+    pub async fn get_json_with_metadata<T, M>(
+        &self,
+        key: &str,
+    ) -> worker::Result<(Option<T>, Option<M>)>
+    where
+        T: DeserializeOwned,
+        M: DeserializeOwned,
+    {
+        Ok(self.0.get(key).json_with_metadata::<T, M>().await?)
+    }
+
+    /// Writes `value` as the value of `key`.
+    pub async fn put_json<T: Serialize>(
+        &self,
+        key: &str,
+        value: &T,
+        expiry: KvExpiry,
+    ) -> worker::Result<()> {
+        let put = self.0.put(key, serde_json::to_string(value)?)?;
+        expire(put, expiry).execute().await?;
+        Ok(())
+    }
+
+    /// Writes `value` as the value of `key`, with `metadata` readable by
+    /// [get_json_with_metadata](Kv::get_json_with_metadata) and when listing keys.
+    pub async fn put_json_with_metadata<T: Serialize, M: Serialize>(
+        &self,
+        key: &str,
+        value: &T,
+        metadata: M,
+        expiry: KvExpiry,
+    ) -> worker::Result<()> {
+        let put = self
+            .0
+            .put(key, serde_json::to_string(value)?)?
+            .metadata(metadata)?;
+        expire(put, expiry).execute().await?;
+        Ok(())
+    }
+
+    pub async fn delete(&self, key: &str) -> worker::Result<()> {
+        Ok(self.0.delete(key).await?)
+    }
+}
+
+fn expire(put: PutOptionsBuilder, expiry: KvExpiry) -> PutOptionsBuilder {
+    // KV rejects expirations sooner than a minute
+    match expiry {
+        KvExpiry::Never => put,
+        KvExpiry::Ttl(ttl) => put.expiration_ttl(ttl.as_secs().max(60)),
+        KvExpiry::At(at) => put.expiration(at.max(worker::Date::now().as_millis() / 1000 + 60)),
+    }
+}
@@ -0,0 +1,485 @@
+//! One caching interface over the Cache API, Workers KV and the memory of the isolate.
+//!
+//! The stores trade consistency for latency differently:
+//!
+//! - [MemoryCache] answers without any I/O, but every isolate has its own bounded copy and loses it when
+//!   evicted
+//! - [CacheApiCache] is shared by the isolates of a data center and purged per data center
+//! - [KvCache] is global and survives deployments, but writes take up to a minute to propagate
+//!
+//! Features that cache (e.g. [CachedContent](crate::cms::CachedContent)) take a [KeyValueCache] so each
+//! can pick the store that fits it, and [get_or_load] caches anything serializable, e.g. configuration
+//! loaded in a server function:
+//!
+//! ```ignore
+//! static CONFIG_CACHE: MemoryCache = MemoryCache::new("config");
+//!
+//! let config: SiteConfig = get_or_load(&CONFIG_CACHE, &env, "site", Duration::from_secs(60), || {
+//!     load_site_config(&env)
+//! })
+//! .await?;
+//! ```
+//!
+//! For hot lookups such as session validation, [tiered_cache] keeps recently used KV entries in memory,
+//! so most reads never reach KV:
+//!
+//! ```ignore
+//! static SESSIONS: TieredCache = tiered_cache("SESSIONS", 10_000).memory_ttl(Duration::from_secs(30));
+//! ```
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::channel::oneshot;
+use futures::future::LocalBoxFuture;
+use futures::FutureExt;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use worker::Env;
+
+use crate::memo_cache::{memo_cache, MemoCache};
+
+/// A cache of byte values by string key, with a TTL per entry.
+pub trait KeyValueCache: 'static {
+    fn get<'a>(
+        &'a self,
+        env: &'a Env,
+        key: &'a str,
+    ) -> LocalBoxFuture<'a, worker::Result<Option<Vec<u8>>>>;
+
+    fn put<'a>(
+        &'a self,
+        env: &'a Env,
+        key: &'a str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> LocalBoxFuture<'a, worker::Result<()>>;
+
+    fn delete<'a>(&'a self, env: &'a Env, key: &'a str) -> LocalBoxFuture<'a, worker::Result<()>>;
+
+    /// Identifies the entries of the cache, e.g. by its store and namespace or binding, so that
+    /// separate instances over the same entries share the loads of [get_or_load].
+    fn id(&self) -> String;
+}
+
+/// Returns the value cached for `key`, or loads, caches and returns it. Concurrent misses for the same
+/// key in an isolate wait for the first one to load the value instead of loading it again.
+pub async fn get_or_load<T, F, Fut, E>(
+    cache: &dyn KeyValueCache,
+    env: &Env,
+    key: &str,
+    ttl: Duration,
+    load: F,
+) -> Result<T, E>
+where
+    T: Serialize + DeserializeOwned,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: From<worker::Error>,
+{
+    if let Some(value) = get_decoded(cache, env, key).await? {
+        return Ok(value);
+    }
+    let flight = join_flight(format!("load:{}:{key}", cache.id())).await;
+    if flight.is_none() {
+        if let Some(value) = get_decoded(cache, env, key).await? {
+            return Ok(value);
+        }
+    }
+
+    let value = load().await?;
+    cache
+        .put(
+            env,
+            key,
+            serde_json::to_vec(&value).map_err(worker::Error::from)?,
+            ttl,
+        )
+        .await?;
+    Ok(value)
+}
+
+async fn get_decoded<T: DeserializeOwned>(
+    cache: &dyn KeyValueCache,
+    env: &Env,
+    key: &str,
+) -> worker::Result<Option<T>> {
+    let Some(cached) = cache.get(env, key).await? else {
+        return Ok(None);
+    };
+    match serde_json::from_slice(&cached) {
+        Ok(value) => Ok(Some(value)),
+        Err(err) => {
+            tracing::warn!("ignoring undecodable cache entry {key}: {err}");
+            Ok(None)
+        }
+    }
+}
+
+thread_local! {
+    // Keys being populated -> the tasks waiting for them
+    static IN_FLIGHT: RefCell<HashMap<String, Vec<oneshot::Sender<()>>>> = RefCell::new(HashMap::new());
+}
+
+/// Populating a key, which wakes up the waiting tasks when dropped.
+struct Flight {
+    key: String,
+}
+
+impl Drop for Flight {
+    fn drop(&mut self) {
+        let waiters = IN_FLIGHT.with(|in_flight| in_flight.borrow_mut().remove(&self.key));
+        for waiter in waiters.into_iter().flatten() {
+            let _ = waiter.send(());
+        }
+    }
+}
+
+/// Returns a [Flight] if no other task is populating `key`, or waits until that task is done and
+/// returns `None`, after which the value should be in the cache.
+async fn join_flight(key: String) -> Option<Flight> {
+    let waiting = IN_FLIGHT.with(|in_flight| {
+        let mut in_flight = in_flight.borrow_mut();
+        match in_flight.get_mut(&key) {
+            Some(waiters) => {
+                let (sender, receiver) = oneshot::channel();
+                waiters.push(sender);
+                Some(receiver)
+            }
+            None => {
+                in_flight.insert(key.clone(), Vec::new());
+                None
+            }
+        }
+    });
+
+    match waiting {
+        Some(receiver) => {
+            let _ = receiver.await;
+            None
+        }
+        None => Some(Flight { key }),
+    }
+}
+
+/// The most bytes of keys and values [MemoryCache]s keep in an isolate, together. The least recently
+/// used entries are evicted past it.
+pub const MEMORY_CACHE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Entries in the memory of the isolate, up to [MEMORY_CACHE_BYTES] for all of them.
+pub struct MemoryCache {
+    namespace: &'static str,
+}
+
+/// A value in memory, which expires on its own TTL rather than that of its [MemoCache].
+#[derive(Clone)]
+struct MemoryEntry {
+    value: Rc<[u8]>,
+    expires_at: f64,
+}
+
+impl MemoryEntry {
+    fn new(value: Vec<u8>, ttl: Duration) -> Self {
+        Self {
+            value: value.into(),
+            expires_at: js_sys::Date::now() + ttl.as_millis() as f64,
+        }
+    }
+}
+
+/// The value of `key` in `memory` unless it is missing or expired.
+fn memory_get(memory: &MemoCache<String, MemoryEntry>, key: &String) -> Option<Vec<u8>> {
+    let entry = memory.get(key)?;
+    if entry.expires_at > js_sys::Date::now() {
+        Some(entry.value.to_vec())
+    } else {
+        memory.remove(key);
+        None
+    }
+}
+
+thread_local! {
+    // Keyed by `namespace:key`, shared by every MemoryCache of the isolate
+    static MEMORY: MemoCache<String, MemoryEntry> = memo_cache(MEMORY_CACHE_BYTES)
+        .weigher(|key, entry| key.len() + entry.value.len());
+}
+
+impl MemoryCache {
+    /// `namespace` separates the entries of different caches.
+    pub const fn new(namespace: &'static str) -> Self {
+        Self { namespace }
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}:{key}", self.namespace)
+    }
+}
+
+impl KeyValueCache for MemoryCache {
+    fn get<'a>(
+        &'a self,
+        _env: &'a Env,
+        key: &'a str,
+    ) -> LocalBoxFuture<'a, worker::Result<Option<Vec<u8>>>> {
+        let key = self.key(key);
+        let value = MEMORY.with(|memory| memory_get(memory, &key));
+        futures::future::ready(Ok(value)).boxed_local()
+    }
+
+    fn put<'a>(
+        &'a self,
+        _env: &'a Env,
+        key: &'a str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> LocalBoxFuture<'a, worker::Result<()>> {
+        let key = self.key(key);
+        MEMORY.with(|memory| memory.insert(key, MemoryEntry::new(value, ttl)));
+        futures::future::ready(Ok(())).boxed_local()
+    }
+
+    fn delete<'a>(&'a self, _env: &'a Env, key: &'a str) -> LocalBoxFuture<'a, worker::Result<()>> {
+        let key = self.key(key);
+        MEMORY.with(|memory| memory.remove(&key));
+        futures::future::ready(Ok(())).boxed_local()
+    }
+
+    fn id(&self) -> String {
+        format!("memory:{}", self.namespace)
+    }
+}
+
+/// Entries in the Cache API of the data center.
+pub struct CacheApiCache {
+    namespace: &'static str,
+}
+
+impl CacheApiCache {
+    /// `namespace` separates the entries of different caches.
+    pub const fn new(namespace: &'static str) -> Self {
+        Self { namespace }
+    }
+
+    /// The Cache API is keyed by URL, so keys are turned into one on a host that is never fetched.
+    fn url(&self, key: &str) -> String {
+        let key = String::from(js_sys::encode_uri_component(key));
+        format!("https://kv-cache.internal/{}/{key}", self.namespace)
+    }
+}
+
+impl KeyValueCache for CacheApiCache {
+    fn get<'a>(
+        &'a self,
+        _env: &'a Env,
+        key: &'a str,
+    ) -> LocalBoxFuture<'a, worker::Result<Option<Vec<u8>>>> {
+        async move {
+            match worker::Cache::default()
+                .get(self.url(key).as_str(), false)
+                .await?
+            {
+                Some(mut response) => Ok(Some(response.bytes().await?)),
+                None => Ok(None),
+            }
+        }
+        .boxed_local()
+    }
+
+    fn put<'a>(
+        &'a self,
+        _env: &'a Env,
+        key: &'a str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> LocalBoxFuture<'a, worker::Result<()>> {
+        async move {
+            let mut response = worker::Response::from_bytes(value)?;
+            response
+                .headers_mut()
+                .set("Cache-Control", &format!("max-age={}", ttl.as_secs()))?;
+            worker::Cache::default()
+                .put(self.url(key).as_str(), response)
+                .await
+        }
+        .boxed_local()
+    }
+
+    fn delete<'a>(&'a self, _env: &'a Env, key: &'a str) -> LocalBoxFuture<'a, worker::Result<()>> {
+        async move {
+            worker::Cache::default()
+                .delete(self.url(key).as_str(), false)
+                .await?;
+            Ok(())
+        }
+        .boxed_local()
+    }
+
+    fn id(&self) -> String {
+        format!("cache-api:{}", self.namespace)
+    }
+}
+
+/// Entries in a KV namespace, by binding. KV requires a TTL of at least 60 seconds, shorter ones are
+/// raised to that.
+pub struct KvCache {
+    binding: &'static str,
+}
+
+impl KvCache {
+    pub const fn new(binding: &'static str) -> Self {
+        Self { binding }
+    }
+}
+
+impl KeyValueCache for KvCache {
+    fn get<'a>(
+        &'a self,
+        env: &'a Env,
+        key: &'a str,
+    ) -> LocalBoxFuture<'a, worker::Result<Option<Vec<u8>>>> {
+        async move { Ok(env.kv(self.binding)?.get(key).bytes().await?) }.boxed_local()
+    }
+
+    fn put<'a>(
+        &'a self,
+        env: &'a Env,
+        key: &'a str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> LocalBoxFuture<'a, worker::Result<()>> {
+        async move {
+            env.kv(self.binding)?
+                .put_bytes(key, &value)?
+                .expiration_ttl(ttl.as_secs().max(60))
+                .execute()
+                .await?;
+            Ok(())
+        }
+        .boxed_local()
+    }
+
+    fn delete<'a>(&'a self, env: &'a Env, key: &'a str) -> LocalBoxFuture<'a, worker::Result<()>> {
+        async move { Ok(env.kv(self.binding)?.delete(key).await?) }.boxed_local()
+    }
+
+    fn id(&self) -> String {
+        format!("kv:{}", self.binding)
+    }
+}
+
+/// Creates a [TieredCache] in front of the KV namespace `binding`, keeping up to `capacity` entries in
+/// memory. Caches in front of the same binding share their entries, with the capacity of the first
+/// one used.
+pub const fn tiered_cache(binding: &'static str, capacity: usize) -> TieredCache {
+    TieredCache {
+        kv: KvCache::new(binding),
+        capacity,
+        memory_ttl: Duration::from_secs(60),
+    }
+}
+
+/// The least recently used entries of a KV namespace, kept in the memory of the isolate.
+///
+/// Reads are answered from memory when possible. Misses read KV, and concurrent misses for the same key
+/// share a single read. Writes and deletes go to both tiers, but other isolates keep serving their copy
+/// for up to [memory_ttl](TieredCache::memory_ttl).
+pub struct TieredCache {
+    kv: KvCache,
+    capacity: usize,
+    memory_ttl: Duration,
+}
+
+thread_local! {
+    // By KV binding
+    static TIERS: RefCell<HashMap<&'static str, MemoCache<String, MemoryEntry>>> =
+        RefCell::new(HashMap::new());
+}
+
+impl TieredCache {
+    /// How long an entry is served from memory before KV is read again, 60 seconds by default.
+    pub const fn memory_ttl(mut self, memory_ttl: Duration) -> Self {
+        self.memory_ttl = memory_ttl;
+        self
+    }
+
+    /// The entries in memory for the binding, shared by every [TieredCache] in front of it.
+    fn memory(&self) -> MemoCache<String, MemoryEntry> {
+        TIERS.with(|tiers| {
+            tiers
+                .borrow_mut()
+                .entry(self.kv.binding)
+                .or_insert_with(|| memo_cache(self.capacity))
+                .clone()
+        })
+    }
+
+    fn memory_get(&self, key: &str) -> Option<Vec<u8>> {
+        memory_get(&self.memory(), &key.to_string())
+    }
+
+    fn memory_put(&self, key: &str, value: Vec<u8>, ttl: Duration) {
+        let entry = MemoryEntry::new(value, ttl.min(self.memory_ttl));
+        self.memory().insert(key.to_string(), entry);
+    }
+
+    fn memory_delete(&self, key: &str) {
+        self.memory().remove(&key.to_string());
+    }
+}
+
+impl KeyValueCache for TieredCache {
+    fn get<'a>(
+        &'a self,
+        env: &'a Env,
+        key: &'a str,
+    ) -> LocalBoxFuture<'a, worker::Result<Option<Vec<u8>>>> {
+        async move {
+            if let Some(value) = self.memory_get(key) {
+                return Ok(Some(value));
+            }
+            let flight = join_flight(format!("tier:{}:{key}", self.kv.binding)).await;
+            if flight.is_none() {
+                if let Some(value) = self.memory_get(key) {
+                    return Ok(Some(value));
+                }
+            }
+
+            let value = self.kv.get(env, key).await?;
+            if let Some(value) = &value {
+                self.memory_put(key, value.clone(), self.memory_ttl);
+            }
+            drop(flight);
+            Ok(value)
+        }
+        .boxed_local()
+    }
+
+    fn put<'a>(
+        &'a self,
+        env: &'a Env,
+        key: &'a str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> LocalBoxFuture<'a, worker::Result<()>> {
+        async move {
+            self.memory_put(key, value.clone(), ttl);
+            self.kv.put(env, key, value, ttl).await
+        }
+        .boxed_local()
+    }
+
+    fn delete<'a>(&'a self, env: &'a Env, key: &'a str) -> LocalBoxFuture<'a, worker::Result<()>> {
+        async move {
+            self.memory_delete(key);
+            self.kv.delete(env, key).await
+        }
+        .boxed_local()
+    }
+
+    fn id(&self) -> String {
+        format!("tiered:{}", self.kv.binding)
+    }
+}
@@ -1,20 +1,213 @@
-use std::collections::HashSet;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
 use futures::{Stream, StreamExt};
 use leptos::leptos_server::server_fn_by_path;
 use leptos::server_fn::{Encoding, Payload};
-use leptos::{create_runtime, provide_context, raw_scope_and_disposer, Scope};
+use leptos::{create_runtime, provide_context, raw_scope_and_disposer, Scope, ScopeDisposer};
 use leptos::{
     ssr::render_to_stream_with_prefix_undisposed_with_context_and_block_replacement, use_context,
     IntoView, LeptosOptions, RuntimeId, ScopeId, View,
 };
-use leptos_integration_utils::{build_async_response, html_parts_separated};
+use leptos_integration_utils::build_async_response;
 use leptos_meta::{generate_head_metadata_separated, MetaContext};
 use leptos_router::{provide_server_redirect, RouteListing, SsrMode};
 use leptos_router::{Method as LeptosMethod, RouterIntegrationContext, ServerIntegration};
 
 use worker::Headers;
 
+pub mod abort;
+pub mod access_log;
+#[cfg(feature = "admin")]
+pub mod admin;
+pub mod analytics;
+pub mod api;
+#[cfg(feature = "api-keys")]
+pub mod api_keys;
+pub mod app_env;
+pub mod assets;
+pub mod audit;
+pub mod background;
+pub mod builder;
+pub mod cache_tags;
+pub mod canary;
+#[cfg(feature = "client-errors")]
+pub mod client_errors;
+#[cfg(feature = "cms")]
+pub mod cms;
+pub mod concurrency;
+pub mod content_version;
+#[cfg(feature = "nonce")]
+pub mod csp;
+#[cfg(feature = "d1")]
+pub mod d1;
+pub mod debug;
+pub mod device;
+pub mod di;
+pub mod download;
+pub mod durable;
+#[cfg(feature = "email")]
+pub mod email;
+pub mod error;
+pub mod experiments;
+#[cfg(feature = "csv")]
+pub mod export;
+#[cfg(feature = "flash")]
+pub mod flash;
+#[cfg(feature = "forms")]
+pub mod forms;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+#[cfg(feature = "grpc-web")]
+pub mod grpc_web;
+pub mod headers;
+#[cfg(feature = "hydration-report")]
+pub mod hydration_report;
+#[cfg(feature = "hyperdrive")]
+pub mod hyperdrive;
+pub mod isolate;
+pub mod jobs;
+#[cfg(feature = "json-rpc")]
+pub mod json_rpc;
+pub mod kv;
+pub mod kv_cache;
+#[cfg(feature = "live")]
+pub mod live;
+pub mod locale;
+#[cfg(feature = "locks")]
+pub mod lock;
+#[cfg(feature = "lockout")]
+pub mod lockout;
+#[cfg(feature = "magic-link")]
+pub mod magic_link;
+pub mod memo_cache;
+pub mod mirror;
+pub mod mount;
+pub mod normalize;
+#[cfg(feature = "og-image")]
+pub mod og_image;
+#[cfg(feature = "openapi")]
+pub mod openapi;
+#[cfg(feature = "outbox")]
+pub mod outbox;
+pub mod page_cache;
+#[cfg(feature = "passwords")]
+pub mod password;
+#[cfg(feature = "preview")]
+pub mod preview;
+pub mod query;
+#[cfg(feature = "queue")]
+pub mod queue;
+pub mod r2;
+#[cfg(feature = "rate-limit")]
+pub mod rate_limit;
+pub mod raw;
+pub mod redirects;
+pub mod request_headers;
+pub mod response;
+pub mod robots;
+pub mod route_config;
+pub mod scheduled;
+#[cfg(feature = "seal")]
+pub mod seal;
+pub mod segments;
+pub mod shell;
+#[cfg(feature = "signed-urls")]
+pub mod signed_url;
+#[cfg(feature = "singletons")]
+pub mod singleton;
+#[cfg(feature = "sockets")]
+pub mod socket;
+pub mod streaming;
+#[cfg(feature = "stripe")]
+pub mod stripe;
+#[cfg(feature = "tail")]
+pub mod tail;
+#[cfg(feature = "totp")]
+pub mod totp;
+pub mod trailers;
+#[cfg(feature = "uploads")]
+pub mod uploads;
+mod util;
+pub mod vectorize;
+pub mod warm;
+#[cfg(feature = "webhooks")]
+pub mod webhooks;
+pub mod websocket;
+pub mod zone_purge;
+
+pub use abort::use_abort_signal;
+pub use access_log::AccessLog;
+pub use analytics::{use_analytics, AnalyticsDataset, DataPoint, RequestMetrics};
+pub use api::{ApiRequest, ApiRoutes};
+pub use app_env::{app_with_env, use_env};
+pub use assets::AssetSource;
+pub use background::{use_wait_until, WaitUntil};
+pub use builder::{BuildError, WorkerRouterDataBuilder};
+pub use cache_tags::{set_cache_tags, CacheStore, TaggedCache};
+pub use canary::{Canary, Release};
+pub use content_version::{use_content_version, ContentVersions};
+pub use device::{use_device, Device};
+pub use di::{use_dep, Dependencies, Provide};
+pub use download::{download_response, DownloadBody};
+pub use error::{ErrorRenderer, LeptosCloudflareError, ProblemDetails};
+pub use experiments::{use_variant, Experiment};
+pub use headers::HeaderRules;
+pub use isolate::{use_isolate_state, IsolateState, ProvideIsolateState, RevalidatingIsolateState};
+pub use kv::{use_kv, Kv, KvExpiry};
+pub use kv_cache::{tiered_cache, CacheApiCache, KeyValueCache, KvCache, MemoryCache, TieredCache};
+pub use locale::{use_request_locale, use_request_time, RequestLocale, RequestTime};
+pub use memo_cache::{memo_cache, MemoCache};
+pub use mirror::Mirror;
+pub use mount::LeptosRoutesUnder;
+pub use normalize::{normalize_request, PathError};
+pub use page_cache::PageCache;
+#[cfg(feature = "preview")]
+pub use preview::{use_preview_mode, PreviewConfig, PreviewMode};
+pub use query::{use_request_query, Query};
+pub use r2::{r2_get, r2_head, r2_put, use_r2, ObjectMeta, R2Get, R2Put, StoredObject};
+pub use raw::{BinaryResponse, RawRequest, RawRoutes};
+pub use redirects::{RedirectQuery, Redirects};
+pub use request_headers::RequestHeaderPolicy;
+pub use response::{IntoWorkerResponse, Json, Redirect};
+pub use robots::{set_robots, Robots, RobotsDirectives};
+pub use route_config::RouteConfig;
+pub use scheduled::{handle_scheduled, ScheduledEventParts};
+pub use segments::{use_cache_segment, CacheSegment, CacheSegments};
+pub use shell::{render_in_shell, HtmlShell, ShellHooks};
+pub use streaming::{ChunkSender, StreamingResponse};
+pub use trailers::set_trailer;
+pub use vectorize::{
+    use_vectorize, Vector, VectorFilter, VectorMatch, VectorMutation, VectorQuery, VectorizeIndex,
+};
+pub use warm::{WarmList, WarmReport};
+pub use websocket::{WebSocketRoutes, WebSocketSession};
+
+pub use http::StatusCode;
+
+/// Runs several D1 statements as one atomic batch. Each statement is written as a tuple of the query and
+/// its parameters, which can be anything that converts into a `JsValue`:
+///
+/// ```ignore
+/// d1_batch!(
+///     &db,
+///     ("INSERT INTO posts (title) VALUES (?1)", title),
+///     ("UPDATE stats SET post_count = post_count + 1"),
+/// )
+/// .await?;
+/// ```
+#[cfg(feature = "d1")]
+#[macro_export]
+macro_rules! d1_batch {
+    ($db:expr, $(($query:expr $(, $param:expr)* $(,)?)),+ $(,)?) => {
+        $crate::d1::d1_batch(
+            $db,
+            vec![$(($query, vec![$($crate::d1::__JsValue::from($param)),*])),+],
+        )
+    };
+}
+
 pub trait LeptosRoutes {
     fn leptos_routes(self, paths: Vec<RouteListing>) -> Self;
 }
@@ -24,6 +217,7 @@ pub trait LeptosRoutes {
 /// but [worker::Request](worker::Request) doesn't implement Clone, so we need to wrap it in a struct.
 #[derive(Debug, Clone)]
 pub struct RequestParts {
+    /// The body, empty for methods without one. See [generate_request_parts].
     pub body: Vec<u8>,
     pub method: worker::Method,
     pub headers: worker::Headers,
@@ -33,28 +227,98 @@ pub struct RequestParts {
 
 /// This struct lets you define headers and override the status of the Response from an Element or a Server Function
 /// Typically contained inside of a ResponseOptions. Setting this is useful for cookies and custom responses.
-#[derive(Debug, Clone)]
+///
+/// Clones share the same status and headers, so changes made through a copy obtained with
+/// [use_context](leptos::use_context) are visible to the handler that builds the response.
+///
+/// The status is read with [status](ResponseOptions::status) and overridden with
+/// [set_status](ResponseOptions::set_status). The public `status` field is deprecated, since a copy
+/// of an `Option<u16>` can't be shared: a status assigned to it is still sent, but only if it was
+/// assigned on the copy the response is built from.
+#[derive(Debug)]
 pub struct ResponseOptions {
+    #[deprecated(
+        note = "use `status()` and `set_status()`, whose status is shared between clones"
+    )]
     pub status: Option<u16>,
+    shared_status: Rc<Cell<Option<u16>>>,
     pub headers: worker::Headers,
 }
 
 /// Cloudflare Worker handler can only access variables from [RouterContext](worker::RouteContext). Therefore,
 /// we want to put all the variables we need in route handler into this struct.
+///
+/// Prefer [WorkerRouterData::builder], which validates the settings.
 #[derive(Clone)]
 pub struct WorkerRouterData<IV, AppFn>
 where
     IV: IntoView + 'static,
-    AppFn: Fn(leptos::Scope) -> IV + Clone + Send + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
 {
     pub options: LeptosOptions,
     /// A set of local directories that should serve static assets from the KV store.
     pub static_dirs: HashSet<String>,
+    /// Where the static assets are stored. See [AssetSource](AssetSource).
+    pub assets: AssetSource,
+    /// Markup added to the document around the app. See [ShellHooks](ShellHooks).
+    pub shell: ShellHooks,
+    /// The root component. Workers run each isolate on a single thread, so it doesn't have to be `Send`
+    /// and can capture `Rc`s and other single-threaded state.
     pub app_fn: AppFn,
+    /// The path server functions are posted to. Only used by [into_router](WorkerRouterData::into_router).
+    pub server_fn_prefix: String,
+    /// Per-route settings keyed by route path, e.g. `/post/:id`. See [RouteConfig](RouteConfig).
+    pub route_config: HashMap<String, RouteConfig>,
+    /// Per-isolate values provided into the context of every request. See [IsolateState](IsolateState).
+    pub isolate_states: Vec<&'static dyn ProvideIsolateState>,
+    /// Services available through [use_dep](use_dep). See [Dependencies](Dependencies).
+    pub deps: Dependencies,
+    /// The cookie-derived segments pages are cached by. See [CacheSegments](CacheSegments).
+    pub cache_segments: CacheSegments,
+    /// How many chunks of a streamed page are rendered ahead of the client. See [streaming].
+    pub stream_buffer: usize,
+    /// Renders the crate's error responses as JSON or HTML. See [ErrorRenderer](ErrorRenderer).
+    pub error_renderer: ErrorRenderer,
+    /// The context of the fetch handler, for background work. See [background](background).
+    pub context: Option<Rc<worker::Context>>,
+    /// Selects the version of the synced assets from a pointer in KV. See [content_version].
+    pub content_versions: Option<content_version::ContentVersions>,
+    /// Records a data point for every page and server function call. See [analytics](analytics).
+    pub request_metrics: Option<analytics::RequestMetrics>,
+    /// The request headers exposed in the context. See [request_headers](request_headers).
+    pub request_headers: request_headers::RequestHeaderPolicy,
+    /// Caches pages rendered with `SsrMode::Async`. See [page_cache](page_cache).
+    pub page_cache: Option<page_cache::PageCache>,
+    /// What [redirect] from a page does with the query string of the request.
+    pub redirect_query: RedirectQuery,
+    /// Enables preview mode. See [PreviewMode](PreviewMode).
+    #[cfg(feature = "preview")]
+    pub preview: Option<PreviewConfig>,
+    /// Checks the API keys sent to server functions. See [ApiKeyAuth](api_keys::ApiKeyAuth).
+    #[cfg(feature = "api-keys")]
+    pub api_keys: Option<api_keys::ApiKeyAuth>,
+    /// Enables flash messages. See [flash](flash).
+    #[cfg(feature = "flash")]
+    pub flash: Option<seal::Sealer>,
 }
 
+/// Reads `req` into [RequestParts]. The body is left empty for `GET`, `HEAD` and `OPTIONS` requests,
+/// which don't have one, so page loads don't wait for the body stream.
 pub async fn generate_request_parts(req: &mut worker::Request) -> worker::Result<RequestParts> {
-    let body = req.bytes().await.unwrap_or_default();
+    let read_body = !matches!(
+        req.method(),
+        worker::Method::Get | worker::Method::Head | worker::Method::Options
+    );
+    request_parts(req, read_body).await
+}
+
+/// Reads `req` into [RequestParts], with an empty body unless `read_body`.
+async fn request_parts(req: &mut worker::Request, read_body: bool) -> worker::Result<RequestParts> {
+    let body = if read_body {
+        req.bytes().await.unwrap_or_default()
+    } else {
+        Vec::new()
+    };
     let method = req.method();
     let headers = req.headers().clone();
     let edge_request = req.inner();
@@ -72,52 +336,222 @@ pub async fn generate_request_parts(req: &mut worker::Request) -> worker::Result
 /// Provides an easy way to redirect the user from within a server function. Mimicking the Remix `redirect()`,
 /// it sets a [StatusCode] of 302 and a [LOCATION](header::LOCATION) header with the provided value.
 /// If looking to redirect from the client, `leptos_router::use_navigate()` should be used instead.
+///
+/// Redirects from a page carry the query string of the request over as the
+/// [redirect_query](WorkerRouterDataBuilder::redirect_query) of the router data says. Those from a server
+/// function don't, since its query holds the arguments of the call.
 #[tracing::instrument(level = "trace", fields(error), skip_all)]
 pub fn redirect(cx: leptos::Scope, path: &str) {
+    let location = match (
+        use_context::<RedirectQuery>(cx),
+        use_context::<RequestParts>(cx),
+    ) {
+        (Some(query), Some(req)) => query.apply(path, req.url.query()),
+        _ => path.to_string(),
+    };
     if let Some(mut response_options) = use_context::<ResponseOptions>(cx) {
-        response_options.status = Some(302);
+        response_options.set_status(302);
         response_options
-            .insert_header("location", path)
+            .insert_header("location", &location)
             .expect("failed to insert header value");
     }
 }
 
 #[tracing::instrument(level = "trace", fields(error), skip_all)]
 pub async fn handle_server_fns<IV, AppFn>(
+    req: worker::Request,
+    ctx: worker::RouteContext<WorkerRouterData<IV, AppFn>>,
+) -> worker::Result<worker::Response>
+where
+    IV: IntoView + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+{
+    let time = locale::RequestTime::new(Some(req.cf().timezone_name()));
+    let Some(metrics) = ctx.data.request_metrics else {
+        return call_server_fn(req, &ctx.data, &ctx.env, time).await;
+    };
+    let started_at = worker::Date::now().as_millis();
+    let method = req.method();
+    let path = req.path();
+    let env = ctx.env.clone();
+
+    let response = call_server_fn(req, &ctx.data, &ctx.env, time).await;
+    let status = response.as_ref().ok().map(worker::Response::status_code);
+    metrics.record(&env, &path, None, &method, status, started_at);
+    response
+}
+
+/// Provides the contexts server functions run with, up to the [ResponseOptions] they set the status
+/// and headers of the response with. Other handlers of server-side code, like
+/// [handle_graphql](crate::graphql::handle_graphql), run with the same contexts.
+pub(crate) async fn provide_request_contexts<IV, AppFn>(
+    cx: Scope,
+    router_data: &WorkerRouterData<IV, AppFn>,
+    env: &worker::Env,
+    req_parts: &RequestParts,
+    time: locale::RequestTime,
+) -> worker::Result<()>
+where
+    IV: IntoView + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+{
+    provide_context(cx, router_data.request_headers.apply(req_parts));
+    provide_context(cx, request_headers::FullRequestParts(req_parts.clone()));
+    isolate::provide_isolate_states(cx, &router_data.isolate_states);
+    provide_context(
+        cx,
+        locale::RequestLocale::from_accept_language(
+            req_parts.headers.get("Accept-Language")?.as_deref(),
+        ),
+    );
+    provide_context(cx, time);
+    provide_context(cx, app_env::RequestEnv(env.clone()));
+    provide_context(cx, background::WaitUntil(router_data.context.clone()));
+    provide_context(
+        cx,
+        content_version::ContentVersion(
+            content_version::active_version(router_data.content_versions.as_ref(), env).await,
+        ),
+    );
+    provide_context(cx, experiments::Buckets::default());
+    provide_context(cx, device::Device::from_headers(&req_parts.headers));
+    if let Some(signal) = abort::RequestSignal::of(&req_parts.edge_request) {
+        provide_context(cx, signal);
+    }
+    provide_context(
+        cx,
+        di::DepContainer::new(router_data.deps.clone(), env.clone(), req_parts.clone()),
+    );
+    // Add this so that we can set headers and status of the response
+    let mut res_options = ResponseOptions::default();
+    #[cfg(feature = "flash")]
+    provide_context(cx, flash::FlashOutbox::default());
+    #[cfg(feature = "forms")]
+    provide_context(cx, forms::FormOutbox::default());
+    #[cfg(feature = "preview")]
+    if let Some(config) = &router_data.preview {
+        let preview_mode = preview::preview_mode(config, env, req_parts, &mut res_options)?;
+        provide_context(cx, preview_mode);
+    }
+    provide_context(cx, res_options);
+    Ok(())
+}
+
+/// Stores what was collected in the contexts of [provide_request_contexts] for later requests, e.g.
+/// flash messages, once the handler has run.
+#[cfg_attr(not(feature = "flash"), allow(unused_variables))]
+pub(crate) async fn commit_request_contexts<IV, AppFn>(
+    cx: Scope,
+    router_data: &WorkerRouterData<IV, AppFn>,
+    env: &worker::Env,
+) -> worker::Result<()>
+where
+    IV: IntoView + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+{
+    #[cfg(feature = "flash")]
+    if let Some(sealer) = &router_data.flash {
+        flash::commit(cx, sealer, env).await?;
+        #[cfg(feature = "forms")]
+        forms::commit(cx, sealer, env).await?;
+    }
+    Ok(())
+}
+
+/// A Leptos runtime with its root scope, both disposed when it is dropped.
+pub(crate) struct ScopedRuntime {
+    pub(crate) cx: Scope,
+    disposer: Option<ScopeDisposer>,
+}
+
+impl ScopedRuntime {
+    pub(crate) fn new() -> Self {
+        let runtime = create_runtime();
+        let (cx, disposer) = raw_scope_and_disposer(runtime);
+        Self {
+            cx,
+            disposer: Some(disposer),
+        }
+    }
+}
+
+impl Drop for ScopedRuntime {
+    fn drop(&mut self) {
+        if let Some(disposer) = self.disposer.take() {
+            disposer.dispose();
+        }
+        self.cx.runtime.dispose();
+    }
+}
+
+pub(crate) async fn call_server_fn<IV, AppFn>(
     mut req: worker::Request,
-    _ctx: worker::RouteContext<WorkerRouterData<IV, AppFn>>,
+    router_data: &WorkerRouterData<IV, AppFn>,
+    env: &worker::Env,
+    time: locale::RequestTime,
 ) -> worker::Result<worker::Response>
 where
     IV: IntoView + 'static,
-    AppFn: Fn(leptos::Scope) -> IV + Clone + Send + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
 {
     let url = req.url()?;
     let path_segments = url.path_segments();
     let path_segments = match path_segments {
         Some(path_segments) => path_segments,
-        None => return worker::Response::error("Server functions cannot be hosted at root /", 500),
+        None => {
+            return router_data.error_renderer.render_for(
+                &req,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Server functions cannot be hosted at root /",
+            )
+        }
     };
 
     // last element must exist, since we already checked that path_segments is not empty
     let api_path = path_segments.last().unwrap();
 
     if let Some(server_fn) = server_fn_by_path(api_path) {
-        let runtime = create_runtime();
-        let (cx, disposer) = raw_scope_and_disposer(runtime);
+        #[cfg(feature = "api-keys")]
+        let api_key = match &router_data.api_keys {
+            Some(api_keys) => {
+                let scope = api_keys.required_scope(url.path());
+                match api_keys.authenticate(env, req.headers(), scope).await {
+                    Ok(None) if scope.is_some() => {
+                        return LeptosCloudflareError::Unauthorized.into_worker_response()
+                    }
+                    Ok(api_key) => api_key,
+                    Err(err) => return err.into_worker_response(),
+                }
+            }
+            None => None,
+        };
 
-        let req_parts = generate_request_parts(&mut req).await?;
-        provide_context(cx, req_parts.clone());
-        // Add this so that we can set headers and status of the response
-        provide_context(cx, ResponseOptions::default());
+        // The body is read before the runtime exists, so a failed read has nothing to clean up.
+        // Server functions with a GET encoding take their arguments from the query instead.
+        let read_body = matches!(server_fn.encoding(), Encoding::Url | Encoding::Cbor);
+        let req_parts = request_parts(&mut req, read_body).await?;
+
+        // Disposed on every way out of here, including the `?`s below
+        let runtime = ScopedRuntime::new();
+        let cx = runtime.cx;
+        provide_request_contexts(cx, router_data, env, &req_parts, time).await?;
+        #[cfg(feature = "api-keys")]
+        if let Some(api_key) = api_key {
+            provide_context(cx, api_key);
+        }
 
-        let query_bytes = &url.query().unwrap_or("").as_bytes();
+        let query = query::index_repeated_params(url.query().unwrap_or(""));
+        let query_bytes = query.as_bytes();
 
         let data = match &server_fn.encoding() {
             Encoding::Url | Encoding::Cbor => req_parts.body.as_slice(),
             Encoding::GetJSON | Encoding::GetCBOR => query_bytes,
         };
 
-        let response = match server_fn.call(cx, data).await {
+        let result = server_fn.call(cx, data).await;
+        commit_request_contexts(cx, router_data, env).await?;
+
+        let response = match result {
             Ok(serialized) => {
                 // If ResponseOptions are set, add the headers and status to the request
                 let res_options = use_context::<ResponseOptions>(cx);
@@ -154,9 +588,9 @@ where
                     };
                 }
 
-                let overriding_status = &res_options.unwrap().status;
+                let overriding_status = res_options.unwrap().status();
                 match overriding_status {
-                    Some(overriding_status) => status = *overriding_status,
+                    Some(overriding_status) => status = overriding_status,
                     None => {}
                 };
                 match serialized {
@@ -195,39 +629,34 @@ where
                 }
             }
             Err(err) => {
-                worker::Response::from_bytes(err.to_string().as_bytes().to_vec())?.with_status(500)
+                #[cfg(feature = "admin")]
+                admin::record_error(format!("server function {api_path}: {err}"));
+                // Keep an error status set by the server function (e.g. 409), otherwise it's a 500,
+                // and the headers it set, e.g. Retry-After
+                let res_options = use_context::<ResponseOptions>(cx).unwrap_or_default();
+                let status = res_options
+                    .status()
+                    .filter(|status| *status >= 400)
+                    .unwrap_or(500);
+                worker::Response::from_bytes(err.to_string().as_bytes().to_vec())?
+                    .with_status(status)
+                    .with_headers(res_options.headers)
             }
         };
-        // clean up the scope
-        disposer.dispose();
-        runtime.dispose();
-
         Ok(response)
     } else {
-        let response = worker::Response::from_bytes(
-            format!(
-                "Could not find a server function at the \
-                 route {api_path}. \n\nIt's likely that \
-                 either 
-                 1. The API prefix you specify in the \
-                 `#[server]` macro doesn't match the \
-                 prefix at which your server function \
-                 handler is mounted, or \n2. You are on a \
-                 platform that doesn't support automatic \
-                 server function registration and you \
-                 need to call \
-                 ServerFn::register_explicit() on the \
-                 server function type, somewhere in your \
-                 `main` function.",
-            )
-            .into(),
+        router_data.error_renderer.render_for(
+            &req,
+            StatusCode::NOT_FOUND,
+            &format!(
+                "Could not find a server function at the route {api_path}. It's likely that either \
+                 1. the API prefix you specify in the `#[server]` macro doesn't match the prefix at \
+                 which your server function handler is mounted, or 2. you are on a platform that \
+                 doesn't support automatic server function registration and you need to call \
+                 ServerFn::register_explicit() on the server function type, somewhere in your \
+                 `main` function."
+            ),
         )
-        // The only possible error is setting the header inside this function
-        // as octet-stream, and it should never go wrong
-        .unwrap()
-        .with_status(400);
-
-        Ok(response)
     }
 }
 
@@ -239,50 +668,301 @@ pub async fn serve_static_from_kv<IV, AppFn>(
 ) -> worker::Result<worker::Response>
 where
     IV: IntoView + 'static,
-    AppFn: Fn(leptos::Scope) -> IV + Clone + Send + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
 {
-    let url = req.url();
-    let asset_key = url
-        .as_ref()
-        .ok()
-        .and_then(|url| url.path_segments())
-        .and_then(|mut path_segments| {
-            path_segments.next().and_then(|pkg_dir| {
-                if pkg_dir == ctx.data.options.site_pkg_dir
-                    || ctx.data.static_dirs.contains(pkg_dir)
-                {
-                    path_segments.next()
-                } else {
-                    None
-                }
-            })
-        });
+    // Keys are derived from the decoded segments, so they can't contain a separator or a dot segment
+    let segments = match normalize::decode_segments(req.url()?.path()) {
+        Ok(segments) => segments,
+        Err(err) => {
+            return ctx.data.error_renderer.render_for(
+                &req,
+                StatusCode::BAD_REQUEST,
+                &err.to_string(),
+            )
+        }
+    };
+    let mut path_segments = segments.iter().map(String::as_str);
+    let in_static_dir = path_segments.next().is_some_and(|pkg_dir| {
+        pkg_dir == ctx.data.options.site_pkg_dir || ctx.data.static_dirs.contains(pkg_dir)
+    });
+    if !in_static_dir {
+        return ctx
+            .data
+            .error_renderer
+            .render_for(&req, StatusCode::NOT_FOUND, "Not found");
+    }
+
+    if ctx.data.assets != AssetSource::WorkerSites {
+        let path = path_segments.collect::<Vec<_>>().join("/");
+        let version =
+            content_version::active_version(ctx.data.content_versions.as_ref(), &ctx.env).await;
+        return assets::serve_synced_asset(
+            &ctx.env,
+            ctx.data.assets,
+            version.as_deref(),
+            &path,
+            &req,
+            &ctx.data.error_renderer,
+        )
+        .await;
+    }
+
+    let asset_key = path_segments.next();
 
     let asset_key = match asset_key {
         Some(asset_key) => asset_key,
-        None => return worker::Response::error("Not found", 404),
+        None => {
+            return ctx
+                .data
+                .error_renderer
+                .render_for(&req, StatusCode::NOT_FOUND, "Not found")
+        }
     };
     let store = ctx.env.kv("__STATIC_CONTENT")?;
     let file_path = match ctx.env.asset_key(asset_key) {
         Ok(file_path) => file_path,
-        Err(_) => return worker::Response::error("Not found", 404),
+        Err(_) => {
+            return ctx
+                .data
+                .error_renderer
+                .render_for(&req, StatusCode::NOT_FOUND, "Not found")
+        }
     };
 
     if let Some(bytes) = store.get(&file_path).bytes().await? {
         let mut response = worker::Response::from_bytes(bytes)?;
         let content_type = match mime_guess::from_path(file_path).first() {
             Some(content_type) => content_type,
-            None => return worker::Response::error("Unsupported file type", 415),
+            None => {
+                return ctx.data.error_renderer.render_for(
+                    &req,
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    "Unsupported file type",
+                )
+            }
         };
         response
             .headers_mut()
             .set("Content-Type", content_type.essence_str())?;
         Ok(response)
     } else {
-        worker::Response::error("Not found", 404)
+        ctx.data
+            .error_renderer
+            .render_for(&req, StatusCode::NOT_FOUND, "Not found")
     }
 }
 
+/// Reads the request and builds the root view shared by every [SsrMode](SsrMode) handler.
+/// The configuration of the matching route is applied to `res_options` before anything renders.
+async fn prepare_app<IV, AppFn>(
+    req: &mut worker::Request,
+    env: &worker::Env,
+    data: &WorkerRouterData<IV, AppFn>,
+    res_options: &mut ResponseOptions,
+) -> worker::Result<impl FnOnce(leptos::Scope) -> View + 'static>
+where
+    IV: IntoView + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+{
+    let request_parts = generate_request_parts(req).await?;
+    let route_config = route_config::route_config_for(&data.route_config, request_parts.url.path())
+        .cloned()
+        .unwrap_or_default();
+
+    if let Some(robots) = route_config.robots {
+        robots.apply(res_options)?;
+    }
+
+    #[cfg(feature = "preview")]
+    let preview_mode = match &data.preview {
+        Some(config) => preview::preview_mode(config, env, &request_parts, res_options)?,
+        None => PreviewMode::default(),
+    };
+
+    #[cfg(feature = "flash")]
+    let flash_messages = match &data.flash {
+        Some(sealer) => flash::take(sealer, env, &request_parts, res_options).await?,
+        None => flash::Flash::default(),
+    };
+    #[cfg(feature = "forms")]
+    let rejected_form = match &data.flash {
+        Some(sealer) => forms::take(sealer, env, &request_parts, res_options).await?,
+        None => forms::RejectedForm::default(),
+    };
+
+    let content_version = content_version::ContentVersion(
+        content_version::active_version(data.content_versions.as_ref(), env).await,
+    );
+    let deps = di::DepContainer::new(data.deps.clone(), env.clone(), request_parts.clone());
+    let env = app_env::RequestEnv(env.clone());
+    let wait_until = background::WaitUntil(data.context.clone());
+    let locale = locale::RequestLocale::from_accept_language(
+        request_parts.headers.get("Accept-Language")?.as_deref(),
+    );
+    let time = locale::RequestTime::new(Some(req.cf().timezone_name()));
+    let segment_values = data.cache_segments.resolve(&request_parts.headers);
+    let device = device::Device::from_headers(&request_parts.headers);
+    let trailers = trailers::Trailers::new();
+    let signal = abort::RequestSignal::of(&request_parts.edge_request);
+    let stream_buffer = streaming::StreamBuffer(data.stream_buffer);
+    let audited = audit::audit_requested(&data.options, &request_parts.url);
+    let exposed_parts = data.request_headers.apply(&request_parts);
+    // The path the Leptos router matches is encoded as the worker router saw it through `serve`
+    let router_url = normalize::normalize_url(&request_parts.url);
+    let app_fn = data.app_fn.clone();
+    let res_options = res_options.clone();
+    let isolate_states = data.isolate_states.clone();
+    let redirect_query = data.redirect_query;
+
+    Ok(move |cx| {
+        provide_contexts(cx, router_url.to_string(), exposed_parts, res_options);
+        provide_context(cx, request_headers::FullRequestParts(request_parts));
+        isolate::provide_isolate_states(cx, &isolate_states);
+        provide_context(cx, deps);
+        provide_context(cx, env);
+        provide_context(cx, wait_until);
+        provide_context(cx, content_version);
+        provide_context(cx, experiments::Buckets::default());
+        locale::provide_request_locale(cx, locale, time);
+        provide_context(cx, segment_values);
+        provide_context(cx, device);
+        provide_context(cx, trailers);
+        provide_context(cx, stream_buffer);
+        provide_context(cx, redirect_query);
+        if let Some(signal) = signal {
+            provide_context(cx, signal);
+        }
+        #[cfg(feature = "preview")]
+        provide_context(cx, preview_mode);
+        #[cfg(feature = "flash")]
+        provide_context(cx, flash_messages);
+        #[cfg(feature = "forms")]
+        provide_context(cx, rejected_form);
+        if let Some(robots) = route_config.robots {
+            robots::robots_meta(cx, robots);
+        }
+        if route_config.no_hydrate || audited {
+            provide_context(cx, shell::HydrationDisabled);
+        }
+        if audited {
+            provide_context(cx, audit::NoJsAudit);
+        }
+        (app_fn)(cx).into_view(cx)
+    })
+}
+
+/// Renders the app of `data` for `req` with the given [SsrMode](SsrMode). A page that fails to
+/// render is answered by the [ErrorRenderer](ErrorRenderer) of `data`.
+pub(crate) async fn render_with_mode<IV, AppFn>(
+    mut req: worker::Request,
+    env: &worker::Env,
+    data: &WorkerRouterData<IV, AppFn>,
+    mode: SsrMode,
+) -> worker::Result<worker::Response>
+where
+    IV: IntoView + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+{
+    let started_at = worker::Date::now().as_millis();
+    let mode = debug::ssr_mode_override(&data.options, &req).unwrap_or(mode);
+    let method = req.method();
+    let path = req.path();
+
+    let cache_key = match &data.page_cache {
+        Some(cache) if matches!(mode, SsrMode::Async) && matches!(method, worker::Method::Get) => {
+            let url = req.url()?;
+            #[cfg(feature = "preview")]
+            if let Some(config) = &data.preview {
+                if preview::preview_requested(config, &url, req.headers()) {
+                    return render_page(req, env, data, mode).await;
+                }
+            }
+            cache.cache_key(&url, req.headers(), &data.cache_segments)
+        }
+        _ => None,
+    };
+    let cached = match &cache_key {
+        Some(key) => page_cache::cached(key).await,
+        None => None,
+    };
+
+    let response = match cached {
+        Some(page) => Ok(page),
+        None => {
+            let mut response = render_page(req, env, data, mode).await;
+            if let (Some(cache), Some(key), Ok(response)) =
+                (&data.page_cache, cache_key, response.as_mut())
+            {
+                let wait_until = background::WaitUntil(data.context.clone());
+                if let Err(err) = cache.store(key, response, &wait_until) {
+                    tracing::warn!("failed to cache {path}: {err}");
+                }
+            }
+            response
+        }
+    };
+
+    if let Some(metrics) = &data.request_metrics {
+        let status = response.as_ref().ok().map(worker::Response::status_code);
+        let route = analytics::route_of(&path);
+        metrics.record(env, &route, Some(mode), &method, status, started_at);
+    }
+    response
+}
+
+/// Renders the app of `data` for `req`, or the error page if it fails.
+async fn render_page<IV, AppFn>(
+    mut req: worker::Request,
+    env: &worker::Env,
+    data: &WorkerRouterData<IV, AppFn>,
+    mode: SsrMode,
+) -> worker::Result<worker::Response>
+where
+    IV: IntoView + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+{
+    let accept = req.headers().get("Accept")?;
+    let path = req.path();
+
+    let rendered = async {
+        let mut res_options = ResponseOptions::default();
+        let app = prepare_app(&mut req, env, data, &mut res_options).await?;
+        let options = &data.options;
+
+        match mode {
+            SsrMode::OutOfOrder => {
+                stream_app(options, data.shell, app, res_options, |_| {}, false).await
+            }
+            SsrMode::PartiallyBlocked => {
+                stream_app(options, data.shell, app, res_options, |_| {}, true).await
+            }
+            SsrMode::Async => {
+                render_app_async_helper(options, data.shell, app, res_options, |_| {}).await
+            }
+            SsrMode::InOrder => {
+                stream_app_in_order(options, data.shell, app, res_options, |_| {}).await
+            }
+        }
+    }
+    .await;
+
+    rendered.or_else(|err| {
+        tracing::error!("failed to render {path}: {err}");
+        #[cfg(feature = "admin")]
+        admin::record_error(format!("render {path}: {err}"));
+        // The cause is only shown in development, it may reveal internals
+        let message = match data.options.env {
+            leptos::leptos_config::Env::DEV => err.to_string(),
+            _ => "Internal server error".to_string(),
+        };
+        data.error_renderer.render(
+            accept.as_deref(),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &message,
+        )
+    })
+}
+
 #[tracing::instrument(level = "trace", fields(error), skip_all)]
 pub fn render_app_to_stream_with_context<'a, 'b, IV, AppFn>(
     method: LeptosMethod,
@@ -291,31 +971,10 @@ pub fn render_app_to_stream_with_context<'a, 'b, IV, AppFn>(
 ) -> worker::Router<'b, WorkerRouterData<IV, AppFn>>
 where
     IV: IntoView + 'static,
-    AppFn: Fn(leptos::Scope) -> IV + Clone + Send + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
 {
-    let handler = |mut req: worker::Request,
-                   ctx: worker::RouteContext<WorkerRouterData<IV, AppFn>>| async move {
-        let options = ctx.data.options;
-        let app_fn = ctx.data.app_fn;
-        let res_options = ResponseOptions::default();
-        let app = {
-            let app_fn = app_fn.clone();
-            let res_options = res_options.clone();
-
-            let request_parts = generate_request_parts(&mut req).await?;
-
-            move |cx| {
-                provide_contexts(
-                    cx,
-                    request_parts.url.to_string(),
-                    request_parts,
-                    res_options,
-                );
-                (app_fn)(cx).into_view(cx)
-            }
-        };
-
-        stream_app(&options, app, res_options, |_| {}, false).await
+    let handler = |req: worker::Request, ctx: worker::RouteContext<WorkerRouterData<IV, AppFn>>| async move {
+        render_with_mode(req, &ctx.env, &ctx.data, SsrMode::OutOfOrder).await
     };
 
     match method {
@@ -341,31 +1000,10 @@ pub fn render_app_to_stream_with_context_and_replace_blocks<'a, 'b, IV, AppFn>(
 ) -> worker::Router<'b, WorkerRouterData<IV, AppFn>>
 where
     IV: IntoView + 'static,
-    AppFn: Fn(leptos::Scope) -> IV + Clone + Send + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
 {
-    let handler = |mut req: worker::Request,
-                   ctx: worker::RouteContext<WorkerRouterData<IV, AppFn>>| async move {
-        let options = ctx.data.options;
-        let app_fn = ctx.data.app_fn;
-        let res_options = ResponseOptions::default();
-        let app = {
-            let app_fn = app_fn.clone();
-            let res_options = res_options.clone();
-
-            let request_parts = generate_request_parts(&mut req).await?;
-
-            move |cx| {
-                provide_contexts(
-                    cx,
-                    request_parts.url.to_string(),
-                    request_parts,
-                    res_options,
-                );
-                (app_fn)(cx).into_view(cx)
-            }
-        };
-
-        stream_app(&options, app, res_options, |_| {}, true).await
+    let handler = |req: worker::Request, ctx: worker::RouteContext<WorkerRouterData<IV, AppFn>>| async move {
+        render_with_mode(req, &ctx.env, &ctx.data, SsrMode::PartiallyBlocked).await
     };
 
     match method {
@@ -432,8 +1070,9 @@ where
 #[tracing::instrument(level = "trace", fields(error), skip_all)]
 async fn render_app_async_helper(
     options: &LeptosOptions,
+    shell: ShellHooks,
     app: impl FnOnce(leptos::Scope) -> View + 'static,
-    mut res_options: ResponseOptions,
+    res_options: ResponseOptions,
     additional_context: impl Fn(leptos::Scope) + 'static + Clone + Send,
 ) -> Result<worker::Response, worker::Error> {
     let (stream, runtime, scope) =
@@ -443,9 +1082,24 @@ async fn render_app_async_helper(
             additional_context,
         );
 
+    let cx = leptos::Scope { runtime, id: scope };
+    let hooks = shell.render(cx);
+    let hydrate = use_context::<shell::HydrationDisabled>(cx).is_none();
+    let audited = use_context::<audit::NoJsAudit>(cx).is_some();
+    let trailers = use_context::<trailers::Trailers>(cx);
+    let stream = abort::until_aborted(stream, use_context::<abort::RequestSignal>(cx));
     let html = build_async_response(stream, options, runtime, scope).await;
+    let html = if hydrate {
+        html
+    } else {
+        shell::strip_hydration(html, options)
+    };
+    let html = shell::apply_hooks(hooks, html);
+    if audited {
+        audit::audit_html(&html);
+    }
 
-    let status = res_options.status.unwrap_or(200);
+    let status = res_options.status().unwrap_or(200);
 
     let mut res = worker::Response::from_html(html)?;
 
@@ -453,7 +1107,11 @@ async fn render_app_async_helper(
 
     // Add headers manipulated in the response
     for (key, value) in res_options.headers.into_iter() {
-        res_options.append_header(&key, &value)?;
+        res.headers_mut().append(&key, &value)?;
+    }
+    // The whole page is rendered before anything is sent, so trailers still fit in the headers
+    for (key, value) in trailers.iter().flat_map(|trailers| trailers.finish()) {
+        res.headers_mut().append(&key, &value)?;
     }
 
     Ok(res.with_status(status))
@@ -467,31 +1125,10 @@ pub fn render_app_async_with_context<'a, 'b, IV, AppFn>(
 ) -> worker::Router<'b, WorkerRouterData<IV, AppFn>>
 where
     IV: IntoView + 'static,
-    AppFn: Fn(leptos::Scope) -> IV + Clone + Send + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
 {
-    let handler = |mut req: worker::Request,
-                   ctx: worker::RouteContext<WorkerRouterData<IV, AppFn>>| async move {
-        let options = ctx.data.options;
-        let app_fn = ctx.data.app_fn;
-        let res_options = ResponseOptions::default();
-        let app = {
-            let app_fn = app_fn.clone();
-            let res_options = res_options.clone();
-
-            let request_parts = generate_request_parts(&mut req).await?;
-
-            move |cx| {
-                provide_contexts(
-                    cx,
-                    request_parts.url.to_string(),
-                    request_parts,
-                    res_options,
-                );
-                (app_fn)(cx).into_view(cx)
-            }
-        };
-
-        render_app_async_helper(&options, app, res_options, |_| {}).await
+    let handler = |req: worker::Request, ctx: worker::RouteContext<WorkerRouterData<IV, AppFn>>| async move {
+        render_with_mode(req, &ctx.env, &ctx.data, SsrMode::Async).await
     };
 
     match method {
@@ -511,31 +1148,10 @@ pub fn render_app_to_stream_in_order_with_context<'a, 'b, IV, AppFn>(
 ) -> worker::Router<'b, WorkerRouterData<IV, AppFn>>
 where
     IV: IntoView + 'static,
-    AppFn: Fn(leptos::Scope) -> IV + Clone + Send + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
 {
-    let handler = |mut req: worker::Request,
-                   ctx: worker::RouteContext<WorkerRouterData<IV, AppFn>>| async move {
-        let options = ctx.data.options;
-        let app_fn = ctx.data.app_fn;
-        let res_options = ResponseOptions::default();
-        let app = {
-            let app_fn = app_fn.clone();
-            let res_options = res_options.clone();
-
-            let request_parts = generate_request_parts(&mut req).await?;
-
-            move |cx| {
-                provide_contexts(
-                    cx,
-                    request_parts.url.to_string(),
-                    request_parts,
-                    res_options,
-                );
-                (app_fn)(cx).into_view(cx)
-            }
-        };
-
-        stream_app_in_order(&options, app, res_options, |_| {}).await
+    let handler = |req: worker::Request, ctx: worker::RouteContext<WorkerRouterData<IV, AppFn>>| async move {
+        render_with_mode(req, &ctx.env, &ctx.data, SsrMode::InOrder).await
     };
 
     match method {
@@ -549,6 +1165,7 @@ where
 
 async fn stream_app_in_order(
     options: &LeptosOptions,
+    shell: ShellHooks,
     app: impl FnOnce(leptos::Scope) -> View + 'static,
     res_options: ResponseOptions,
     additional_context: impl Fn(leptos::Scope) + 'static + Clone + Send,
@@ -560,31 +1177,59 @@ async fn stream_app_in_order(
             additional_context,
         );
 
-    build_stream_response(options, res_options, stream, runtime, scope).await
+    build_stream_response(options, shell, res_options, stream, runtime, scope).await
 }
 
 #[tracing::instrument(level = "trace", fields(error), skip_all)]
 async fn build_stream_response(
     options: &LeptosOptions,
-    mut res_options: ResponseOptions,
+    shell: ShellHooks,
+    res_options: ResponseOptions,
     stream: impl Stream<Item = String> + 'static,
     runtime: RuntimeId,
     scope: ScopeId,
 ) -> worker::Result<worker::Response> {
     let cx = leptos::Scope { runtime, id: scope };
-    let mut stream = Box::pin(stream);
+    let mut stream = Box::pin(abort::until_aborted(
+        stream,
+        use_context::<abort::RequestSignal>(cx),
+    ));
 
     // wait for any blocking resources to load before pulling metadata
     let first_app_chunk = stream.next().await.unwrap_or_default();
 
-    let (head, tail) = html_parts_separated(cx, options, use_context::<MetaContext>(cx).as_ref());
+    // The first chunk starts with the <body> tag, which the shell hooks need to see
+    let (head, first_app_chunk, tail) = shell::document_parts(cx, options, shell, first_app_chunk);
+
+    // The audit needs the whole document, so it is only collected when the page is audited
+    let rendered = Rc::new(RefCell::new(String::new()));
+    let audited = use_context::<audit::NoJsAudit>(cx).is_some();
+    let trailers = use_context::<trailers::Trailers>(cx);
+    let buffer = use_context::<streaming::StreamBuffer>(cx)
+        .map(|buffer| buffer.0)
+        .unwrap_or(streaming::DEFAULT_STREAM_BUFFER);
 
     let mut stream = Box::pin(
-        futures::stream::once(async move { head.clone() })
+        futures::stream::once(async move { head })
             .chain(futures::stream::once(async move { first_app_chunk }).chain(stream))
+            .inspect({
+                let rendered = rendered.clone();
+                move |html| {
+                    if audited {
+                        rendered.borrow_mut().push_str(html);
+                    }
+                }
+            })
             .chain(futures::stream::once(async move {
+                if audited {
+                    audit::audit_html(&rendered.borrow());
+                }
                 runtime.dispose();
-                tail.to_string()
+                // The headers are long gone, so the trailers go in front of </body>
+                match trailers {
+                    Some(trailers) => trailers.render_inline() + &tail,
+                    None => tail,
+                }
             }))
             .map(|html| worker::Result::Ok(html.into_bytes())),
     );
@@ -593,16 +1238,16 @@ async fn build_stream_response(
     let first_chunk = stream.next().await;
     let second_chunk = stream.next().await;
 
-    let status = res_options.status.unwrap_or(200);
+    let status = res_options.status().unwrap_or(200);
 
     let complete_stream =
         futures::stream::iter([first_chunk.unwrap(), second_chunk.unwrap()]).chain(stream);
-    let mut response = worker::Response::from_stream(complete_stream)?;
+    let mut response = streaming::response_from_stream(complete_stream, buffer)?;
     response.headers_mut().set("Content-Type", "text/html")?;
 
     // Add headers manipulated in the response
     for (key, value) in res_options.headers.into_iter() {
-        res_options.append_header(&key, &value)?;
+        response.headers_mut().append(&key, &value)?;
     }
 
     Ok(response.with_status(status))
@@ -611,6 +1256,7 @@ async fn build_stream_response(
 #[tracing::instrument(level = "trace", fields(error), skip_all)]
 async fn stream_app(
     options: &LeptosOptions,
+    shell: ShellHooks,
     app: impl FnOnce(leptos::Scope) -> View + 'static,
     res_options: ResponseOptions,
     additional_context: impl Fn(leptos::Scope) + 'static + Clone,
@@ -624,7 +1270,7 @@ async fn stream_app(
             replace_blocks,
         );
 
-    build_stream_response(options, res_options, stream, runtime, scope).await
+    build_stream_response(options, shell, res_options, stream, runtime, scope).await
 }
 
 fn provide_contexts(
@@ -644,6 +1290,15 @@ fn provide_contexts(
 }
 
 impl ResponseOptions {
+    /// The status the response will be sent with, if overridden
+    #[allow(deprecated)]
+    pub fn status(&self) -> Option<u16> {
+        self.status.or(self.shared_status.get())
+    }
+    /// Override the status the response will be sent with
+    pub fn set_status(&self, status: u16) {
+        self.shared_status.set(Some(status));
+    }
     /// Insert a header, overwriting any previous value with the same key
     pub fn insert_header(&mut self, key: &str, value: &str) -> worker::Result<()> {
         self.headers.set(key, value)
@@ -652,14 +1307,22 @@ impl ResponseOptions {
     pub fn append_header(&mut self, key: &str, value: &str) -> worker::Result<()> {
         self.headers.append(key, value)
     }
+    /// Make browsers save the response as `filename` instead of displaying it
+    pub fn as_attachment(&mut self, filename: &str) -> worker::Result<()> {
+        self.insert_header(
+            "Content-Disposition",
+            &download::content_disposition(filename),
+        )
+    }
 }
 
 impl<'a, IV, AppFn> LeptosRoutes for worker::Router<'a, WorkerRouterData<IV, AppFn>>
 where
     IV: IntoView + 'static,
-    AppFn: Fn(leptos::Scope) -> IV + Clone + Send + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
 {
     fn leptos_routes(self, paths: Vec<RouteListing>) -> Self {
+        analytics::register_routes(paths.iter().map(RouteListing::path));
         let mut cf_router = self;
         for listing in paths.iter() {
             let path = listing.path();
@@ -685,10 +1348,25 @@ where
     }
 }
 
+impl Clone for ResponseOptions {
+    #[allow(deprecated)]
+    fn clone(&self) -> Self {
+        Self {
+            status: self.status,
+            shared_status: self.shared_status.clone(),
+            // `worker::Headers::clone` copies the headers into a new JS object, while the JS
+            // object itself is shared by reference
+            headers: worker::Headers(self.headers.0.clone()),
+        }
+    }
+}
+
 impl Default for ResponseOptions {
+    #[allow(deprecated)]
     fn default() -> Self {
         Self {
-            status: Some(200),
+            status: None,
+            shared_status: Rc::new(Cell::new(Some(200))),
             headers: Headers::new(),
         }
     }
@@ -0,0 +1,208 @@
+//! Server-pushed updates over WebSockets, fanned out by a Durable Object.
+//!
+//! Each channel, e.g. `counter` or `presence:room-1`, is an instance of [LiveDurableObject] that
+//! holds the sockets of its subscribers. Server functions and other handlers publish JSON values to a
+//! channel with [use_live], and every subscriber receives them:
+//!
+//! ```ignore
+//! #[server(Increment, "/api")]
+//! pub async fn increment(cx: Scope) -> Result<(), ServerFnError> {
+//!     let count = bump_counter(cx).await?;
+//!     use_live(cx, "LIVE")?.publish("counter", &count).await?;
+//!     Ok(())
+//! }
+//!
+//! router
+//!     .leptos_routes(routes)
+//!     .live_route("/live/:channel", "LIVE")
+//! ```
+//!
+//! ```toml
+//! [durable_objects]
+//! bindings = [{ name = "LIVE", class_name = "LiveDurableObject" }]
+//!
+//! [[migrations]]
+//! tag = "v2"
+//! new_classes = ["LiveDurableObject"]
+//! ```
+//!
+//! Components subscribe with `use_live_signal` of the `leptos-cloudflare-live` crate, which the app
+//! depends on in both its client and server builds, since this crate is server only:
+//!
+//! ```ignore
+//! let count = use_live_signal(cx, "/live/counter", initial_count);
+//! view! { cx, <p>{move || count.get()} " clicks"</p> }
+//! ```
+//!
+//! The signal holds the rendered value until the page is hydrated, then each published value. A new
+//! subscriber is sent the last published value at once, so it doesn't miss what changed while the
+//! page loaded. [subscribers](LiveChannels::subscribers) counts the sockets of a channel, e.g. for
+//! presence.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use http::StatusCode;
+use leptos::{IntoView, Scope};
+use serde::Serialize;
+use wasm_bindgen::JsValue;
+use worker::{durable_object, Env, ObjectNamespace, Request, Response, State, WebSocket};
+
+use crate::normalize::decode_param;
+use crate::route_config::{extract_params, path_matches, static_segments};
+use crate::{use_env, LeptosCloudflareError, WorkerRouterData};
+
+/// The storage key of the last value published to a channel.
+const LAST_VALUE_KEY: &str = "last";
+
+thread_local! {
+    // Route pattern -> binding of the namespace, since the router only accepts function pointers
+    static LIVE_ROUTES: RefCell<HashMap<String, &'static str>> = RefCell::new(HashMap::new());
+}
+
+/// The live channels of the namespace bound as `binding`, from the bindings of the current request.
+pub fn use_live(cx: Scope, binding: &str) -> Result<LiveChannels, LeptosCloudflareError> {
+    Ok(LiveChannels::from_env(&use_env(cx)?, binding)?)
+}
+
+/// The channels of a [LiveDurableObject] namespace.
+pub struct LiveChannels {
+    namespace: ObjectNamespace,
+}
+
+impl LiveChannels {
+    /// The channels of the namespace bound as `binding` in `env`.
+    pub fn from_env(env: &Env, binding: &str) -> worker::Result<Self> {
+        Ok(Self {
+            namespace: env.durable_object(binding)?,
+        })
+    }
+
+    /// Sends `value` as JSON to the subscribers of `channel`.
+    pub async fn publish<T: Serialize>(
+        &self,
+        channel: &str,
+        value: &T,
+    ) -> Result<(), LeptosCloudflareError> {
+        let body = serde_json::to_string(value).map_err(worker::Error::from)?;
+        let mut init = worker::RequestInit::new();
+        init.with_method(worker::Method::Post)
+            .with_body(Some(JsValue::from_str(&body)));
+        let req = Request::new_with_init("https://live/publish", &init)?;
+        self.stub(channel)?.fetch_with_request(req).await?;
+        Ok(())
+    }
+
+    /// The number of sockets subscribed to `channel`. Sockets closed since the last value was
+    /// published are still counted.
+    pub async fn subscribers(&self, channel: &str) -> Result<usize, LeptosCloudflareError> {
+        let req = Request::new("https://live/subscribers", worker::Method::Get)?;
+        let mut response = self.stub(channel)?.fetch_with_request(req).await?;
+        Ok(response.json::<usize>().await?)
+    }
+
+    /// Hands the WebSocket handshake `req` to `channel`, which answers it with the client end of a
+    /// socket subscribed to the channel.
+    pub async fn subscribe(&self, channel: &str, req: Request) -> worker::Result<Response> {
+        self.stub(channel)?.fetch_with_request(req).await
+    }
+
+    fn stub(&self, channel: &str) -> worker::Result<worker::Stub> {
+        self.namespace.id_from_name(channel)?.get_stub()
+    }
+}
+
+pub trait LiveRoutes {
+    /// Subscribes WebSocket handshakes to `path` to the channel named by its `:channel` parameter, in
+    /// the namespace bound as `binding`.
+    fn live_route(self, path: &str, binding: &'static str) -> Self;
+}
+
+impl<'a, IV, AppFn> LiveRoutes for worker::Router<'a, WorkerRouterData<IV, AppFn>>
+where
+    IV: IntoView + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+{
+    fn live_route(self, path: &str, binding: &'static str) -> Self {
+        LIVE_ROUTES.with(|routes| routes.borrow_mut().insert(path.to_string(), binding));
+        self.get_async(path, dispatch_live::<IV, AppFn>)
+    }
+}
+
+async fn dispatch_live<IV, AppFn>(
+    req: Request,
+    ctx: worker::RouteContext<WorkerRouterData<IV, AppFn>>,
+) -> worker::Result<Response>
+where
+    IV: IntoView + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+{
+    let path = req.path();
+    let matched = LIVE_ROUTES.with(|routes| {
+        let routes = routes.borrow();
+        let (pattern, binding) = routes
+            .iter()
+            .filter(|(pattern, _)| path_matches(pattern, &path))
+            .max_by_key(|(pattern, _)| static_segments(pattern))?;
+        let channel = extract_params(pattern, &path)?.remove("channel")?;
+        Some((decode_param(&channel).ok()?, *binding))
+    });
+    let Some((channel, binding)) = matched else {
+        return ctx
+            .data
+            .error_renderer
+            .render_for(&req, StatusCode::NOT_FOUND, "Not found");
+    };
+    let upgrade = req.headers().get("Upgrade")?;
+    if !upgrade.is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket")) {
+        return ctx.data.error_renderer.render_for(
+            &req,
+            StatusCode::UPGRADE_REQUIRED,
+            "Expected a WebSocket handshake",
+        );
+    }
+
+    LiveChannels::from_env(&ctx.env, binding)?
+        .subscribe(&channel, req)
+        .await
+}
+
+/// The Durable Object class behind [LiveChannels]. Each channel is a separate instance.
+#[durable_object]
+pub struct LiveDurableObject {
+    state: State,
+    sockets: Vec<WebSocket>,
+}
+
+#[durable_object]
+impl DurableObject for LiveDurableObject {
+    fn new(state: State, _env: Env) -> Self {
+        Self {
+            state,
+            sockets: Vec::new(),
+        }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> worker::Result<Response> {
+        match req.path().as_str() {
+            "/publish" => {
+                let value = req.text().await?;
+                self.state.storage().put(LAST_VALUE_KEY, &value).await?;
+                // Sockets that can't be sent to were closed by their client
+                self.sockets
+                    .retain(|socket| socket.send_with_str(&value).is_ok());
+                Response::empty()
+            }
+            "/subscribers" => Response::from_json(&self.sockets.len()),
+            _ => {
+                let pair = worker::WebSocketPair::new()?;
+                pair.server.accept()?;
+                if let Ok(last) = self.state.storage().get::<String>(LAST_VALUE_KEY).await {
+                    pair.server.send_with_str(&last)?;
+                }
+                self.sockets.push(pair.server);
+                Response::from_websocket(pair.client)
+            }
+        }
+    }
+}
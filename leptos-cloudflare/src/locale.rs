@@ -0,0 +1,196 @@
+//! The visitor's locale and time zone, for formatting dates and numbers during SSR.
+//!
+//! The server renders in the visitor's locale (from `Accept-Language`) and time zone (from Cloudflare's
+//! `cf` object), which are provided as [RequestLocale] and [RequestTime] in the context of every request:
+//!
+//! ```ignore
+//! #[component]
+//! fn PublishedAt(cx: Scope, millis: f64) -> impl IntoView {
+//!     let locale = use_request_locale(cx);
+//!     let time = use_request_time(cx);
+//!     view! { cx, <time>{format_date_time(&locale.locale, &time.time_zone, millis)}</time> }
+//! }
+//! ```
+//!
+//! The formatting is done by `Intl`, like in the browser. Both values are also rendered as
+//! `<meta name="request-locale">` and `<meta name="request-time-zone">`, so that the hydrated app can
+//! read them and format with the same settings instead of the browser's, which would change the text
+//! on hydration.
+
+use leptos::{use_context, view, Scope};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+
+/// The locale used when the request doesn't ask for one.
+pub const DEFAULT_LOCALE: &str = "en-US";
+
+/// The time zone used when Cloudflare doesn't know the visitor's.
+pub const DEFAULT_TIME_ZONE: &str = "UTC";
+
+/// The locales the visitor accepts, by preference.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestLocale {
+    /// The preferred locale, e.g. `de-CH`.
+    pub locale: String,
+    /// All accepted locales, the preferred one first.
+    pub accepted: Vec<String>,
+}
+
+impl RequestLocale {
+    /// Parses an `Accept-Language` header, e.g. `de-CH, de;q=0.9, en;q=0.8`.
+    pub fn from_accept_language(header: Option<&str>) -> Self {
+        let mut accepted: Vec<(String, f32)> = header
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|part| {
+                let mut params = part.split(';');
+                let tag = params.next()?.trim();
+                let quality = params
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .and_then(|quality| quality.parse().ok())
+                    .unwrap_or(1.0);
+                (is_language_tag(tag) && quality > 0.0).then(|| (tag.to_string(), quality))
+            })
+            .collect();
+        // Stable, so locales with the same quality keep the order of the header
+        accepted.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        let accepted: Vec<String> = accepted.into_iter().map(|(tag, _)| tag).collect();
+
+        Self {
+            locale: accepted
+                .first()
+                .cloned()
+                .unwrap_or_else(|| DEFAULT_LOCALE.to_string()),
+            accepted,
+        }
+    }
+
+    /// The first accepted locale that the app supports, matching `de-CH` to a supported `de`, or the first
+    /// supported locale if none match.
+    pub fn negotiate<'a>(&self, supported: &[&'a str]) -> Option<&'a str> {
+        self.accepted
+            .iter()
+            .find_map(|accepted| {
+                let language = accepted.split('-').next().unwrap_or_default();
+                supported
+                    .iter()
+                    .find(|supported| supported.eq_ignore_ascii_case(accepted))
+                    .or_else(|| {
+                        supported
+                            .iter()
+                            .find(|supported| supported.eq_ignore_ascii_case(language))
+                    })
+            })
+            .or(supported.first())
+            .copied()
+    }
+}
+
+/// When the request came in and the visitor's time zone.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestTime {
+    /// Milliseconds since the Unix epoch. Workers only advance the clock on I/O, so this is the same for
+    /// the whole render.
+    pub now_millis: f64,
+    /// An IANA time zone, e.g. `Europe/Zurich`.
+    pub time_zone: String,
+}
+
+impl RequestTime {
+    pub(crate) fn new(time_zone: Option<String>) -> Self {
+        Self {
+            now_millis: js_sys::Date::now(),
+            time_zone: time_zone
+                .filter(|time_zone| !time_zone.is_empty())
+                .unwrap_or_else(|| DEFAULT_TIME_ZONE.to_string()),
+        }
+    }
+}
+
+/// The locale of the current request, or the [DEFAULT_LOCALE] outside of a request.
+pub fn use_request_locale(cx: Scope) -> RequestLocale {
+    use_context::<RequestLocale>(cx).unwrap_or_else(|| RequestLocale::from_accept_language(None))
+}
+
+/// The time of the current request, or the current time in the [DEFAULT_TIME_ZONE] outside of a request.
+pub fn use_request_time(cx: Scope) -> RequestTime {
+    use_context::<RequestTime>(cx).unwrap_or_else(|| RequestTime::new(None))
+}
+
+/// Formats a point in time, in milliseconds since the Unix epoch, as a date and time.
+pub fn format_date_time(locale: &str, time_zone: &str, millis: f64) -> String {
+    let options = intl_options(&[
+        ("timeZone", time_zone),
+        ("dateStyle", "medium"),
+        ("timeStyle", "short"),
+    ]);
+    let format = js_sys::Intl::DateTimeFormat::new(&locales(locale), &options).format();
+    call_format(
+        &format,
+        &js_sys::Date::new(&JsValue::from_f64(millis)).into(),
+    )
+}
+
+/// Formats a point in time, in milliseconds since the Unix epoch, as a date.
+pub fn format_date(locale: &str, time_zone: &str, millis: f64) -> String {
+    let options = intl_options(&[("timeZone", time_zone), ("dateStyle", "long")]);
+    let format = js_sys::Intl::DateTimeFormat::new(&locales(locale), &options).format();
+    call_format(
+        &format,
+        &js_sys::Date::new(&JsValue::from_f64(millis)).into(),
+    )
+}
+
+/// Formats a number with the separators of `locale`.
+pub fn format_number(locale: &str, number: f64) -> String {
+    let format = js_sys::Intl::NumberFormat::new(&locales(locale), &js_sys::Object::new()).format();
+    call_format(&format, &JsValue::from_f64(number))
+}
+
+/// Formats an amount of money, e.g. `format_currency("de-CH", "CHF", 12.5)`.
+pub fn format_currency(locale: &str, currency: &str, amount: f64) -> String {
+    let options = intl_options(&[("style", "currency"), ("currency", currency)]);
+    let format = js_sys::Intl::NumberFormat::new(&locales(locale), &options).format();
+    call_format(&format, &JsValue::from_f64(amount))
+}
+
+/// Provides the locale and time of the request, and registers the `<meta>` tags the client reads them from.
+pub(crate) fn provide_request_locale(cx: Scope, locale: RequestLocale, time: RequestTime) {
+    let _ = view! { cx,
+        <leptos_meta::Meta name="request-locale" content=locale.locale.clone()/>
+        <leptos_meta::Meta name="request-time-zone" content=time.time_zone.clone()/>
+    };
+    leptos::provide_context(cx, locale);
+    leptos::provide_context(cx, time);
+}
+
+/// Whether `tag` is shaped like a BCP 47 language tag, which `Intl` throws on otherwise.
+fn is_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let language = subtags.next().unwrap_or_default();
+    (2..=8).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+fn locales(locale: &str) -> js_sys::Array {
+    js_sys::Array::of1(&JsValue::from_str(locale))
+}
+
+fn intl_options(options: &[(&str, &str)]) -> js_sys::Object {
+    let object = js_sys::Object::new();
+    for (key, value) in options {
+        let _ = js_sys::Reflect::set(&object, &JsValue::from_str(key), &JsValue::from_str(value));
+    }
+    object
+}
+
+fn call_format(format: &js_sys::Function, value: &JsValue) -> String {
+    format
+        .call1(&JsValue::NULL, value)
+        .ok()
+        .and_then(|formatted| formatted.as_string())
+        .unwrap_or_default()
+}
@@ -0,0 +1,160 @@
+//! Distributed locks backed by a Durable Object.
+//!
+//! A Durable Object exists once across all of Cloudflare's network and handles its requests one at a
+//! time, which makes it a natural mutex for critical sections that must not run concurrently in several
+//! isolates (e.g. regenerating a static page). Add the class to `wrangler.toml`:
+//!
+//! ```toml
+//! [durable_objects]
+//! bindings = [{ name = "LOCKS", class_name = "LockDurableObject" }]
+//!
+//! [[migrations]]
+//! tag = "v1"
+//! new_classes = ["LockDurableObject"]
+//! ```
+
+use std::future::Future;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use wasm_bindgen::JsValue;
+use worker::{durable_object, Env, ObjectNamespace, Request, Response, State};
+
+use crate::LeptosCloudflareError;
+
+#[derive(Error, Debug)]
+pub enum LockError {
+    #[error("The lock {0} is held by someone else")]
+    Busy(String),
+    #[error(transparent)]
+    Worker(#[from] worker::Error),
+}
+
+impl From<LockError> for LeptosCloudflareError {
+    fn from(err: LockError) -> Self {
+        match err {
+            LockError::Busy(_) => LeptosCloudflareError::Conflict(err.to_string()),
+            LockError::Worker(err) => LeptosCloudflareError::Worker(err),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct LockRequest {
+    holder: String,
+    ttl_ms: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LockState {
+    holder: String,
+    expires_at: u64,
+}
+
+/// Runs `op` while holding the lock `key` of the lock Durable Object bound as `namespace`.
+///
+/// Fails with [LockError::Busy] without running `op` if another holder has the lock. The lock is released
+/// when `op` completes; `ttl` bounds how long it stays held if the isolate dies before releasing it, so it
+/// should be longer than `op` can take.
+///
+/// ```ignore
+/// let locks = env.durable_object("LOCKS")?;
+/// with_lock(&locks, "regenerate:/blog", Duration::from_secs(30), || regenerate_blog(&env)).await?;
+/// ```
+pub async fn with_lock<F, Fut, T>(
+    namespace: &ObjectNamespace,
+    key: &str,
+    ttl: Duration,
+    op: F,
+) -> Result<T, LockError>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = T>,
+{
+    let stub = namespace.id_from_name(key)?.get_stub()?;
+    let holder = format!(
+        "{:x}-{:x}",
+        worker::Date::now().as_millis(),
+        (js_sys::Math::random() * u32::MAX as f64) as u32
+    );
+    let body = LockRequest {
+        holder,
+        ttl_ms: ttl.as_millis() as u64,
+    };
+
+    let acquired = stub
+        .fetch_with_request(lock_request("acquire", &body)?)
+        .await?;
+    if acquired.status_code() != 200 {
+        return Err(LockError::Busy(key.to_string()));
+    }
+
+    let output = op().await;
+
+    // An expired lock may already belong to someone else, in which case the object ignores this.
+    // `op` already ran, so a failed release is only logged: the lock expires with its TTL anyway
+    let released = match lock_request("release", &body) {
+        Ok(request) => stub.fetch_with_request(request).await.map(drop),
+        Err(err) => Err(err),
+    };
+    if let Err(err) = released {
+        tracing::warn!("failed to release the lock {key}, it expires in {ttl:?}: {err}");
+    }
+
+    Ok(output)
+}
+
+fn lock_request(action: &str, body: &LockRequest) -> worker::Result<Request> {
+    let mut init = worker::RequestInit::new();
+    init.with_method(worker::Method::Post)
+        .with_body(Some(JsValue::from_str(&serde_json::to_string(body)?)));
+    Request::new_with_init(&format!("https://lock/{action}"), &init)
+}
+
+/// The Durable Object class behind [with_lock]. Each lock key is a separate instance.
+#[durable_object]
+pub struct LockDurableObject {
+    state: State,
+}
+
+#[durable_object]
+impl DurableObject for LockDurableObject {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> worker::Result<Response> {
+        let body = req.json::<LockRequest>().await?;
+        let now = worker::Date::now().as_millis();
+        let mut storage = self.state.storage();
+        let current = storage.get::<LockState>("lock").await.ok();
+
+        match req.path().as_str() {
+            "/acquire" => match current {
+                Some(lock) if lock.expires_at > now && lock.holder != body.holder => {
+                    Response::error("Locked", 409)
+                }
+                _ => {
+                    storage
+                        .put(
+                            "lock",
+                            LockState {
+                                holder: body.holder,
+                                expires_at: now + body.ttl_ms,
+                            },
+                        )
+                        .await?;
+                    Response::ok("")
+                }
+            },
+            "/release" => {
+                if current.is_some_and(|lock| lock.holder == body.holder) {
+                    storage.delete("lock").await?;
+                }
+                Response::ok("")
+            }
+            _ => Response::error("Not found", 404),
+        }
+    }
+}
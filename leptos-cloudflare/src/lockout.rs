@@ -0,0 +1,550 @@
+//! Login throttling with exponentially growing lockouts.
+//!
+//! A [LoginThrottle] counts the failed attempts per key (e.g. `login:<email>` or `login-ip:<address>`).
+//! Once a key reaches [max_failures](LoginThrottle::max_failures) failures, it is locked out for
+//! [base_lockout](LoginThrottle::base_lockout), and each further lockout doubles that up to
+//! [max_lockout](LoginThrottle::max_lockout). [guard](LoginThrottle::guard) wraps the body of an
+//! authentication server function:
+//!
+//! ```ignore
+//! #[server(LogIn, "/api")]
+//! pub async fn log_in(cx: Scope, email: String, password: String) -> Result<(), ServerFnError> {
+//!     let throttle = LoginThrottle::new(ThrottleStore::DurableObject("LOCKOUTS"));
+//!     throttle
+//!         .guard(cx, &format!("login:{email}"), async {
+//!             check_password(cx, &email, &password).await
+//!         })
+//!         .await
+//! }
+//! ```
+//!
+//! [guard](LoginThrottle::guard) reserves each attempt before running it: a key accepts no more
+//! attempts in flight than the failures it has left before a lockout, so a burst of parallel guesses
+//! can't all pass the check before the first failure is counted. An attempt that never settles, e.g.
+//! because the isolate was stopped, stops counting after a minute.
+//!
+//! Counters live in KV or in a Durable Object. KV is cheaper but eventually consistent, so concurrent
+//! attempts may be undercounted or reserved twice; the Durable Object reserves and counts exactly. Add
+//! its class to `wrangler.toml` to use it:
+//!
+//! ```toml
+//! [durable_objects]
+//! bindings = [{ name = "LOCKOUTS", class_name = "LockoutDurableObject" }]
+//!
+//! [[migrations]]
+//! tag = "v2"
+//! new_classes = ["LockoutDurableObject"]
+//! ```
+//!
+//! Every failure, lockout and rejected attempt is logged as a [LockoutEvent] with the `audit` tracing
+//! target, and passed to the hook set with [on_event](LoginThrottle::on_event).
+
+use std::future::Future;
+use std::time::Duration;
+
+use leptos::{use_context, Scope};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+use worker::{durable_object, Env, Request, Response, State};
+
+use crate::app_env::RequestEnv;
+use crate::LeptosCloudflareError;
+
+/// Where the counters are kept. Both name a binding.
+#[derive(Debug, Clone, Copy)]
+pub enum ThrottleStore {
+    Kv(&'static str),
+    DurableObject(&'static str),
+}
+
+/// How long a reserved attempt counts as in flight if it is never settled.
+const PENDING_TTL_MS: u64 = 60_000;
+
+/// What happened to a throttled key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockoutEventKind {
+    Failure,
+    LockedOut,
+    /// An attempt while locked out, or while enough attempts to lock the key out were in flight,
+    /// which wasn't run.
+    Rejected,
+    /// A success that reset the failures of the key.
+    Reset,
+}
+
+/// A structured event for the audit log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockoutEvent {
+    pub kind: LockoutEventKind,
+    pub key: String,
+    /// Failures since the last lockout or success.
+    pub failures: u32,
+    /// Milliseconds since the epoch, if the key is locked out.
+    pub locked_until: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct ThrottleState {
+    failures: u32,
+    lockouts: u32,
+    locked_until: u64,
+    last_failure: u64,
+    /// Attempts reserved by [guard](LoginThrottle::guard) and not settled yet.
+    #[serde(default)]
+    pending: u32,
+    #[serde(default)]
+    last_reserved: u64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Policy {
+    max_failures: u32,
+    base_lockout_ms: u64,
+    max_lockout_ms: u64,
+    window_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum Outcome {
+    Failure,
+    Success,
+}
+
+#[derive(Serialize, Deserialize)]
+struct UpdateRequest {
+    outcome: Outcome,
+    policy: Policy,
+}
+
+/// The answer of the Durable Object to a reservation.
+#[derive(Serialize, Deserialize)]
+struct Reservation {
+    reserved: bool,
+    state: ThrottleState,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LoginThrottle {
+    store: ThrottleStore,
+    policy: Policy,
+    on_event: Option<fn(&LockoutEvent)>,
+}
+
+impl LoginThrottle {
+    /// Locks a key out for 30 seconds after 5 failures within 15 minutes, doubling up to an hour.
+    pub const fn new(store: ThrottleStore) -> Self {
+        Self {
+            store,
+            policy: Policy {
+                max_failures: 5,
+                base_lockout_ms: 30_000,
+                max_lockout_ms: 3_600_000,
+                window_ms: 900_000,
+            },
+            on_event: None,
+        }
+    }
+
+    /// How many failures lock a key out.
+    pub const fn max_failures(mut self, max_failures: u32) -> Self {
+        self.policy.max_failures = max_failures;
+        self
+    }
+
+    /// How long the first lockout lasts.
+    pub const fn base_lockout(mut self, lockout: Duration) -> Self {
+        self.policy.base_lockout_ms = lockout.as_millis() as u64;
+        self
+    }
+
+    /// The longest lockout.
+    pub const fn max_lockout(mut self, lockout: Duration) -> Self {
+        self.policy.max_lockout_ms = lockout.as_millis() as u64;
+        self
+    }
+
+    /// How long failures are remembered; a key whose last failure is older starts over.
+    pub const fn window(mut self, window: Duration) -> Self {
+        self.policy.window_ms = window.as_millis() as u64;
+        self
+    }
+
+    /// Also passes every event to `hook`, e.g. to write it to an audit table.
+    pub const fn on_event(mut self, hook: fn(&LockoutEvent)) -> Self {
+        self.on_event = Some(hook);
+        self
+    }
+
+    /// Runs `attempt` unless `key` is locked out, and counts its outcome. Fails with
+    /// `429 Too Many Requests` without running `attempt` while locked out, or while the attempts in
+    /// flight could lock the key out. Only works while handling a request, e.g. in a server function.
+    pub async fn guard<Fut, T, E>(&self, cx: Scope, key: &str, attempt: Fut) -> Result<T, E>
+    where
+        Fut: Future<Output = Result<T, E>>,
+        E: From<LeptosCloudflareError>,
+    {
+        let env = use_context::<RequestEnv>(cx).ok_or_else(|| {
+            LeptosCloudflareError::Internal(
+                "LoginThrottle::guard called outside of a request handled by leptos-cloudflare"
+                    .to_string(),
+            )
+        })?;
+        self.reserve(&env.0, key).await?;
+
+        let result = attempt.await;
+        if result.is_ok() {
+            self.record_success(&env.0, key).await?;
+        } else {
+            self.record_failure(&env.0, key).await?;
+        }
+        result
+    }
+
+    /// Fails with `429 Too Many Requests` if `key` is locked out.
+    pub async fn check(&self, env: &Env, key: &str) -> Result<(), LeptosCloudflareError> {
+        let state = self.load(env, key).await?;
+        if state.locked_until > worker::Date::now().as_millis() {
+            self.emit(LockoutEventKind::Rejected, key, &state);
+            return Err(LeptosCloudflareError::TooManyRequests);
+        }
+        Ok(())
+    }
+
+    /// Reserves an attempt for `key`, to be settled with [record_failure](LoginThrottle::record_failure)
+    /// or [record_success](LoginThrottle::record_success). Fails with `429 Too Many Requests` if
+    /// `key` is locked out or has as many attempts in flight as failures left before a lockout.
+    pub async fn reserve(&self, env: &Env, key: &str) -> Result<(), LeptosCloudflareError> {
+        let now = worker::Date::now().as_millis();
+        let (reserved, state) = match self.store {
+            ThrottleStore::Kv(binding) => {
+                match reserve(self.load(env, key).await?, &self.policy, now) {
+                    Ok(state) => {
+                        self.store_kv(env, binding, key, &state).await?;
+                        (true, state)
+                    }
+                    Err(state) => (false, state),
+                }
+            }
+            ThrottleStore::DurableObject(binding) => {
+                let reservation: Reservation = self
+                    .call_durable_object(env, binding, key, "reserve", &self.policy)
+                    .await?;
+                (reservation.reserved, reservation.state)
+            }
+        };
+        if !reserved {
+            self.emit(LockoutEventKind::Rejected, key, &state);
+            return Err(LeptosCloudflareError::TooManyRequests);
+        }
+        Ok(())
+    }
+
+    /// Counts a failed attempt for `key`, returning until when it is locked out if this failure locked
+    /// it.
+    pub async fn record_failure(
+        &self,
+        env: &Env,
+        key: &str,
+    ) -> Result<Option<u64>, LeptosCloudflareError> {
+        let state = self.update(env, key, Outcome::Failure).await?;
+        if state.failures == 0 {
+            self.emit(LockoutEventKind::LockedOut, key, &state);
+            Ok(Some(state.locked_until))
+        } else {
+            self.emit(LockoutEventKind::Failure, key, &state);
+            Ok(None)
+        }
+    }
+
+    /// Forgets the failures of `key`. Past lockouts still count towards the length of the next one
+    /// until the window passes.
+    pub async fn record_success(&self, env: &Env, key: &str) -> Result<(), LeptosCloudflareError> {
+        let state = self.update(env, key, Outcome::Success).await?;
+        self.emit(LockoutEventKind::Reset, key, &state);
+        Ok(())
+    }
+
+    async fn load(&self, env: &Env, key: &str) -> worker::Result<ThrottleState> {
+        match self.store {
+            ThrottleStore::Kv(binding) => Ok(env
+                .kv(binding)?
+                .get(&kv_key(key))
+                .json::<ThrottleState>()
+                .await?
+                .unwrap_or_default()),
+            ThrottleStore::DurableObject(binding) => {
+                let stub = env.durable_object(binding)?.id_from_name(key)?.get_stub()?;
+                stub.fetch_with_str("https://lockout/state")
+                    .await?
+                    .json()
+                    .await
+            }
+        }
+    }
+
+    async fn update(
+        &self,
+        env: &Env,
+        key: &str,
+        outcome: Outcome,
+    ) -> worker::Result<ThrottleState> {
+        match self.store {
+            ThrottleStore::Kv(binding) => {
+                let state = self.load(env, key).await?;
+                let state = apply(
+                    state,
+                    outcome,
+                    &self.policy,
+                    worker::Date::now().as_millis(),
+                );
+                self.store_kv(env, binding, key, &state).await?;
+                Ok(state)
+            }
+            ThrottleStore::DurableObject(binding) => {
+                let body = UpdateRequest {
+                    outcome,
+                    policy: self.policy,
+                };
+                self.call_durable_object(env, binding, key, "update", &body)
+                    .await
+            }
+        }
+    }
+
+    async fn store_kv(
+        &self,
+        env: &Env,
+        binding: &str,
+        key: &str,
+        state: &ThrottleState,
+    ) -> worker::Result<()> {
+        env.kv(binding)?
+            .put(&kv_key(key), serde_json::to_string(state)?)?
+            // Kept until the lockouts no longer count
+            .expiration_ttl(((self.policy.window_ms + self.policy.max_lockout_ms) / 1000).max(60))
+            .execute()
+            .await
+    }
+
+    async fn call_durable_object<B: Serialize, R: serde::de::DeserializeOwned>(
+        &self,
+        env: &Env,
+        binding: &str,
+        key: &str,
+        action: &str,
+        body: &B,
+    ) -> worker::Result<R> {
+        let stub = env.durable_object(binding)?.id_from_name(key)?.get_stub()?;
+        let mut init = worker::RequestInit::new();
+        init.with_method(worker::Method::Post)
+            .with_body(Some(JsValue::from_str(&serde_json::to_string(body)?)));
+        let request = Request::new_with_init(&format!("https://lockout/{action}"), &init)?;
+        stub.fetch_with_request(request).await?.json().await
+    }
+
+    fn emit(&self, kind: LockoutEventKind, key: &str, state: &ThrottleState) {
+        let event = LockoutEvent {
+            kind,
+            key: key.to_string(),
+            failures: state.failures,
+            locked_until: (state.locked_until > worker::Date::now().as_millis())
+                .then_some(state.locked_until),
+        };
+        match serde_json::to_string(&event) {
+            Ok(json) => tracing::info!(target: "audit", "{json}"),
+            Err(err) => tracing::error!("failed to serialize a lockout event: {err}"),
+        }
+        if let Some(hook) = self.on_event {
+            hook(&event);
+        }
+    }
+}
+
+/// `state` without the failures and reservations that no longer count at `now`.
+fn expire(mut state: ThrottleState, policy: &Policy, now: u64) -> ThrottleState {
+    if now.saturating_sub(state.last_reserved) > PENDING_TTL_MS {
+        state.pending = 0;
+    }
+    if now.saturating_sub(state.last_failure) > policy.window_ms && state.locked_until <= now {
+        state = ThrottleState {
+            pending: state.pending,
+            last_reserved: state.last_reserved,
+            ..ThrottleState::default()
+        };
+    }
+    state
+}
+
+/// The state of a key after reserving an attempt, or the state that rejected it.
+fn reserve(
+    state: ThrottleState,
+    policy: &Policy,
+    now: u64,
+) -> Result<ThrottleState, ThrottleState> {
+    let mut state = expire(state, policy, now);
+    // Every attempt in flight may fail, so no more may run than would lock the key out
+    if state.locked_until > now || state.failures + state.pending >= policy.max_failures {
+        return Err(state);
+    }
+    state.pending += 1;
+    state.last_reserved = now;
+    Ok(state)
+}
+
+/// The state of a key after `outcome`, which settles a reserved attempt if there is one.
+fn apply(state: ThrottleState, outcome: Outcome, policy: &Policy, now: u64) -> ThrottleState {
+    let mut state = expire(state, policy, now);
+    state.pending = state.pending.saturating_sub(1);
+
+    match outcome {
+        Outcome::Success => state.failures = 0,
+        Outcome::Failure => {
+            state.failures += 1;
+            state.last_failure = now;
+            if state.failures >= policy.max_failures {
+                let lockout = policy
+                    .base_lockout_ms
+                    .saturating_mul(1u64 << state.lockouts.min(32))
+                    .min(policy.max_lockout_ms);
+                state.failures = 0;
+                state.lockouts += 1;
+                state.locked_until = now + lockout;
+            }
+        }
+    }
+    state
+}
+
+fn kv_key(key: &str) -> String {
+    format!("lockout:{key}")
+}
+
+/// The Durable Object class behind [ThrottleStore::DurableObject]. Each key is a separate instance.
+#[durable_object]
+pub struct LockoutDurableObject {
+    state: State,
+}
+
+#[durable_object]
+impl DurableObject for LockoutDurableObject {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> worker::Result<Response> {
+        let mut storage = self.state.storage();
+        let current = storage
+            .get::<ThrottleState>("state")
+            .await
+            .unwrap_or_default();
+
+        match req.path().as_str() {
+            "/state" => Response::from_json(&current),
+            "/reserve" => {
+                let policy = req.json::<Policy>().await?;
+                let now = worker::Date::now().as_millis();
+                // The input gate holds other requests back until the reservation is stored, so
+                // parallel attempts are reserved one after the other
+                match reserve(current, &policy, now) {
+                    Ok(state) => {
+                        storage.put("state", state).await?;
+                        Response::from_json(&Reservation {
+                            reserved: true,
+                            state,
+                        })
+                    }
+                    Err(state) => Response::from_json(&Reservation {
+                        reserved: false,
+                        state,
+                    }),
+                }
+            }
+            "/update" => {
+                let body = req.json::<UpdateRequest>().await?;
+                let now = worker::Date::now().as_millis();
+                let updated = apply(current, body.outcome, &body.policy, now);
+                storage.put("state", updated).await?;
+                Response::from_json(&updated)
+            }
+            _ => Response::error("Not found", 404),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: Policy = Policy {
+        max_failures: 3,
+        base_lockout_ms: 1_000,
+        max_lockout_ms: 3_000,
+        window_ms: 60_000,
+    };
+
+    /// The state after `failures` reserved and failed attempts at `now`.
+    fn fail(mut state: ThrottleState, failures: u32, now: u64) -> ThrottleState {
+        for _ in 0..failures {
+            state = reserve(state, &POLICY, now).expect("the attempt should be reserved");
+            state = apply(state, Outcome::Failure, &POLICY, now);
+        }
+        state
+    }
+
+    #[test]
+    fn locks_out_with_doubling_lockouts() {
+        let state = fail(ThrottleState::default(), 2, 0);
+        assert_eq!(state.failures, 2);
+        assert_eq!(state.locked_until, 0);
+
+        let state = fail(state, 1, 10);
+        assert_eq!(state.failures, 0);
+        assert_eq!(state.lockouts, 1);
+        assert_eq!(state.locked_until, 1_010);
+        assert!(reserve(state, &POLICY, 500).is_err());
+
+        let state = fail(state, 3, 2_000);
+        assert_eq!(state.locked_until, 4_000);
+        // Capped at max_lockout_ms
+        let state = fail(state, 3, 5_000);
+        assert_eq!(state.locked_until, 8_000);
+    }
+
+    #[test]
+    fn success_resets_failures_but_not_lockouts() {
+        let state = fail(ThrottleState::default(), 3, 0);
+        let state = fail(state, 2, 2_000);
+        let state = reserve(state, &POLICY, 2_000).unwrap();
+        let state = apply(state, Outcome::Success, &POLICY, 2_000);
+        assert_eq!(state.failures, 0);
+        assert_eq!(state.lockouts, 1);
+        assert_eq!(state.pending, 0);
+    }
+
+    #[test]
+    fn forgets_failures_after_the_window() {
+        let state = fail(ThrottleState::default(), 3, 0);
+        let state = fail(state, 2, 2_000);
+        let state = fail(state, 1, 2_000 + POLICY.window_ms + 1);
+        assert_eq!(state.failures, 1);
+        assert_eq!(state.lockouts, 0);
+    }
+
+    #[test]
+    fn reserves_no_more_attempts_than_failures_left() {
+        let state = fail(ThrottleState::default(), 1, 0);
+        let state = reserve(state, &POLICY, 0).unwrap();
+        let state = reserve(state, &POLICY, 0).unwrap();
+        assert_eq!(state.pending, 2);
+        assert!(reserve(state, &POLICY, 0).is_err());
+
+        // Settling an attempt frees its reservation
+        let settled = apply(state, Outcome::Success, &POLICY, 0);
+        assert_eq!(settled.pending, 1);
+        assert!(reserve(settled, &POLICY, 0).is_ok());
+
+        // Attempts that never settle stop counting
+        assert!(reserve(state, &POLICY, PENDING_TTL_MS + 1).is_ok());
+    }
+}
@@ -0,0 +1,494 @@
+//! Passwordless login with links sent by email.
+//!
+//! [magic_link_routes](MagicLinkRoutes::magic_link_routes) mounts three endpoints:
+//!
+//! - `POST /__auth/magic-link` takes an `email` (as a form or JSON), stores a single-use token in KV and
+//!   sends a [signed URL](crate::signed_url) with it through a [MagicLinkMailer].
+//! - `GET /__auth/verify` checks the signature, consumes the token, stores a [Session] in KV, sets the
+//!   session cookie and redirects to [redirect_to](MagicLink::redirect_to).
+//! - `POST /__auth/logout` deletes the session and clears the cookie.
+//!
+//! ```ignore
+//! let magic_link = MagicLink::new(
+//!     ResendMailer::new("RESEND_API_KEY", "Acme <login@acme.com>"),
+//!     "SESSIONS",
+//!     "MAGIC_LINK_SECRET",
+//! )
+//! .redirect_to("/account");
+//!
+//! router.magic_link_routes(magic_link.clone())
+//!
+//! // in a server function
+//! let session = magic_link.session(cx).await?.ok_or(LeptosCloudflareError::Unauthorized)?;
+//! ```
+//!
+//! Requesting a link always succeeds, so the endpoint doesn't reveal which addresses have accounts.
+//! Each address and each client IP can only request a few links an hour, see
+//! [send_throttle](MagicLink::send_throttle), so the endpoint can't be used to flood inboxes.
+//! KV is eventually consistent, so a token can in rare cases be used twice within a few seconds from
+//! different locations, and a revoked session may be accepted for up to a minute. Some mail scanners
+//! open the links of incoming messages; [link_ttl](MagicLink::link_ttl) should stay short.
+
+use std::future::Future;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::future::LocalBoxFuture;
+use futures::FutureExt;
+use leptos::{use_context, Scope};
+use serde::{Deserialize, Serialize};
+use worker::Env;
+
+use crate::api::{ApiRequest, ApiRoutes};
+use crate::app_env::RequestEnv;
+use crate::lockout::{LoginThrottle, ThrottleStore};
+use crate::request_headers::use_full_request_parts;
+use crate::signed_url::{sign_url, verify_signed_url};
+use crate::util::{cookie, random_bytes};
+use crate::LeptosCloudflareError;
+
+pub const MAGIC_LINK_REQUEST_PATH: &str = "/__auth/magic-link";
+pub const MAGIC_LINK_VERIFY_PATH: &str = "/__auth/verify";
+pub const LOGOUT_PATH: &str = "/__auth/logout";
+
+/// Sends the login link to an address.
+pub trait MagicLinkMailer: 'static {
+    fn send(
+        &self,
+        env: Env,
+        email: String,
+        link: String,
+    ) -> LocalBoxFuture<'static, Result<(), LeptosCloudflareError>>;
+}
+
+impl<F, Fut> MagicLinkMailer for F
+where
+    F: Fn(Env, String, String) -> Fut + 'static,
+    Fut: Future<Output = Result<(), LeptosCloudflareError>> + 'static,
+{
+    fn send(
+        &self,
+        env: Env,
+        email: String,
+        link: String,
+    ) -> LocalBoxFuture<'static, Result<(), LeptosCloudflareError>> {
+        self(env, email, link).boxed_local()
+    }
+}
+
+/// Sends the link with the Resend API as a plain text message.
+pub struct ResendMailer {
+    /// Name of the secret with the API key.
+    pub api_key_secret: &'static str,
+    /// The sender, e.g. `Acme <login@acme.com>`.
+    pub from: &'static str,
+    pub subject: &'static str,
+}
+
+impl ResendMailer {
+    pub fn new(api_key_secret: &'static str, from: &'static str) -> Self {
+        Self {
+            api_key_secret,
+            from,
+            subject: "Your login link",
+        }
+    }
+}
+
+impl MagicLinkMailer for ResendMailer {
+    fn send(
+        &self,
+        env: Env,
+        email: String,
+        link: String,
+    ) -> LocalBoxFuture<'static, Result<(), LeptosCloudflareError>> {
+        let api_key_secret = self.api_key_secret;
+        let message = serde_json::json!({
+            "from": self.from,
+            "to": [email],
+            "subject": self.subject,
+            "text": format!("Open this link to log in:\n\n{link}\n\nIf you didn't ask for it, ignore this email."),
+        });
+        async move {
+            let headers = worker::Headers::new();
+            headers.set(
+                "Authorization",
+                &format!("Bearer {}", env.secret(api_key_secret)?.to_string()),
+            )?;
+            headers.set("Content-Type", "application/json")?;
+            let mut init = worker::RequestInit::new();
+            init.with_method(worker::Method::Post)
+                .with_headers(headers)
+                .with_body(Some(message.to_string().into()));
+            let request = worker::Request::new_with_init("https://api.resend.com/emails", &init)?;
+            let response = worker::Fetch::Request(request).send().await?;
+            if response.status_code() >= 300 {
+                return Err(LeptosCloudflareError::Internal(format!(
+                    "Resend responded with {}",
+                    response.status_code()
+                )));
+            }
+            Ok(())
+        }
+        .boxed_local()
+    }
+}
+
+/// A logged in user, stored in KV under `session:<id>`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub email: String,
+    /// Milliseconds since the epoch.
+    pub created_at: u64,
+    /// Whether a second factor was verified for this session, see
+    /// [mark_mfa_verified](MagicLink::mark_mfa_verified).
+    #[serde(default)]
+    pub mfa_verified: bool,
+}
+
+#[derive(Deserialize)]
+struct LinkRequest {
+    email: String,
+}
+
+/// The configuration of the magic link flow, see the [module documentation](self).
+#[derive(Clone)]
+pub struct MagicLink {
+    mailer: Rc<dyn MagicLinkMailer>,
+    binding: &'static str,
+    secret: &'static str,
+    link_ttl: Duration,
+    session_ttl: Duration,
+    cookie_name: &'static str,
+    redirect_to: &'static str,
+    sent_redirect: &'static str,
+    send_throttle: LoginThrottle,
+}
+
+impl MagicLink {
+    /// Sends links with `mailer`, keeps tokens and sessions in the KV namespace `binding` and signs the
+    /// links with the secret named `secret`.
+    pub fn new(mailer: impl MagicLinkMailer, binding: &'static str, secret: &'static str) -> Self {
+        Self {
+            mailer: Rc::new(mailer),
+            binding,
+            secret,
+            link_ttl: Duration::from_secs(15 * 60),
+            session_ttl: Duration::from_secs(30 * 24 * 3600),
+            cookie_name: "__session",
+            redirect_to: "/",
+            sent_redirect: "/",
+            send_throttle: LoginThrottle::new(ThrottleStore::Kv(binding))
+                .max_failures(10)
+                .window(Duration::from_secs(3600))
+                .base_lockout(Duration::from_secs(15 * 60)),
+        }
+    }
+
+    /// How long a link can be used, 15 minutes by default.
+    pub fn link_ttl(mut self, link_ttl: Duration) -> Self {
+        self.link_ttl = link_ttl;
+        self
+    }
+
+    /// How long a session lasts, 30 days by default.
+    pub fn session_ttl(mut self, session_ttl: Duration) -> Self {
+        self.session_ttl = session_ttl;
+        self
+    }
+
+    /// The name of the session cookie, `__session` by default.
+    pub fn cookie_name(mut self, cookie_name: &'static str) -> Self {
+        self.cookie_name = cookie_name;
+        self
+    }
+
+    /// Where to go after logging in or out, `/` by default.
+    pub fn redirect_to(mut self, path: &'static str) -> Self {
+        self.redirect_to = path;
+        self
+    }
+
+    /// Where a form requesting a link is redirected once the link is sent, e.g. a "check your inbox"
+    /// page. Requests sent as JSON get `204 No Content` instead.
+    pub fn sent_redirect(mut self, path: &'static str) -> Self {
+        self.sent_redirect = path;
+        self
+    }
+
+    /// Limits the links requested for each address and from each client IP. By default 10 links within
+    /// an hour, counted in the KV namespace of the tokens, lock the address or IP out for 15 minutes,
+    /// doubling with every further lockout. Each requested link counts as a failure of `throttle`,
+    /// and appears as one in its audit log.
+    pub fn send_throttle(mut self, throttle: LoginThrottle) -> Self {
+        self.send_throttle = throttle;
+        self
+    }
+
+    /// The session of the current request, if it is logged in. Only works while handling a request,
+    /// e.g. in a server function.
+    pub async fn session(&self, cx: Scope) -> Result<Option<Session>, LeptosCloudflareError> {
+        let (Some(env), Some(req)) = (use_context::<RequestEnv>(cx), use_full_request_parts(cx))
+        else {
+            return Err(LeptosCloudflareError::Internal(
+                "MagicLink::session called outside of a request handled by leptos-cloudflare"
+                    .to_string(),
+            ));
+        };
+        self.session_for(&env.0, &req.headers).await
+    }
+
+    /// Records that the current session passed a second factor, e.g. after
+    /// `totp::verify_totp` accepted a code. Fails with `401` if the request isn't
+    /// logged in.
+    pub async fn mark_mfa_verified(&self, cx: Scope) -> Result<(), LeptosCloudflareError> {
+        let (Some(env), Some(req)) = (use_context::<RequestEnv>(cx), use_full_request_parts(cx))
+        else {
+            return Err(LeptosCloudflareError::Internal(
+                "MagicLink::mark_mfa_verified called outside of a request handled by leptos-cloudflare"
+                    .to_string(),
+            ));
+        };
+        let id =
+            cookie(&req.headers, self.cookie_name).ok_or(LeptosCloudflareError::Unauthorized)?;
+        let mut session = self
+            .session_for(&env.0, &req.headers)
+            .await?
+            .ok_or(LeptosCloudflareError::Unauthorized)?;
+        session.mfa_verified = true;
+        // Keeps the session expiring when it was going to
+        let expires_at = session.created_at / 1000 + self.session_ttl.as_secs();
+        let ttl = expires_at.saturating_sub(worker::Date::now().as_millis() / 1000);
+        self.store_session(&env.0, &id, &session, ttl).await?;
+        Ok(())
+    }
+
+    /// The session of a request with `headers`, e.g. in an [api_route](crate::ApiRoutes::api_route).
+    pub async fn session_for(
+        &self,
+        env: &Env,
+        headers: &worker::Headers,
+    ) -> Result<Option<Session>, LeptosCloudflareError> {
+        let Some(id) = cookie(headers, self.cookie_name) else {
+            return Ok(None);
+        };
+        let session = env
+            .kv(self.binding)?
+            .get(&session_key(&id))
+            .json::<Session>()
+            .await
+            .map_err(worker::Error::from)?;
+        Ok(session)
+    }
+
+    /// Counts a link requested for `email` by `client`, failing with `429 Too Many Requests` if either
+    /// requested too many.
+    async fn throttle(
+        &self,
+        env: &Env,
+        email: &str,
+        client: &str,
+    ) -> Result<(), LeptosCloudflareError> {
+        // The IP goes first, so a client asking for links to many addresses doesn't lock them out
+        for key in [
+            format!("magic-link-ip:{client}"),
+            format!("magic-link:{email}"),
+        ] {
+            self.send_throttle.reserve(env, &key).await?;
+            self.send_throttle.record_failure(env, &key).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_link(&self, env: Env, origin: &str, email: String) -> worker::Result<()> {
+        let token = hex::encode(random_bytes(16)?);
+        env.kv(self.binding)?
+            .put(&token_key(&token), email.clone())?
+            // KV doesn't expire keys sooner than a minute
+            .expiration_ttl(self.link_ttl.as_secs().max(60))
+            .execute()
+            .await?;
+
+        let secret = env.secret(self.secret)?.to_string();
+        let link = format!(
+            "{origin}{}",
+            sign_url(
+                &secret,
+                &format!("{MAGIC_LINK_VERIFY_PATH}?token={token}"),
+                self.link_ttl
+            )
+        );
+        if let Err(err) = self.mailer.send(env, email, link).await {
+            tracing::error!("failed to send a magic link: {err}");
+        }
+        Ok(())
+    }
+
+    async fn log_in(&self, env: &Env, url: &worker::Url) -> Result<String, LeptosCloudflareError> {
+        let secret = env.secret(self.secret)?.to_string();
+        if !verify_signed_url(&secret, url) {
+            return Err(LeptosCloudflareError::Forbidden);
+        }
+        let token = url
+            .query_pairs()
+            .find(|(key, _)| key == "token")
+            .map(|(_, token)| token.into_owned())
+            .ok_or(LeptosCloudflareError::Forbidden)?;
+
+        let kv = env.kv(self.binding)?;
+        let email = kv
+            .get(&token_key(&token))
+            .text()
+            .await
+            .map_err(worker::Error::from)?
+            .ok_or(LeptosCloudflareError::Forbidden)?;
+        kv.delete(&token_key(&token))
+            .await
+            .map_err(worker::Error::from)?;
+
+        let id = hex::encode(random_bytes(32)?);
+        let session = Session {
+            email,
+            created_at: worker::Date::now().as_millis(),
+            mfa_verified: false,
+        };
+        self.store_session(env, &id, &session, self.session_ttl.as_secs())
+            .await?;
+        Ok(id)
+    }
+
+    async fn store_session(
+        &self,
+        env: &Env,
+        id: &str,
+        session: &Session,
+        ttl_seconds: u64,
+    ) -> worker::Result<()> {
+        env.kv(self.binding)?
+            .put(&session_key(id), serde_json::to_string(session)?)?
+            // KV doesn't expire keys sooner than a minute
+            .expiration_ttl(ttl_seconds.max(60))
+            .execute()
+            .await?;
+        Ok(())
+    }
+
+    fn redirect_with_cookie(
+        &self,
+        location: &str,
+        value: &str,
+        max_age: u64,
+    ) -> worker::Result<worker::Response> {
+        let headers = worker::Headers::new();
+        headers.set("Location", location)?;
+        headers.set(
+            "Set-Cookie",
+            &format!(
+                "{}={value}; Path=/; Max-Age={max_age}; HttpOnly; Secure; SameSite=Lax",
+                self.cookie_name
+            ),
+        )?;
+        Ok(worker::Response::empty()?
+            .with_status(303)
+            .with_headers(headers))
+    }
+}
+
+pub trait MagicLinkRoutes {
+    /// Mounts the login and logout endpoints of `magic_link`.
+    fn magic_link_routes(self, magic_link: MagicLink) -> Self;
+}
+
+impl<'a, D: 'static> MagicLinkRoutes for worker::Router<'a, D> {
+    fn magic_link_routes(self, magic_link: MagicLink) -> Self {
+        let request = magic_link.clone();
+        let verify = magic_link.clone();
+        self.api_route(
+            worker::Method::Post,
+            MAGIC_LINK_REQUEST_PATH,
+            move |req: ApiRequest| {
+                let magic_link = request.clone();
+                async move {
+                    let is_form =
+                        req.parts
+                            .headers
+                            .get("Content-Type")?
+                            .is_some_and(|content_type| {
+                                content_type.starts_with("application/x-www-form-urlencoded")
+                            });
+                    let LinkRequest { email } = if is_form {
+                        serde_urlencoded::from_bytes(&req.parts.body)
+                            .map_err(|err| LeptosCloudflareError::BadRequest(err.to_string()))?
+                    } else {
+                        serde_json::from_slice(&req.parts.body)
+                            .map_err(|err| LeptosCloudflareError::BadRequest(err.to_string()))?
+                    };
+                    let email = email.trim().to_lowercase();
+                    if email.len() > 254 || !email.contains('@') {
+                        return Err(LeptosCloudflareError::BadRequest(
+                            "Invalid email address".to_string(),
+                        ));
+                    }
+
+                    let client = req
+                        .parts
+                        .headers
+                        .get("CF-Connecting-IP")?
+                        .unwrap_or_default();
+                    magic_link.throttle(&req.env, &email, &client).await?;
+
+                    let origin = req.parts.url.origin().ascii_serialization();
+                    magic_link.send_link(req.env, &origin, email).await?;
+                    if is_form {
+                        let location = req
+                            .parts
+                            .url
+                            .join(magic_link.sent_redirect)
+                            .map_err(|err| LeptosCloudflareError::Internal(err.to_string()))?;
+                        worker::Response::redirect_with_status(location, 303)
+                            .map_err(LeptosCloudflareError::from)
+                    } else {
+                        Ok(worker::Response::empty()?.with_status(204))
+                    }
+                }
+            },
+        )
+        .api_route(
+            worker::Method::Get,
+            MAGIC_LINK_VERIFY_PATH,
+            move |req: ApiRequest| {
+                let magic_link = verify.clone();
+                async move {
+                    let id = magic_link.log_in(&req.env, &req.parts.url).await?;
+                    magic_link
+                        .redirect_with_cookie(
+                            magic_link.redirect_to,
+                            &id,
+                            magic_link.session_ttl.as_secs(),
+                        )
+                        .map_err(LeptosCloudflareError::from)
+                }
+            },
+        )
+        .api_route(worker::Method::Post, LOGOUT_PATH, move |req: ApiRequest| {
+            let magic_link = magic_link.clone();
+            async move {
+                if let Some(id) = cookie(&req.parts.headers, magic_link.cookie_name) {
+                    req.env
+                        .kv(magic_link.binding)?
+                        .delete(&session_key(&id))
+                        .await?;
+                }
+                magic_link.redirect_with_cookie(magic_link.redirect_to, "", 0)
+            }
+        })
+    }
+}
+
+fn token_key(token: &str) -> String {
+    format!("magic-link:{token}")
+}
+
+fn session_key(id: &str) -> String {
+    format!("session:{id}")
+}
@@ -0,0 +1,209 @@
+//! Bounded memoization in the memory of the isolate.
+//!
+//! An isolate has 128 MB of memory for everything, so an unbounded `HashMap` memoizing e.g. parsed
+//! documents grows until the isolate is killed. A [MemoCache] evicts its least recently used entries once
+//! their total weight exceeds its capacity, and optionally expires them after a TTL:
+//!
+//! ```ignore
+//! thread_local! {
+//!     static RENDERED: MemoCache<String, Rc<String>> = memo_cache(4 * 1024 * 1024)
+//!         .weigher(|_, html| html.len())
+//!         .ttl(Duration::from_secs(300));
+//! }
+//!
+//! let html = RENDERED.with(|rendered| {
+//!     rendered.get_or_insert_with(slug.clone(), || Rc::new(render_markdown(&source)))
+//! });
+//! ```
+//!
+//! By default every entry weighs 1, so the capacity is a number of entries. Clones share the same
+//! entries.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Creates a [MemoCache] holding entries of a total weight of at most `capacity`.
+pub fn memo_cache<K, V>(capacity: usize) -> MemoCache<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    MemoCache {
+        inner: Rc::new(RefCell::new(Inner {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            weight: 0,
+        })),
+        capacity,
+        ttl: None,
+        weigher: |_, _| 1,
+    }
+}
+
+/// A least recently used cache with a weight limit, see the [module documentation](self).
+pub struct MemoCache<K, V> {
+    inner: Rc<RefCell<Inner<K, V>>>,
+    capacity: usize,
+    ttl: Option<Duration>,
+    weigher: fn(&K, &V) -> usize,
+}
+
+struct Inner<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    /// Last use -> key, oldest first.
+    recency: BTreeMap<u64, K>,
+    clock: u64,
+    weight: usize,
+}
+
+struct Entry<V> {
+    value: V,
+    weight: usize,
+    last_used: u64,
+    /// Milliseconds since the epoch.
+    expires_at: Option<f64>,
+}
+
+impl<K, V> Clone for MemoCache<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            capacity: self.capacity,
+            ttl: self.ttl,
+            weigher: self.weigher,
+        }
+    }
+}
+
+impl<K, V> MemoCache<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    /// Expires entries `ttl` after they were inserted.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Weighs entries with `weigher`, e.g. by their size in bytes, instead of counting them. An entry
+    /// heavier than the capacity is never kept.
+    pub fn weigher(mut self, weigher: fn(&K, &V) -> usize) -> Self {
+        self.weigher = weigher;
+        self
+    }
+
+    /// Returns the value for `key` unless it is missing or expired.
+    pub fn get(&self, key: &K) -> Option<V> {
+        let now = js_sys::Date::now();
+        let mut inner = self.inner.borrow_mut();
+        let expired = match inner.entries.get(key) {
+            None => return None,
+            Some(entry) => entry.expires_at.is_some_and(|expires_at| expires_at <= now),
+        };
+        if expired {
+            inner.remove(key);
+            return None;
+        }
+
+        inner.clock += 1;
+        let clock = inner.clock;
+        let entry = inner.entries.get_mut(key).expect("entry checked above");
+        let last_used = std::mem::replace(&mut entry.last_used, clock);
+        let value = entry.value.clone();
+        inner.recency.remove(&last_used);
+        inner.recency.insert(clock, key.clone());
+        Some(value)
+    }
+
+    /// Inserts `value` for `key`, evicting the least recently used entries to make room.
+    pub fn insert(&self, key: K, value: V) {
+        let weight = (self.weigher)(&key, &value);
+        let mut inner = self.inner.borrow_mut();
+        inner.remove(&key);
+        if weight > self.capacity {
+            return;
+        }
+
+        while inner.weight + weight > self.capacity {
+            let Some((_, oldest)) = inner.recency.pop_first() else {
+                break;
+            };
+            if let Some(evicted) = inner.entries.remove(&oldest) {
+                inner.weight -= evicted.weight;
+            }
+        }
+
+        inner.clock += 1;
+        let clock = inner.clock;
+        let expires_at = self
+            .ttl
+            .map(|ttl| js_sys::Date::now() + ttl.as_millis() as f64);
+        inner.recency.insert(clock, key.clone());
+        inner.weight += weight;
+        inner.entries.insert(
+            key,
+            Entry {
+                value,
+                weight,
+                last_used: clock,
+                expires_at,
+            },
+        );
+    }
+
+    /// Returns the value for `key`, computing and inserting it with `compute` if there is none.
+    pub fn get_or_insert_with(&self, key: K, compute: impl FnOnce() -> V) -> V {
+        if let Some(value) = self.get(&key) {
+            return value;
+        }
+        let value = compute();
+        self.insert(key, value.clone());
+        value
+    }
+
+    /// The entries that haven't expired, in no particular order.
+    pub fn entries(&self) -> Vec<(K, V)> {
+        let now = js_sys::Date::now();
+        self.inner
+            .borrow()
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.expires_at.map_or(true, |expires_at| expires_at > now))
+            .map(|(key, entry)| (key.clone(), entry.value.clone()))
+            .collect()
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.inner.borrow_mut().remove(key)
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.borrow_mut();
+        inner.entries.clear();
+        inner.recency.clear();
+        inner.weight = 0;
+    }
+
+    /// The number of entries, including expired ones that weren't evicted yet.
+    pub fn len(&self) -> usize {
+        self.inner.borrow().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K: Hash + Eq, V> Inner<K, V> {
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.recency.remove(&entry.last_used);
+        self.weight -= entry.weight;
+        Some(entry.value)
+    }
+}
@@ -0,0 +1,251 @@
+//! Shadow traffic: a sample of production requests replayed against a staging worker.
+//!
+//! [Mirror::wrap] runs the fetch handler as usual and, for a [percent](Mirror::percent) of the
+//! requests, sends a copy to the worker bound as a service under
+//! [Context::wait_until](worker::Context::wait_until). The production response never waits for the
+//! staging worker, and what staging answers is only compared to it: a different status is logged with
+//! the `mirror` tracing target.
+//!
+//! ```ignore
+//! static MIRROR: Mirror = Mirror::new("STAGING").percent(5.0).exclude(&["/api/checkout"]);
+//!
+//! #[worker::event(fetch)]
+//! pub async fn main(req: Request, env: Env, ctx: Context) -> worker::Result<Response> {
+//!     MIRROR
+//!         .wrap(req, env, &ctx, |req, env| async move {
+//!             data.into_router().leptos_routes(routes).run(req, env).await
+//!         })
+//!         .await
+//! }
+//! ```
+//!
+//! ```toml
+//! services = [{ binding = "STAGING", service = "my-app-staging" }]
+//! ```
+//!
+//! Copies are sent without cookies, credentials or client addresses, and are marked with
+//! `X-Mirrored-Request: 1`. Only `GET` and `HEAD` requests are mirrored by default, since bodies carry
+//! what users type into forms, passwords included. Requests with a body are mirrored once a
+//! [body filter](Mirror::bodies) is set, which redacts each body or skips the request:
+//!
+//! ```ignore
+//! static MIRROR: Mirror = Mirror::new("STAGING").bodies(|url, body| {
+//!     // Only the search API, whose bodies hold nothing personal
+//!     url.path().starts_with("/api/search").then_some(body)
+//! });
+//! ```
+//!
+//! Point staging at its own databases and keep it from sending emails or charging cards, since every
+//! mirrored request is replayed for real.
+
+use std::future::Future;
+
+use futures::StreamExt;
+use wasm_bindgen::JsValue;
+
+use crate::normalize::{is_under, normalize_path};
+use crate::request_headers::DEFAULT_REDACTED_HEADERS;
+
+/// Headers with the address of the client, never forwarded to staging. Nor are the
+/// [DEFAULT_REDACTED_HEADERS] with credentials.
+const CLIENT_ADDRESS_HEADERS: &[&str] = &[
+    "cf-connecting-ip",
+    "cf-connecting-ipv6",
+    "true-client-ip",
+    "x-forwarded-for",
+    "x-real-ip",
+];
+
+pub struct Mirror {
+    service: &'static str,
+    percent: f64,
+    exclude: &'static [&'static str],
+    redact: &'static [&'static str],
+    max_body: usize,
+    body_filter: Option<fn(&worker::Url, Vec<u8>) -> Option<Vec<u8>>>,
+}
+
+impl Mirror {
+    /// Mirrors 1% of the requests to the worker bound as the service `service`, except bodies over
+    /// 1 MiB.
+    pub const fn new(service: &'static str) -> Self {
+        Self {
+            service,
+            percent: 1.0,
+            exclude: &[],
+            redact: &[],
+            max_body: 1024 * 1024,
+            body_filter: None,
+        }
+    }
+
+    /// How many requests out of 100 are mirrored.
+    pub const fn percent(mut self, percent: f64) -> Self {
+        self.percent = percent;
+        self
+    }
+
+    /// Path prefixes that are never mirrored, e.g. endpoints with side effects outside of staging.
+    /// They are compared segment by segment with the [normalized](crate::normalize) path, so
+    /// `/api/checkout` excludes `/api/checkout/confirm` but not `/api/checkouts`. Paths that can't be
+    /// normalized are never mirrored.
+    pub const fn exclude(mut self, prefixes: &'static [&'static str]) -> Self {
+        self.exclude = prefixes;
+        self
+    }
+
+    /// More headers to remove from the copies, besides cookies, credentials and client addresses.
+    pub const fn redact(mut self, headers: &'static [&'static str]) -> Self {
+        self.redact = headers;
+        self
+    }
+
+    /// Requests with a larger body are not mirrored.
+    pub const fn max_body(mut self, max_body: usize) -> Self {
+        self.max_body = max_body;
+        self
+    }
+
+    /// Also mirrors requests with a body, e.g. `POST`s, passing the URL and body of each through
+    /// `filter`, which returns the body to send, e.g. with fields redacted, or `None` to skip the
+    /// request.
+    pub const fn bodies(mut self, filter: fn(&worker::Url, Vec<u8>) -> Option<Vec<u8>>) -> Self {
+        self.body_filter = Some(filter);
+        self
+    }
+
+    /// Runs `handler` for `req`, mirroring the request if it is sampled.
+    pub async fn wrap<F, Fut>(
+        &'static self,
+        req: worker::Request,
+        env: worker::Env,
+        ctx: &worker::Context,
+        handler: F,
+    ) -> worker::Result<worker::Response>
+    where
+        F: FnOnce(worker::Request, worker::Env) -> Fut,
+        Fut: Future<Output = worker::Result<worker::Response>>,
+    {
+        // The copy tees the body, so the handler can still read it
+        let copy = if self.sampled(&req) {
+            req.clone().ok()
+        } else {
+            None
+        };
+
+        let response = handler(req, env.clone()).await;
+
+        if let Some(copy) = copy {
+            let status = response
+                .as_ref()
+                .ok()
+                .map(|response| response.status_code());
+            ctx.wait_until(async move {
+                if let Err(err) = self.send(copy, &env, status).await {
+                    tracing::warn!(target: "mirror", "failed to mirror a request: {err}");
+                }
+            });
+        }
+        response
+    }
+
+    fn sampled(&self, req: &worker::Request) -> bool {
+        let Ok(url) = req.url() else {
+            return false;
+        };
+        let Ok(path) = normalize_path(url.path()) else {
+            return false;
+        };
+        if self.exclude.iter().any(|prefix| is_under(&path, prefix)) {
+            return false;
+        }
+        let has_body = !matches!(req.method(), worker::Method::Get | worker::Method::Head);
+        if has_body && self.body_filter.is_none() {
+            return false;
+        }
+        let too_large = req
+            .headers()
+            .get("Content-Length")
+            .ok()
+            .flatten()
+            .and_then(|length| length.parse::<usize>().ok())
+            .is_some_and(|length| length > self.max_body);
+        !too_large && js_sys::Math::random() * 100.0 < self.percent
+    }
+
+    /// Sends `copy` to staging and compares its status with `production_status`.
+    async fn send(
+        &self,
+        mut copy: worker::Request,
+        env: &worker::Env,
+        production_status: Option<u16>,
+    ) -> worker::Result<()> {
+        let method = copy.method();
+        let url = copy.url()?;
+        let body = match (&method, self.body_filter) {
+            (worker::Method::Get | worker::Method::Head, _) => None,
+            (_, Some(filter)) => {
+                let Some(body) = self.read_body(&mut copy).await? else {
+                    return Ok(());
+                };
+                match filter(&url, body) {
+                    Some(body) => Some(body),
+                    None => return Ok(()),
+                }
+            }
+            (_, None) => return Ok(()),
+        };
+
+        let headers = worker::Headers::new();
+        for (name, value) in copy.headers().entries() {
+            let redacted = DEFAULT_REDACTED_HEADERS
+                .iter()
+                .chain(CLIENT_ADDRESS_HEADERS)
+                .any(|redact| *redact == name)
+                || self
+                    .redact
+                    .iter()
+                    .any(|redact| redact.eq_ignore_ascii_case(&name));
+            if !redacted {
+                headers.append(&name, &value)?;
+            }
+        }
+        headers.set("X-Mirrored-Request", "1")?;
+
+        let mut init = worker::RequestInit::new();
+        init.with_method(method.clone()).with_headers(headers);
+        if let Some(body) = body {
+            init.with_body(Some(JsValue::from(js_sys::Uint8Array::from(
+                body.as_slice(),
+            ))));
+        }
+        let request = worker::Request::new_with_init(url.as_str(), &init)?;
+
+        let response = env.service(self.service)?.fetch_request(request).await?;
+        if production_status.is_some_and(|status| status != response.status_code()) {
+            tracing::warn!(
+                target: "mirror",
+                "{method} {} answered {} in staging and {} in production",
+                url.path(),
+                response.status_code(),
+                production_status.unwrap_or_default(),
+            );
+        }
+        Ok(())
+    }
+
+    /// The body of `copy`, or `None` as soon as it is larger than [max_body](Mirror::max_body).
+    /// Bodies sent without a `Content-Length` are only known to be too large while reading them.
+    async fn read_body(&self, copy: &mut worker::Request) -> worker::Result<Option<Vec<u8>>> {
+        let mut body = Vec::new();
+        let mut chunks = copy.stream()?;
+        while let Some(chunk) = chunks.next().await {
+            body.extend(chunk?);
+            if body.len() > self.max_body {
+                // The rest of the body is left unread
+                return Ok(None);
+            }
+        }
+        Ok(Some(body))
+    }
+}
@@ -0,0 +1,134 @@
+//! Several independent Leptos apps in one worker.
+//!
+//! [WorkerRouterData] ties a router to a single app, so further apps are mounted under a path prefix with
+//! [leptos_routes_under](LeptosRoutesUnder::leptos_routes_under), each with its own [WorkerRouterData]:
+//! its own options (and so its own wasm bundle), route configuration and isolate states.
+//!
+//! ```ignore
+//! let blog_routes = generate_route_list(|cx| view! { cx, <blog::App/> });
+//! let shop_routes = generate_route_list(|cx| view! { cx, <shop::App/> });
+//!
+//! Router::with_data(main_data)
+//!     .leptos_routes(main_routes)
+//!     .leptos_routes_under("/blog", blog_routes, blog_data)
+//!     .leptos_routes_under("/shop", shop_routes, shop_data)
+//! ```
+//!
+//! The router of a mounted app sees the full path, so its `<Router>` should have the prefix as `base`.
+//! Server functions of all apps are handled by the one server function route of the worker.
+
+use std::rc::Rc;
+
+use futures::future::LocalBoxFuture;
+use http::StatusCode;
+use leptos::IntoView;
+use leptos_router::{Method as LeptosMethod, RouteListing};
+
+use crate::api::{route, RouteRegistry};
+use crate::normalize::is_under;
+use crate::{render_with_mode, ErrorRenderer, WorkerRouterData};
+
+type MountedHandler = Rc<
+    dyn Fn(
+        worker::Request,
+        worker::Env,
+    ) -> LocalBoxFuture<'static, worker::Result<worker::Response>>,
+>;
+
+thread_local! {
+    // The handlers capture the data of their app, which the worker router can't hold
+    static MOUNTED_ROUTES: RouteRegistry<MountedHandler> = RouteRegistry::new();
+}
+
+pub trait LeptosRoutesUnder {
+    /// Registers the routes of another app under `prefix`. Paths in `paths` that aren't already under
+    /// `prefix`, segment by segment, are prefixed with it: with the prefix `/blog`, `/blog/:slug` is
+    /// kept as is and `/blogroll` becomes `/blog/blogroll`.
+    fn leptos_routes_under<IV, AppFn>(
+        self,
+        prefix: &str,
+        paths: Vec<RouteListing>,
+        data: WorkerRouterData<IV, AppFn>,
+    ) -> Self
+    where
+        IV: IntoView + 'static,
+        AppFn: Fn(leptos::Scope) -> IV + Clone + 'static;
+}
+
+impl<'a, D: 'static> LeptosRoutesUnder for worker::Router<'a, D> {
+    fn leptos_routes_under<IV, AppFn>(
+        self,
+        prefix: &str,
+        paths: Vec<RouteListing>,
+        data: WorkerRouterData<IV, AppFn>,
+    ) -> Self
+    where
+        IV: IntoView + 'static,
+        AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+    {
+        let prefix = prefix.trim_end_matches('/');
+        let data = Rc::new(data);
+        let mut cf_router = self;
+
+        for listing in paths.iter() {
+            let path = mounted_path(prefix, listing.path());
+            let mode = listing.mode();
+
+            for method in listing.methods() {
+                let method = match method {
+                    LeptosMethod::Get => worker::Method::Get,
+                    LeptosMethod::Post => worker::Method::Post,
+                    LeptosMethod::Put => worker::Method::Put,
+                    LeptosMethod::Delete => worker::Method::Delete,
+                    LeptosMethod::Patch => worker::Method::Patch,
+                };
+                let data = data.clone();
+                let handler: MountedHandler = Rc::new(move |req, env| {
+                    let data = data.clone();
+                    Box::pin(async move { render_with_mode(req, &env, &data, mode).await })
+                });
+
+                MOUNTED_ROUTES.with(|routes| routes.register(method.clone(), &path, handler));
+                cf_router = route(cf_router, &method, &path, dispatch_mounted_app::<D>);
+            }
+        }
+
+        cf_router
+    }
+}
+
+/// `path` of an app mounted under `prefix`, which has no trailing slash.
+fn mounted_path(prefix: &str, path: &str) -> String {
+    if is_under(path, prefix) {
+        path.to_string()
+    } else if path == "/" {
+        prefix.to_string()
+    } else {
+        format!("{prefix}{path}")
+    }
+}
+
+async fn dispatch_mounted_app<D>(
+    req: worker::Request,
+    ctx: worker::RouteContext<D>,
+) -> worker::Result<worker::Response> {
+    let matched = MOUNTED_ROUTES.with(|routes| routes.find(&req.method(), &req.path()));
+    match matched {
+        Some((handler, _)) => handler(req, ctx.env).await,
+        None => ErrorRenderer::installed().render_for(&req, StatusCode::NOT_FOUND, "Not found"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixes_paths_outside_the_prefix() {
+        assert_eq!(mounted_path("/blog", "/"), "/blog");
+        assert_eq!(mounted_path("/blog", "/blog"), "/blog");
+        assert_eq!(mounted_path("/blog", "/blog/:slug"), "/blog/:slug");
+        assert_eq!(mounted_path("/blog", "/blogroll"), "/blog/blogroll");
+        assert_eq!(mounted_path("/blog", "/about"), "/blog/about");
+    }
+}
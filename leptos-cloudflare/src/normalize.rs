@@ -0,0 +1,314 @@
+//! Strict normalization of request paths.
+//!
+//! The worker router, the Leptos router and the asset handlers each interpret the raw path of a request
+//! on their own, so a path they read differently, e.g. `/admin//users`, `/p%6Fsts/1` or
+//! `/pkg/..%2Fsecret`, could reach a handler that another layer was meant to guard.
+//! [normalize_request] gives them all the same path:
+//!
+//! - empty and `.` segments are removed and `..` segments resolved, never above the root,
+//! - percent-encoded unreserved characters are decoded, and other escapes are upper-cased,
+//! - invalid escapes, encoded `/` and `\`, and control characters are rejected.
+//!
+//! [serve](crate::WorkerRouterData::serve) normalizes every request before routing it, and answers
+//! paths that are rejected with `400 Bad Request`. Wrappers that run before it and check the path, like
+//! `SignedUrls` and [Mirror](crate::mirror::Mirror), compare the normalized path too. Routers run
+//! directly should do the same:
+//!
+//! ```ignore
+//! let req = match normalize_request(req) {
+//!     Ok(req) => req,
+//!     Err(err) => return err.into_worker_response(),
+//! };
+//! router.run(req, env).await
+//! ```
+//!
+//! The asset handlers additionally derive their keys from [decode_segments], so a key can never contain
+//! a separator or a dot segment that wasn't in the normalized path.
+//!
+//! ## Route parameters
+//!
+//! Parameters are matched in the normalized path, where they are still percent-encoded, and decoded
+//! with [decode_param] once matched: `/tag/caf%C3%A9%20au%20lait` gives `café au lait` to `/tag/:name`,
+//! and `/files/a%3Fb` gives `a?b`, as the browser decodes them. The Leptos router of the render
+//! handlers is given the normalized URL too, so `use_params` reads the same value when the page is
+//! rendered and when it hydrates, whichever way the link was encoded. An encoded `/` is rejected rather
+//! than decoded, since a parameter could otherwise span segments.
+
+use crate::LeptosCloudflareError;
+
+/// Why a path can't be normalized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum PathError {
+    #[error("invalid percent-encoding in the path")]
+    InvalidEncoding,
+    #[error("the path contains an encoded separator or a control character")]
+    ForbiddenCharacter,
+}
+
+impl From<PathError> for LeptosCloudflareError {
+    fn from(err: PathError) -> Self {
+        LeptosCloudflareError::BadRequest(err.to_string())
+    }
+}
+
+/// `path` normalized, still percent-encoded. A trailing slash is kept.
+pub fn normalize_path(path: &str) -> Result<String, PathError> {
+    let mut segments: Vec<String> = Vec::new();
+    // Whether the path ends in a directory, like `/a/` or `/a/b/..`
+    let mut trailing_slash = false;
+    for raw in path.split('/') {
+        let segment = normalize_segment(raw)?;
+        trailing_slash = matches!(segment.as_str(), "" | "." | "..");
+        match segment.as_str() {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    let mut normalized = format!("/{}", segments.join("/"));
+    if trailing_slash && !segments.is_empty() {
+        normalized.push('/');
+    }
+    Ok(normalized)
+}
+
+/// The segments of `path` after normalization, fully decoded.
+pub fn decode_segments(path: &str) -> Result<Vec<String>, PathError> {
+    normalize_path(path)?
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(decode_param)
+        .collect()
+}
+
+/// The decoded value of a route parameter matched in a normalized path.
+pub fn decode_param(value: &str) -> Result<String, PathError> {
+    String::from_utf8(percent_decode(value)?).map_err(|_| PathError::InvalidEncoding)
+}
+
+/// `req` with its path normalized. Requests whose path is already normalized are returned unchanged.
+pub fn normalize_request(req: worker::Request) -> Result<worker::Request, LeptosCloudflareError> {
+    let mut url = req.url()?;
+    let normalized = normalize_path(url.path())?;
+    if normalized == url.path() {
+        return Ok(req);
+    }
+
+    url.set_path(&normalized);
+    // A request as the init of another keeps its method, headers, body and `cf` properties
+    let rewritten = web_sys::Request::new_with_str_and_request(url.as_str(), req.inner())
+        .map_err(worker::Error::from)?;
+    Ok(worker::Request::from(rewritten))
+}
+
+/// `url` with its path normalized, or unchanged if it can't be.
+pub(crate) fn normalize_url(url: &worker::Url) -> worker::Url {
+    let mut url = url.clone();
+    if let Ok(normalized) = normalize_path(url.path()) {
+        url.set_path(&normalized);
+    }
+    url
+}
+
+/// Whether `path` is `prefix` or below it, comparing whole segments: `/blog/post` is under `/blog`,
+/// `/blogroll` isn't.
+pub(crate) fn is_under(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+fn normalize_segment(segment: &str) -> Result<String, PathError> {
+    let bytes = segment.as_bytes();
+    let mut normalized = String::with_capacity(segment.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = bytes[i];
+        if byte == b'%' {
+            let decoded = decode_escape(bytes.get(i + 1..i + 3))?;
+            if is_unreserved(decoded) {
+                normalized.push(decoded as char);
+            } else if decoded == b'/' || decoded == b'\\' || decoded.is_ascii_control() {
+                return Err(PathError::ForbiddenCharacter);
+            } else {
+                normalized.push_str(&format!("%{decoded:02X}"));
+            }
+            i += 3;
+        } else {
+            if byte == b'\\' || byte.is_ascii_control() {
+                return Err(PathError::ForbiddenCharacter);
+            }
+            // Multi-byte characters are copied whole, their bytes are never `%` or ASCII
+            let len = utf8_len(byte);
+            normalized.push_str(segment.get(i..i + len).ok_or(PathError::InvalidEncoding)?);
+            i += len;
+        }
+    }
+    Ok(normalized)
+}
+
+fn percent_decode(segment: &str) -> Result<Vec<u8>, PathError> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            decoded.push(decode_escape(bytes.get(i + 1..i + 3))?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Ok(decoded)
+}
+
+fn decode_escape(hex: Option<&[u8]>) -> Result<u8, PathError> {
+    let hex = hex
+        .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+        .ok_or(PathError::InvalidEncoding)?;
+    let digit = |byte: u8| (byte as char).to_digit(16).unwrap_or_default() as u8;
+    Ok((digit(hex[0]) << 4) | digit(hex[1]))
+}
+
+fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~')
+}
+
+fn utf8_len(first_byte: u8) -> usize {
+    match first_byte {
+        0xF0.. => 4,
+        0xE0.. => 3,
+        0xC0.. => 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::route_config::extract_params;
+
+    /// The value of `:param` in `/p/:param` for the raw `path`.
+    fn param(path: &str) -> Result<String, PathError> {
+        let normalized = normalize_path(path)?;
+        let params = extract_params("/p/:param", &normalized).expect("the path should match");
+        decode_param(&params["param"])
+    }
+
+    #[test]
+    fn decodes_spaces_and_unicode() {
+        assert_eq!(param("/p/hello%20world").unwrap(), "hello world");
+        assert_eq!(param("/p/caf%C3%A9").unwrap(), "café");
+        assert_eq!(param("/p/caf%c3%a9").unwrap(), "café");
+        assert_eq!(param("/p/%E6%97%A5%E6%9C%AC").unwrap(), "日本");
+        assert_eq!(param("/p/%F0%9F%A6%80").unwrap(), "🦀");
+        assert_eq!(param("/p/café").unwrap(), "café");
+    }
+
+    #[test]
+    fn decodes_reserved_characters() {
+        assert_eq!(param("/p/a%3Fb").unwrap(), "a?b");
+        assert_eq!(param("/p/a%23b").unwrap(), "a#b");
+        assert_eq!(param("/p/100%25").unwrap(), "100%");
+        assert_eq!(param("/p/a%2Bb").unwrap(), "a+b");
+        assert_eq!(param("/p/a%26b%3Dc").unwrap(), "a&b=c");
+        assert_eq!(param("/p/C%23%20%26%20F%23").unwrap(), "C# & F#");
+    }
+
+    #[test]
+    fn keeps_literal_characters() {
+        // `+` only means a space in query strings
+        assert_eq!(param("/p/a+b").unwrap(), "a+b");
+        assert_eq!(param("/p/a;b,c").unwrap(), "a;b,c");
+        assert_eq!(param("/p/%7Euser").unwrap(), "~user");
+    }
+
+    #[test]
+    fn decodes_once() {
+        assert_eq!(param("/p/%2520").unwrap(), "%20");
+        assert_eq!(normalize_path("/p/%2520").unwrap(), "/p/%2520");
+    }
+
+    #[test]
+    fn canonicalizes_equivalent_encodings() {
+        let canonical = normalize_path("/p/caf%C3%A9%20au%20lait").unwrap();
+        assert_eq!(
+            normalize_path("/p/caf%c3%a9%20au%20lait").unwrap(),
+            canonical
+        );
+        assert_eq!(
+            normalize_path("/p/%63af%C3%A9%20au%20lait").unwrap(),
+            canonical
+        );
+        assert_eq!(
+            normalize_path("/p//caf%C3%A9%20au%20lait").unwrap(),
+            canonical
+        );
+        assert_eq!(
+            normalize_path("/q/../p/./caf%C3%A9%20au%20lait").unwrap(),
+            canonical
+        );
+    }
+
+    #[test]
+    fn normalizes_segments() {
+        assert_eq!(normalize_path("/").unwrap(), "/");
+        assert_eq!(normalize_path("").unwrap(), "/");
+        assert_eq!(normalize_path("//").unwrap(), "/");
+        assert_eq!(normalize_path("/a//b").unwrap(), "/a/b");
+        assert_eq!(normalize_path("/a/b/").unwrap(), "/a/b/");
+        assert_eq!(normalize_path("/a/b/..").unwrap(), "/a/");
+        assert_eq!(normalize_path("/../../a").unwrap(), "/a");
+        assert_eq!(normalize_path("/a/%2E%2E/b").unwrap(), "/b");
+        assert_eq!(normalize_path("/a/.%2e/b").unwrap(), "/b");
+    }
+
+    #[test]
+    fn rejects_forbidden_characters() {
+        assert_eq!(param("/p/a%2Fb"), Err(PathError::ForbiddenCharacter));
+        assert_eq!(param("/p/a%2fb"), Err(PathError::ForbiddenCharacter));
+        assert_eq!(param("/p/a%5Cb"), Err(PathError::ForbiddenCharacter));
+        assert_eq!(param("/p/a%00b"), Err(PathError::ForbiddenCharacter));
+        assert_eq!(param("/p/a%0Ab"), Err(PathError::ForbiddenCharacter));
+        assert_eq!(
+            normalize_path("/p/a\\b"),
+            Err(PathError::ForbiddenCharacter)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_encodings() {
+        assert_eq!(param("/p/%"), Err(PathError::InvalidEncoding));
+        assert_eq!(param("/p/%2"), Err(PathError::InvalidEncoding));
+        assert_eq!(param("/p/%G0"), Err(PathError::InvalidEncoding));
+        assert_eq!(param("/p/%+1"), Err(PathError::InvalidEncoding));
+        // Valid escapes that aren't UTF-8
+        assert_eq!(param("/p/%C3"), Err(PathError::InvalidEncoding));
+        assert_eq!(param("/p/%FF"), Err(PathError::InvalidEncoding));
+    }
+
+    #[test]
+    fn compares_prefixes_by_segment() {
+        assert!(is_under("/api/checkout", "/api/checkout"));
+        assert!(is_under("/api/checkout/confirm", "/api/checkout"));
+        assert!(is_under("/api/checkout/", "/api/checkout/"));
+        assert!(!is_under("/api/checkoutx", "/api/checkout"));
+        assert!(!is_under("/api", "/api/checkout"));
+        assert!(is_under("/anything", "/"));
+    }
+
+    #[test]
+    fn decodes_wildcards_and_asset_segments() {
+        let normalized = normalize_path("/docs/a%20b//c%C3%A9/../d").unwrap();
+        let params = extract_params("/docs/*rest", &normalized).unwrap();
+        assert_eq!(decode_param(&params["rest"]).unwrap(), "a b/d");
+        assert_eq!(
+            decode_segments("/pkg/../pkg/app%20v2.js").unwrap(),
+            vec!["pkg", "app v2.js"]
+        );
+    }
+}
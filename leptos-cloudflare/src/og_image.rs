@@ -0,0 +1,289 @@
+//! Open Graph and Twitter card images rendered on demand with Browser Rendering.
+//!
+//! An [og_image_route](OgImageRoutes::og_image_route) screenshots a page, typically a route of the
+//! Leptos app laid out as a card, or an HTML fragment, and answers with the PNG:
+//!
+//! ```ignore
+//! static CARDS: Screenshot = Screenshot::new("BROWSER").max_age(86_400);
+//!
+//! router
+//!     .leptos_routes(routes)
+//!     .og_image_route("/og/posts/:slug", CARDS, |req| async move {
+//!         // `/cards/posts/:slug` is a Leptos route that renders a 1200×630 card
+//!         Ok(req.page(&format!("/cards/posts/{}", req.params["slug"]))?)
+//!     })
+//! ```
+//!
+//! ```html
+//! <meta property="og:image" content="https://example.com/og/posts/hello-world" />
+//! ```
+//!
+//! The browser is driven with `@cloudflare/puppeteer`, which the worker's `package.json` must depend
+//! on, and the binding is declared in `wrangler.toml`:
+//!
+//! ```toml
+//! browser = { binding = "BROWSER" }
+//! ```
+//!
+//! Launching a browser takes a few seconds and counts towards the Browser Rendering limits, so the
+//! images are sent with a long `Cache-Control` by default, for the zone cache to serve repeated
+//! requests.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::rc::Rc;
+
+use futures::future::LocalBoxFuture;
+use http::StatusCode;
+use js_sys::{Object, Reflect};
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use worker::Env;
+
+use crate::api::{decode_params, RouteRegistry};
+use crate::raw::BinaryResponse;
+use crate::{ErrorRenderer, LeptosCloudflareError};
+
+#[wasm_bindgen(module = "@cloudflare/puppeteer")]
+extern "C" {
+    #[wasm_bindgen(catch, js_name = launch)]
+    fn launch_js(binding: &JsValue) -> Result<JsValue, JsValue>;
+}
+
+/// What to screenshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OgSource {
+    /// The page at an absolute URL, loaded until the network is idle.
+    Url(String),
+    /// An HTML document.
+    Html(String),
+}
+
+/// Screenshots with the browser bound as `binding`.
+#[derive(Debug, Clone, Copy)]
+pub struct Screenshot {
+    binding: &'static str,
+    width: u32,
+    height: u32,
+    device_scale_factor: f64,
+    max_age: u32,
+}
+
+impl Screenshot {
+    /// Screenshots of 1200×630, the size Open Graph and Twitter cards are shown at, cached for a day.
+    pub const fn new(binding: &'static str) -> Self {
+        Self {
+            binding,
+            width: 1200,
+            height: 630,
+            device_scale_factor: 1.0,
+            max_age: 86_400,
+        }
+    }
+
+    /// The size of the viewport in CSS pixels.
+    pub const fn size(mut self, width: u32, height: u32) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    /// Device pixels per CSS pixel, e.g. `2.0` for images twice the size of the viewport.
+    pub const fn device_scale_factor(mut self, factor: f64) -> Self {
+        self.device_scale_factor = factor;
+        self
+    }
+
+    /// How long the images may be cached, in seconds.
+    pub const fn max_age(mut self, seconds: u32) -> Self {
+        self.max_age = seconds;
+        self
+    }
+
+    /// Renders `source` in a new browser and returns the PNG.
+    pub async fn capture(
+        &self,
+        env: &Env,
+        source: &OgSource,
+    ) -> Result<Vec<u8>, LeptosCloudflareError> {
+        let binding =
+            Reflect::get(env, &JsValue::from_str(self.binding)).map_err(worker::Error::from)?;
+        if binding.is_undefined() {
+            return Err(worker::Error::RustError(format!(
+                "no browser is bound as {}",
+                self.binding
+            ))
+            .into());
+        }
+        let browser = JsFuture::from(js_sys::Promise::from(
+            launch_js(&binding).map_err(worker::Error::from)?,
+        ))
+        .await
+        .map_err(worker::Error::from)?;
+
+        let png = self.screenshot(&browser, source).await;
+        // The browser keeps counting towards the limits until it is closed
+        if let Err(err) = call(&browser, "close", &[]).await {
+            tracing::warn!("failed to close the browser: {err}");
+        }
+        png
+    }
+
+    async fn screenshot(
+        &self,
+        browser: &JsValue,
+        source: &OgSource,
+    ) -> Result<Vec<u8>, LeptosCloudflareError> {
+        let page = call(browser, "newPage", &[]).await?;
+        let viewport = object(&[
+            ("width", self.width.into()),
+            ("height", self.height.into()),
+            ("deviceScaleFactor", self.device_scale_factor.into()),
+        ])?;
+        call(&page, "setViewport", &[viewport]).await?;
+
+        let wait = object(&[("waitUntil", "networkidle0".into())])?;
+        match source {
+            OgSource::Url(url) => call(&page, "goto", &[url.into(), wait]).await?,
+            OgSource::Html(html) => call(&page, "setContent", &[html.into(), wait]).await?,
+        };
+        let png = call(&page, "screenshot", &[object(&[("type", "png".into())])?]).await?;
+        Ok(js_sys::Uint8Array::new(&png).to_vec())
+    }
+
+    async fn respond(
+        &self,
+        env: &Env,
+        source: &OgSource,
+    ) -> Result<worker::Response, LeptosCloudflareError> {
+        let png = self.capture(env, source).await?;
+        Ok(BinaryResponse::new("image/png")
+            .header(
+                "Cache-Control",
+                &format!("public, max-age={}", self.max_age),
+            )
+            .bytes(png)?)
+    }
+}
+
+/// Calls the method `name` of `target` and awaits the promise it returns.
+async fn call(target: &JsValue, name: &str, args: &[JsValue]) -> worker::Result<JsValue> {
+    let function: js_sys::Function = Reflect::get(target, &name.into())?.unchecked_into();
+    let args = args.iter().collect::<js_sys::Array>();
+    let promise: js_sys::Promise = function.apply(target, &args)?.unchecked_into();
+    Ok(JsFuture::from(promise).await?)
+}
+
+fn object(entries: &[(&str, JsValue)]) -> worker::Result<JsValue> {
+    let object = Object::new();
+    for (key, value) in entries {
+        Reflect::set(&object, &JsValue::from_str(key), value)?;
+    }
+    Ok(object.into())
+}
+
+/// The request for an image.
+pub struct OgRequest {
+    /// The URL of the image.
+    pub url: worker::Url,
+    /// The decoded `:param` and `*wildcard` values of the route.
+    pub params: HashMap<String, String>,
+    pub env: Env,
+}
+
+impl OgRequest {
+    /// The page at `path` on the origin of the request, e.g. a route of the app that renders the card.
+    /// Paths that lead to another origin, e.g. `//example.com/` or `https://example.com/` built from a
+    /// route param, are rejected, so the browser never screenshots a page of someone else's choosing.
+    pub fn page(&self, path: &str) -> Result<OgSource, LeptosCloudflareError> {
+        let url = self.url.join(path).map_err(|err| {
+            LeptosCloudflareError::BadRequest(format!("Invalid page {path}: {err}"))
+        })?;
+        if url.origin() != self.url.origin() {
+            return Err(LeptosCloudflareError::BadRequest(format!(
+                "Page {path} is not on the origin of the request"
+            )));
+        }
+        Ok(OgSource::Url(url.to_string()))
+    }
+}
+
+type BoxedOgHandler =
+    Rc<dyn Fn(OgRequest) -> LocalBoxFuture<'static, Result<OgSource, LeptosCloudflareError>>>;
+
+#[derive(Clone)]
+struct OgRoute {
+    screenshot: Screenshot,
+    handler: BoxedOgHandler,
+}
+
+thread_local! {
+    static OG_ROUTES: RouteRegistry<OgRoute> = RouteRegistry::new();
+}
+
+pub trait OgImageRoutes {
+    /// Answers `GET` requests to `path` with a screenshot of the source `handler` returns for them.
+    /// `path` uses the same `:param` and `*wildcard` syntax as the worker router.
+    fn og_image_route<H, Fut>(self, path: &str, screenshot: Screenshot, handler: H) -> Self
+    where
+        H: Fn(OgRequest) -> Fut + 'static,
+        Fut: Future<Output = Result<OgSource, LeptosCloudflareError>> + 'static;
+}
+
+impl<'a, D: 'static> OgImageRoutes for worker::Router<'a, D> {
+    fn og_image_route<H, Fut>(self, path: &str, screenshot: Screenshot, handler: H) -> Self
+    where
+        H: Fn(OgRequest) -> Fut + 'static,
+        Fut: Future<Output = Result<OgSource, LeptosCloudflareError>> + 'static,
+    {
+        let handler: BoxedOgHandler = Rc::new(move |req| Box::pin(handler(req)));
+        let og_route = OgRoute {
+            screenshot,
+            handler,
+        };
+        OG_ROUTES.with(|routes| routes.register(worker::Method::Get, path, og_route));
+
+        self.get_async(path, dispatch_og_image::<D>)
+    }
+}
+
+async fn dispatch_og_image<D>(
+    req: worker::Request,
+    ctx: worker::RouteContext<D>,
+) -> worker::Result<worker::Response> {
+    let path = req.path();
+    let matched = OG_ROUTES.with(|routes| routes.find(&worker::Method::Get, &path));
+    let Some((og_route, params)) = matched else {
+        return ErrorRenderer::installed().render_for(&req, StatusCode::NOT_FOUND, "Not found");
+    };
+    let Ok(params) = decode_params(params) else {
+        return ErrorRenderer::installed().render_for(
+            &req,
+            StatusCode::BAD_REQUEST,
+            "Invalid path parameters",
+        );
+    };
+
+    let og_request = OgRequest {
+        url: req.url()?,
+        params,
+        env: ctx.env.clone(),
+    };
+    let result = match (og_route.handler)(og_request).await {
+        Ok(source) => og_route.screenshot.respond(&ctx.env, &source).await,
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(response) => Ok(response),
+        Err(err) => {
+            tracing::error!("failed to render the image for {path}: {err}");
+            // The messages of internal errors may reveal internals
+            let message = match err.status().is_server_error() {
+                true => "Failed to render the image".to_string(),
+                false => err.to_string(),
+            };
+            ErrorRenderer::installed().render_for(&req, err.status(), &message)
+        }
+    }
+}
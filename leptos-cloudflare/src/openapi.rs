@@ -0,0 +1,306 @@
+//! OpenAPI 3.1 documents describing server functions.
+//!
+//! Server functions are plain HTTP endpoints, so clients in other languages can call them once they
+//! know the paths and payloads. [OpenApi] describes the server functions added to it, with the
+//! schemas of their arguments and results generated by [schemars]:
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize, JsonSchema)]
+//! pub struct Todo { id: u32, title: String, done: bool }
+//!
+//! #[server(AddTodo, "/api", "Url", "add_todo")]
+//! pub async fn add_todo(cx: Scope, title: String) -> Result<Todo, ServerFnError> { ... }
+//!
+//! /// The arguments of `add_todo`, since the struct generated by `#[server]` can't derive `JsonSchema`
+//! #[derive(JsonSchema)]
+//! struct AddTodoArgs { title: String }
+//!
+//! fn api_document() -> OpenApi {
+//!     OpenApi::new("Todos", "1.0.0").server_fn::<AddTodo, AddTodoArgs>("Adds a todo")
+//! }
+//!
+//! router
+//!     .get_async("/openapi.json", |_req, _ctx| async move { api_document().into_response() })
+//!     .get("/docs", |_req, _ctx| swagger_ui("/openapi.json"))
+//! ```
+//!
+//! Functions with a `Url` or `Cbor` encoding are `POST` operations that take their arguments in the
+//! body, those with `GetJSON` or `GetCBOR` are `GET` operations that take them from the query. Errors
+//! are answered with the message of the [ServerFnError](leptos::ServerFnError) as text.
+//!
+//! # Registry for code generators
+//!
+//! Generators of typed clients, e.g. for TypeScript, can read every registered server function from a
+//! [server_fn_registry_route](ServerFnRegistryRoutes::server_fn_registry_route), served in development
+//! only. It lists the name, path, method and encoding of each function, with the Rust types and
+//! schemas of its arguments and result for those the document describes:
+//!
+//! ```ignore
+//! router.server_fn_registry_route("/__server_fns", api_document)
+//! ```
+//!
+//! ```json
+//! {
+//!     "serverFns": [{
+//!         "name": "add_todo",
+//!         "path": "/api/add_todo",
+//!         "method": "POST",
+//!         "encoding": "Url",
+//!         "args": { "type": "app::AddTodoArgs", "schema": { ... } },
+//!         "output": { "type": "app::Todo", "schema": { ... } }
+//!     }],
+//!     "definitions": { ... }
+//! }
+//! ```
+
+use std::cell::Cell;
+use std::collections::HashMap;
+
+use http::StatusCode;
+use leptos::leptos_config::Env;
+use leptos::leptos_server::{server_fn_by_path, server_fns_by_path};
+use leptos::server_fn::{Encoding, ServerFn};
+use leptos::{IntoView, Scope};
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+
+use crate::WorkerRouterData;
+
+thread_local! {
+    // The router only accepts function pointers
+    static REGISTRY_DOCUMENT: Cell<Option<fn() -> OpenApi>> = Cell::new(None);
+}
+
+/// An OpenAPI document under construction.
+pub struct OpenApi {
+    title: String,
+    version: String,
+    description: Option<String>,
+    paths: Map<String, Value>,
+    /// The types of the described server functions, by their URL.
+    types: HashMap<&'static str, Value>,
+    generator: SchemaGenerator,
+}
+
+impl OpenApi {
+    pub fn new(title: &str, version: &str) -> Self {
+        let settings = SchemaSettings::draft2019_09().with(|settings| {
+            settings.definitions_path = "#/components/schemas/".to_string();
+        });
+        Self {
+            title: title.to_string(),
+            version: version.to_string(),
+            description: None,
+            paths: Map::new(),
+            types: HashMap::new(),
+            generator: settings.into_generator(),
+        }
+    }
+
+    /// The description of the API, which may use Markdown.
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    /// Describes the server function `F`, whose arguments have the schema of `Args`, typically a
+    /// struct with the same fields as `F`.
+    pub fn server_fn<F, Args>(mut self, summary: &str) -> Self
+    where
+        F: ServerFn<Scope>,
+        F::Output: JsonSchema,
+        Args: JsonSchema,
+    {
+        let path = format!("{}/{}", F::prefix().trim_end_matches('/'), F::url());
+        let args = Args::json_schema(&mut self.generator);
+        let output = F::Output::json_schema(&mut self.generator);
+        let (method, args_type, output_type) = match F::encoding() {
+            Encoding::Url => (
+                "post",
+                "application/x-www-form-urlencoded",
+                "application/json",
+            ),
+            Encoding::Cbor => ("post", "application/cbor", "application/cbor"),
+            Encoding::GetJSON => ("get", "", "application/json"),
+            Encoding::GetCBOR => ("get", "", "application/cbor"),
+        };
+
+        let mut operation = json!({
+            "operationId": F::url(),
+            "summary": summary,
+            "responses": {
+                "200": {
+                    "description": "The result of the server function",
+                    "content": { output_type: { "schema": output } },
+                },
+                "500": {
+                    "description": "The server function failed",
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                },
+            },
+        });
+        if method == "get" {
+            operation["parameters"] = Value::Array(query_parameters(&args));
+        } else {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { args_type: { "schema": args } },
+            });
+        }
+        self.types.insert(
+            F::url(),
+            json!({
+                "args": {
+                    "type": std::any::type_name::<Args>(),
+                    "schema": operation_schema(&operation, method, args_type),
+                },
+                "output": {
+                    "type": std::any::type_name::<F::Output>(),
+                    "schema": operation["responses"]["200"]["content"][output_type]["schema"],
+                },
+            }),
+        );
+        self.paths.insert(path, json!({ method: operation }));
+        self
+    }
+
+    /// Every registered server function, with its types if the document describes it.
+    pub fn registry(&self) -> Value {
+        let mut paths = server_fns_by_path();
+        paths.sort_unstable();
+        let server_fns = paths
+            .into_iter()
+            .filter_map(|path| {
+                let server_fn = server_fn_by_path(path)?;
+                let (method, encoding) = match server_fn.encoding() {
+                    Encoding::Url => ("POST", "Url"),
+                    Encoding::Cbor => ("POST", "Cbor"),
+                    Encoding::GetJSON => ("GET", "GetJSON"),
+                    Encoding::GetCBOR => ("GET", "GetCBOR"),
+                };
+                let types = self.types.get(server_fn.url());
+                Some(json!({
+                    "name": server_fn.url(),
+                    "path": format!("{}/{}", server_fn.prefix().trim_end_matches('/'), server_fn.url()),
+                    "method": method,
+                    "encoding": encoding,
+                    "args": types.map(|types| &types["args"]),
+                    "output": types.map(|types| &types["output"]),
+                }))
+            })
+            .collect::<Vec<_>>();
+        json!({
+            "serverFns": server_fns,
+            "definitions": self.generator.definitions(),
+        })
+    }
+
+    /// The document as JSON.
+    pub fn to_json(&self) -> Value {
+        let mut info = json!({ "title": self.title, "version": self.version });
+        if let Some(description) = &self.description {
+            info["description"] = description.clone().into();
+        }
+        json!({
+            "openapi": "3.1.0",
+            "info": info,
+            "paths": self.paths,
+            "components": { "schemas": self.generator.definitions() },
+        })
+    }
+
+    /// Serves the document as JSON.
+    pub fn into_response(self) -> worker::Result<worker::Response> {
+        worker::Response::from_json(&self.to_json())
+    }
+}
+
+/// The schema of the arguments of `operation`, from its body or its query parameters.
+fn operation_schema(operation: &Value, method: &str, args_type: &str) -> Value {
+    if method != "get" {
+        return operation["requestBody"]["content"][args_type]["schema"].clone();
+    }
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+    for parameter in operation["parameters"].as_array().into_iter().flatten() {
+        let name = parameter["name"].as_str().unwrap_or_default().to_string();
+        if parameter["required"] == Value::Bool(true) {
+            required.push(Value::String(name.clone()));
+        }
+        properties.insert(name, parameter["schema"].clone());
+    }
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+/// The properties of the object schema `args` as query parameters, e.g. `page` for `{ page: u32 }`.
+fn query_parameters(args: &Schema) -> Vec<Value> {
+    let Schema::Object(args) = args else {
+        return Vec::new();
+    };
+    let Some(object) = &args.object else {
+        return Vec::new();
+    };
+    object
+        .properties
+        .iter()
+        .map(|(name, schema)| {
+            json!({
+                "name": name,
+                "in": "query",
+                "required": object.required.contains(name),
+                "schema": schema,
+            })
+        })
+        .collect()
+}
+
+/// A Swagger UI page for the document at `document_url`, loaded from a CDN.
+pub fn swagger_ui(document_url: &str) -> worker::Result<worker::Response> {
+    let document_url = serde_json::to_string(document_url)?;
+    worker::Response::from_html(format!(
+        r##"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8" />
+    <title>API</title>
+    <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css" />
+</head>
+<body>
+    <div id="swagger-ui"></div>
+    <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+    <script>SwaggerUIBundle({{ url: {document_url}, dom_id: "#swagger-ui" }});</script>
+</body>
+</html>"##
+    ))
+}
+
+pub trait ServerFnRegistryRoutes {
+    /// Serves the [registry](OpenApi::registry) of the server functions at `path` in development,
+    /// with the types described by the document `document` builds. In production the route answers
+    /// `404 Not Found`.
+    fn server_fn_registry_route(self, path: &str, document: fn() -> OpenApi) -> Self;
+}
+
+impl<'a, IV, AppFn> ServerFnRegistryRoutes for worker::Router<'a, WorkerRouterData<IV, AppFn>>
+where
+    IV: IntoView + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+{
+    fn server_fn_registry_route(self, path: &str, document: fn() -> OpenApi) -> Self {
+        REGISTRY_DOCUMENT.with(|registry| registry.set(Some(document)));
+        self.get(path, |req, ctx| {
+            let document = REGISTRY_DOCUMENT.with(Cell::get);
+            match document {
+                Some(document) if ctx.data.options.env == Env::DEV => {
+                    worker::Response::from_json(&document().registry())
+                }
+                _ => ctx
+                    .data
+                    .error_renderer
+                    .render_for(&req, StatusCode::NOT_FOUND, "Not found"),
+            }
+        })
+    }
+}
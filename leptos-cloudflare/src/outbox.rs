@@ -0,0 +1,256 @@
+//! Reliable side effects (emails, webhooks, ...) from server functions with a D1 outbox table.
+//!
+//! Instead of performing a side effect directly, a server function records it in the outbox in the same
+//! D1 batch as its own writes, so that either both are committed or neither is. A consumer, run from a
+//! cron trigger or under `wait_until`, then performs the recorded effects and retries failures with
+//! exponential backoff. Effects that keep failing are marked as dead instead of being retried forever.
+//!
+//! ```ignore
+//! with_transaction(&db, |tx| {
+//!     tx.execute("INSERT INTO orders (id, email) VALUES (?1, ?2)", &[id.into(), email.into()])?;
+//!     enqueue_outbox(tx, "send_receipt", &Receipt { order_id: id })?;
+//!     Ok(())
+//! })
+//! .await?;
+//!
+//! // in the consumer
+//! let handlers = OutboxHandlers::new().on("send_receipt", |message| async move {
+//!     let receipt: Receipt = message.payload()?;
+//!     send_receipt(receipt).await
+//! });
+//! process_outbox(&db, &handlers, &OutboxConfig::default()).await?;
+//! ```
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::future::LocalBoxFuture;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+use worker::d1::D1Database;
+
+use crate::d1::{d1_query_as, D1Error, D1Transaction};
+use crate::LeptosCloudflareError;
+
+/// Creates the outbox table. Run it once, e.g. in a D1 migration.
+pub const OUTBOX_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS leptos_outbox (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at INTEGER NOT NULL,
+    last_error TEXT,
+    created_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS leptos_outbox_due ON leptos_outbox (status, next_attempt_at);";
+
+/// Adds a side effect of type `kind` to the transaction. It is only recorded if the transaction commits.
+pub fn enqueue_outbox<T: Serialize>(
+    tx: &mut D1Transaction,
+    kind: &str,
+    payload: &T,
+) -> Result<(), D1Error> {
+    let payload = serde_json::to_string(payload).map_err(worker::Error::from)?;
+    let now = worker::Date::now().as_millis() as f64;
+    tx.execute(
+        "INSERT INTO leptos_outbox (kind, payload, next_attempt_at, created_at) VALUES (?1, ?2, ?3, ?3)",
+        &[kind.into(), payload.into(), now.into()],
+    )?;
+    Ok(())
+}
+
+/// A recorded side effect, as handed to its handler.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxMessage {
+    pub id: i64,
+    pub kind: String,
+    pub payload: String,
+    /// How many times the effect has failed before.
+    pub attempts: u32,
+}
+
+impl OutboxMessage {
+    pub fn payload<T: DeserializeOwned>(&self) -> Result<T, LeptosCloudflareError> {
+        serde_json::from_str(&self.payload)
+            .map_err(|err| LeptosCloudflareError::Internal(err.to_string()))
+    }
+}
+
+type OutboxHandler =
+    Rc<dyn Fn(OutboxMessage) -> LocalBoxFuture<'static, Result<(), LeptosCloudflareError>>>;
+
+/// Handlers of the consumer, by message kind.
+#[derive(Clone, Default)]
+pub struct OutboxHandlers {
+    handlers: HashMap<String, OutboxHandler>,
+}
+
+impl OutboxHandlers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn on<F, Fut>(mut self, kind: &str, handler: F) -> Self
+    where
+        F: Fn(OutboxMessage) -> Fut + 'static,
+        Fut: Future<Output = Result<(), LeptosCloudflareError>> + 'static,
+    {
+        self.handlers.insert(
+            kind.to_string(),
+            Rc::new(move |message| Box::pin(handler(message))),
+        );
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OutboxConfig {
+    /// How many messages a single [process_outbox] call claims.
+    pub batch_size: u32,
+    /// After this many failed attempts a message is marked as `dead`.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled on every further failure.
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// How long a claimed message is hidden from other consumers while its handler runs.
+    pub lease: Duration,
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 25,
+            max_attempts: 8,
+            base_delay: Duration::from_secs(30),
+            max_delay: Duration::from_secs(3600),
+            lease: Duration::from_secs(60),
+        }
+    }
+}
+
+impl OutboxConfig {
+    fn backoff(&self, attempts: u32) -> Duration {
+        self.base_delay
+            .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+            .min(self.max_delay)
+    }
+}
+
+#[derive(Deserialize)]
+struct AbandonedMessage {
+    id: i64,
+}
+
+/// Summary of a [process_outbox] run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutboxReport {
+    pub delivered: u32,
+    pub retried: u32,
+    pub dead: u32,
+}
+
+/// Claims the messages that are due and runs their handlers.
+///
+/// Successful messages are deleted. Failed ones are retried later with exponential backoff, and marked as
+/// `dead` once they reach [max_attempts](OutboxConfig::max_attempts); dead messages stay in the table with
+/// their last error for inspection. Messages without a handler count as failures. Claiming a message
+/// counts as an attempt, so one whose handler never returns, e.g. because it exceeds the CPU limit,
+/// is marked as `dead` too once its lease has expired `max_attempts` times.
+pub async fn process_outbox(
+    db: &D1Database,
+    handlers: &OutboxHandlers,
+    config: &OutboxConfig,
+) -> worker::Result<OutboxReport> {
+    let now = worker::Date::now().as_millis();
+    let abandoned = d1_query_as::<AbandonedMessage>(
+        db,
+        "UPDATE leptos_outbox
+            SET status = 'dead', last_error = 'abandoned after its lease expired'
+            WHERE status = 'pending' AND attempts >= ?1 AND next_attempt_at <= ?2
+            RETURNING id",
+        &[config.max_attempts.into(), (now as f64).into()],
+    )
+    .await?;
+    for message in &abandoned {
+        tracing::warn!("outbox message {} was abandoned by its handler", message.id);
+    }
+
+    // Claiming by moving next_attempt_at into the future keeps concurrent consumers from picking
+    // the same messages
+    let messages = d1_query_as::<OutboxMessage>(
+        db,
+        "UPDATE leptos_outbox SET next_attempt_at = ?1, attempts = attempts + 1
+            WHERE id IN (
+                SELECT id FROM leptos_outbox
+                WHERE status = 'pending' AND next_attempt_at <= ?2
+                ORDER BY id LIMIT ?3
+            )
+            RETURNING id, kind, payload, attempts - 1 AS attempts",
+        &[
+            ((now + config.lease.as_millis() as u64) as f64).into(),
+            (now as f64).into(),
+            config.batch_size.into(),
+        ],
+    )
+    .await?;
+
+    let mut report = OutboxReport {
+        dead: abandoned.len() as u32,
+        ..OutboxReport::default()
+    };
+    for message in messages {
+        let id = message.id as f64;
+        let attempts = message.attempts + 1;
+        let result = match handlers.handlers.get(&message.kind) {
+            Some(handler) => handler(message.clone()).await,
+            None => Err(LeptosCloudflareError::Internal(format!(
+                "No outbox handler for {}",
+                message.kind
+            ))),
+        };
+
+        match result {
+            Ok(()) => {
+                db.prepare("DELETE FROM leptos_outbox WHERE id = ?1")
+                    .bind(&[id.into()])?
+                    .run()
+                    .await?;
+                report.delivered += 1;
+            }
+            Err(err) => {
+                let dead = attempts >= config.max_attempts;
+                let next_attempt_at = now + config.backoff(attempts).as_millis() as u64;
+                tracing::warn!(
+                    "outbox message {} ({}) failed on attempt {attempts}: {err}",
+                    message.id,
+                    message.kind
+                );
+                db.prepare(
+                    "UPDATE leptos_outbox
+                        SET status = ?1, attempts = ?2, next_attempt_at = ?3, last_error = ?4
+                        WHERE id = ?5",
+                )
+                .bind(&[
+                    JsValue::from_str(if dead { "dead" } else { "pending" }),
+                    attempts.into(),
+                    (next_attempt_at as f64).into(),
+                    err.to_string().into(),
+                    id.into(),
+                ])?
+                .run()
+                .await?;
+                if dead {
+                    report.dead += 1;
+                } else {
+                    report.retried += 1;
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,199 @@
+//! Full-page caching of rendered pages in the Cache API.
+//!
+//! With a [PageCache] set on the router data, the render handlers look up pages rendered with
+//! [SsrMode::Async](leptos_router::SsrMode::Async) in `caches.default` before rendering them, and store
+//! what they render. A page that is mostly static is then only rendered once per TTL in each data
+//! center:
+//!
+//! ```ignore
+//! let data = WorkerRouterData::builder()
+//!     .page_cache(
+//!         PageCache::new(Duration::from_secs(300))
+//!             .routes(&["/", "/blog", "/blog/:slug"])
+//!             .query_params(&["page"])
+//!             .bypass_cookies(&["session"]),
+//!     )
+//!     // ...
+//! ```
+//!
+//! Pages are cached by URL, with only the [query_params](PageCache::query_params) that change what
+//! they show, by the [CacheSegments](crate::CacheSegments) of the request, and by the
+//! [release](PageCache::release) of the app. Only `GET` requests
+//! answered with `200` are stored, and never responses that set cookies or are marked `private` or
+//! `no-store`, e.g. pages in preview mode. Requests with a [bypass cookie](PageCache::bypass_cookies),
+//! a preview token, or the cookie of a pending flash message or rejected form are always rendered,
+//! since their page shows that state once and clears the cookie. Responses carry
+//! `X-Page-Cache: HIT` or `MISS`.
+//!
+//! Streamed pages are sent while they render, so they are never cached. Cached pages can be removed
+//! with [zone purges](crate::zone_purge) of their URL, or wait for the TTL to pass.
+
+use std::time::Duration;
+
+use worker::Headers;
+
+use crate::background::WaitUntil;
+use crate::route_config::path_matches;
+use crate::util::cookie;
+use crate::CacheSegments;
+
+/// The response header telling whether a page came from the cache.
+pub const PAGE_CACHE_HEADER: &str = "X-Page-Cache";
+
+/// The query parameter that carries the [release](PageCache::release) in cache keys.
+pub const RELEASE_QUERY_PARAM: &str = "__release";
+
+/// Cookies with state for the next page only, which a cached page would neither show nor clear.
+const ONE_TIME_COOKIES: &[&str] = &[
+    #[cfg(feature = "flash")]
+    crate::flash::COOKIE_NAME,
+    #[cfg(feature = "forms")]
+    crate::forms::COOKIE_NAME,
+];
+
+#[derive(Debug, Clone, Copy)]
+pub struct PageCache {
+    ttl: Duration,
+    routes: Option<&'static [&'static str]>,
+    query_params: Option<&'static [&'static str]>,
+    bypass_cookies: &'static [&'static str],
+    release: Option<&'static str>,
+}
+
+impl PageCache {
+    /// Caches every page rendered with `SsrMode::Async` for `ttl`, keyed by its full URL.
+    pub const fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            routes: None,
+            query_params: None,
+            bypass_cookies: &[],
+            release: None,
+        }
+    }
+
+    /// Only caches the routes matching `patterns`, e.g. `/blog/:slug`.
+    pub const fn routes(mut self, patterns: &'static [&'static str]) -> Self {
+        self.routes = Some(patterns);
+        self
+    }
+
+    /// Only keeps `params` of the query string in the cache key. Other parameters, e.g. tracking ones,
+    /// are served the same page.
+    pub const fn query_params(mut self, params: &'static [&'static str]) -> Self {
+        self.query_params = Some(params);
+        self
+    }
+
+    /// Renders requests with any of `cookies` instead of using the cache, e.g. for logged-in visitors.
+    pub const fn bypass_cookies(mut self, cookies: &'static [&'static str]) -> Self {
+        self.bypass_cookies = cookies;
+        self
+    }
+
+    /// Caches the pages of the release `release` apart from those of other releases, e.g. the stable
+    /// and canary versions of a [Canary](crate::Canary) rollout, which serve the same URLs from the
+    /// same cache.
+    pub const fn release(mut self, release: &'static str) -> Self {
+        self.release = Some(release);
+        self
+    }
+
+    /// The cache key of the page at `url`, or `None` if it isn't cached for this request.
+    pub(crate) fn cache_key(
+        &self,
+        url: &worker::Url,
+        headers: &Headers,
+        segments: &CacheSegments,
+    ) -> Option<String> {
+        if let Some(routes) = self.routes {
+            if !routes
+                .iter()
+                .any(|pattern| path_matches(pattern, url.path()))
+            {
+                return None;
+            }
+        }
+        if self
+            .bypass_cookies
+            .iter()
+            .chain(ONE_TIME_COOKIES)
+            .any(|name| cookie(headers, name).is_some())
+        {
+            return None;
+        }
+
+        let mut url = url.clone();
+        url.set_fragment(None);
+        if let Some(params) = self.query_params {
+            let pairs: Vec<(String, String)> = url
+                .query_pairs()
+                .filter(|(name, _)| params.contains(&name.as_ref()))
+                .map(|(name, value)| (name.into_owned(), value.into_owned()))
+                .collect();
+            if pairs.is_empty() {
+                url.set_query(None);
+            } else {
+                url.query_pairs_mut().clear().extend_pairs(pairs);
+            }
+        }
+        if let Some(release) = self.release {
+            url.query_pairs_mut()
+                .append_pair(RELEASE_QUERY_PARAM, release);
+        }
+        Some(segments.cache_key(&url, headers))
+    }
+
+    /// Stores `response` under `key` once it is sent, if it can be shared.
+    pub(crate) fn store(
+        &self,
+        key: String,
+        response: &mut worker::Response,
+        wait_until: &WaitUntil,
+    ) -> worker::Result<()> {
+        let cache_control = response.headers().get("Cache-Control")?;
+        let private = cache_control.as_deref().is_some_and(|cache_control| {
+            let cache_control = cache_control.to_ascii_lowercase();
+            cache_control.contains("private") || cache_control.contains("no-store")
+        });
+        if response.status_code() != 200 || response.headers().has("Set-Cookie")? || private {
+            return Ok(());
+        }
+
+        response.headers_mut().set(PAGE_CACHE_HEADER, "MISS")?;
+        let mut cached = response.cloned()?;
+        // s-maxage sets the TTL of the cache without changing what browsers are told
+        let shared_max_age = format!("s-maxage={}", self.ttl.as_secs());
+        cached.headers_mut().set(
+            "Cache-Control",
+            &match cache_control {
+                Some(cache_control) => format!("{cache_control}, {shared_max_age}"),
+                None => shared_max_age,
+            },
+        )?;
+        wait_until.spawn(async move {
+            if let Err(err) = worker::Cache::default().put(&key, cached).await {
+                tracing::warn!("failed to cache the page {key}: {err}");
+            }
+        });
+        Ok(())
+    }
+}
+
+/// The page cached under `key`, if any.
+pub(crate) async fn cached(key: &str) -> Option<worker::Response> {
+    let response = match worker::Cache::default().get(key, false).await {
+        Ok(response) => response?,
+        Err(err) => {
+            tracing::warn!("failed to read the page cache: {err}");
+            return None;
+        }
+    };
+    // Cached responses have immutable headers, so they are copied to mark the hit
+    let headers = Headers::new();
+    for (name, value) in response.headers().entries() {
+        headers.append(&name, &value).ok()?;
+    }
+    headers.set(PAGE_CACHE_HEADER, "HIT").ok()?;
+    Some(response.with_headers(headers))
+}
@@ -0,0 +1,166 @@
+//! Password hashing with PBKDF2 from the runtime's `crypto.subtle`.
+//!
+//! Memory-hard hashes like argon2 run as wasm and easily exceed the CPU time of a request, while
+//! PBKDF2 runs natively. Workers limit PBKDF2 to 100,000 iterations, which is the default; each hash
+//! then takes tens of milliseconds of CPU time. Hashes are stored as
+//! `pbkdf2-sha256$<iterations>$<salt>$<hash>`, so the parameters can change over time and old hashes
+//! are upgraded at the next login with [needs_rehash](PasswordHasher::needs_rehash):
+//!
+//! ```ignore
+//! const HASHER: PasswordHasher = PasswordHasher::new();
+//!
+//! if !HASHER.verify(&password, &user.password_hash).await? {
+//!     return Err(LeptosCloudflareError::Unauthorized.into());
+//! }
+//! if HASHER.needs_rehash(&user.password_hash) {
+//!     save_password_hash(cx, &user.id, &HASHER.hash(&password).await?).await?;
+//! }
+//! ```
+
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use wasm_bindgen::JsValue;
+
+use crate::util::{constant_time_eq, random_bytes, subtle};
+
+/// The most iterations the Workers runtime accepts.
+pub const MAX_ITERATIONS: u32 = 100_000;
+
+const ALGORITHM: &str = "pbkdf2-sha256";
+const SALT_BYTES: usize = 16;
+const HASH_BITS: u32 = 256;
+
+#[derive(Debug, Clone, Copy)]
+pub struct PasswordHasher {
+    iterations: u32,
+}
+
+impl Default for PasswordHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PasswordHasher {
+    /// Hashes with [MAX_ITERATIONS] iterations.
+    pub const fn new() -> Self {
+        Self {
+            iterations: MAX_ITERATIONS,
+        }
+    }
+
+    /// Uses `iterations` instead, at most [MAX_ITERATIONS]. Fewer iterations make logins cheaper and
+    /// brute-forcing a leaked hash easier in the same proportion.
+    pub const fn iterations(mut self, iterations: u32) -> Self {
+        self.iterations = if iterations > MAX_ITERATIONS {
+            MAX_ITERATIONS
+        } else {
+            iterations
+        };
+        self
+    }
+
+    /// Hashes `password` with a new random salt.
+    pub async fn hash(&self, password: &str) -> worker::Result<String> {
+        let salt = random_bytes(SALT_BYTES)?;
+        let hash = pbkdf2(password, &salt, self.iterations).await?;
+        Ok(format!(
+            "{ALGORITHM}${}${}${}",
+            self.iterations,
+            STANDARD_NO_PAD.encode(salt),
+            STANDARD_NO_PAD.encode(hash)
+        ))
+    }
+
+    /// Checks `password` against a hash created by [hash](PasswordHasher::hash), with any parameters.
+    /// Returns `false` for malformed hashes.
+    pub async fn verify(&self, password: &str, hash: &str) -> worker::Result<bool> {
+        let Some((iterations, salt, expected)) = parse(hash) else {
+            return Ok(false);
+        };
+        let actual = pbkdf2(password, &salt, iterations).await?;
+        Ok(constant_time_eq(&actual, &expected))
+    }
+
+    /// Whether `hash` was created with other parameters than this hasher's, and should be replaced
+    /// by a new hash once the password is known, i.e. after a successful login.
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        parse(hash).map_or(true, |(iterations, _, _)| iterations != self.iterations)
+    }
+}
+
+fn parse(hash: &str) -> Option<(u32, Vec<u8>, Vec<u8>)> {
+    let mut parts = hash.split('$');
+    if parts.next()? != ALGORITHM {
+        return None;
+    }
+    let iterations = parts.next()?.parse::<u32>().ok()?;
+    let salt = STANDARD_NO_PAD.decode(parts.next()?).ok()?;
+    let hash = STANDARD_NO_PAD.decode(parts.next()?).ok()?;
+    if parts.next().is_some() || iterations == 0 || iterations > MAX_ITERATIONS {
+        return None;
+    }
+    Some((iterations, salt, hash))
+}
+
+async fn pbkdf2(password: &str, salt: &[u8], iterations: u32) -> worker::Result<Vec<u8>> {
+    let algorithm = js_sys::Object::new();
+    js_sys::Reflect::set(&algorithm, &"name".into(), &"PBKDF2".into())?;
+    let key = subtle(
+        "importKey",
+        js_sys::Array::of5(
+            &"raw".into(),
+            &js_sys::Uint8Array::from(password.as_bytes()),
+            &algorithm,
+            &JsValue::FALSE,
+            &js_sys::Array::of1(&"deriveBits".into()),
+        ),
+    )
+    .await?;
+
+    let params = js_sys::Object::new();
+    js_sys::Reflect::set(&params, &"name".into(), &"PBKDF2".into())?;
+    js_sys::Reflect::set(&params, &"hash".into(), &"SHA-256".into())?;
+    js_sys::Reflect::set(&params, &"salt".into(), &js_sys::Uint8Array::from(salt))?;
+    js_sys::Reflect::set(&params, &"iterations".into(), &iterations.into())?;
+    let bits = subtle(
+        "deriveBits",
+        js_sys::Array::of3(&params, &key, &HASH_BITS.into()),
+    )
+    .await?;
+    Ok(js_sys::Uint8Array::new(&bits).to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_stored_hashes() {
+        let (iterations, salt, hash) = parse("pbkdf2-sha256$100000$AAECAw$/w").unwrap();
+        assert_eq!(iterations, 100_000);
+        assert_eq!(salt, [0, 1, 2, 3]);
+        assert_eq!(hash, [255]);
+    }
+
+    #[test]
+    fn rejects_malformed_hashes() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("bcrypt$100000$AAECAw$/w"), None);
+        assert_eq!(parse("pbkdf2-sha256$many$AAECAw$/w"), None);
+        assert_eq!(parse("pbkdf2-sha256$0$AAECAw$/w"), None);
+        assert_eq!(parse("pbkdf2-sha256$100001$AAECAw$/w"), None);
+        assert_eq!(parse("pbkdf2-sha256$100000$AAECAw"), None);
+        assert_eq!(parse("pbkdf2-sha256$100000$AAECAw$/w$extra"), None);
+        // Padding isn't written, so it isn't accepted either
+        assert_eq!(parse("pbkdf2-sha256$100000$AAECAw==$/w"), None);
+    }
+
+    #[test]
+    fn rehashes_hashes_with_other_parameters() {
+        let hasher = PasswordHasher::new().iterations(50_000);
+        assert!(!hasher.needs_rehash("pbkdf2-sha256$50000$AAECAw$/w"));
+        assert!(hasher.needs_rehash("pbkdf2-sha256$100000$AAECAw$/w"));
+        assert!(hasher.needs_rehash("not a hash"));
+    }
+}
@@ -0,0 +1,153 @@
+//! Preview mode for CMS-backed sites, in the spirit of Next.js preview mode.
+//!
+//! An editor opens a page with a signed, expiring token in the query string, e.g. a link built by the CMS
+//! with [create_preview_token]. A valid token sets a cookie so that following navigations stay in preview
+//! mode, and [use_preview_mode] returns `true` during SSR so that the app can render draft content. Preview
+//! responses are marked `Cache-Control: private, no-store` so that drafts never end up in a shared cache.
+//!
+//! ```ignore
+//! WorkerRouterData::builder()
+//!     .preview(PreviewConfig::new("PREVIEW_SECRET"))
+//!     // ...
+//!
+//! #[component]
+//! fn Post(cx: Scope) -> impl IntoView {
+//!     let drafts = use_preview_mode(cx);
+//!     let post = create_resource(cx, move || drafts, |drafts| load_post(drafts));
+//!     // ...
+//! }
+//! ```
+
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use leptos::{use_context, Scope};
+use sha2::Sha256;
+
+use crate::util::cookie;
+use crate::{RequestParts, ResponseOptions};
+
+#[derive(Debug, Clone)]
+pub struct PreviewConfig {
+    /// Name of the secret the tokens are signed with.
+    pub secret: &'static str,
+    /// Query parameter that carries a token to enter preview mode.
+    pub query_param: &'static str,
+    pub cookie_name: &'static str,
+}
+
+impl PreviewConfig {
+    pub fn new(secret: &'static str) -> Self {
+        Self {
+            secret,
+            query_param: "preview",
+            cookie_name: "__preview",
+        }
+    }
+}
+
+/// Whether the current request is rendered in preview mode. Provided in the context of every request
+/// when [WorkerRouterData::preview](crate::WorkerRouterData::preview) is set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PreviewMode(pub bool);
+
+/// Returns `true` if the current request is in preview mode.
+pub fn use_preview_mode(cx: Scope) -> bool {
+    use_context::<PreviewMode>(cx).unwrap_or_default().0
+}
+
+/// Creates a token that enables preview mode for `ttl`, signed with `secret` (the value of the secret,
+/// not its name).
+pub fn create_preview_token(secret: &str, ttl: Duration) -> String {
+    let expires_at = worker::Date::now().as_millis() / 1000 + ttl.as_secs();
+    format!("{expires_at}.{}", sign(secret, expires_at))
+}
+
+/// Checks that `token` was created with `secret` and hasn't expired.
+pub fn verify_preview_token(secret: &str, token: &str) -> bool {
+    let Some((expires_at, signature)) = token.split_once('.') else {
+        return false;
+    };
+    let Ok(expires_at) = expires_at.parse::<u64>() else {
+        return false;
+    };
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+
+    let mut mac = mac(secret);
+    mac.update(expires_at.to_string().as_bytes());
+    mac.verify_slice(&signature).is_ok() && expires_at > worker::Date::now().as_millis() / 1000
+}
+
+/// Leaves preview mode by clearing the cookie, e.g. from a server function behind an "exit preview" button.
+pub fn exit_preview(cx: Scope, config: &PreviewConfig) {
+    if let Some(mut res_options) = use_context::<ResponseOptions>(cx) {
+        res_options
+            .append_header(
+                "Set-Cookie",
+                &format!(
+                    "{}=; Path=/; Max-Age=0; HttpOnly; Secure",
+                    config.cookie_name
+                ),
+            )
+            .expect("failed to append header value");
+    }
+}
+
+/// Decides whether `req` is in preview mode. A valid token in the query string is moved into the cookie.
+pub(crate) fn preview_mode(
+    config: &PreviewConfig,
+    env: &worker::Env,
+    req: &RequestParts,
+    res_options: &mut ResponseOptions,
+) -> worker::Result<PreviewMode> {
+    let secret = env.secret(config.secret)?.to_string();
+
+    let from_query = req
+        .url
+        .query_pairs()
+        .find(|(key, _)| key == config.query_param)
+        .map(|(_, token)| token.into_owned());
+    if let Some(token) = from_query.filter(|token| verify_preview_token(&secret, token)) {
+        let expires_at = token
+            .split_once('.')
+            .and_then(|(expires_at, _)| expires_at.parse::<u64>().ok())
+            .unwrap_or_default();
+        let max_age = expires_at.saturating_sub(worker::Date::now().as_millis() / 1000);
+        res_options.append_header(
+            "Set-Cookie",
+            &format!(
+                "{}={token}; Path=/; Max-Age={max_age}; HttpOnly; Secure; SameSite=Lax",
+                config.cookie_name
+            ),
+        )?;
+    } else if !cookie(&req.headers, config.cookie_name)
+        .is_some_and(|token| verify_preview_token(&secret, &token))
+    {
+        return Ok(PreviewMode(false));
+    }
+
+    res_options.insert_header("Cache-Control", "private, no-store")?;
+    Ok(PreviewMode(true))
+}
+
+/// Whether the request may be in preview mode, without checking the token, e.g. to bypass caches.
+pub(crate) fn preview_requested(
+    config: &PreviewConfig,
+    url: &worker::Url,
+    headers: &worker::Headers,
+) -> bool {
+    url.query_pairs().any(|(key, _)| key == config.query_param)
+        || cookie(headers, config.cookie_name).is_some()
+}
+
+fn mac(secret: &str) -> Hmac<Sha256> {
+    Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size")
+}
+
+fn sign(secret: &str, expires_at: u64) -> String {
+    let mut mac = mac(secret);
+    mac.update(expires_at.to_string().as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
@@ -0,0 +1,129 @@
+//! Query strings with repeated parameters.
+//!
+//! Browsers and HTML forms send lists as repeated parameters, e.g. `?tag=rust&tag=wasm` for a
+//! `<select multiple name="tag">`. [Query] reads them into `Vec` fields, and a parameter that is
+//! sent once still gives a list of one:
+//!
+//! ```ignore
+//! #[derive(Deserialize)]
+//! struct Filters {
+//!     tag: Vec<String>,
+//!     page: Option<u32>,
+//! }
+//!
+//! let Query(filters) = use_request_query::<Filters>(cx)?;
+//! ```
+//!
+//! The query of [API routes](crate::ApiRoutes) is read the same way. Server functions with a `GetJSON`
+//! or `GetCBOR` encoding decode their arguments as Leptos does, which only reads lists from indexed
+//! parameters like `tag[0]=rust&tag[1]=wasm`, so repeated parameters are indexed before the call and
+//! links that repeat them reach the server function with every value.
+
+use std::collections::HashMap;
+
+use leptos::{use_context, Scope};
+use serde::de::DeserializeOwned;
+
+use crate::{LeptosCloudflareError, RequestParts};
+
+/// A query string deserialized into `T`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned> Query<T> {
+    /// Deserializes `query`, without the leading `?`.
+    pub fn parse(query: &str) -> Result<Self, LeptosCloudflareError> {
+        serde_html_form::from_str(query).map(Query).map_err(|err| {
+            LeptosCloudflareError::BadRequest(format!("Invalid query string: {err}"))
+        })
+    }
+}
+
+/// The query string of the current request, deserialized into `T`.
+pub fn use_request_query<T: DeserializeOwned>(
+    cx: Scope,
+) -> Result<Query<T>, LeptosCloudflareError> {
+    let req = use_context::<RequestParts>(cx).ok_or_else(|| {
+        LeptosCloudflareError::Internal("use_request_query called outside of a request".to_string())
+    })?;
+    Query::parse(req.url.query().unwrap_or_default())
+}
+
+/// `query` with the parameters that are repeated indexed, e.g. `tag=a&tag=b` as `tag[0]=a&tag[1]=b`.
+/// Parameters that appear once, and those already using brackets, are left as they are.
+pub(crate) fn index_repeated_params(query: &str) -> String {
+    let pairs: Vec<(&str, &str)> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+        .collect();
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for &(name, _) in &pairs {
+        *counts.entry(name).or_default() += 1;
+    }
+    if counts.values().all(|count| *count == 1) {
+        return query.to_string();
+    }
+
+    let mut indexes: HashMap<&str, usize> = HashMap::new();
+    pairs
+        .iter()
+        .map(|&(name, value)| {
+            // Names are compared encoded, so `tag%5B%5D` is a bracketed name too
+            let bracketed = name.contains('[') || name.to_ascii_uppercase().contains("%5B");
+            if counts[name] == 1 || bracketed {
+                return format!("{name}={value}");
+            }
+            let index = indexes.entry(name).or_default();
+            let pair = format!("{name}[{index}]={value}");
+            *index += 1;
+            pair
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indexes_repeated_params() {
+        assert_eq!(index_repeated_params("tag=a&tag=b"), "tag[0]=a&tag[1]=b");
+        assert_eq!(
+            index_repeated_params("tag=a&page=2&tag=b&tag=c"),
+            "tag[0]=a&page=2&tag[1]=b&tag[2]=c"
+        );
+        assert_eq!(index_repeated_params("flag&flag"), "flag[0]=&flag[1]=");
+    }
+
+    #[test]
+    fn keeps_single_and_bracketed_params() {
+        assert_eq!(index_repeated_params(""), "");
+        assert_eq!(index_repeated_params("page=2&q=a%20b"), "page=2&q=a%20b");
+        assert_eq!(index_repeated_params("tag[]=a&tag[]=b"), "tag[]=a&tag[]=b");
+        assert_eq!(
+            index_repeated_params("tag%5B%5D=a&tag%5b%5d=b&tag%5B%5D=c"),
+            "tag%5B%5D=a&tag%5b%5d=b&tag%5B%5D=c"
+        );
+        assert_eq!(
+            index_repeated_params("tag[0]=a&tag[1]=b&id=1&id=2"),
+            "tag[0]=a&tag[1]=b&id[0]=1&id[1]=2"
+        );
+    }
+
+    #[test]
+    fn repeated_params_reach_get_json_server_fns() {
+        #[derive(Debug, serde::Deserialize)]
+        struct GetTodos {
+            tag: Vec<String>,
+            done: bool,
+        }
+
+        // A link to `/api/get_todos?tag=a&tag=b&done=false`, decoded as Leptos decodes `GetJSON`
+        let query = index_repeated_params("tag=a&tag=b&done=false");
+        let args: GetTodos = serde_qs::from_str(&query).unwrap();
+        assert_eq!(args.tag, ["a", "b"]);
+        assert!(!args.done);
+    }
+}
@@ -0,0 +1,413 @@
+//! Cloudflare Queues helpers.
+//!
+//! Messages are wrapped in a [QueueEnvelope] that counts delivery attempts, so that a message that keeps
+//! failing can be moved to a dead-letter queue after a fixed number of attempts instead of being retried
+//! forever, and a hook can be notified about it.
+//!
+//! [handle_queue] runs the consumer of a batch inside a Leptos runtime, so that code shared with the
+//! server functions can process messages:
+//!
+//! ```ignore
+//! #[worker::event(queue)]
+//! pub async fn queue(batch: MessageBatch<Order>, env: Env, _ctx: Context) -> worker::Result<()> {
+//!     handle_queue(batch, env, |cx| async move {
+//!         let batch = use_message_batch::<Order>(cx).unwrap();
+//!         for message in batch.messages()? {
+//!             fulfil_order(cx, message.body()).await?;
+//!             message.ack();
+//!         }
+//!         Ok(())
+//!     })
+//!     .await?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! On the producer side, [use_queue] gets a queue from the bindings of the request, so that a server
+//! function can hand heavy work to a consumer and answer `202 Accepted` right away:
+//!
+//! ```ignore
+//! #[server(ExportReport, "/api")]
+//! pub async fn export_report(cx: Scope, report: ReportRequest) -> Result<(), ServerFnError> {
+//!     use_queue(cx, "EXPORTS")?
+//!         .send_with(&report, SendOptions::new().delay(Duration::from_secs(30)))
+//!         .await?;
+//!     accepted(cx);
+//!     Ok(())
+//! }
+//! ```
+
+use std::future::Future;
+use std::rc::Rc;
+use std::time::Duration;
+
+use js_sys::{Array, Object, Reflect};
+use leptos::{create_runtime, provide_context, raw_scope_and_disposer, use_context, Scope};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use worker::{Env, MessageBatch};
+
+use crate::app_env::RequestEnv;
+use crate::{use_env, LeptosCloudflareError, ResponseOptions};
+
+/// The batch being consumed, provided in the context of [handle_queue].
+pub struct QueueBatch<T>(pub Rc<MessageBatch<T>>);
+
+impl<T> Clone for QueueBatch<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+/// Runs `handler` in a new Leptos runtime with the batch and the bindings of the worker in its context.
+/// If `handler` fails, the messages it didn't acknowledge are retried. There is no request, so helpers
+/// that read it, like [use_dep](crate::use_dep), aren't available.
+pub async fn handle_queue<T, F, Fut>(
+    batch: MessageBatch<T>,
+    env: Env,
+    handler: F,
+) -> Result<(), LeptosCloudflareError>
+where
+    T: 'static,
+    F: FnOnce(Scope) -> Fut,
+    Fut: Future<Output = Result<(), LeptosCloudflareError>>,
+{
+    let runtime = create_runtime();
+    let (cx, disposer) = raw_scope_and_disposer(runtime);
+
+    let batch = Rc::new(batch);
+    provide_context(cx, RequestEnv(env));
+    provide_context(cx, QueueBatch(batch.clone()));
+    let result = handler(cx).await;
+    if let Err(err) = &result {
+        tracing::error!("queue {} consumer failed: {err}", batch.queue());
+        batch.retry_all();
+    }
+
+    disposer.dispose();
+    runtime.dispose();
+    result
+}
+
+/// The batch consumed by [handle_queue], if called from its handler with the message type of the batch.
+pub fn use_message_batch<T: 'static>(cx: Scope) -> Option<Rc<MessageBatch<T>>> {
+    use_context::<QueueBatch<T>>(cx).map(|batch| batch.0)
+}
+
+/// Acknowledges every message of the batch consumed by [handle_queue], so none of them is retried.
+pub fn ack_batch<T: 'static>(cx: Scope) {
+    if let Some(batch) = use_message_batch::<T>(cx) {
+        batch.ack_all();
+    }
+}
+
+/// Retries every message of the batch consumed by [handle_queue] that wasn't acknowledged.
+pub fn retry_batch<T: 'static>(cx: Scope) {
+    if let Some(batch) = use_message_batch::<T>(cx) {
+        batch.retry_all();
+    }
+}
+
+/// A queue message body together with the number of times processing it has failed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueEnvelope<T> {
+    pub attempts: u32,
+    pub body: T,
+}
+
+impl<T> QueueEnvelope<T> {
+    pub fn new(body: T) -> Self {
+        Self { attempts: 0, body }
+    }
+}
+
+/// Where failed messages go.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Attempts after which a message is considered poisoned.
+    pub max_attempts: u32,
+    /// Producer binding of the queue being consumed, used to send failed messages back for another attempt.
+    pub retry_queue: String,
+    /// How long the first retry is delayed. Each further retry waits twice as long as the previous
+    /// one, at most 12 hours.
+    pub retry_delay: Duration,
+    /// Producer binding of the dead-letter queue. Poisoned messages are dropped if it's `None`.
+    pub dead_letter_queue: Option<String>,
+}
+
+impl RetryPolicy {
+    /// Retries through `retry_queue` up to `max_attempts` times, 10 seconds after the first failure
+    /// and twice as long after each further one, and then drops the message.
+    pub fn new(max_attempts: u32, retry_queue: &str) -> Self {
+        Self {
+            max_attempts,
+            retry_queue: retry_queue.to_string(),
+            retry_delay: Duration::from_secs(10),
+            dead_letter_queue: None,
+        }
+    }
+
+    /// Moves poisoned messages to the queue bound as `dead_letter_queue`.
+    pub fn dead_letter_queue(mut self, dead_letter_queue: &str) -> Self {
+        self.dead_letter_queue = Some(dead_letter_queue.to_string());
+        self
+    }
+
+    /// The delay before the retry that follows the failed attempt number `attempts`.
+    fn backoff(&self, attempts: u32) -> Duration {
+        self.retry_delay
+            .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+            .min(MAX_DELAY)
+    }
+}
+
+/// The longest delay a message can be sent with.
+const MAX_DELAY: Duration = Duration::from_secs(12 * 3600);
+
+/// Summary of a [process_batch] run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchReport {
+    pub processed: u32,
+    pub retried: u32,
+    pub dead_lettered: u32,
+}
+
+/// Runs `handler` for every message of `batch` and accounts for failures according to `policy`.
+///
+/// A failed message is re-sent to [retry_queue](RetryPolicy::retry_queue) with its attempt count
+/// increased, delayed with exponential backoff from [retry_delay](RetryPolicy::retry_delay) so that a
+/// failing downstream isn't hammered. Once it reaches [max_attempts](RetryPolicy::max_attempts), `on_poison` is called with
+/// the message and the last error, and the message is moved to the
+/// [dead_letter_queue](RetryPolicy::dead_letter_queue). Either way the original delivery is acknowledged.
+///
+/// ```ignore
+/// #[worker::event(queue)]
+/// pub async fn queue(batch: MessageBatch<QueueEnvelope<Email>>, env: Env, _ctx: Context) -> worker::Result<()> {
+///     process_batch(&batch, &env, &policy, |email| send_email(email), |envelope, err| {
+///         tracing::error!("giving up on email to {}: {err}", envelope.body.to);
+///     })
+///     .await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn process_batch<T, F, Fut, P>(
+    batch: &MessageBatch<QueueEnvelope<T>>,
+    env: &Env,
+    policy: &RetryPolicy,
+    handler: F,
+    on_poison: P,
+) -> worker::Result<BatchReport>
+where
+    T: Serialize + DeserializeOwned + Clone,
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = Result<(), LeptosCloudflareError>>,
+    P: Fn(&QueueEnvelope<T>, &LeptosCloudflareError),
+{
+    let mut report = BatchReport::default();
+
+    for message in batch.messages()? {
+        let envelope = message.body().clone();
+        match handler(envelope.body.clone()).await {
+            Ok(()) => report.processed += 1,
+            Err(err) => {
+                let failed = QueueEnvelope {
+                    attempts: envelope.attempts + 1,
+                    body: envelope.body,
+                };
+                if failed.attempts >= policy.max_attempts {
+                    tracing::error!(
+                        "queue message {} poisoned after {} attempts: {err}",
+                        message.id(),
+                        failed.attempts
+                    );
+                    on_poison(&failed, &err);
+                    if let Some(dead_letter_queue) = &policy.dead_letter_queue {
+                        env.queue(dead_letter_queue)?.send(&failed).await?;
+                    }
+                    report.dead_lettered += 1;
+                } else {
+                    let options = SendOptions::new().delay(policy.backoff(failed.attempts));
+                    QueueProducer::from_env(env, &policy.retry_queue)?
+                        .send_with(&failed, options)
+                        .await
+                        .map_err(|err| match err {
+                            LeptosCloudflareError::Worker(err) => err,
+                            err => worker::Error::RustError(err.to_string()),
+                        })?;
+                    report.retried += 1;
+                }
+            }
+        }
+        message.ack();
+    }
+
+    Ok(report)
+}
+
+/// The queue bound as `binding`, from the bindings of the current request.
+pub fn use_queue(cx: Scope, binding: &str) -> Result<QueueProducer, LeptosCloudflareError> {
+    Ok(QueueProducer::from_env(&use_env(cx)?, binding)?)
+}
+
+/// Answers the current server function with `202 Accepted`, for work that was queued.
+pub fn accepted(cx: Scope) {
+    if let Some(res_options) = use_context::<ResponseOptions>(cx) {
+        res_options.set_status(202);
+    }
+}
+
+/// How a message body is encoded for its consumer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QueueContentType {
+    /// Serialized as JSON, readable by consumers in any language.
+    #[default]
+    Json,
+    /// A string body as is, other bodies as JSON text.
+    Text,
+    /// The structured clone of the body, the default of the JavaScript API.
+    V8,
+}
+
+impl QueueContentType {
+    fn as_str(self) -> &'static str {
+        match self {
+            QueueContentType::Json => "json",
+            QueueContentType::Text => "text",
+            QueueContentType::V8 => "v8",
+        }
+    }
+}
+
+/// Options of [QueueProducer::send_with] and [QueueProducer::send_batch_with].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SendOptions {
+    content_type: QueueContentType,
+    delay: Option<Duration>,
+}
+
+impl SendOptions {
+    pub const fn new() -> Self {
+        Self {
+            content_type: QueueContentType::Json,
+            delay: None,
+        }
+    }
+
+    pub const fn content_type(mut self, content_type: QueueContentType) -> Self {
+        self.content_type = content_type;
+        self
+    }
+
+    /// Delivers the messages after `delay`, at most 12 hours.
+    pub const fn delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    fn to_js(self) -> worker::Result<Object> {
+        let options = Object::new();
+        Reflect::set(
+            &options,
+            &"contentType".into(),
+            &self.content_type.as_str().into(),
+        )?;
+        if let Some(delay) = self.delay {
+            Reflect::set(
+                &options,
+                &"delaySeconds".into(),
+                &(delay.as_secs() as f64).into(),
+            )?;
+        }
+        Ok(options)
+    }
+}
+
+/// The producer of a queue.
+#[derive(Clone)]
+pub struct QueueProducer(JsValue);
+
+impl QueueProducer {
+    /// The queue bound as `binding` in `env`.
+    pub fn from_env(env: &Env, binding: &str) -> worker::Result<Self> {
+        let queue = Reflect::get(env, &JsValue::from_str(binding))?;
+        if queue.is_undefined() {
+            return Err(worker::Error::RustError(format!(
+                "no queue is bound as {binding}"
+            )));
+        }
+        Ok(Self(queue))
+    }
+
+    /// Sends `body` as JSON.
+    pub async fn send<T: Serialize>(&self, body: &T) -> Result<(), LeptosCloudflareError> {
+        self.send_with(body, SendOptions::new()).await
+    }
+
+    pub async fn send_with<T: Serialize>(
+        &self,
+        body: &T,
+        options: SendOptions,
+    ) -> Result<(), LeptosCloudflareError> {
+        let body = encode(body, options.content_type)?;
+        self.call("send", &[body, options.to_js()?.into()]).await
+    }
+
+    /// Sends `bodies` as JSON in one call, at most 100 messages.
+    pub async fn send_batch<T: Serialize>(
+        &self,
+        bodies: impl IntoIterator<Item = T>,
+    ) -> Result<(), LeptosCloudflareError> {
+        self.send_batch_with(bodies, SendOptions::new()).await
+    }
+
+    /// Sends `bodies` in one call. The options apply to every message.
+    pub async fn send_batch_with<T: Serialize>(
+        &self,
+        bodies: impl IntoIterator<Item = T>,
+        options: SendOptions,
+    ) -> Result<(), LeptosCloudflareError> {
+        let messages = Array::new();
+        for body in bodies {
+            let message = options.to_js()?;
+            Reflect::set(
+                &message,
+                &"body".into(),
+                &encode(&body, options.content_type)?,
+            )
+            .map_err(worker::Error::from)?;
+            messages.push(&message);
+        }
+        if messages.length() == 0 {
+            return Ok(());
+        }
+        self.call("sendBatch", &[messages.into()]).await
+    }
+
+    async fn call(&self, method: &str, args: &[JsValue]) -> Result<(), LeptosCloudflareError> {
+        let function: js_sys::Function = Reflect::get(&self.0, &method.into())
+            .map_err(worker::Error::from)?
+            .unchecked_into();
+        let args = args.iter().collect::<Array>();
+        let promise: js_sys::Promise = function
+            .apply(&self.0, &args)
+            .map_err(worker::Error::from)?
+            .unchecked_into();
+        JsFuture::from(promise).await.map_err(worker::Error::from)?;
+        Ok(())
+    }
+}
+
+/// `body` as the JavaScript value sent with `content_type`.
+fn encode<T: Serialize>(
+    body: &T,
+    content_type: QueueContentType,
+) -> Result<JsValue, LeptosCloudflareError> {
+    let json = serde_json::to_value(body).map_err(worker::Error::from)?;
+    match (content_type, json) {
+        (QueueContentType::Text, serde_json::Value::String(text)) => Ok(JsValue::from_str(&text)),
+        (QueueContentType::Text, json) => Ok(JsValue::from_str(&json.to_string())),
+        (_, json) => Ok(js_sys::JSON::parse(&json.to_string()).map_err(worker::Error::from)?),
+    }
+}
@@ -0,0 +1,272 @@
+//! Storing and fetching [R2](worker::Bucket) objects from server functions and API routes.
+//!
+//! [use_r2] gets a bucket from the bindings of the request. Objects are read with [r2_get], which
+//! keeps the body as a stream: an [R2Get] is returned from an [api_route](crate::ApiRoutes::api_route)
+//! as is and streams the object with its `ETag`, `Last-Modified` and HTTP metadata, answering
+//! `304 Not Modified` when the client already has it:
+//!
+//! ```ignore
+//! router.api_route(worker::Method::Get, "/files/*key", |req: ApiRequest<FilePath>| async move {
+//!     let bucket = req.env.bucket("FILES")?;
+//!     r2_get(&bucket, &req.path.key, Some(&req.parts.headers)).await
+//! })
+//! ```
+//!
+//! Server functions serialize what they return, so they can't stream. They read small objects into
+//! memory with [R2Get::into_stored], which fails beyond a size limit, or return the [ObjectMeta] and
+//! let the client fetch the body from a route like the one above:
+//!
+//! ```ignore
+//! #[server(SaveAvatar, "/api")]
+//! pub async fn save_avatar(cx: Scope, user: String, png: Vec<u8>) -> Result<ObjectMeta, ServerFnError> {
+//!     let bucket = use_r2(cx, "FILES")?;
+//!     let put = R2Put::new().content_type("image/png").metadata("user", &user);
+//!     Ok(r2_put(&bucket, &format!("avatars/{user}.png"), png, put).await?)
+//! }
+//! ```
+
+use std::collections::HashMap;
+
+use leptos::Scope;
+use serde::{Deserialize, Serialize};
+use worker::{Bucket, Conditional, Data, HttpMetadata, Object, ObjectBody};
+
+use crate::response::IntoWorkerResponse;
+use crate::{use_env, LeptosCloudflareError};
+
+/// The R2 bucket bound as `binding`, from the bindings of the current request.
+pub fn use_r2(cx: Scope, binding: &str) -> Result<Bucket, LeptosCloudflareError> {
+    Ok(use_env(cx)?.bucket(binding)?)
+}
+
+/// What R2 knows about an object, without its body.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectMeta {
+    pub key: String,
+    pub size: u64,
+    /// The etag quoted as in the `ETag` header.
+    pub http_etag: String,
+    /// Milliseconds since the epoch.
+    pub uploaded: u64,
+    pub content_type: Option<String>,
+    pub content_disposition: Option<String>,
+    pub content_encoding: Option<String>,
+    pub content_language: Option<String>,
+    pub cache_control: Option<String>,
+    pub custom_metadata: HashMap<String, String>,
+}
+
+impl ObjectMeta {
+    fn of(object: &Object) -> worker::Result<Self> {
+        let http = object.http_metadata();
+        Ok(Self {
+            key: object.key(),
+            size: u64::from(object.size()),
+            http_etag: object.http_etag(),
+            uploaded: object.uploaded().as_millis(),
+            content_type: http.content_type,
+            content_disposition: http.content_disposition,
+            content_encoding: http.content_encoding,
+            content_language: http.content_language,
+            cache_control: http.cache_control,
+            custom_metadata: object.custom_metadata()?,
+        })
+    }
+
+    /// Sets the `ETag`, `Last-Modified` and HTTP metadata headers of a response for the object.
+    fn set_headers(&self, headers: &mut worker::Headers) -> worker::Result<()> {
+        headers.set("ETag", &self.http_etag)?;
+        headers.set(
+            "Last-Modified",
+            &js_sys::Date::new(&(self.uploaded as f64).into())
+                .to_utc_string()
+                .as_string()
+                .unwrap_or_default(),
+        )?;
+        let optional = [
+            ("Content-Type", &self.content_type),
+            ("Content-Disposition", &self.content_disposition),
+            ("Content-Encoding", &self.content_encoding),
+            ("Content-Language", &self.content_language),
+            ("Cache-Control", &self.cache_control),
+        ];
+        for (name, value) in optional {
+            if let Some(value) = value {
+                headers.set(name, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An object and its body, read into memory so that a server function can return it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredObject {
+    pub meta: ObjectMeta,
+    pub body: Vec<u8>,
+}
+
+/// The outcome of [r2_get].
+pub enum R2Get {
+    NotFound,
+    /// The object matches the `If-None-Match` of the request, so its body wasn't read.
+    NotModified(ObjectMeta),
+    Found(ObjectMeta, ObjectBody),
+}
+
+impl R2Get {
+    pub fn meta(&self) -> Option<&ObjectMeta> {
+        match self {
+            R2Get::NotFound => None,
+            R2Get::NotModified(meta) | R2Get::Found(meta, _) => Some(meta),
+        }
+    }
+
+    /// Reads the body into memory, failing with `400 Bad Request` if the object is larger than
+    /// `max_size` bytes. A not modified object has no body to read, so only use this without
+    /// conditions.
+    pub async fn into_stored(
+        self,
+        max_size: u64,
+    ) -> Result<Option<StoredObject>, LeptosCloudflareError> {
+        match self {
+            R2Get::NotFound => Ok(None),
+            R2Get::NotModified(meta) => Err(LeptosCloudflareError::Internal(format!(
+                "the body of {} wasn't read because it was not modified",
+                meta.key
+            ))),
+            R2Get::Found(meta, _) if meta.size > max_size => {
+                Err(LeptosCloudflareError::BadRequest(format!(
+                    "{} is {} bytes, more than the limit of {max_size}",
+                    meta.key, meta.size
+                )))
+            }
+            R2Get::Found(meta, body) => Ok(Some(StoredObject {
+                meta,
+                body: body.bytes().await?,
+            })),
+        }
+    }
+}
+
+impl IntoWorkerResponse for R2Get {
+    fn into_worker_response(self) -> worker::Result<worker::Response> {
+        match self {
+            R2Get::NotFound => LeptosCloudflareError::NotFound.into_worker_response(),
+            R2Get::NotModified(meta) => {
+                let mut response = worker::Response::empty()?.with_status(304);
+                response.headers_mut().set("ETag", &meta.http_etag)?;
+                Ok(response)
+            }
+            R2Get::Found(meta, body) => {
+                let mut response = worker::Response::from_body(body.response_body()?)?;
+                meta.set_headers(response.headers_mut())?;
+                response
+                    .headers_mut()
+                    .set("Content-Length", &meta.size.to_string())?;
+                Ok(response)
+            }
+        }
+    }
+}
+
+/// Fetches the object `key`. With the `headers` of a request carrying a single etag in
+/// `If-None-Match`, the body is only read if the object changed.
+pub async fn r2_get(
+    bucket: &Bucket,
+    key: &str,
+    headers: Option<&worker::Headers>,
+) -> worker::Result<R2Get> {
+    let if_none_match = match headers {
+        Some(headers) => headers.get("If-None-Match")?,
+        None => None,
+    };
+    let mut get = bucket.get(key);
+    // R2 only takes a single etag, without the quotes or the weak prefix
+    if let Some(etag) = if_none_match
+        .as_deref()
+        .filter(|value| !value.contains(','))
+        .map(|value| value.trim().trim_start_matches("W/").trim_matches('"'))
+        .filter(|etag| !etag.is_empty() && *etag != "*")
+    {
+        get = get.only_if(Conditional {
+            etag_does_not_match: Some(etag.to_string()),
+            ..Default::default()
+        });
+    }
+
+    let Some(object) = get.execute().await? else {
+        return Ok(R2Get::NotFound);
+    };
+    let meta = ObjectMeta::of(&object)?;
+    Ok(match object.body() {
+        Some(body) => R2Get::Found(meta, body),
+        None => R2Get::NotModified(meta),
+    })
+}
+
+/// The metadata of the object `key`, without fetching its body.
+pub async fn r2_head(bucket: &Bucket, key: &str) -> worker::Result<Option<ObjectMeta>> {
+    match bucket.head(key).await? {
+        Some(object) => Ok(Some(ObjectMeta::of(&object)?)),
+        None => Ok(None),
+    }
+}
+
+/// Metadata stored along with an object by [r2_put].
+#[derive(Debug, Clone, Default)]
+pub struct R2Put {
+    http: HttpMetadata,
+    custom: HashMap<String, String>,
+}
+
+impl R2Put {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn content_type(mut self, content_type: &str) -> Self {
+        self.http.content_type = Some(content_type.to_string());
+        self
+    }
+
+    pub fn content_disposition(mut self, content_disposition: &str) -> Self {
+        self.http.content_disposition = Some(content_disposition.to_string());
+        self
+    }
+
+    pub fn cache_control(mut self, cache_control: &str) -> Self {
+        self.http.cache_control = Some(cache_control.to_string());
+        self
+    }
+
+    /// Adds a custom metadata entry, returned in [ObjectMeta::custom_metadata].
+    pub fn metadata(mut self, name: &str, value: &str) -> Self {
+        self.custom.insert(name.to_string(), value.to_string());
+        self
+    }
+}
+
+/// Stores `body` as the object `key`. Bodies can be bytes, text or a stream of known length, e.g. the
+/// body of a request or of another object. The content type is guessed from the extension of `key`
+/// unless `put` sets one.
+pub async fn r2_put(
+    bucket: &Bucket,
+    key: &str,
+    body: impl Into<Data>,
+    put: R2Put,
+) -> worker::Result<ObjectMeta> {
+    let mut http = put.http;
+    if http.content_type.is_none() {
+        http.content_type = mime_guess::from_path(key)
+            .first()
+            .map(|mime| mime.essence_str().to_string());
+    }
+    let object = bucket
+        .put(key, body)
+        .http_metadata(http)
+        .custom_metadata(put.custom)
+        .execute()
+        .await?;
+    ObjectMeta::of(&object)
+}
@@ -0,0 +1,164 @@
+//! The Workers Rate Limiting binding.
+//!
+//! Each binding has a limit of requests per period of 10 or 60 seconds, counted per key at the
+//! Cloudflare location that handles the request:
+//!
+//! ```toml
+//! [[unsafe.bindings]]
+//! name = "LOGIN_LIMITER"
+//! type = "ratelimit"
+//! namespace_id = "1001"
+//! simple = { limit = 10, period = 60 }
+//! ```
+//!
+//! A [RateLimiter] names the binding and its period, and [guard](RateLimiter::guard) fails with
+//! `429 Too Many Requests` and a `Retry-After` header once the key is over the limit:
+//!
+//! ```ignore
+//! static LOGIN_LIMITER: RateLimiter = RateLimiter::new("LOGIN_LIMITER").period(60);
+//!
+//! #[server(Login, "/api")]
+//! pub async fn login(cx: Scope, email: String, password: String) -> Result<(), ServerFnError> {
+//!     LOGIN_LIMITER
+//!         .guard(cx, &email)
+//!         .await
+//!         .map_err(|err| ServerFnError::ServerError(err.to_string()))?;
+//!     // ...
+//! }
+//! ```
+//!
+//! Handlers without a scope, like those of [API routes](crate::ApiRoutes), use
+//! [check](RateLimiter::check) instead, whose [RateLimited] error responds the same way:
+//!
+//! ```ignore
+//! async fn search(req: ApiRequest<(), SearchQuery>) -> Result<Json<Vec<Hit>>, RateLimited> {
+//!     SEARCH_LIMITER.check(&req.env, &client_ip(&req.parts)).await?;
+//!     // ...
+//! }
+//! ```
+//!
+//! The counts are eventually consistent and local to a location, so the binding suits abuse
+//! protection rather than exact quotas.
+
+use js_sys::{Object, Reflect};
+use leptos::{use_context, Scope};
+use thiserror::Error;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use worker::Env;
+
+use crate::response::IntoWorkerResponse;
+use crate::{use_env, LeptosCloudflareError, ResponseOptions};
+
+/// A rate limit bound with the Workers Rate Limiting API.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimiter {
+    binding: &'static str,
+    period: u32,
+}
+
+impl RateLimiter {
+    /// The limiter bound as `binding`, with a period of 60 seconds.
+    pub const fn new(binding: &'static str) -> Self {
+        Self {
+            binding,
+            period: 60,
+        }
+    }
+
+    /// The period of the binding in seconds, sent as `Retry-After` when the limit is exceeded.
+    pub const fn period(mut self, seconds: u32) -> Self {
+        self.period = seconds;
+        self
+    }
+
+    /// Counts a request for `key`, returning whether it is within the limit.
+    pub async fn limit(&self, env: &Env, key: &str) -> worker::Result<bool> {
+        let limiter = Reflect::get(env, &JsValue::from_str(self.binding))?;
+        if limiter.is_undefined() {
+            return Err(worker::Error::RustError(format!(
+                "no rate limiter is bound as {}",
+                self.binding
+            )));
+        }
+        let options = Object::new();
+        Reflect::set(&options, &"key".into(), &JsValue::from_str(key))?;
+        let function: js_sys::Function = Reflect::get(&limiter, &"limit".into())?.unchecked_into();
+        let promise: js_sys::Promise = function.call1(&limiter, &options)?.unchecked_into();
+        let outcome = JsFuture::from(promise).await?;
+        Ok(Reflect::get(&outcome, &"success".into())?.is_truthy())
+    }
+
+    /// Counts a request for `key`, failing with [RateLimited] if it exceeds the limit.
+    pub async fn check(&self, env: &Env, key: &str) -> Result<(), RateLimited> {
+        if self.limit(env, key).await? {
+            Ok(())
+        } else {
+            Err(RateLimited::Exceeded {
+                retry_after: self.period,
+            })
+        }
+    }
+
+    /// Counts a request for `key` with the bindings of the current request. If it exceeds the limit,
+    /// the response is sent with `429 Too Many Requests` and `Retry-After`, and it fails with
+    /// [LeptosCloudflareError::TooManyRequests].
+    pub async fn guard(&self, cx: Scope, key: &str) -> Result<(), LeptosCloudflareError> {
+        let env = use_env(cx)?;
+        let retry_after = match self.check(&env, key).await {
+            Ok(()) => return Ok(()),
+            Err(RateLimited::Exceeded { retry_after }) => retry_after,
+            Err(RateLimited::Worker(err)) => return Err(err.into()),
+        };
+        if let Some(mut response_options) = use_context::<ResponseOptions>(cx) {
+            response_options.set_status(429);
+            response_options.insert_header("Retry-After", &retry_after.to_string())?;
+        }
+        Err(LeptosCloudflareError::TooManyRequests)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum RateLimited {
+    #[error("Too many requests")]
+    Exceeded {
+        /// Seconds until the limit resets at the latest.
+        retry_after: u32,
+    },
+    #[error(transparent)]
+    Worker(#[from] worker::Error),
+}
+
+impl From<RateLimited> for LeptosCloudflareError {
+    fn from(err: RateLimited) -> Self {
+        match err {
+            RateLimited::Exceeded { .. } => LeptosCloudflareError::TooManyRequests,
+            RateLimited::Worker(err) => LeptosCloudflareError::Worker(err),
+        }
+    }
+}
+
+impl IntoWorkerResponse for RateLimited {
+    fn into_worker_response(self) -> worker::Result<worker::Response> {
+        let retry_after = match &self {
+            RateLimited::Exceeded { retry_after } => Some(*retry_after),
+            RateLimited::Worker(_) => None,
+        };
+        let mut response = LeptosCloudflareError::from(self).into_worker_response()?;
+        if let Some(retry_after) = retry_after {
+            response
+                .headers_mut()
+                .set("Retry-After", &retry_after.to_string())?;
+        }
+        Ok(response)
+    }
+}
+
+impl<T: IntoWorkerResponse> IntoWorkerResponse for Result<T, RateLimited> {
+    fn into_worker_response(self) -> worker::Result<worker::Response> {
+        match self {
+            Ok(value) => value.into_worker_response(),
+            Err(err) => err.into_worker_response(),
+        }
+    }
+}
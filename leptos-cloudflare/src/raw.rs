@@ -0,0 +1,194 @@
+//! Routes that get the request body untouched, for binary protocols such as protobuf or gRPC-web.
+//!
+//! [API routes](crate::ApiRoutes) and server functions read the whole body into
+//! [RequestParts](crate::RequestParts) before the handler runs. A [raw_route](RawRoutes::raw_route)
+//! handler gets the request as it arrived instead, and reads its body as a stream when and if it
+//! needs it. [BinaryResponse] answers with bytes or a stream of them:
+//!
+//! ```ignore
+//! async fn say_hello(mut req: RawRequest) -> worker::Result<worker::Response> {
+//!     let mut frames = req.bytes_stream()?;
+//!     let request = read_grpc_web_frame::<HelloRequest>(&mut frames).await?;
+//!     BinaryResponse::new("application/grpc-web+proto")
+//!         .header("grpc-status", "0")
+//!         .bytes(grpc_web_frame(&HelloReply::from(request)))
+//! }
+//!
+//! router
+//!     .leptos_routes(routes)
+//!     .raw_route(Method::Post, "/rpc/helloworld.Greeter/SayHello", say_hello)
+//! ```
+//!
+//! A proxied body can be handed on without being read, e.g. with
+//! [readable_stream](BinaryResponse::readable_stream) of [body](RawRequest::body).
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::rc::Rc;
+
+use futures::future::LocalBoxFuture;
+use futures::{Stream, StreamExt};
+use http::StatusCode;
+
+use crate::api::{decode_params, route, RouteRegistry};
+use crate::response::IntoWorkerResponse;
+use crate::ErrorRenderer;
+
+/// A request whose body hasn't been read.
+pub struct RawRequest {
+    /// The decoded `:param` and `*wildcard` values of the route.
+    pub params: HashMap<String, String>,
+    pub env: worker::Env,
+    req: worker::Request,
+}
+
+impl RawRequest {
+    pub fn method(&self) -> worker::Method {
+        self.req.method()
+    }
+
+    pub fn url(&self) -> worker::Result<worker::Url> {
+        self.req.url()
+    }
+
+    pub fn headers(&self) -> &worker::Headers {
+        self.req.headers()
+    }
+
+    /// The body as the runtime received it, `None` for requests without one.
+    pub fn body(&self) -> Option<web_sys::ReadableStream> {
+        self.req.inner().body()
+    }
+
+    /// The body as a stream of chunks.
+    pub fn bytes_stream(&mut self) -> worker::Result<worker::ByteStream> {
+        self.req.stream()
+    }
+
+    /// The underlying `web_sys::Request`.
+    pub fn web_sys(&self) -> &web_sys::Request {
+        self.req.inner()
+    }
+
+    pub fn into_inner(self) -> worker::Request {
+        self.req
+    }
+}
+
+/// A response with a binary body.
+pub struct BinaryResponse {
+    status: u16,
+    headers: worker::Headers,
+}
+
+impl BinaryResponse {
+    /// A `200` response with the `Content-Type` `content_type`.
+    pub fn new(content_type: &str) -> Self {
+        let headers = worker::Headers::new();
+        // Content-Type is a valid header name, setting it only fails for invalid values
+        let _ = headers.set("Content-Type", content_type);
+        Self {
+            status: 200,
+            headers,
+        }
+    }
+
+    pub fn status(mut self, status: u16) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Sets the header `name`. Invalid headers are skipped.
+    pub fn header(self, name: &str, value: &str) -> Self {
+        if let Err(err) = self.headers.set(name, value) {
+            tracing::warn!("skipping invalid header {name}: {err}");
+        }
+        self
+    }
+
+    pub fn bytes(self, bytes: Vec<u8>) -> worker::Result<worker::Response> {
+        Ok(worker::Response::from_bytes(bytes)?
+            .with_status(self.status)
+            .with_headers(self.headers))
+    }
+
+    /// Sends the chunks of `stream` as they are produced. An error ends the body early.
+    pub fn stream(
+        self,
+        stream: impl Stream<Item = worker::Result<Vec<u8>>> + 'static,
+    ) -> worker::Result<worker::Response> {
+        let stream = stream.map(|chunk| chunk.map_err(|err| err.to_string()));
+        Ok(worker::Response::from_stream(stream)?
+            .with_status(self.status)
+            .with_headers(self.headers))
+    }
+
+    /// Sends `stream` as is, e.g. the body of another request or response.
+    pub fn readable_stream(
+        self,
+        stream: web_sys::ReadableStream,
+    ) -> worker::Result<worker::Response> {
+        Ok(
+            worker::Response::from_body(worker::ResponseBody::Stream(stream))?
+                .with_status(self.status)
+                .with_headers(self.headers),
+        )
+    }
+}
+
+type BoxedRawHandler =
+    Rc<dyn Fn(RawRequest) -> LocalBoxFuture<'static, worker::Result<worker::Response>>>;
+
+thread_local! {
+    static RAW_ROUTES: RouteRegistry<BoxedRawHandler> = RouteRegistry::new();
+}
+
+pub trait RawRoutes {
+    /// Registers `handler` for requests with `method` whose path matches `path`, which uses the
+    /// same `:param` and `*wildcard` syntax as the worker router. The body is left for the handler.
+    fn raw_route<H, Fut, R>(self, method: worker::Method, path: &str, handler: H) -> Self
+    where
+        H: Fn(RawRequest) -> Fut + 'static,
+        Fut: Future<Output = R> + 'static,
+        R: IntoWorkerResponse + 'static;
+}
+
+impl<'a, D: 'static> RawRoutes for worker::Router<'a, D> {
+    fn raw_route<H, Fut, R>(self, method: worker::Method, path: &str, handler: H) -> Self
+    where
+        H: Fn(RawRequest) -> Fut + 'static,
+        Fut: Future<Output = R> + 'static,
+        R: IntoWorkerResponse + 'static,
+    {
+        let handler: BoxedRawHandler = Rc::new(move |req| {
+            let response = handler(req);
+            Box::pin(async move { response.await.into_worker_response() })
+        });
+        RAW_ROUTES.with(|routes| routes.register(method.clone(), path, handler));
+        route(self, &method, path, dispatch_raw_route::<D>)
+    }
+}
+
+async fn dispatch_raw_route<D>(
+    req: worker::Request,
+    ctx: worker::RouteContext<D>,
+) -> worker::Result<worker::Response> {
+    let matched = RAW_ROUTES.with(|routes| routes.find(&req.method(), &req.path()));
+    let Some((handler, params)) = matched else {
+        return ErrorRenderer::installed().render_for(&req, StatusCode::NOT_FOUND, "Not found");
+    };
+
+    let Ok(params) = decode_params(params) else {
+        return ErrorRenderer::installed().render_for(
+            &req,
+            StatusCode::BAD_REQUEST,
+            "Invalid path parameters",
+        );
+    };
+    handler(RawRequest {
+        params,
+        env: ctx.env,
+        req,
+    })
+    .await
+}
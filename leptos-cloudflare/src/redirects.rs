@@ -0,0 +1,378 @@
+//! Redirects and rewrites managed as data instead of routes.
+//!
+//! The rules use the format of a Cloudflare Pages `_redirects` file, one rule per line:
+//!
+//! ```text
+//! # source            destination                 status
+//! /pricing            /plans                      301
+//! /blog/:slug         /news/:slug                 302
+//! /docs/*             https://docs.example.com/:splat
+//! /guide/*            /documentation/:splat       200
+//! ```
+//!
+//! A source may contain `:param` segments and end with `*`, whose match is available as `:splat` in
+//! the destination. The status defaults to 301, and the first matching rule wins. Like on Pages, a status
+//! of 200 makes the rule a rewrite: the worker handles the request as if it was made for the destination,
+//! while the browser keeps showing the original URL. The table is either embedded in the worker or stored
+//! in KV, where it can be edited without a deployment, and is applied in the `fetch` handler before the
+//! router runs:
+//!
+//! ```ignore
+//! static REDIRECTS: Redirects = Redirects::kv("CONFIG", "redirects");
+//! // or: Redirects::embedded(include_str!("../_redirects"))
+//!
+//! #[worker::event(fetch)]
+//! pub async fn main(req: Request, env: Env, _ctx: Context) -> worker::Result<Response> {
+//!     if let Some(redirect) = REDIRECTS.apply(&env, &req).await? {
+//!         return Ok(redirect);
+//!     }
+//!     let req = REDIRECTS.rewrite(&env, req).await?;
+//!     // ...
+//! }
+//! ```
+//!
+//! A rewritten page is hydrated at the original URL, so the client router has to render the same view
+//! there. Rewrites to pages served without hydration (see [RouteConfig](crate::RouteConfig)) or to API
+//! routes don't have this constraint.
+//!
+//! A table from KV is cached per isolate and read again once it is older than the ttl, one minute by
+//! default.
+//!
+//! ## Query strings
+//!
+//! Redirects keep the query string of the request, as on Pages, so that e.g. `/pricing?ref=mail`
+//! leads to `/plans?ref=mail`. Parameters of the destination take precedence over those of the request.
+//! [with_query](Redirects::with_query) takes a [RedirectQuery] that drops or filters them, and sorts
+//! them into a canonical order:
+//!
+//! ```ignore
+//! static REDIRECTS: Redirects = Redirects::kv("CONFIG", "redirects")
+//!     .with_query(RedirectQuery::preserve().except(&["fbclid", "gclid"]).sorted());
+//! ```
+//!
+//! The same policy applies to [redirect](crate::redirect) from a page, set with
+//! [WorkerRouterDataBuilder::redirect_query](crate::WorkerRouterDataBuilder::redirect_query), and to a
+//! [Redirect](crate::Redirect) with [with_query_from](crate::Redirect::with_query_from).
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+use worker::Env;
+
+use crate::route_config::extract_params;
+
+/// The status that marks a rule as a rewrite.
+pub const REWRITE_STATUS: u16 = 200;
+
+/// Where the rules are read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RedirectsSource {
+    /// A table compiled into the worker, e.g. with `include_str!`.
+    Embedded(&'static str),
+    /// A table stored under `key` in the KV namespace bound as `binding`.
+    Kv {
+        binding: &'static str,
+        key: &'static str,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedirectRule {
+    /// The source path, in the syntax of [path_matches](crate::route_config::path_matches).
+    pub from: String,
+    pub to: String,
+    pub status: u16,
+}
+
+impl RedirectRule {
+    /// The destination for `path`, if it matches the source.
+    pub fn destination(&self, path: &str) -> Option<String> {
+        let params = extract_params(&self.from, path)?;
+        let mut destination = self.to.clone();
+        // Longest names first, so that `:id` doesn't replace the start of `:identifier`
+        let mut params: Vec<_> = params.into_iter().collect();
+        params.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+        for (name, value) in params {
+            destination = destination.replace(&format!(":{name}"), &value);
+        }
+        Some(destination)
+    }
+}
+
+/// What becomes of the query string of a request that is redirected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RedirectQuery {
+    preserve: bool,
+    only: Option<&'static [&'static str]>,
+    except: &'static [&'static str],
+    sorted: bool,
+}
+
+impl Default for RedirectQuery {
+    fn default() -> Self {
+        Self::preserve()
+    }
+}
+
+impl RedirectQuery {
+    /// Appends the parameters of the request to the destination.
+    pub const fn preserve() -> Self {
+        Self {
+            preserve: true,
+            only: None,
+            except: &[],
+            sorted: false,
+        }
+    }
+
+    /// Only keeps the query of the destination.
+    pub const fn discard() -> Self {
+        Self {
+            preserve: false,
+            ..Self::preserve()
+        }
+    }
+
+    /// Only appends the parameters of the request named in `params`.
+    pub const fn only(mut self, params: &'static [&'static str]) -> Self {
+        self.only = Some(params);
+        self
+    }
+
+    /// Doesn't append the parameters of the request named in `params`, e.g. tracking ones.
+    pub const fn except(mut self, params: &'static [&'static str]) -> Self {
+        self.except = params;
+        self
+    }
+
+    /// Sorts the parameters of the destination by name. Repeated parameters keep their order.
+    pub const fn sorted(mut self) -> Self {
+        self.sorted = true;
+        self
+    }
+
+    /// `location` with the parameters of `query`, the query string of the request, that are kept.
+    pub fn apply(&self, location: &str, query: Option<&str>) -> String {
+        let (location, fragment) = match location.split_once('#') {
+            Some((location, fragment)) => (location, Some(fragment)),
+            None => (location, None),
+        };
+        let (path, own_query) = match location.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (location, None),
+        };
+
+        let mut params = parse_query(own_query.unwrap_or_default());
+        let own_names: Vec<String> = params.iter().map(|(name, _)| name.clone()).collect();
+        let kept = parse_query(query.filter(|_| self.preserve).unwrap_or_default())
+            .into_iter()
+            .filter(|(name, _)| {
+                self.only.is_none_or(|only| only.contains(&name.as_str()))
+                    && !self.except.contains(&name.as_str())
+                    && !own_names.contains(name)
+            })
+            .collect::<Vec<_>>();
+        if kept.is_empty() && !self.sorted {
+            return match fragment {
+                Some(fragment) => format!("{location}#{fragment}"),
+                None => location.to_string(),
+            };
+        }
+
+        params.extend(kept);
+        if self.sorted {
+            params.sort_by(|(a, _), (b, _)| a.cmp(b));
+        }
+        let mut destination = path.to_string();
+        if !params.is_empty() {
+            destination.push('?');
+            destination.push_str(&serde_urlencoded::to_string(&params).unwrap_or_default());
+        }
+        if let Some(fragment) = fragment {
+            destination.push('#');
+            destination.push_str(fragment);
+        }
+        destination
+    }
+}
+
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    serde_urlencoded::from_str(query).unwrap_or_default()
+}
+
+/// A redirects table. See the [module documentation](self).
+#[derive(Debug, Clone, Copy)]
+pub struct Redirects {
+    source: RedirectsSource,
+    ttl: Duration,
+    query: RedirectQuery,
+}
+
+thread_local! {
+    static TABLES: RefCell<HashMap<RedirectsSource, (Rc<Vec<RedirectRule>>, u64)>> =
+        RefCell::new(HashMap::new());
+}
+
+impl Redirects {
+    pub const fn embedded(table: &'static str) -> Self {
+        Self {
+            source: RedirectsSource::Embedded(table),
+            ttl: Duration::from_secs(60),
+            query: RedirectQuery::preserve(),
+        }
+    }
+
+    pub const fn kv(binding: &'static str, key: &'static str) -> Self {
+        Self {
+            source: RedirectsSource::Kv { binding, key },
+            ttl: Duration::from_secs(60),
+            query: RedirectQuery::preserve(),
+        }
+    }
+
+    /// How long a table read from KV is used before it is read again.
+    pub const fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// What redirects do with the query string of the request, [RedirectQuery::preserve] by default.
+    /// Rewrites always keep it, unless the destination has its own.
+    pub const fn with_query(mut self, query: RedirectQuery) -> Self {
+        self.query = query;
+        self
+    }
+
+    /// Returns the redirect for `req`, if the first rule matching its path is a redirect.
+    pub async fn apply(
+        &self,
+        env: &Env,
+        req: &worker::Request,
+    ) -> worker::Result<Option<worker::Response>> {
+        let url = req.url()?;
+        let rules = self.rules(env).await?;
+        let Some((destination, status)) = first_match(&rules, url.path()) else {
+            return Ok(None);
+        };
+        if status == REWRITE_STATUS {
+            return Ok(None);
+        }
+
+        let mut response = worker::Response::empty()?.with_status(status);
+        let destination = self.query.apply(&destination, url.query());
+        response.headers_mut().set("Location", &destination)?;
+        Ok(Some(response))
+    }
+
+    /// Returns `req` for the destination path if the first rule matching its path is a rewrite, and
+    /// `req` itself otherwise. The method, headers and body are kept.
+    pub async fn rewrite(
+        &self,
+        env: &Env,
+        req: worker::Request,
+    ) -> worker::Result<worker::Request> {
+        let mut url = req.url()?;
+        let rules = self.rules(env).await?;
+        let Some((destination, REWRITE_STATUS)) = first_match(&rules, url.path()) else {
+            return Ok(req);
+        };
+
+        // Only paths are rewritten, anything else would make the worker a proxy
+        let (path, query) = match destination.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (destination.as_str(), None),
+        };
+        url.set_path(path);
+        if query.is_some() {
+            url.set_query(query);
+        }
+        let rewritten = web_sys::Request::new_with_str_and_request(url.as_str(), req.inner())
+            .map_err(worker::Error::from)?;
+        Ok(worker::Request::from(rewritten))
+    }
+
+    /// The current rules of the table.
+    pub async fn rules(&self, env: &Env) -> worker::Result<Rc<Vec<RedirectRule>>> {
+        let now = worker::Date::now().as_millis();
+        let cached = TABLES.with(|tables| tables.borrow().get(&self.source).cloned());
+        if let Some((rules, loaded_at)) = cached {
+            let fresh = matches!(self.source, RedirectsSource::Embedded(_))
+                || now.saturating_sub(loaded_at) < self.ttl.as_millis() as u64;
+            if fresh {
+                return Ok(rules);
+            }
+        }
+
+        let table = match self.source {
+            RedirectsSource::Embedded(table) => table.to_string(),
+            RedirectsSource::Kv { binding, key } => {
+                env.kv(binding)?.get(key).text().await?.unwrap_or_default()
+            }
+        };
+        let rules = Rc::new(parse_redirects(&table));
+        TABLES.with(|tables| {
+            tables
+                .borrow_mut()
+                .insert(self.source, (rules.clone(), now));
+        });
+        Ok(rules)
+    }
+}
+
+fn first_match(rules: &[RedirectRule], path: &str) -> Option<(String, u16)> {
+    rules
+        .iter()
+        .find_map(|rule| Some((rule.destination(path)?, rule.status)))
+}
+
+/// Parses a `_redirects` table. Invalid lines are logged and skipped, so that one typo doesn't disable
+/// every other redirect.
+pub fn parse_redirects(table: &str) -> Vec<RedirectRule> {
+    table
+        .lines()
+        .enumerate()
+        .filter_map(|(number, line)| {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+
+            let rule = parse_rule(line);
+            if rule.is_none() {
+                tracing::warn!("skipping invalid redirect on line {}: {line}", number + 1);
+            }
+            rule
+        })
+        .collect()
+}
+
+fn parse_rule(line: &str) -> Option<RedirectRule> {
+    let mut fields = line.split_whitespace();
+    let from = fields.next()?;
+    let to = fields.next()?;
+    let status = match fields.next() {
+        Some(status) => status.trim_end_matches('!').parse().ok()?,
+        None => 301,
+    };
+    let valid = match status {
+        REWRITE_STATUS => to.starts_with('/'),
+        301 | 302 | 303 | 307 | 308 => true,
+        _ => false,
+    };
+    if !from.starts_with('/') || !valid {
+        return None;
+    }
+
+    // A trailing `*` matches the rest of the path, which the destination refers to as `:splat`
+    let from = match from.strip_suffix('*') {
+        Some(prefix) => format!("{prefix}*splat"),
+        None => from.to_string(),
+    };
+    Some(RedirectRule {
+        from,
+        to: to.to_string(),
+        status,
+    })
+}
@@ -0,0 +1,111 @@
+//! Which request headers components and server functions can read.
+//!
+//! The [RequestParts] provided in the context of a request are read by a lot of code that has no use
+//! for credentials, and that may log them or put them into serialized resources by accident. Its
+//! headers are filtered by the [RequestHeaderPolicy] of
+//! [WorkerRouterData::request_headers](crate::WorkerRouterData::request_headers), which removes
+//! `Cookie`, `Authorization`, `Proxy-Authorization` and `X-Api-Key` by default:
+//!
+//! ```ignore
+//! WorkerRouterData::builder()
+//!     // Only what the app needs, e.g. for content negotiation and localization
+//!     .request_headers(RequestHeaderPolicy::new().allow(&["accept", "accept-language", "user-agent"]))
+//!     // ...
+//! ```
+//!
+//! The handlers and the helpers of this crate that authenticate requests, such as
+//! [MagicLink](crate::magic_link::MagicLink) sessions, API keys and preview mode, still read the full
+//! headers, and so do the providers of [Dependencies](crate::Dependencies), which usually build the
+//! session of the request. [edge_request](RequestParts::edge_request) is the original request and is
+//! never filtered.
+
+use leptos::{use_context, Scope};
+
+use crate::RequestParts;
+
+/// The headers removed by default.
+pub const DEFAULT_REDACTED_HEADERS: &[&str] = &[
+    "authorization",
+    "cookie",
+    "proxy-authorization",
+    "x-api-key",
+];
+
+/// The request headers exposed through the context of a request.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestHeaderPolicy {
+    allow: Option<&'static [&'static str]>,
+    redact: &'static [&'static str],
+}
+
+impl Default for RequestHeaderPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RequestHeaderPolicy {
+    /// Exposes every header except [DEFAULT_REDACTED_HEADERS].
+    pub const fn new() -> Self {
+        Self {
+            allow: None,
+            redact: DEFAULT_REDACTED_HEADERS,
+        }
+    }
+
+    /// Exposes every header, as the request was received.
+    pub const fn expose_all() -> Self {
+        Self {
+            allow: None,
+            redact: &[],
+        }
+    }
+
+    /// Only exposes `headers`, except those that are redacted.
+    pub const fn allow(mut self, headers: &'static [&'static str]) -> Self {
+        self.allow = Some(headers);
+        self
+    }
+
+    /// Removes `headers` instead of [DEFAULT_REDACTED_HEADERS].
+    pub const fn redact(mut self, headers: &'static [&'static str]) -> Self {
+        self.redact = headers;
+        self
+    }
+
+    /// Whether the header `name` is exposed.
+    pub fn exposes(&self, name: &str) -> bool {
+        let listed = |headers: &[&str]| {
+            headers
+                .iter()
+                .any(|header| header.eq_ignore_ascii_case(name))
+        };
+        self.allow.is_none_or(listed) && !listed(self.redact)
+    }
+
+    /// `parts` with only the exposed headers.
+    pub(crate) fn apply(&self, parts: &RequestParts) -> RequestParts {
+        let headers = worker::Headers::new();
+        for (name, value) in parts.headers.entries() {
+            if self.exposes(&name) {
+                // Names and values come from valid headers, appending them again can't fail
+                let _ = headers.append(&name, &value);
+            }
+        }
+        RequestParts {
+            headers,
+            ..parts.clone()
+        }
+    }
+}
+
+/// The unfiltered request, provided in the context for the helpers that authenticate it.
+#[derive(Debug, Clone)]
+pub(crate) struct FullRequestParts(pub(crate) RequestParts);
+
+/// The request of the current context with all of its headers.
+pub(crate) fn use_full_request_parts(cx: Scope) -> Option<RequestParts> {
+    use_context::<FullRequestParts>(cx)
+        .map(|parts| parts.0)
+        .or_else(|| use_context::<RequestParts>(cx))
+}
@@ -0,0 +1,116 @@
+use http::StatusCode;
+use serde::Serialize;
+
+use crate::error::LeptosCloudflareError;
+use crate::redirects::RedirectQuery;
+
+/// Conversion into the [worker::Response](worker::Response) returned from a route handler.
+pub trait IntoWorkerResponse {
+    fn into_worker_response(self) -> worker::Result<worker::Response>;
+}
+
+/// Serializes the inner value as an `application/json` body.
+#[derive(Debug, Clone)]
+pub struct Json<T>(pub T);
+
+/// A redirect to another location. Unlike [redirect](crate::redirect), it does not need a Leptos runtime.
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    location: String,
+    status: StatusCode,
+}
+
+impl Redirect {
+    /// `303 See Other`, the usual answer to a form submission.
+    pub fn to(location: impl Into<String>) -> Self {
+        Self {
+            location: location.into(),
+            status: StatusCode::SEE_OTHER,
+        }
+    }
+
+    /// `307 Temporary Redirect`, which keeps the request method and body.
+    pub fn temporary(location: impl Into<String>) -> Self {
+        Self {
+            location: location.into(),
+            status: StatusCode::TEMPORARY_REDIRECT,
+        }
+    }
+
+    /// `308 Permanent Redirect`, which keeps the request method and body.
+    pub fn permanent(location: impl Into<String>) -> Self {
+        Self {
+            location: location.into(),
+            status: StatusCode::PERMANENT_REDIRECT,
+        }
+    }
+
+    /// Carries the query string of `url`, usually the URL of the request, over to the location as
+    /// `query` says.
+    pub fn with_query_from(mut self, url: &worker::Url, query: RedirectQuery) -> Self {
+        self.location = query.apply(&self.location, url.query());
+        self
+    }
+}
+
+impl IntoWorkerResponse for worker::Response {
+    fn into_worker_response(self) -> worker::Result<worker::Response> {
+        Ok(self)
+    }
+}
+
+impl IntoWorkerResponse for String {
+    fn into_worker_response(self) -> worker::Result<worker::Response> {
+        worker::Response::ok(self)
+    }
+}
+
+impl IntoWorkerResponse for &'static str {
+    fn into_worker_response(self) -> worker::Result<worker::Response> {
+        worker::Response::ok(self)
+    }
+}
+
+impl IntoWorkerResponse for () {
+    fn into_worker_response(self) -> worker::Result<worker::Response> {
+        Ok(worker::Response::empty()?.with_status(StatusCode::NO_CONTENT.as_u16()))
+    }
+}
+
+impl<T: Serialize> IntoWorkerResponse for Json<T> {
+    fn into_worker_response(self) -> worker::Result<worker::Response> {
+        worker::Response::from_json(&self.0)
+    }
+}
+
+impl IntoWorkerResponse for Redirect {
+    fn into_worker_response(self) -> worker::Result<worker::Response> {
+        let mut headers = worker::Headers::new();
+        headers.set("Location", &self.location)?;
+        Ok(worker::Response::empty()?
+            .with_status(self.status.as_u16())
+            .with_headers(headers))
+    }
+}
+
+impl<T: IntoWorkerResponse> IntoWorkerResponse for (StatusCode, T) {
+    fn into_worker_response(self) -> worker::Result<worker::Response> {
+        let (status, body) = self;
+        Ok(body.into_worker_response()?.with_status(status.as_u16()))
+    }
+}
+
+impl<T: IntoWorkerResponse> IntoWorkerResponse for worker::Result<T> {
+    fn into_worker_response(self) -> worker::Result<worker::Response> {
+        self.and_then(IntoWorkerResponse::into_worker_response)
+    }
+}
+
+impl<T: IntoWorkerResponse> IntoWorkerResponse for Result<T, LeptosCloudflareError> {
+    fn into_worker_response(self) -> worker::Result<worker::Response> {
+        match self {
+            Ok(value) => value.into_worker_response(),
+            Err(err) => err.into_worker_response(),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use leptos::{component, use_context, view, IntoView, Scope};
+
+use crate::ResponseOptions;
+
+/// Indexing directives for search engine crawlers.
+///
+/// The directives can be set for a whole route through [RouteConfig](crate::RouteConfig), or from within
+/// a view with [set_robots] or the [Robots] component. Either way, they end up in both the
+/// `X-Robots-Tag` response header and a `<meta name="robots">` tag.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RobotsDirectives {
+    pub noindex: bool,
+    pub nofollow: bool,
+}
+
+impl RobotsDirectives {
+    /// Directives that only forbid indexing of the page.
+    pub fn noindex() -> Self {
+        Self {
+            noindex: true,
+            nofollow: false,
+        }
+    }
+
+    /// Directives that forbid both indexing the page and following its links.
+    pub fn none() -> Self {
+        Self {
+            noindex: true,
+            nofollow: true,
+        }
+    }
+
+    /// Renders the directives in the format shared by `X-Robots-Tag` and `<meta name="robots">`.
+    /// Returns `None` when nothing is restricted, since `index, follow` is the default anyway.
+    pub fn directive(&self) -> Option<String> {
+        let directives = [(self.noindex, "noindex"), (self.nofollow, "nofollow")]
+            .into_iter()
+            .filter_map(|(enabled, directive)| enabled.then_some(directive))
+            .collect::<Vec<_>>();
+
+        if directives.is_empty() {
+            None
+        } else {
+            Some(directives.join(", "))
+        }
+    }
+
+    /// Sets the `X-Robots-Tag` header on the response.
+    pub fn apply(&self, res_options: &mut ResponseOptions) -> worker::Result<()> {
+        match self.directive() {
+            Some(directive) => res_options.insert_header("X-Robots-Tag", &directive),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Sets the robots directives of the current page from within a view or a server function.
+///
+/// The header is only sent if this is called before the response headers are flushed, i.e. while
+/// rendering the app shell or in a blocking resource.
+#[tracing::instrument(level = "trace", fields(error), skip_all)]
+pub fn set_robots(cx: Scope, directives: RobotsDirectives) {
+    if let Some(mut response_options) = use_context::<ResponseOptions>(cx) {
+        directives
+            .apply(&mut response_options)
+            .expect("failed to insert header value");
+    }
+
+    robots_meta(cx, directives);
+}
+
+/// Registers the `<meta name="robots">` tag without touching the response headers.
+pub(crate) fn robots_meta(cx: Scope, directives: RobotsDirectives) {
+    if let Some(directive) = directives.directive() {
+        let _ = view! { cx, <leptos_meta::Meta name="robots" content=directive/> };
+    }
+}
+
+/// Component form of [set_robots], e.g. `<Robots noindex=true/>` on a page that should not be indexed.
+#[component]
+pub fn Robots(
+    cx: Scope,
+    #[prop(optional)] noindex: bool,
+    #[prop(optional)] nofollow: bool,
+) -> impl IntoView {
+    set_robots(cx, RobotsDirectives { noindex, nofollow });
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+use crate::robots::RobotsDirectives;
+
+/// Per-route settings that are applied by the render handlers before the app is rendered.
+///
+/// Keys of the map in [WorkerRouterData](crate::WorkerRouterData) use the same path syntax as
+/// the routes generated by [generate_route_list](crate::generate_route_list), e.g. `/post/:id` or `/*any`.
+#[derive(Debug, Clone, Default)]
+pub struct RouteConfig {
+    /// Indexing directives emitted as both an `X-Robots-Tag` header and a `<meta name="robots">` tag.
+    pub robots: Option<RobotsDirectives>,
+    /// Serve the page as plain HTML, without the wasm bundle and the script that hydrates it. For pages
+    /// that never need interactivity, such as terms or a privacy policy.
+    pub no_hydrate: bool,
+}
+
+/// Finds the configuration of the route that matches `path`.
+///
+/// When several patterns match (e.g. `/post/:id` and `/*any`), the one with the most static
+/// segments wins, so that catch-all routes never shadow more specific ones.
+pub fn route_config_for<'a>(
+    route_config: &'a HashMap<String, RouteConfig>,
+    path: &str,
+) -> Option<&'a RouteConfig> {
+    route_config
+        .iter()
+        .filter_map(|(pattern, config)| {
+            path_matches(pattern, path).then(|| (static_segments(pattern), config))
+        })
+        .max_by_key(|(specificity, _)| *specificity)
+        .map(|(_, config)| config)
+}
+
+/// Checks whether `path` matches a route `pattern` that may contain `:param` and `*wildcard` segments.
+pub fn path_matches(pattern: &str, path: &str) -> bool {
+    let mut pattern_segments = pattern.split('/').filter(|segment| !segment.is_empty());
+    let mut path_segments = path.split('/').filter(|segment| !segment.is_empty());
+
+    loop {
+        match (pattern_segments.next(), path_segments.next()) {
+            (Some(pattern_segment), _) if pattern_segment.starts_with('*') => return true,
+            (Some(pattern_segment), Some(_)) if pattern_segment.starts_with(':') => {}
+            (Some(pattern_segment), Some(path_segment)) if pattern_segment == path_segment => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+/// Extracts the values of `:param` and `*wildcard` segments if `path` matches `pattern`.
+/// A wildcard captures the rest of the path, without the leading slash.
+pub fn extract_params(pattern: &str, path: &str) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    let mut pattern_segments = pattern.split('/').filter(|segment| !segment.is_empty());
+    let mut path_segments = path.split('/').filter(|segment| !segment.is_empty());
+
+    loop {
+        match (pattern_segments.next(), path_segments.next()) {
+            (Some(pattern_segment), path_segment) if pattern_segment.starts_with('*') => {
+                let rest = path_segment
+                    .into_iter()
+                    .chain(path_segments)
+                    .collect::<Vec<_>>()
+                    .join("/");
+                params.insert(pattern_segment[1..].to_string(), rest);
+                return Some(params);
+            }
+            (Some(pattern_segment), Some(path_segment)) if pattern_segment.starts_with(':') => {
+                params.insert(pattern_segment[1..].to_string(), path_segment.to_string());
+            }
+            (Some(pattern_segment), Some(path_segment)) if pattern_segment == path_segment => {}
+            (None, None) => return Some(params),
+            _ => return None,
+        }
+    }
+}
+
+pub(crate) fn static_segments(pattern: &str) -> usize {
+    pattern
+        .split('/')
+        .filter(|segment| {
+            !segment.is_empty() && !segment.starts_with(':') && !segment.starts_with('*')
+        })
+        .count()
+}
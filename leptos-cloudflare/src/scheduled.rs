@@ -0,0 +1,221 @@
+//! Cron trigger support.
+//!
+//! A worker has a single `scheduled` handler no matter how many cron triggers it has. [CronRouter]
+//! dispatches each invocation to the handler registered for the cron expression that fired:
+//!
+//! ```ignore
+//! #[worker::event(scheduled)]
+//! pub async fn scheduled(event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+//!     let result = CronRouter::new()
+//!         .named("refresh_cache", "*/5 * * * *", |cron| async move { refresh_cache(&cron.env).await })
+//!         .named("daily_report", "0 0 * * *", |cron| async move { daily_report(&cron.env).await })
+//!         .run(&event, env)
+//!         .await;
+//! }
+//! ```
+//!
+//! The expressions must match the `crons` in `wrangler.toml` exactly, as Cloudflare reports the
+//! expression as written there.
+//!
+//! [handle_scheduled] runs a handler inside a Leptos runtime, like a server function, so that code
+//! written against the context (e.g. the data layer of the server functions) can be reused by jobs. The
+//! invocation is provided as [ScheduledEventParts]:
+//!
+//! ```ignore
+//! #[worker::event(scheduled)]
+//! pub async fn scheduled(event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+//!     let result = handle_scheduled(&event, env, |cx| async move {
+//!         let parts = use_context::<ScheduledEventParts>(cx).unwrap();
+//!         tracing::info!("running {}", parts.cron);
+//!         expire_sessions(cx).await
+//!     })
+//!     .await;
+//! }
+//! ```
+
+use std::future::Future;
+use std::rc::Rc;
+
+use futures::future::LocalBoxFuture;
+use leptos::{create_runtime, provide_context, raw_scope_and_disposer, Scope};
+use worker::{Env, ScheduledEvent};
+
+use crate::app_env::RequestEnv;
+use crate::LeptosCloudflareError;
+
+/// The cron invocation being handled, provided in the context of [handle_scheduled].
+#[derive(Clone)]
+pub struct ScheduledEventParts {
+    /// The cron expression that fired.
+    pub cron: String,
+    /// The time the invocation was scheduled for, in milliseconds since the epoch.
+    pub scheduled_time: f64,
+    pub env: Env,
+}
+
+/// Runs `handler` in a new Leptos runtime with [ScheduledEventParts] and the bindings of the worker
+/// in its context. There is no request, so helpers that read it, like
+/// [use_dep](crate::use_dep), aren't available.
+pub async fn handle_scheduled<F, Fut>(
+    event: &ScheduledEvent,
+    env: Env,
+    handler: F,
+) -> Result<(), LeptosCloudflareError>
+where
+    F: FnOnce(Scope) -> Fut,
+    Fut: Future<Output = Result<(), LeptosCloudflareError>>,
+{
+    let runtime = create_runtime();
+    let (cx, disposer) = raw_scope_and_disposer(runtime);
+
+    provide_context(cx, RequestEnv(env.clone()));
+    provide_context(
+        cx,
+        ScheduledEventParts {
+            cron: event.cron(),
+            scheduled_time: event.schedule(),
+            env,
+        },
+    );
+    let result = handler(cx).await;
+
+    disposer.dispose();
+    runtime.dispose();
+    result
+}
+
+/// What a cron handler receives.
+#[derive(Clone)]
+pub struct CronInvocation {
+    /// The cron expression that fired.
+    pub cron: String,
+    /// The name the schedule was registered with, or the cron expression if it has none.
+    pub name: String,
+    /// The time the invocation was scheduled for, in milliseconds since the epoch.
+    pub scheduled_time: f64,
+    pub env: Env,
+}
+
+type CronHandler =
+    Rc<dyn Fn(CronInvocation) -> LocalBoxFuture<'static, Result<(), LeptosCloudflareError>>>;
+
+struct CronRoute {
+    name: String,
+    cron: String,
+    handler: CronHandler,
+}
+
+#[derive(Default)]
+pub struct CronRouter {
+    routes: Vec<CronRoute>,
+}
+
+impl CronRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `handler` for the cron expression `cron`.
+    pub fn on<F, Fut>(self, cron: &str, handler: F) -> Self
+    where
+        F: Fn(CronInvocation) -> Fut + 'static,
+        Fut: Future<Output = Result<(), LeptosCloudflareError>> + 'static,
+    {
+        self.named(cron, cron, handler)
+    }
+
+    /// Registers `handler` for the cron expression `cron` under `name`, so that it shows up in logs by
+    /// name and can be triggered with [run_named](CronRouter::run_named).
+    pub fn named<F, Fut>(mut self, name: &str, cron: &str, handler: F) -> Self
+    where
+        F: Fn(CronInvocation) -> Fut + 'static,
+        Fut: Future<Output = Result<(), LeptosCloudflareError>> + 'static,
+    {
+        self.routes.push(CronRoute {
+            name: name.to_string(),
+            cron: cron.to_string(),
+            handler: Rc::new(move |invocation| Box::pin(handler(invocation))),
+        });
+        self
+    }
+
+    /// Runs every handler registered for the cron expression of `event`. A failing handler doesn't
+    /// stop the others; the error of a single failure is returned as is, and several failures are
+    /// combined into one.
+    pub async fn run(&self, event: &ScheduledEvent, env: Env) -> Result<(), LeptosCloudflareError> {
+        let cron = event.cron();
+        let routes = self
+            .routes
+            .iter()
+            .filter(|route| route.cron == cron)
+            .collect::<Vec<_>>();
+
+        if routes.is_empty() {
+            tracing::warn!("no handler registered for cron trigger {cron}");
+        }
+
+        let mut failures = Vec::new();
+        for route in routes {
+            if let Err(err) = self.invoke(route, event.schedule(), env.clone()).await {
+                failures.push((route.name.as_str(), err));
+            }
+        }
+        if failures.len() > 1 {
+            let messages = failures
+                .iter()
+                .map(|(name, err)| format!("{name}: {err}"))
+                .collect::<Vec<_>>();
+            return Err(LeptosCloudflareError::Internal(format!(
+                "{} cron handlers failed: {}",
+                failures.len(),
+                messages.join("; ")
+            )));
+        }
+        match failures.pop() {
+            Some((_, err)) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs the handler registered as `name` right away, e.g. from an admin route.
+    pub async fn run_named(&self, name: &str, env: Env) -> Result<(), LeptosCloudflareError> {
+        let route = self
+            .routes
+            .iter()
+            .find(|route| route.name == name)
+            .ok_or(LeptosCloudflareError::NotFound)?;
+
+        self.invoke(route, worker::Date::now().as_millis() as f64, env)
+            .await
+    }
+
+    /// The registered schedules, as `(name, cron)` pairs.
+    pub fn schedules(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.routes
+            .iter()
+            .map(|route| (route.name.as_str(), route.cron.as_str()))
+    }
+
+    async fn invoke(
+        &self,
+        route: &CronRoute,
+        scheduled_time: f64,
+        env: Env,
+    ) -> Result<(), LeptosCloudflareError> {
+        let started_at = worker::Date::now().as_millis();
+        let result = (route.handler)(CronInvocation {
+            cron: route.cron.clone(),
+            name: route.name.clone(),
+            scheduled_time,
+            env,
+        })
+        .await;
+        let elapsed = worker::Date::now().as_millis() - started_at;
+
+        match &result {
+            Ok(()) => tracing::info!("cron {} finished in {elapsed}ms", route.name),
+            Err(err) => tracing::error!("cron {} failed after {elapsed}ms: {err}", route.name),
+        }
+        result
+    }
+}
@@ -0,0 +1,137 @@
+//! Encrypted values for cookies and other state kept by the client.
+//!
+//! [Sealer::seal] encrypts and authenticates a value with AES-256-GCM from the runtime's
+//! `crypto.subtle`, so a cookie can carry data the visitor can neither read nor change. The key is
+//! derived from a secret binding. Keys are rotated by adding the new secret in front of the old one:
+//! values are sealed with the first secret and unsealed with any of them, so existing cookies keep
+//! working until they are sealed again.
+//!
+//! ```ignore
+//! const SEALER: Sealer = Sealer::new(&["COOKIE_KEY", "COOKIE_KEY_PREVIOUS"]);
+//!
+//! let cookie = SEALER.seal(&env, &serde_json::to_string(&cart)?).await?;
+//! // ...
+//! let cart = match SEALER.unseal(&env, &cookie).await? {
+//!     Some(json) => serde_json::from_str(&json)?,
+//!     None => Cart::default(),
+//! };
+//! ```
+//!
+//! Sealed values are URL-safe base64 and can be used as cookie values as is. They expire only with the
+//! cookie, so values that must expire should contain their expiry.
+//!
+//! Flash messages and rejected form submissions (the `flash` and `forms` features) are sealed into
+//! their cookies, since they carry data the visitor shouldn't read or change. Two cookies deliberately
+//! aren't:
+//!
+//! - The preview mode cookie holds the signed token the CMS put in the link, which only carries its
+//!   expiry. The CMS creates tokens without the worker's `crypto.subtle`, so an HMAC it can compute
+//!   synchronously is kept, and sealing it would only hide a timestamp.
+//! - The magic link session cookie holds an opaque random id of a session stored in KV. The session
+//!   itself never reaches the client, and logging out or revoking it must work from the server,
+//!   which a sealed session couldn't do.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use wasm_bindgen::JsValue;
+use worker::Env;
+
+use crate::util::{random_bytes, subtle};
+
+const IV_BYTES: usize = 12;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sealer {
+    secrets: &'static [&'static str],
+}
+
+impl Sealer {
+    /// Seals with the secret named `secrets[0]` and unseals with any of `secrets`.
+    pub const fn new(secrets: &'static [&'static str]) -> Self {
+        Self { secrets }
+    }
+
+    /// Encrypts `value`.
+    pub async fn seal(&self, env: &Env, value: &str) -> worker::Result<String> {
+        let secret = self
+            .secrets
+            .first()
+            .ok_or_else(|| worker::Error::RustError("Sealer has no secrets".to_string()))?;
+        let key = key(env, secret).await?;
+        let iv = random_bytes(IV_BYTES)?;
+        let ciphertext = subtle(
+            "encrypt",
+            js_sys::Array::of3(
+                &params(&iv)?,
+                &key,
+                &js_sys::Uint8Array::from(value.as_bytes()),
+            ),
+        )
+        .await?;
+
+        let mut sealed = iv;
+        sealed.extend(js_sys::Uint8Array::new(&ciphertext).to_vec());
+        Ok(URL_SAFE_NO_PAD.encode(sealed))
+    }
+
+    /// Decrypts a value sealed with any of the secrets, returning `None` if it was sealed with another
+    /// key, tampered with or isn't a sealed value at all.
+    pub async fn unseal(&self, env: &Env, sealed: &str) -> worker::Result<Option<String>> {
+        let Ok(sealed) = URL_SAFE_NO_PAD.decode(sealed) else {
+            return Ok(None);
+        };
+        if sealed.len() <= IV_BYTES {
+            return Ok(None);
+        }
+        let (iv, ciphertext) = sealed.split_at(IV_BYTES);
+
+        for secret in self.secrets {
+            let key = key(env, secret).await?;
+            // Decrypting with the wrong key fails the authentication, which rejects the promise
+            let Ok(plaintext) = subtle(
+                "decrypt",
+                js_sys::Array::of3(&params(iv)?, &key, &js_sys::Uint8Array::from(ciphertext)),
+            )
+            .await
+            else {
+                continue;
+            };
+            return Ok(String::from_utf8(js_sys::Uint8Array::new(&plaintext).to_vec()).ok());
+        }
+        Ok(None)
+    }
+}
+
+/// The AES key derived from the secret named `secret`.
+async fn key(env: &Env, secret: &str) -> worker::Result<JsValue> {
+    let secret = env.secret(secret)?.to_string();
+    let digest = subtle(
+        "digest",
+        js_sys::Array::of2(
+            &"SHA-256".into(),
+            &js_sys::Uint8Array::from(secret.as_bytes()),
+        ),
+    )
+    .await?;
+
+    let algorithm = js_sys::Object::new();
+    js_sys::Reflect::set(&algorithm, &"name".into(), &"AES-GCM".into())?;
+    subtle(
+        "importKey",
+        js_sys::Array::of5(
+            &"raw".into(),
+            &digest,
+            &algorithm,
+            &JsValue::FALSE,
+            &js_sys::Array::of2(&"encrypt".into(), &"decrypt".into()),
+        ),
+    )
+    .await
+}
+
+fn params(iv: &[u8]) -> worker::Result<js_sys::Object> {
+    let params = js_sys::Object::new();
+    js_sys::Reflect::set(&params, &"name".into(), &"AES-GCM".into())?;
+    js_sys::Reflect::set(&params, &"iv".into(), &js_sys::Uint8Array::from(iv))?;
+    Ok(params)
+}
@@ -0,0 +1,195 @@
+//! Cache segments: caching personalized pages once per segment instead of once per visitor.
+//!
+//! Many pages only differ by a few coarse properties of the visitor, e.g. whether they are logged in or
+//! which currency they picked. Declaring those as [CacheSegment]s, each derived from a cookie and limited
+//! to a small set of values, makes such pages cacheable: the cache key of a page includes the segment
+//! values, and the page reads them with [use_cache_segment] instead of the cookies, so that it can't
+//! render anything that isn't part of the key.
+//!
+//! ```ignore
+//! const SEGMENTS: &[CacheSegment] = &[
+//!     CacheSegment::present("logged_in", "session"),
+//!     CacheSegment::values("currency", "currency", &["USD", "EUR", "CHF"], "USD"),
+//! ];
+//!
+//! let data = WorkerRouterData::builder()
+//!     .cache_segments(CacheSegments::new(SEGMENTS))
+//!     // ...
+//!
+//! // in a handler that caches pages
+//! let key = segments.cache_key(&url, req.headers());
+//! if let Some(page) = cache.get(&env, &key).await? { ... }
+//!
+//! #[component]
+//! fn Price(cx: Scope, cents: u64) -> impl IntoView {
+//!     let currency = use_cache_segment(cx, "currency").unwrap_or_default();
+//!     // ...
+//! }
+//! ```
+
+use leptos::{use_context, Scope};
+use worker::Headers;
+
+use crate::device::Device;
+use crate::util::cookie;
+
+/// The query parameter that carries the segment values in cache keys.
+pub const SEGMENT_QUERY_PARAM: &str = "__segment";
+
+/// A property of the visitor that pages may depend on, derived from a cookie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheSegment {
+    pub name: &'static str,
+    pub cookie: &'static str,
+    /// The values the segment can take. An empty list means "1" if the cookie is set and "0" otherwise.
+    pub values: &'static [&'static str],
+    /// The value used when the cookie is missing or has a value that isn't in `values`.
+    pub default: &'static str,
+}
+
+impl CacheSegment {
+    /// A segment that is `"1"` if `cookie` is set and `"0"` otherwise, e.g. for logged-in visitors.
+    pub const fn present(name: &'static str, cookie: &'static str) -> Self {
+        Self {
+            name,
+            cookie,
+            values: &[],
+            default: "0",
+        }
+    }
+
+    /// A segment that takes the value of `cookie` if it is one of `values`, and `default` otherwise.
+    pub const fn values(
+        name: &'static str,
+        cookie: &'static str,
+        values: &'static [&'static str],
+        default: &'static str,
+    ) -> Self {
+        Self {
+            name,
+            cookie,
+            values,
+            default,
+        }
+    }
+
+    fn resolve(&self, headers: &Headers) -> &'static str {
+        let value = cookie(headers, self.cookie);
+        if self.values.is_empty() {
+            if value.is_some() {
+                "1"
+            } else {
+                "0"
+            }
+        } else {
+            value
+                .and_then(|value| self.values.iter().find(|allowed| **allowed == value))
+                .copied()
+                .unwrap_or(self.default)
+        }
+    }
+}
+
+/// The segments a worker caches its pages by.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheSegments {
+    segments: &'static [CacheSegment],
+    by_device: bool,
+}
+
+impl CacheSegments {
+    pub const fn new(segments: &'static [CacheSegment]) -> Self {
+        Self {
+            segments,
+            by_device: false,
+        }
+    }
+
+    /// Also segments by the [Device], as the `device` segment, for apps that render adaptive layouts.
+    pub const fn by_device(mut self) -> Self {
+        self.by_device = true;
+        self
+    }
+
+    /// The segment values of the request with `headers`.
+    pub fn resolve(&self, headers: &Headers) -> SegmentValues {
+        let device = self
+            .by_device
+            .then(|| ("device", Device::from_headers(headers).as_str()));
+        SegmentValues(
+            self.segments
+                .iter()
+                .map(|segment| (segment.name, segment.resolve(headers)))
+                .chain(device)
+                .collect(),
+        )
+    }
+
+    /// The cache key of `url` for the request with `headers`: `url` with the segment values added as the
+    /// [SEGMENT_QUERY_PARAM]. Returns `url` unchanged if no segments are declared.
+    pub fn cache_key(&self, url: &worker::Url, headers: &Headers) -> String {
+        self.resolve(headers).cache_key(url)
+    }
+}
+
+/// The segment values of a request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SegmentValues(Vec<(&'static str, &'static str)>);
+
+impl SegmentValues {
+    pub fn get(&self, name: &str) -> Option<&'static str> {
+        self.0
+            .iter()
+            .find(|(segment, _)| *segment == name)
+            .map(|(_, value)| *value)
+    }
+
+    /// See [CacheSegments::cache_key].
+    pub fn cache_key(&self, url: &worker::Url) -> String {
+        if self.0.is_empty() {
+            return url.to_string();
+        }
+
+        let values = self
+            .0
+            .iter()
+            .map(|(name, value)| format!("{name}.{value}"))
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut url = url.clone();
+        url.query_pairs_mut()
+            .append_pair(SEGMENT_QUERY_PARAM, &values);
+        url.to_string()
+    }
+}
+
+/// The value of the segment `name` for the current request. Returns `None` outside of a request or if no
+/// such segment is declared.
+pub fn use_cache_segment(cx: Scope, name: &str) -> Option<&'static str> {
+    use_context::<SegmentValues>(cx).and_then(|values| values.get(name))
+}
+
+/// Removes the segment values from a cache key, giving back the URL the page is served at.
+pub(crate) fn public_url(key: &str) -> String {
+    let Ok(mut url) = worker::Url::parse(key) else {
+        return key.to_string();
+    };
+    if !url
+        .query_pairs()
+        .any(|(name, _)| name == SEGMENT_QUERY_PARAM)
+    {
+        return key.to_string();
+    }
+
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(name, _)| name != SEGMENT_QUERY_PARAM)
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    if pairs.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    url.to_string()
+}
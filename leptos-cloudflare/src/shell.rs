@@ -0,0 +1,189 @@
+//! The HTML document around the app, shared with routes that don't render the app.
+//!
+//! Every Leptos route is wrapped in the same document: the `<head>` with the metadata registered through
+//! `leptos_meta`, the stylesheet and the wasm bundle, and the closing tags. [ShellHooks] add markup at
+//! fixed points of that document, and [render_in_shell] wraps any other HTML in it, so that e.g. a status
+//! page or a legacy page served by an API route looks like the rest of the site:
+//!
+//! ```ignore
+//! const SHELL: ShellHooks = ShellHooks {
+//!     head: Some(|_| r#"<link rel="icon" href="/static/favicon.ico">"#.to_string()),
+//!     ..ShellHooks::NONE
+//! };
+//!
+//! router.api_route(worker::Method::Get, "/status", |_req: ApiRequest| async move {
+//!     let html = render_in_shell(&leptos_options, SHELL, |cx| {
+//!         view! { cx, <Title text="Status"/> };
+//!     }, "<h1>All systems operational</h1>");
+//!     worker::Response::from_html(html)
+//! })
+//! ```
+
+use leptos::{
+    create_runtime, provide_context, raw_scope_and_disposer, use_context, LeptosOptions, Scope,
+};
+use leptos_integration_utils::html_parts_separated;
+use leptos_meta::{generate_head_metadata_separated, MetaContext};
+
+/// Markup added to the document of every page. Each hook runs once per request in the scope of the
+/// app, so it can read the context of the request.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShellHooks {
+    /// Inserted at the end of `<head>`.
+    pub head: Option<fn(Scope) -> String>,
+    /// Inserted right after `<body>`.
+    pub body_start: Option<fn(Scope) -> String>,
+    /// Inserted right before `</body>`.
+    pub body_end: Option<fn(Scope) -> String>,
+}
+
+impl ShellHooks {
+    /// No hooks, for use in `const` struct update syntax.
+    pub const NONE: ShellHooks = ShellHooks {
+        head: None,
+        body_start: None,
+        body_end: None,
+    };
+
+    pub(crate) fn render(&self, cx: Scope) -> [String; 3] {
+        [self.head, self.body_start, self.body_end]
+            .map(|hook| hook.map(|hook| hook(cx)).unwrap_or_default())
+    }
+}
+
+/// Provided in the context of pages that are served without hydration.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct HydrationDisabled;
+
+/// The document up to and including `<body>`, and from `</body>` on.
+#[derive(Debug, Clone)]
+pub struct HtmlShell {
+    pub head: String,
+    pub tail: String,
+}
+
+impl HtmlShell {
+    /// The full document with `body` as the content of `<body>`.
+    pub fn wrap(&self, body: &str) -> String {
+        format!("{}{body}{}", self.head, self.tail)
+    }
+}
+
+/// Generates the document around the app for `cx`, using the metadata registered in its [MetaContext].
+pub fn html_shell(cx: Scope, options: &LeptosOptions, hooks: ShellHooks) -> HtmlShell {
+    let body_tag = generate_head_metadata_separated(cx).1;
+    let (head, body_tag, tail) = document_parts(cx, options, hooks, body_tag);
+    HtmlShell {
+        head: head + &body_tag,
+        tail,
+    }
+}
+
+/// Wraps `body` in the same document as the app. `meta` can register metadata such as a `<Title/>`.
+pub fn render_in_shell(
+    options: &LeptosOptions,
+    hooks: ShellHooks,
+    meta: impl FnOnce(Scope),
+    body: &str,
+) -> String {
+    let runtime = create_runtime();
+    let (cx, disposer) = raw_scope_and_disposer(runtime);
+    provide_context(cx, MetaContext::new());
+    meta(cx);
+
+    let html = html_shell(cx, options, hooks).wrap(body);
+    disposer.dispose();
+    runtime.dispose();
+    html
+}
+
+/// Generates the document around the app as the part before the body and the part after it. The
+/// streaming renderers emit the `<body>` tag as the prefix of their first chunk, so that chunk is passed
+/// in as `first_chunk` and returned with the hooks applied in between.
+pub(crate) fn document_parts(
+    cx: Scope,
+    options: &LeptosOptions,
+    hooks: ShellHooks,
+    first_chunk: String,
+) -> (String, String, String) {
+    let (head, tail) = html_parts_separated(cx, options, use_context::<MetaContext>(cx).as_ref());
+    let [head_hook, body_start, body_end] = hooks.render(cx);
+    let head = if use_context::<HydrationDisabled>(cx).is_some() {
+        strip_hydration(head, options)
+    } else {
+        head
+    };
+
+    (
+        insert_before(head, "</head>", &head_hook),
+        insert_after_body_tag(first_chunk, &body_start),
+        format!("{body_end}{tail}"),
+    )
+}
+
+/// Applies rendered hooks to a complete document, for the renderers that generate the shell themselves.
+pub(crate) fn apply_hooks(hooks: [String; 3], html: String) -> String {
+    let [head_hook, body_start, body_end] = hooks;
+
+    let html = insert_before(html, "</head>", &head_hook);
+    let html = insert_after_body_tag(html, &body_start);
+    match html.rfind("</body>") {
+        Some(end) => {
+            let mut html = html;
+            html.insert_str(end, &body_end);
+            html
+        }
+        None => html,
+    }
+}
+
+/// Removes the `<link>` and `<script>` tags that load the wasm bundle of the app.
+pub(crate) fn strip_hydration(html: String, options: &LeptosOptions) -> String {
+    let bundle = format!("/{}/{}", options.site_pkg_dir, options.output_name);
+    let mut stripped = String::with_capacity(html.len());
+    let mut rest = html.as_str();
+
+    while let Some(start) = rest
+        .find("<link")
+        .into_iter()
+        .chain(rest.find("<script"))
+        .min()
+    {
+        let end = if rest[start..].starts_with("<script") {
+            rest[start..]
+                .find("</script>")
+                .map(|end| end + "</script>".len())
+        } else {
+            rest[start..].find('>').map(|end| end + 1)
+        };
+        let Some(end) = end.map(|end| start + end) else {
+            break;
+        };
+
+        stripped.push_str(&rest[..start]);
+        if !rest[start..end].contains(&bundle) {
+            stripped.push_str(&rest[start..end]);
+        }
+        rest = &rest[end..];
+    }
+
+    stripped.push_str(rest);
+    stripped
+}
+
+fn insert_before(mut html: String, tag: &str, markup: &str) -> String {
+    if let Some(position) = html.find(tag) {
+        html.insert_str(position, markup);
+    }
+    html
+}
+
+fn insert_after_body_tag(mut html: String, markup: &str) -> String {
+    let body_tag_end = html
+        .find("<body")
+        .and_then(|start| html[start..].find('>').map(|end| start + end + 1));
+    if let Some(position) = body_tag_end {
+        html.insert_str(position, markup);
+    }
+    html
+}
@@ -0,0 +1,168 @@
+//! Time-limited links that are checked at the edge, without a database lookup.
+//!
+//! A signed URL carries its expiry and an HMAC of its path and query, so a download, unsubscribe or
+//! login link can be handed out from a server function and validated by any isolate that knows the
+//! secret. [SignedUrls::wrap] rejects requests to the protected prefixes that aren't signed, expired or
+//! tampered with before they reach the router:
+//!
+//! ```ignore
+//! static SIGNED_URLS: SignedUrls = SignedUrls::new("URL_SIGNING_SECRET", &["/downloads/", "/unsubscribe"]);
+//!
+//! #[worker::event(fetch)]
+//! pub async fn main(req: Request, env: Env, _ctx: Context) -> worker::Result<Response> {
+//!     SIGNED_URLS
+//!         .wrap(req, env, |req, env| async move {
+//!             data.into_router().leptos_routes(routes).run(req, env).await
+//!         })
+//!         .await
+//! }
+//!
+//! #[server(DownloadLink, "/api")]
+//! pub async fn download_link(cx: Scope, file: String) -> Result<String, ServerFnError> {
+//!     let url = SIGNED_URLS.sign(cx, &format!("/downloads/{file}"), Duration::from_secs(3600))?;
+//!     Ok(url)
+//! }
+//! ```
+//!
+//! The signature covers the [normalized](crate::normalize) path and the query as it appears in the
+//! request, so paths must be signed percent-encoded. [SignedUrls::wrap] normalizes the request before
+//! checking it, so `//downloads/x` or `/%64ownloads/x` can't get past the check of `/downloads/`, and
+//! passes the normalized request on. The `expires` and `signature` parameters are appended to the
+//! query and must stay last. A signed URL can be used any number of times until it expires; links that must work only once
+//! also need a record of their use.
+
+use std::future::Future;
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use http::StatusCode;
+use leptos::{use_context, Scope};
+use sha2::Sha256;
+
+use crate::app_env::RequestEnv;
+use crate::normalize::{normalize_path, normalize_request};
+use crate::{ErrorRenderer, IntoWorkerResponse};
+
+/// Signs URLs and checks them for a set of path prefixes.
+#[derive(Debug, Clone, Copy)]
+pub struct SignedUrls {
+    secret: &'static str,
+    protected: &'static [&'static str],
+}
+
+impl SignedUrls {
+    /// Signs with the secret named `secret` and requires a valid signature for the paths starting
+    /// with one of `protected`.
+    pub const fn new(secret: &'static str, protected: &'static [&'static str]) -> Self {
+        Self { secret, protected }
+    }
+
+    /// Signs `path`, which may include a query, so that it is valid for `expiry`. Only works while
+    /// handling a request, e.g. in a server function.
+    pub fn sign(&self, cx: Scope, path: &str, expiry: Duration) -> worker::Result<String> {
+        let env = use_context::<RequestEnv>(cx).ok_or_else(|| {
+            worker::Error::RustError(
+                "SignedUrls::sign called outside of a request handled by leptos-cloudflare"
+                    .to_string(),
+            )
+        })?;
+        let secret = env.0.secret(self.secret)?.to_string();
+        Ok(sign_url(&secret, path, expiry))
+    }
+
+    /// Whether requests to `path` need a signature. Paths that can't be
+    /// [normalized](crate::normalize) are protected.
+    pub fn protects(&self, path: &str) -> bool {
+        let Ok(path) = normalize_path(path) else {
+            return true;
+        };
+        self.protected.iter().any(|prefix| path.starts_with(prefix))
+    }
+
+    /// Runs `handler` for `req`, with its path [normalized](crate::normalize), if that path isn't
+    /// protected or its URL is validly signed. Responds with `403 Forbidden` otherwise, and with
+    /// `400 Bad Request` for paths that can't be normalized.
+    pub async fn wrap<F, Fut>(
+        &self,
+        req: worker::Request,
+        env: worker::Env,
+        handler: F,
+    ) -> worker::Result<worker::Response>
+    where
+        F: FnOnce(worker::Request, worker::Env) -> Fut,
+        Fut: Future<Output = worker::Result<worker::Response>>,
+    {
+        let req = match normalize_request(req) {
+            Ok(req) => req,
+            Err(err) => return err.into_worker_response(),
+        };
+        let url = req.url()?;
+        if self.protects(url.path()) {
+            let secret = env.secret(self.secret)?.to_string();
+            if !verify_signed_url(&secret, &url) {
+                return ErrorRenderer::installed().render_for(
+                    &req,
+                    StatusCode::FORBIDDEN,
+                    "This link is invalid or has expired",
+                );
+            }
+        }
+        handler(req, env).await
+    }
+}
+
+/// Signs `path`, which may include a query, with `secret` (the value of the secret, not its name) so
+/// that it is valid for `expiry`. The path is [normalized](crate::normalize) first.
+pub fn sign_url(secret: &str, path: &str, expiry: Duration) -> String {
+    let expires_at = worker::Date::now().as_millis() / 1000 + expiry.as_secs();
+    let (path, query) = match path.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (path, None),
+    };
+    // A path that can't be normalized is rejected by `wrap` whatever its signature
+    let path = normalize_path(path).unwrap_or_else(|_| path.to_string());
+    let signed = match query {
+        Some(query) => format!("{path}?{query}&expires={expires_at}"),
+        None => format!("{path}?expires={expires_at}"),
+    };
+    let signature = sign(secret, &signed);
+    format!("{signed}&signature={signature}")
+}
+
+/// Checks that `url` was signed with `secret` and hasn't expired. The path of `url` must already be
+/// [normalized](crate::normalize).
+pub fn verify_signed_url(secret: &str, url: &worker::Url) -> bool {
+    let Some(query) = url.query() else {
+        return false;
+    };
+    let Some((signed_query, signature)) = query.rsplit_once("signature=") else {
+        return false;
+    };
+    let Some(signed_query) = signed_query.strip_suffix('&') else {
+        return false;
+    };
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    let Some(expires_at) = signed_query
+        .rsplit_once("expires=")
+        .filter(|(before, _)| before.is_empty() || before.ends_with('&'))
+        .and_then(|(_, expires_at)| expires_at.parse::<u64>().ok())
+    else {
+        return false;
+    };
+
+    let mut mac = mac(secret);
+    mac.update(format!("{}?{signed_query}", url.path()).as_bytes());
+    mac.verify_slice(&signature).is_ok() && expires_at > worker::Date::now().as_millis() / 1000
+}
+
+fn mac(secret: &str) -> Hmac<Sha256> {
+    Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size")
+}
+
+fn sign(secret: &str, signed: &str) -> String {
+    let mut mac = mac(secret);
+    mac.update(signed.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
@@ -0,0 +1,142 @@
+//! Periodic tasks that run exactly once across all isolates, scheduled by Durable Object alarms.
+//!
+//! Each task is driven by its own instance of [SingletonTaskDurableObject]. Since a Durable Object
+//! exists only once and its alarms fire once, the task never runs in parallel with itself, no matter how
+//! many isolates serve the worker. Tasks are registered in the `start` event, so that they are known in
+//! the isolate the Durable Object runs in as well:
+//!
+//! ```ignore
+//! #[worker::event(start)]
+//! fn start() {
+//!     register_singleton_task("fetch-feeds", Duration::from_secs(600), |env| {
+//!         Box::pin(async move { fetch_feeds(&env).await })
+//!     });
+//! }
+//!
+//! #[worker::event(fetch)]
+//! pub async fn main(req: Request, env: Env, ctx: Context) -> worker::Result<Response> {
+//!     ensure_singleton_tasks(&env, "SINGLETON_TASKS").await?;
+//!     // ...
+//! }
+//! ```
+//!
+//! with the class bound in `wrangler.toml`:
+//!
+//! ```toml
+//! [durable_objects]
+//! bindings = [{ name = "SINGLETON_TASKS", class_name = "SingletonTaskDurableObject" }]
+//! ```
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use futures::future::LocalBoxFuture;
+use worker::{durable_object, Env, Request, Response, State};
+
+type SingletonTaskFn = fn(Env) -> LocalBoxFuture<'static, worker::Result<()>>;
+
+#[derive(Clone, Copy)]
+struct SingletonTask {
+    interval: Duration,
+    run: SingletonTaskFn,
+}
+
+thread_local! {
+    static SINGLETON_TASKS: RefCell<HashMap<String, SingletonTask>> = RefCell::new(HashMap::new());
+    static SCHEDULED: Cell<bool> = Cell::new(false);
+}
+
+/// Registers a task that runs every `interval`, once across the whole worker.
+/// Must be called from the `start` event, see the [module docs](self).
+pub fn register_singleton_task(name: &str, interval: Duration, run: SingletonTaskFn) {
+    SINGLETON_TASKS.with(|tasks| {
+        tasks
+            .borrow_mut()
+            .insert(name.to_string(), SingletonTask { interval, run });
+    });
+}
+
+/// Makes sure that every registered task has an alarm scheduled in its Durable Object bound as `binding`.
+///
+/// Only the first call in an isolate talks to the Durable Objects; later calls return immediately.
+pub async fn ensure_singleton_tasks(env: &Env, binding: &str) -> worker::Result<()> {
+    if SCHEDULED.with(|scheduled| scheduled.replace(true)) {
+        return Ok(());
+    }
+
+    let names = SINGLETON_TASKS.with(|tasks| tasks.borrow().keys().cloned().collect::<Vec<_>>());
+    let namespace = env.durable_object(binding)?;
+    for name in names {
+        let stub = namespace.id_from_name(&name)?.get_stub()?;
+        let result = stub
+            .fetch_with_str(&format!("https://singleton/schedule/{name}"))
+            .await;
+        if let Err(err) = result {
+            // Try again in the next request rather than never scheduling the task
+            SCHEDULED.with(|scheduled| scheduled.set(false));
+            return Err(err);
+        }
+    }
+
+    Ok(())
+}
+
+fn singleton_task(name: &str) -> Option<SingletonTask> {
+    SINGLETON_TASKS.with(|tasks| tasks.borrow().get(name).copied())
+}
+
+/// The Durable Object class behind [register_singleton_task]. Each task is a separate instance.
+#[durable_object]
+pub struct SingletonTaskDurableObject {
+    state: State,
+    env: Env,
+}
+
+#[durable_object]
+impl DurableObject for SingletonTaskDurableObject {
+    fn new(state: State, env: Env) -> Self {
+        Self { state, env }
+    }
+
+    async fn fetch(&mut self, req: Request) -> worker::Result<Response> {
+        let path = req.path();
+        let Some(name) = path.strip_prefix("/schedule/") else {
+            return Response::error("Not found", 404);
+        };
+        let Some(task) = singleton_task(name) else {
+            return Response::error(format!("Unknown singleton task {name}"), 404);
+        };
+
+        let mut storage = self.state.storage();
+        storage.put("name", name).await?;
+        if storage.get_alarm().await?.is_none() {
+            storage.set_alarm(task.interval.as_millis() as i64).await?;
+        }
+
+        Response::ok("")
+    }
+
+    async fn alarm(&mut self) -> worker::Result<Response> {
+        let mut storage = self.state.storage();
+        let name = storage.get::<String>("name").await?;
+        let Some(task) = singleton_task(&name) else {
+            // The task was removed from the worker; let the alarm lapse
+            return Response::ok("");
+        };
+
+        // Schedule the next run first, so that a failing run doesn't stop the task for good
+        storage.set_alarm(task.interval.as_millis() as i64).await?;
+
+        let started_at = worker::Date::now().as_millis();
+        match (task.run)(self.env.clone()).await {
+            Ok(()) => tracing::info!(
+                "singleton task {name} finished in {}ms",
+                worker::Date::now().as_millis() - started_at
+            ),
+            Err(err) => tracing::error!("singleton task {name} failed: {err}"),
+        }
+
+        Response::ok("")
+    }
+}
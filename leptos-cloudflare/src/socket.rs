@@ -0,0 +1,286 @@
+//! Outbound TCP connections with the `connect()` API of the runtime.
+//!
+//! [Socket] adapts the streams of a socket from `cloudflare:sockets` to tokio's [AsyncRead] and
+//! [AsyncWrite], which Rust database drivers are written against, and to their `futures` counterparts
+//! for the drivers that use those. Server functions open sockets with [use_socket], e.g. for Redis:
+//!
+//! ```ignore
+//! #[server(Visits, "/api")]
+//! pub async fn visits(cx: Scope) -> Result<u64, ServerFnError> {
+//!     let socket = use_socket(cx, "redis.example.com", 6379, SecureTransport::On)?;
+//!     let mut redis = RedisConnection::new(socket).await?;
+//!     Ok(redis.incr("visits", 1).await?)
+//! }
+//! ```
+//!
+//! Sockets can only be opened while handling a request, and those still open when the request is done
+//! are closed. Connections to Cloudflare's own IP ranges, and to port 25, are refused by the runtime.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use js_sys::{Array, Object, Reflect};
+use leptos::{on_cleanup, use_context, Scope};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+
+use crate::app_env::RequestEnv;
+use crate::LeptosCloudflareError;
+
+#[wasm_bindgen(module = "cloudflare:sockets")]
+extern "C" {
+    #[wasm_bindgen(catch, js_name = connect)]
+    fn connect_js(address: &JsValue, options: &JsValue) -> Result<JsValue, JsValue>;
+}
+
+/// How a socket is encrypted, the `secureTransport` option of `connect()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecureTransport {
+    Off,
+    On,
+    /// Plain until [Socket::start_tls] is called, for protocols that negotiate TLS in band.
+    StartTls,
+}
+
+impl SecureTransport {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SecureTransport::Off => "off",
+            SecureTransport::On => "on",
+            SecureTransport::StartTls => "starttls",
+        }
+    }
+}
+
+/// A TCP connection. Closed when dropped.
+pub struct Socket {
+    inner: JsValue,
+    reader: JsValue,
+    writer: JsValue,
+    read: Option<JsFuture>,
+    // What is left of the last chunk read, after the bytes that fit into the caller's buffer
+    unread: Vec<u8>,
+    write: Option<JsFuture>,
+    close: Option<JsFuture>,
+    closed: bool,
+}
+
+impl Socket {
+    /// Opens a connection to `hostname:port`. The connection is established in the background, and
+    /// errors surface on the first read or write.
+    pub fn connect(
+        hostname: &str,
+        port: u16,
+        secure_transport: SecureTransport,
+    ) -> worker::Result<Self> {
+        let address = Object::new();
+        Reflect::set(&address, &"hostname".into(), &hostname.into())?;
+        Reflect::set(&address, &"port".into(), &JsValue::from(port))?;
+        let options = Object::new();
+        Reflect::set(
+            &options,
+            &"secureTransport".into(),
+            &secure_transport.as_str().into(),
+        )?;
+        Self::from_js(connect_js(&address, &options)?)
+    }
+
+    /// Upgrades a socket opened with [SecureTransport::StartTls] to TLS. Nothing may be pending on the
+    /// socket.
+    pub fn start_tls(mut self) -> worker::Result<Self> {
+        let upgraded = call(&self.inner, "startTls", &[])?;
+        // The runtime closes the plain socket, it must not be closed again when dropped
+        self.closed = true;
+        Self::from_js(upgraded)
+    }
+
+    /// Waits until the connection is established, failing if it can't be.
+    pub async fn opened(&self) -> worker::Result<()> {
+        let opened = Reflect::get(&self.inner, &"opened".into())?;
+        JsFuture::from(js_sys::Promise::from(opened)).await?;
+        Ok(())
+    }
+
+    fn from_js(inner: JsValue) -> worker::Result<Self> {
+        let readable = Reflect::get(&inner, &"readable".into())?;
+        let writable = Reflect::get(&inner, &"writable".into())?;
+        Ok(Self {
+            reader: call(&readable, "getReader", &[])?,
+            writer: call(&writable, "getWriter", &[])?,
+            inner,
+            read: None,
+            unread: Vec::new(),
+            write: None,
+            close: None,
+            closed: false,
+        })
+    }
+
+    /// Waits for the last write to be taken by the socket.
+    fn poll_written(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(write) = self.write.as_mut() {
+            let result = ready!(Pin::new(write).poll(cx));
+            self.write = None;
+            result.map_err(io_error)?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Reads into `dst`, returning how many bytes were read, 0 at the end of the stream.
+    fn poll_read_into(&mut self, cx: &mut Context<'_>, dst: &mut [u8]) -> Poll<io::Result<usize>> {
+        loop {
+            if !self.unread.is_empty() {
+                let len = self.unread.len().min(dst.len());
+                dst[..len].copy_from_slice(&self.unread[..len]);
+                self.unread.drain(..len);
+                return Poll::Ready(Ok(len));
+            }
+
+            if self.read.is_none() {
+                let promise = call(&self.reader, "read", &[]).map_err(io_error)?;
+                self.read = Some(JsFuture::from(js_sys::Promise::from(promise)));
+            }
+            let result = ready!(Pin::new(self.read.as_mut().unwrap()).poll(cx));
+            self.read = None;
+            let result = result.map_err(io_error)?;
+
+            if Reflect::get(&result, &"done".into())
+                .map_err(io_error)?
+                .is_truthy()
+            {
+                return Poll::Ready(Ok(0));
+            }
+            let chunk = Reflect::get(&result, &"value".into()).map_err(io_error)?;
+            self.unread = js_sys::Uint8Array::new(&chunk).to_vec();
+        }
+    }
+
+    fn poll_write_from(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        // One write in flight at a time, so the socket applies backpressure
+        ready!(self.poll_written(cx))?;
+        let chunk = js_sys::Uint8Array::from(buf);
+        let promise = call(&self.writer, "write", &[chunk.into()]).map_err(io_error)?;
+        self.write = Some(JsFuture::from(js_sys::Promise::from(promise)));
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_close_writer(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_written(cx))?;
+        if self.close.is_none() {
+            let promise = call(&self.writer, "close", &[]).map_err(io_error)?;
+            self.close = Some(JsFuture::from(js_sys::Promise::from(promise)));
+        }
+        let result = ready!(Pin::new(self.close.as_mut().unwrap()).poll(cx));
+        result.map_err(io_error)?;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for Socket {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        // Nothing put into `buf` is the end of the stream
+        let read = ready!(self.get_mut().poll_read_into(cx, buf.initialize_unfilled()))?;
+        buf.advance(read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for Socket {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_write_from(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_written(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_close_writer(cx)
+    }
+}
+
+impl futures::io::AsyncRead for Socket {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_read_into(cx, buf)
+    }
+}
+
+impl futures::io::AsyncWrite for Socket {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_write_from(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_written(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_close_writer(cx)
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        if !self.closed {
+            let _ = call(&self.inner, "close", &[]);
+        }
+    }
+}
+
+/// Opens a connection to `hostname:port` for the current request, which closes it when it is done if
+/// it is still open.
+pub fn use_socket(
+    cx: Scope,
+    hostname: &str,
+    port: u16,
+    secure_transport: SecureTransport,
+) -> Result<Socket, LeptosCloudflareError> {
+    if use_context::<RequestEnv>(cx).is_none() {
+        return Err(LeptosCloudflareError::Internal(
+            "use_socket called outside of a request".to_string(),
+        ));
+    }
+    let socket = Socket::connect(hostname, port, secure_transport)?;
+    let inner = socket.inner.clone();
+    on_cleanup(cx, move || {
+        // Already closed sockets reject the call, which is fine
+        let _ = call(&inner, "close", &[]);
+    });
+    Ok(socket)
+}
+
+fn call(target: &JsValue, method: &str, args: &[JsValue]) -> Result<JsValue, JsValue> {
+    let function: js_sys::Function = Reflect::get(target, &method.into())?.unchecked_into();
+    function.apply(target, &args.iter().collect::<Array>())
+}
+
+fn io_error(err: JsValue) -> io::Error {
+    io::Error::other(
+        err.as_string()
+            .or_else(|| {
+                err.dyn_ref::<js_sys::Error>()
+                    .map(|err| String::from(err.message()))
+            })
+            .unwrap_or_else(|| format!("{err:?}")),
+    )
+}
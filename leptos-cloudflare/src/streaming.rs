@@ -0,0 +1,196 @@
+//! Streaming response bodies that are produced only as fast as the client reads them.
+//!
+//! [response_from_stream] bridges a Rust stream to a `ReadableStream` whose `pull` polls the stream for
+//! one chunk at a time. The runtime only pulls while its queue holds fewer than `buffer` chunks, so a
+//! slow client holds back the render instead of making the isolate buffer the whole page. Canceling
+//! the body, e.g. when the client disconnects, drops the stream.
+//!
+//! Custom routes can stream with [StreamingResponse::channel], which returns the response together with
+//! a [ChunkSender] to write into, e.g. for progress reports or long polling:
+//!
+//! ```ignore
+//! router.get_async("/export/progress", |_req, _ctx| async move {
+//!     let (mut sender, response) = StreamingResponse::channel(DEFAULT_STREAM_BUFFER)?;
+//!     wasm_bindgen_futures::spawn_local(async move {
+//!         for step in 1..=10 {
+//!             let done = run_step(step).await;
+//!             // Waits until the client has read the previous chunks, fails once it is gone
+//!             if sender.send(format!("{done}%\n")).await.is_err() {
+//!                 return;
+//!             }
+//!         }
+//!     });
+//!     Ok(response)
+//! });
+//! ```
+//!
+//! The rendered pages use [WorkerRouterData::stream_buffer](crate::WorkerRouterData::stream_buffer),
+//! which defaults to [DEFAULT_STREAM_BUFFER]. A larger buffer lets the render run further ahead of the
+//! client, which helps when chunks are small and the network is fast.
+
+use std::cell::{Cell, RefCell};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use futures::channel::mpsc;
+use futures::stream::LocalBoxStream;
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use js_sys::{Array, Object, Reflect};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+
+/// The number of chunks buffered ahead of the client by default.
+pub const DEFAULT_STREAM_BUFFER: usize = 1;
+
+/// The buffer of the rendered page, provided in the context of the request.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct StreamBuffer(pub(crate) usize);
+
+/// Creates a response whose body pulls from `stream`, with up to `buffer` chunks queued ahead of the
+/// client. An error in the stream errors the body.
+pub fn response_from_stream<S>(stream: S, buffer: usize) -> worker::Result<worker::Response>
+where
+    S: Stream<Item = worker::Result<Vec<u8>>> + 'static,
+{
+    let stream: Rc<RefCell<Option<LocalBoxStream<'static, worker::Result<Vec<u8>>>>>> =
+        Rc::new(RefCell::new(Some(stream.boxed_local())));
+    let canceled = Rc::new(Cell::new(false));
+
+    let pull = Closure::<dyn FnMut(JsValue) -> js_sys::Promise>::new({
+        let stream = stream.clone();
+        let canceled = canceled.clone();
+        move |controller: JsValue| {
+            let stream = stream.clone();
+            let canceled = canceled.clone();
+            wasm_bindgen_futures::future_to_promise(async move {
+                // Taken out for the await, the runtime doesn't pull again before this resolves
+                let Some(mut pulled) = stream.borrow_mut().take() else {
+                    return Ok(JsValue::UNDEFINED);
+                };
+
+                match pulled.next().await {
+                    Some(Ok(chunk)) => {
+                        if canceled.get() {
+                            return Ok(JsValue::UNDEFINED);
+                        }
+                        let chunk = js_sys::Uint8Array::from(chunk.as_slice());
+                        call(&controller, "enqueue", &[chunk.into()])?;
+                        *stream.borrow_mut() = Some(pulled);
+                    }
+                    Some(Err(err)) => {
+                        call(
+                            &controller,
+                            "error",
+                            &[js_sys::Error::new(&err.to_string()).into()],
+                        )?;
+                    }
+                    None => {
+                        call(&controller, "close", &[])?;
+                    }
+                }
+                Ok(JsValue::UNDEFINED)
+            })
+        }
+    });
+    let cancel = Closure::<dyn FnMut(JsValue)>::new(move |_reason: JsValue| {
+        canceled.set(true);
+        stream.borrow_mut().take();
+    });
+
+    let source = Object::new();
+    Reflect::set(&source, &"pull".into(), &pull.into_js_value())?;
+    Reflect::set(&source, &"cancel".into(), &cancel.into_js_value())?;
+    let strategy = Object::new();
+    Reflect::set(
+        &strategy,
+        &"highWaterMark".into(),
+        &JsValue::from_f64(buffer.max(1) as f64),
+    )?;
+
+    let constructor = Reflect::get(&js_sys::global(), &"ReadableStream".into())?;
+    let body = Reflect::construct(
+        constructor.unchecked_ref(),
+        &Array::of2(&source.into(), &strategy.into()),
+    )?;
+    let response = web_sys::Response::new_with_opt_readable_stream(Some(body.unchecked_ref()))?;
+    Ok(worker::Response::from(response))
+}
+
+/// A response whose body is written by the handler after it returned.
+pub struct StreamingResponse;
+
+impl StreamingResponse {
+    /// Creates a streaming response and the sender that writes its body. Writes wait while `buffer`
+    /// chunks are queued ahead of the client. The body ends when the sender is dropped.
+    pub fn channel(buffer: usize) -> worker::Result<(ChunkSender, worker::Response)> {
+        let (sender, receiver) = mpsc::channel(0);
+        let response = response_from_stream(receiver, buffer)?;
+        Ok((ChunkSender { inner: sender }, response))
+    }
+}
+
+/// The client went away, or the body was ended with [fail](ChunkSender::fail).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("the response body is closed")]
+pub struct BodyClosed;
+
+/// Writes the body of a [StreamingResponse]. Also usable as a [Sink] of chunks.
+pub struct ChunkSender {
+    inner: mpsc::Sender<worker::Result<Vec<u8>>>,
+}
+
+impl ChunkSender {
+    /// Writes `chunk`, waiting until the client has caught up if the buffer is full.
+    pub async fn send(&mut self, chunk: impl Into<Vec<u8>>) -> Result<(), BodyClosed> {
+        self.inner
+            .send(Ok(chunk.into()))
+            .await
+            .map_err(|_| BodyClosed)
+    }
+
+    /// Waits until every chunk written so far has been taken into the response body.
+    pub async fn flush(&mut self) -> Result<(), BodyClosed> {
+        SinkExt::flush(&mut self.inner)
+            .await
+            .map_err(|_| BodyClosed)
+    }
+
+    /// Ends the body with an error, which aborts the response on the client.
+    pub async fn fail(mut self, error: worker::Error) {
+        let _ = self.inner.send(Err(error)).await;
+    }
+}
+
+impl<T: Into<Vec<u8>>> Sink<T> for ChunkSender {
+    type Error = BodyClosed;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), BodyClosed>> {
+        Pin::new(&mut self.inner)
+            .poll_ready(cx)
+            .map_err(|_| BodyClosed)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, chunk: T) -> Result<(), BodyClosed> {
+        Pin::new(&mut self.inner)
+            .start_send(Ok(chunk.into()))
+            .map_err(|_| BodyClosed)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), BodyClosed>> {
+        Pin::new(&mut self.inner)
+            .poll_flush(cx)
+            .map_err(|_| BodyClosed)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), BodyClosed>> {
+        Pin::new(&mut self.inner)
+            .poll_close(cx)
+            .map_err(|_| BodyClosed)
+    }
+}
+
+fn call(target: &JsValue, method: &str, args: &[JsValue]) -> Result<JsValue, JsValue> {
+    let function: js_sys::Function = Reflect::get(target, &method.into())?.unchecked_into();
+    function.apply(target, &args.iter().collect::<Array>())
+}
@@ -0,0 +1,228 @@
+//! Stripe Checkout and webhook helpers, talking to the Stripe API through the worker's `fetch`.
+//!
+//! ```ignore
+//! let webhooks = StripeWebhooks::new().on("checkout.session.completed", |event| async move {
+//!     let session: CheckoutSession = event.data_object()?;
+//!     fulfill_order(session.client_reference_id).await
+//! });
+//!
+//! router.api_route(worker::Method::Post, "/webhooks/stripe", move |req: ApiRequest| {
+//!     let webhooks = webhooks.clone();
+//!     async move {
+//!         let secret = req.env.secret("STRIPE_WEBHOOK_SECRET")?.to_string();
+//!         webhooks.handle(secret.as_bytes(), &req.parts).await
+//!     }
+//! })
+//! ```
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::rc::Rc;
+
+use futures::future::LocalBoxFuture;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+
+use crate::webhooks::{verify_stripe, DEFAULT_TOLERANCE};
+use crate::{LeptosCloudflareError, RequestParts};
+
+const STRIPE_API: &str = "https://api.stripe.com/v1";
+
+/// Whether a checkout session collects a one-off payment, starts a subscription or only saves a card.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckoutMode {
+    Payment,
+    Subscription,
+    Setup,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineItem {
+    /// The id of a price created in the Stripe dashboard, e.g. `price_1234`.
+    pub price: String,
+    pub quantity: u32,
+}
+
+/// Parameters of [create_checkout_session]. Only the commonly used subset of the Stripe API is covered.
+#[derive(Debug, Clone)]
+pub struct CheckoutSessionParams {
+    pub mode: CheckoutMode,
+    pub line_items: Vec<LineItem>,
+    pub success_url: String,
+    pub cancel_url: String,
+    pub customer_email: Option<String>,
+    /// Usually the id of the order or user in the app, echoed back in webhook events.
+    pub client_reference_id: Option<String>,
+    pub metadata: HashMap<String, String>,
+}
+
+impl CheckoutSessionParams {
+    /// Encodes the parameters in the bracketed form encoding that the Stripe API expects.
+    fn to_form(&self) -> Vec<(String, String)> {
+        let mode = match self.mode {
+            CheckoutMode::Payment => "payment",
+            CheckoutMode::Subscription => "subscription",
+            CheckoutMode::Setup => "setup",
+        };
+        let mut form = vec![
+            ("mode".to_string(), mode.to_string()),
+            ("success_url".to_string(), self.success_url.clone()),
+            ("cancel_url".to_string(), self.cancel_url.clone()),
+        ];
+
+        for (index, item) in self.line_items.iter().enumerate() {
+            form.push((format!("line_items[{index}][price]"), item.price.clone()));
+            form.push((
+                format!("line_items[{index}][quantity]"),
+                item.quantity.to_string(),
+            ));
+        }
+        if let Some(customer_email) = &self.customer_email {
+            form.push(("customer_email".to_string(), customer_email.clone()));
+        }
+        if let Some(client_reference_id) = &self.client_reference_id {
+            form.push((
+                "client_reference_id".to_string(),
+                client_reference_id.clone(),
+            ));
+        }
+        for (key, value) in &self.metadata {
+            form.push((format!("metadata[{key}]"), value.clone()));
+        }
+
+        form
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckoutSession {
+    pub id: String,
+    /// The hosted checkout page to redirect the customer to. Absent once the session has expired.
+    pub url: Option<String>,
+    pub status: Option<String>,
+    pub payment_status: Option<String>,
+    pub client_reference_id: Option<String>,
+    pub customer: Option<String>,
+    pub customer_email: Option<String>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct StripeApiError {
+    error: StripeApiErrorBody,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct StripeApiErrorBody {
+    message: Option<String>,
+}
+
+/// Creates a Stripe Checkout session with the secret API key `api_key`.
+/// Redirect the customer to the returned [url](CheckoutSession::url) to start the payment.
+pub async fn create_checkout_session(
+    api_key: &str,
+    params: &CheckoutSessionParams,
+) -> Result<CheckoutSession, LeptosCloudflareError> {
+    let body = serde_urlencoded::to_string(params.to_form())
+        .map_err(|err| LeptosCloudflareError::Internal(err.to_string()))?;
+
+    let mut headers = worker::Headers::new();
+    headers.set("Authorization", &format!("Bearer {api_key}"))?;
+    headers.set("Content-Type", "application/x-www-form-urlencoded")?;
+
+    let mut init = worker::RequestInit::new();
+    init.with_method(worker::Method::Post)
+        .with_headers(headers)
+        .with_body(Some(JsValue::from_str(&body)));
+
+    let request =
+        worker::Request::new_with_init(&format!("{STRIPE_API}/checkout/sessions"), &init)?;
+    let mut response = worker::Fetch::Request(request).send().await?;
+
+    if response.status_code() >= 400 {
+        let message = response
+            .json::<StripeApiError>()
+            .await
+            .ok()
+            .and_then(|err| err.error.message)
+            .unwrap_or_else(|| format!("Stripe responded with {}", response.status_code()));
+        return Err(LeptosCloudflareError::Internal(message));
+    }
+
+    Ok(response.json::<CheckoutSession>().await?)
+}
+
+/// A Stripe event, as delivered to a webhook endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StripeEvent {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub created: u64,
+    pub livemode: bool,
+    pub data: StripeEventData,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StripeEventData {
+    pub object: serde_json::Value,
+}
+
+impl StripeEvent {
+    /// Deserializes the object the event is about, e.g. a [CheckoutSession] for `checkout.session.completed`.
+    pub fn data_object<T: DeserializeOwned>(&self) -> Result<T, LeptosCloudflareError> {
+        serde_json::from_value(self.data.object.clone())
+            .map_err(|err| LeptosCloudflareError::BadRequest(err.to_string()))
+    }
+}
+
+type StripeEventHandler =
+    Rc<dyn Fn(StripeEvent) -> LocalBoxFuture<'static, Result<(), LeptosCloudflareError>>>;
+
+/// Verifies Stripe webhook requests and dispatches their events to the handler registered for the
+/// event type. Events without a handler are acknowledged, so that Stripe does not retry them.
+#[derive(Clone, Default)]
+pub struct StripeWebhooks {
+    handlers: HashMap<String, StripeEventHandler>,
+}
+
+impl StripeWebhooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `handler` for events of `event_type`, e.g. `invoice.paid`.
+    pub fn on<F, Fut>(mut self, event_type: &str, handler: F) -> Self
+    where
+        F: Fn(StripeEvent) -> Fut + 'static,
+        Fut: Future<Output = Result<(), LeptosCloudflareError>> + 'static,
+    {
+        self.handlers.insert(
+            event_type.to_string(),
+            Rc::new(move |event| Box::pin(handler(event))),
+        );
+        self
+    }
+
+    /// Verifies the request with the endpoint's signing `secret` and runs the matching handler.
+    /// A failing handler responds with 500, which makes Stripe deliver the event again later.
+    pub async fn handle(
+        &self,
+        secret: &[u8],
+        req: &RequestParts,
+    ) -> Result<worker::Response, LeptosCloudflareError> {
+        verify_stripe(secret, req, DEFAULT_TOLERANCE)?;
+
+        let event = serde_json::from_slice::<StripeEvent>(&req.body)
+            .map_err(|err| LeptosCloudflareError::BadRequest(err.to_string()))?;
+
+        if let Some(handler) = self.handlers.get(&event.event_type) {
+            handler(event).await?;
+        }
+
+        Ok(worker::Response::ok("")?)
+    }
+}
@@ -0,0 +1,243 @@
+//! Tail Workers, which receive the trace events of other workers.
+//!
+//! A tail worker is invoked after the workers it tails (e.g. the SSR worker) handled an event, with
+//! their logs, exceptions and outcome. `worker::event` has no `tail` event, so as for
+//! [email](crate::email) the worker exports the handler itself and passes the events to
+//! [handle_tail], which deserializes them into [TraceItem]s and runs the handler inside a Leptos
+//! runtime:
+//!
+//! ```ignore
+//! static SINK: TailForwarder = TailForwarder::http("https://logs.example.com/ingest")
+//!     .secret_header("Authorization", "LOGS_TOKEN")
+//!     .errors_only();
+//!
+//! #[wasm_bindgen]
+//! pub async fn tail(events: JsValue, env: Env, _ctx: JsValue) -> Result<(), JsValue> {
+//!     handle_tail(events, env, |cx, items| async move {
+//!         SINK.forward(&use_env(cx)?, &items).await
+//!     })
+//!     .await
+//!     .map_err(|err| JsValue::from_str(&err.to_string()))
+//! }
+//! ```
+//!
+//! with `async tail(events, env, ctx) { await wasm.tail(events, env, ctx) }` added to the exported
+//! handlers of `worker-build`'s entry point, and the tail worker listed in the `tail_consumers` of the
+//! tailed worker's `wrangler.toml`:
+//!
+//! ```toml
+//! tail_consumers = [{ service = "my-app-tail" }]
+//! ```
+//!
+//! [TailForwarder] posts the items as newline-delimited JSON, the format Logpush delivers Workers
+//! Trace Events in, so the same HTTP endpoint can ingest both. Logpush itself is enabled with
+//! `logpush = true` in `wrangler.toml` and needs no tail worker.
+
+use std::collections::HashMap;
+use std::future::Future;
+
+use leptos::{create_runtime, provide_context, raw_scope_and_disposer, Scope};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+use worker::Env;
+
+use crate::app_env::RequestEnv;
+use crate::LeptosCloudflareError;
+
+/// The trace of one event handled by a tailed worker.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TraceItem {
+    pub script_name: Option<String>,
+    /// How the handler ended, e.g. `ok`, `exception`, `exceededCpu` or `canceled`.
+    pub outcome: String,
+    /// When the event started, in milliseconds since the epoch.
+    pub event_timestamp: Option<f64>,
+    pub event: Option<TraceEvent>,
+    pub logs: Vec<TraceLog>,
+    pub exceptions: Vec<TraceException>,
+    pub script_tags: Vec<String>,
+    pub dispatch_namespace: Option<String>,
+}
+
+impl TraceItem {
+    /// Whether the handler failed or logged an error.
+    pub fn is_error(&self) -> bool {
+        self.outcome != "ok"
+            || !self.exceptions.is_empty()
+            || self.logs.iter().any(|log| log.level == "error")
+    }
+}
+
+/// The event a trace is for. Only the fields of its kind are set: `request` and `response` for
+/// `fetch`, `cron` and `scheduled_time` for `scheduled`, `queue` and `batch_size` for `queue`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TraceEvent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request: Option<TraceRequest>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<TraceResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scheduled_time: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batch_size: Option<u32>,
+}
+
+/// The request of a `fetch` event. Sensitive headers are redacted by the runtime.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TraceRequest {
+    pub url: String,
+    pub method: String,
+    pub headers: HashMap<String, String>,
+    /// The `cf` object of the request, e.g. its colo and country.
+    pub cf: Option<serde_json::Value>,
+}
+
+/// The response of a `fetch` event, missing if the handler threw.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TraceResponse {
+    pub status: u16,
+}
+
+/// A `console` call of the tailed worker.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TraceLog {
+    pub timestamp: f64,
+    /// `debug`, `info`, `log`, `warn` or `error`.
+    pub level: String,
+    /// The arguments of the call.
+    pub message: serde_json::Value,
+}
+
+/// An uncaught exception of the tailed worker.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TraceException {
+    pub timestamp: f64,
+    pub name: String,
+    pub message: String,
+}
+
+/// Deserializes `events`, the array passed to the `tail` handler, and runs `handler` with them in a
+/// new Leptos runtime with the bindings of the worker in its context. There is no request, so helpers
+/// that read it, like [use_dep](crate::use_dep), aren't available.
+#[tracing::instrument(level = "trace", skip_all)]
+pub async fn handle_tail<F, Fut>(
+    events: JsValue,
+    env: Env,
+    handler: F,
+) -> Result<(), LeptosCloudflareError>
+where
+    F: FnOnce(Scope, Vec<TraceItem>) -> Fut,
+    Fut: Future<Output = Result<(), LeptosCloudflareError>>,
+{
+    let json = js_sys::JSON::stringify(&events)
+        .map_err(worker::Error::from)?
+        .as_string()
+        .unwrap_or_default();
+    let items = serde_json::from_str::<Vec<TraceItem>>(&json)
+        .map_err(|err| LeptosCloudflareError::BadRequest(format!("invalid trace events: {err}")))?;
+
+    let runtime = create_runtime();
+    let (cx, disposer) = raw_scope_and_disposer(runtime);
+
+    provide_context(cx, RequestEnv(env));
+    let result = handler(cx, items).await;
+    if let Err(err) = &result {
+        tracing::error!("tail handler failed: {err}");
+    }
+
+    disposer.dispose();
+    runtime.dispose();
+    result
+}
+
+/// Sends trace items to an HTTP endpoint as newline-delimited JSON.
+#[derive(Debug, Clone, Copy)]
+pub struct TailForwarder {
+    url: &'static str,
+    headers: &'static [(&'static str, &'static str)],
+    secret_header: Option<(&'static str, &'static str)>,
+    errors_only: bool,
+}
+
+impl TailForwarder {
+    /// Posts the items to `url`.
+    pub const fn http(url: &'static str) -> Self {
+        Self {
+            url,
+            headers: &[],
+            secret_header: None,
+            errors_only: false,
+        }
+    }
+
+    /// Sends `headers` with every request, e.g. the source of the logs.
+    pub const fn headers(mut self, headers: &'static [(&'static str, &'static str)]) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Sends the header `name` with the value of the secret bound as `secret`, e.g. a token.
+    pub const fn secret_header(mut self, name: &'static str, secret: &'static str) -> Self {
+        self.secret_header = Some((name, secret));
+        self
+    }
+
+    /// Only forwards the items of failed events and those that logged an error.
+    pub const fn errors_only(mut self) -> Self {
+        self.errors_only = true;
+        self
+    }
+
+    /// Posts `items` to the endpoint, if any is left after filtering. Fails if the endpoint doesn't
+    /// answer with a success status.
+    pub async fn forward(
+        &self,
+        env: &Env,
+        items: &[TraceItem],
+    ) -> Result<(), LeptosCloudflareError> {
+        let mut body = String::new();
+        for item in items
+            .iter()
+            .filter(|item| !self.errors_only || item.is_error())
+        {
+            body.push_str(&serde_json::to_string(item).map_err(worker::Error::from)?);
+            body.push('\n');
+        }
+        if body.is_empty() {
+            return Ok(());
+        }
+
+        let headers = worker::Headers::new();
+        headers.set("Content-Type", "application/x-ndjson")?;
+        for (name, value) in self.headers {
+            headers.set(name, value)?;
+        }
+        if let Some((name, secret)) = self.secret_header {
+            headers.set(name, &env.secret(secret)?.to_string())?;
+        }
+        let mut init = worker::RequestInit::new();
+        init.with_method(worker::Method::Post)
+            .with_headers(headers)
+            .with_body(Some(JsValue::from_str(&body)));
+        let response = worker::Fetch::Request(worker::Request::new_with_init(self.url, &init)?)
+            .send()
+            .await?;
+        if !(200..300).contains(&response.status_code()) {
+            return Err(LeptosCloudflareError::Internal(format!(
+                "the tail sink answered with {}",
+                response.status_code()
+            )));
+        }
+        Ok(())
+    }
+}
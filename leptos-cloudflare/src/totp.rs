@@ -0,0 +1,175 @@
+//! Time-based one-time passwords (RFC 6238) for two-factor authentication.
+//!
+//! The defaults of authenticator apps are used: HMAC-SHA1, 6 digits and 30 second steps. Enrolling
+//! stores a [generated secret](generate_totp_secret) with the account and shows its
+//! [provisioning URI](provisioning_uri) as a QR code; logging in then checks a code with [verify_totp]
+//! and, with the [magic link](crate::magic_link) sessions, marks the session as verified:
+//!
+//! ```ignore
+//! #[server(VerifyCode, "/api")]
+//! pub async fn verify_code(cx: Scope, code: String) -> Result<(), ServerFnError> {
+//!     let magic_link = use_context::<MagicLink>(cx).unwrap();
+//!     let session = magic_link.session(cx).await?.ok_or(LeptosCloudflareError::Unauthorized)?;
+//!     let account = load_account(cx, &session.email).await?;
+//!     let step = verify_totp(&account.totp_secret, &code).ok_or(LeptosCloudflareError::Forbidden)?;
+//!     if account.last_totp_step.is_some_and(|last| step <= last) {
+//!         return Err(LeptosCloudflareError::Forbidden.into());
+//!     }
+//!     save_last_totp_step(cx, &session.email, step).await?;
+//!     magic_link.mark_mfa_verified(cx).await?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Codes are accepted one step before and after the current one to allow for clock drift. A code stays
+//! valid for its whole window, so the step returned by [verify_totp] should be stored and codes of the
+//! same or an earlier step rejected.
+
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+use crate::util::{constant_time_eq, random_bytes};
+
+const STEP_SECONDS: u64 = 30;
+const DIGITS: u32 = 6;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// A new random secret of 160 bits, base32 encoded as authenticator apps expect.
+pub fn generate_totp_secret() -> worker::Result<String> {
+    Ok(base32_encode(&random_bytes(20)?))
+}
+
+/// The `otpauth://` URI to show as a QR code for enrolling `secret`. `issuer` is the name of the site
+/// and `account` e.g. the email address of the user.
+pub fn provisioning_uri(secret: &str, issuer: &str, account: &str) -> String {
+    let issuer = String::from(js_sys::encode_uri_component(issuer));
+    let account = String::from(js_sys::encode_uri_component(account));
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECONDS}"
+    )
+}
+
+/// The code for `secret` at `unix_seconds`, or `None` if `secret` isn't valid base32.
+pub fn totp_code(secret: &str, unix_seconds: u64) -> Option<String> {
+    let key = base32_decode(secret)?;
+    Some(format!(
+        "{:0width$}",
+        hotp(&key, unix_seconds / STEP_SECONDS),
+        width = DIGITS as usize
+    ))
+}
+
+/// Checks `code` against `secret` in time that doesn't depend on how much of it is right. Returns the
+/// time step the code belongs to, or `None` if it is wrong.
+pub fn verify_totp(secret: &str, code: &str) -> Option<u64> {
+    let key = base32_decode(secret)?;
+    let code = code.trim();
+    let current = worker::Date::now().as_millis() / 1000 / STEP_SECONDS;
+
+    let mut matched = None;
+    // Every candidate is checked, so the timing doesn't reveal which step matched
+    for step in current.saturating_sub(1)..=current + 1 {
+        let expected = format!("{:0width$}", hotp(&key, step), width = DIGITS as usize);
+        if constant_time_eq(expected.as_bytes(), code.as_bytes()) {
+            matched = Some(step);
+        }
+    }
+    matched
+}
+
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let code = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    code % 10u32.pow(DIGITS)
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+/// Decodes unpadded or padded base32, ignoring case and spaces as apps display secrets in groups.
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in encoded.chars().filter(|c| *c != ' ' && *c != '=') {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&symbol| symbol as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    (!decoded.is_empty()).then_some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The secret of the RFC 6238 SHA-1 test vectors.
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn matches_the_rfc_6238_vectors() {
+        // The RFC lists 8 digit codes, of which these are the last 6
+        let secret = base32_encode(RFC_SECRET);
+        let code = |unix_seconds| totp_code(&secret, unix_seconds).unwrap();
+        assert_eq!(code(59), "287082");
+        assert_eq!(code(1111111109), "081804");
+        assert_eq!(code(1111111111), "050471");
+        assert_eq!(code(1234567890), "005924");
+        assert_eq!(code(2000000000), "279037");
+        assert_eq!(code(20000000000), "353130");
+    }
+
+    #[test]
+    fn base32_round_trips() {
+        assert_eq!(
+            base32_encode(RFC_SECRET),
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"
+        );
+        for len in 1..=20 {
+            let bytes = (0..len).map(|i| (i * 37 + 11) as u8).collect::<Vec<_>>();
+            assert_eq!(base32_decode(&base32_encode(&bytes)).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn base32_decodes_as_apps_display_secrets() {
+        let expected = base32_decode("GEZDGNBVGY3TQOJQ").unwrap();
+        assert_eq!(base32_decode("gezd gnbv gy3t qojq").unwrap(), expected);
+        assert_eq!(base32_decode("GEZDGNBVGY3TQOJQ====").unwrap(), expected);
+        assert_eq!(base32_decode("GEZDGNBV1"), None);
+        assert_eq!(base32_decode(""), None);
+    }
+}
@@ -0,0 +1,78 @@
+//! Metadata that is only known once the page has rendered, such as render timings or resource counts.
+//!
+//! HTTP trailers would be the natural fit, but the `Response` of workerd (like the Fetch standard) has no
+//! way to send them, and the headers of a streamed response are gone by the time its resources resolve.
+//! So [set_trailer] collects the values during the render and delivers them where they still fit:
+//!
+//! - with [SsrMode::Async](leptos_router::SsrMode::Async), the response is only sent once the render
+//!   is done, so they are sent as regular headers
+//! - with the streaming modes, they are appended to the document as
+//!   `<script type="application/json" id="leptos-trailers">`, right before `</body>`
+//!
+//! The total render time is always added as `Server-Timing: render;dur=<ms>`.
+//!
+//! ```ignore
+//! let posts = create_resource(cx, || (), move |_| async move {
+//!     let posts = list_posts().await;
+//!     set_trailer(cx, "X-Post-Count", &posts.len().to_string());
+//!     posts
+//! });
+//! ```
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use leptos::{use_context, Scope};
+
+/// The id of the script carrying the trailers of a streamed page.
+pub const TRAILERS_SCRIPT_ID: &str = "leptos-trailers";
+
+/// The trailers of a request, provided in its context.
+#[derive(Clone)]
+pub(crate) struct Trailers {
+    started_at: f64,
+    values: Rc<RefCell<Vec<(String, String)>>>,
+}
+
+impl Trailers {
+    pub(crate) fn new() -> Self {
+        Self {
+            started_at: js_sys::Date::now(),
+            values: Rc::default(),
+        }
+    }
+
+    /// The collected trailers, with the render time added.
+    pub(crate) fn finish(&self) -> Vec<(String, String)> {
+        let mut values = self.values.borrow().clone();
+        let duration = js_sys::Date::now() - self.started_at;
+        values.push((
+            "Server-Timing".to_string(),
+            format!("render;dur={duration}"),
+        ));
+        values
+    }
+
+    /// The trailers as a script to append to a streamed document.
+    pub(crate) fn render_inline(&self) -> String {
+        let values: serde_json::Map<String, serde_json::Value> = self
+            .finish()
+            .into_iter()
+            .map(|(name, value)| (name, serde_json::Value::String(value)))
+            .collect();
+        // `<` can't be in the script, or a value could close it
+        let json = serde_json::Value::Object(values)
+            .to_string()
+            .replace('<', "\\u003c");
+        format!(r#"<script type="application/json" id="{TRAILERS_SCRIPT_ID}">{json}</script>"#)
+    }
+}
+
+/// Sets a trailer of the current response, replacing an earlier value with the same name.
+pub fn set_trailer(cx: Scope, name: &str, value: &str) {
+    if let Some(trailers) = use_context::<Trailers>(cx) {
+        let mut values = trailers.values.borrow_mut();
+        values.retain(|(existing, _)| !existing.eq_ignore_ascii_case(name));
+        values.push((name.to_string(), value.to_string()));
+    }
+}
@@ -0,0 +1,559 @@
+//! Resumable uploads to R2, with their progress tracked in a Durable Object.
+//!
+//! Large files are sent in parts that go straight into an R2 multipart upload, so neither the worker
+//! nor the browser holds the whole file. A Durable Object per upload records which parts have arrived;
+//! it backs the progress reported by [Uploads::progress], the `events` stream and resuming after a
+//! lost connection.
+//!
+//! The app decides who may upload what, so uploads are started and completed from its own server
+//! functions:
+//!
+//! ```ignore
+//! const UPLOADS: Uploads = Uploads::new("MEDIA", "UPLOADS").key_prefix("videos/");
+//!
+//! #[server(StartUpload, "/api")]
+//! pub async fn start_upload(cx: Scope, filename: String, size: u64) -> Result<UploadTicket, ServerFnError> {
+//!     require_user(cx).await?;
+//!     Ok(UPLOADS.start(&use_env(cx)?, &filename, size, None).await?)
+//! }
+//!
+//! #[server(GetUploadProgress, "/api")]
+//! pub async fn upload_progress(cx: Scope, id: String) -> Result<UploadProgress, ServerFnError> {
+//!     Ok(UPLOADS.progress(&use_env(cx)?, &id).await?)
+//! }
+//!
+//! #[server(FinishUpload, "/api")]
+//! pub async fn finish_upload(cx: Scope, id: String) -> Result<String, ServerFnError> {
+//!     Ok(UPLOADS.complete(&use_env(cx)?, &id).await?.key)
+//! }
+//!
+//! router.upload_routes(UPLOADS)
+//! ```
+//!
+//! The client then sends part `n` (from 1 to [UploadTicket::parts]) of the file as the body of
+//! `PUT /__uploads/<id>/<n>`, each [UploadTicket::part_size] bytes long except the last one. Parts can
+//! be sent concurrently and in any order. To resume, it asks for the progress and only sends the
+//! [missing_parts](UploadProgress::missing_parts). `GET /__uploads/<id>/events` streams the progress
+//! as server-sent events until the upload is completed or aborted, e.g. for another tab or device
+//! showing it. The id of an upload is unguessable and is all it takes to send its parts, so only give
+//! it to the uploader.
+//!
+//! Add the class of the Durable Object to `wrangler.toml`:
+//!
+//! ```toml
+//! [durable_objects]
+//! bindings = [{ name = "UPLOADS", class_name = "UploadDurableObject" }]
+//!
+//! [[migrations]]
+//! tag = "v3"
+//! new_classes = ["UploadDurableObject"]
+//! ```
+//!
+//! R2 aborts multipart uploads that are not completed within 7 days, after which their parts are gone
+//! and the upload can only be started over.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+use worker::{durable_object, Env, HttpMetadata, Request, Response, State, UploadedPart};
+
+use crate::api::{ApiRequest, ApiRoutes};
+use crate::util::random_bytes;
+use crate::{Json, LeptosCloudflareError};
+
+/// R2 requires every part but the last to be at least this large.
+const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+/// R2 accepts at most this many parts per upload.
+const MAX_PARTS: u64 = 10_000;
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Uploads {
+    bucket: &'static str,
+    tracker: &'static str,
+    base_path: &'static str,
+    key_prefix: &'static str,
+    part_size: u64,
+    max_size: u64,
+}
+
+/// What the client needs to send the parts of a started upload.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadTicket {
+    pub id: String,
+    /// The key of the object in the bucket once the upload is completed.
+    pub key: String,
+    pub part_size: u64,
+    pub parts: u16,
+    /// Where part `n` is sent, as `{upload_path}/{n}`.
+    pub upload_path: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadStatus {
+    InProgress,
+    Completed,
+    Aborted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadProgress {
+    pub id: String,
+    pub key: String,
+    pub size: u64,
+    pub uploaded_bytes: u64,
+    pub parts: u16,
+    pub uploaded_parts: Vec<u16>,
+    pub status: UploadStatus,
+}
+
+impl UploadProgress {
+    /// How much of the file has arrived, from 0 to 1.
+    pub fn fraction(&self) -> f64 {
+        if self.size == 0 {
+            return 1.0;
+        }
+        self.uploaded_bytes as f64 / self.size as f64
+    }
+
+    /// The parts that still have to be sent, in order.
+    pub fn missing_parts(&self) -> Vec<u16> {
+        (1..=self.parts)
+            .filter(|part| self.uploaded_parts.binary_search(part).is_err())
+            .collect()
+    }
+}
+
+/// The state of an upload, kept by its Durable Object.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct UploadRecord {
+    id: String,
+    key: String,
+    r2_upload_id: String,
+    size: u64,
+    part_size: u64,
+    parts: u16,
+    /// Part number -> (etag, size)
+    uploaded: BTreeMap<u16, (String, u64)>,
+    status: UploadStatus,
+}
+
+impl UploadRecord {
+    fn progress(&self) -> UploadProgress {
+        UploadProgress {
+            id: self.id.clone(),
+            key: self.key.clone(),
+            size: self.size,
+            uploaded_bytes: self.uploaded.values().map(|(_, size)| size).sum(),
+            parts: self.parts,
+            uploaded_parts: self.uploaded.keys().copied().collect(),
+            status: self.status,
+        }
+    }
+
+    /// How large part `part` has to be.
+    fn expected_size(&self, part: u16) -> u64 {
+        if part == self.parts {
+            self.size - self.part_size * (self.parts as u64 - 1)
+        } else {
+            self.part_size
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PartUploaded {
+    part: u16,
+    etag: String,
+    size: u64,
+}
+
+#[derive(Deserialize)]
+struct PartPath {
+    id: String,
+    part: u16,
+}
+
+#[derive(Deserialize)]
+struct UploadPath {
+    id: String,
+}
+
+impl Uploads {
+    /// Uploads into the R2 bucket bound as `bucket`, tracked by the [UploadDurableObject] namespace bound
+    /// as `tracker`. Parts are 10 MiB and files up to 5 GiB by default.
+    pub const fn new(bucket: &'static str, tracker: &'static str) -> Self {
+        Self {
+            bucket,
+            tracker,
+            base_path: "/__uploads",
+            key_prefix: "uploads/",
+            part_size: 10 * 1024 * 1024,
+            max_size: 5 * 1024 * 1024 * 1024,
+        }
+    }
+
+    /// Where the routes of [UploadRoutes::upload_routes] are mounted.
+    pub const fn base_path(mut self, base_path: &'static str) -> Self {
+        self.base_path = base_path;
+        self
+    }
+
+    /// Prepended to the keys of the uploaded objects, which are `{prefix}{id}/{filename}`.
+    pub const fn key_prefix(mut self, key_prefix: &'static str) -> Self {
+        self.key_prefix = key_prefix;
+        self
+    }
+
+    /// The size of every part but the last, at least 5 MiB. Each part is sent in one request, so it
+    /// also has to fit in the request body limit of the worker's plan.
+    pub const fn part_size(mut self, part_size: u64) -> Self {
+        self.part_size = if part_size < MIN_PART_SIZE {
+            MIN_PART_SIZE
+        } else {
+            part_size
+        };
+        self
+    }
+
+    /// The largest file that can be uploaded.
+    pub const fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Starts the upload of a file of `size` bytes named `filename`.
+    pub async fn start(
+        &self,
+        env: &Env,
+        filename: &str,
+        size: u64,
+        content_type: Option<&str>,
+    ) -> Result<UploadTicket, LeptosCloudflareError> {
+        if size > self.max_size {
+            return Err(LeptosCloudflareError::BadRequest(format!(
+                "Files can be at most {} bytes",
+                self.max_size
+            )));
+        }
+        let parts = size.div_ceil(self.part_size).max(1);
+        if parts > MAX_PARTS {
+            return Err(LeptosCloudflareError::BadRequest(format!(
+                "Files can be at most {} parts of {} bytes",
+                MAX_PARTS, self.part_size
+            )));
+        }
+
+        let id = hex::encode(random_bytes(16)?);
+        let key = format!("{}{id}/{}", self.key_prefix, sanitize_filename(filename));
+        let content_type = content_type.map(str::to_string).or_else(|| {
+            mime_guess::from_path(filename)
+                .first()
+                .map(|mime| mime.essence_str().to_string())
+        });
+        let upload = env
+            .bucket(self.bucket)?
+            .create_multipart_upload(key.clone())
+            .http_metadata(HttpMetadata {
+                content_type,
+                ..Default::default()
+            })
+            .execute()
+            .await?;
+
+        let record = UploadRecord {
+            id: id.clone(),
+            key: key.clone(),
+            r2_upload_id: upload.upload_id().await,
+            size,
+            part_size: self.part_size,
+            parts: parts as u16,
+            uploaded: BTreeMap::new(),
+            status: UploadStatus::InProgress,
+        };
+        self.call::<UploadRecord>(env, &id, "init", Some(&record))
+            .await?;
+
+        Ok(UploadTicket {
+            upload_path: format!("{}/{id}", self.base_path),
+            id,
+            key,
+            part_size: record.part_size,
+            parts: record.parts,
+        })
+    }
+
+    /// The progress of the upload `id`.
+    pub async fn progress(
+        &self,
+        env: &Env,
+        id: &str,
+    ) -> Result<UploadProgress, LeptosCloudflareError> {
+        Ok(self.record(env, id).await?.progress())
+    }
+
+    /// Assembles the object once all parts have arrived. Fails with `409 Conflict` if some are missing.
+    pub async fn complete(
+        &self,
+        env: &Env,
+        id: &str,
+    ) -> Result<UploadProgress, LeptosCloudflareError> {
+        let record = self.record(env, id).await?;
+        match record.status {
+            UploadStatus::Completed => return Ok(record.progress()),
+            UploadStatus::Aborted => {
+                return Err(LeptosCloudflareError::Conflict(format!(
+                    "The upload {id} was aborted"
+                )))
+            }
+            UploadStatus::InProgress => {}
+        }
+        let missing = record.progress().missing_parts();
+        if !missing.is_empty() {
+            return Err(LeptosCloudflareError::Conflict(format!(
+                "The upload {id} is missing parts {missing:?}"
+            )));
+        }
+
+        let parts = record
+            .uploaded
+            .iter()
+            .map(|(part, (etag, _))| UploadedPart::new(*part, etag.clone()));
+        env.bucket(self.bucket)?
+            .resume_multipart_upload(record.key.clone(), record.r2_upload_id.clone())?
+            .complete(parts)
+            .await?;
+
+        let record = self
+            .call::<UploadRecord>(env, id, "completed", None::<&()>)
+            .await?;
+        Ok(record.progress())
+    }
+
+    /// Discards the upload `id` and the parts sent so far.
+    pub async fn abort(&self, env: &Env, id: &str) -> Result<(), LeptosCloudflareError> {
+        let record = self.record(env, id).await?;
+        if record.status != UploadStatus::InProgress {
+            return Ok(());
+        }
+        env.bucket(self.bucket)?
+            .resume_multipart_upload(record.key, record.r2_upload_id)?
+            .abort()
+            .await?;
+        self.call::<UploadRecord>(env, id, "aborted", None::<&()>)
+            .await?;
+        Ok(())
+    }
+
+    async fn upload_part(
+        &self,
+        env: &Env,
+        id: &str,
+        part: u16,
+        body: Vec<u8>,
+    ) -> Result<UploadProgress, LeptosCloudflareError> {
+        let record = self.record(env, id).await?;
+        if record.status != UploadStatus::InProgress {
+            return Err(LeptosCloudflareError::Conflict(format!(
+                "The upload {id} is no longer in progress"
+            )));
+        }
+        if part == 0 || part > record.parts {
+            return Err(LeptosCloudflareError::BadRequest(format!(
+                "Parts are numbered from 1 to {}",
+                record.parts
+            )));
+        }
+        let size = body.len() as u64;
+        if size != record.expected_size(part) {
+            return Err(LeptosCloudflareError::BadRequest(format!(
+                "Part {part} must be {} bytes, not {size}",
+                record.expected_size(part)
+            )));
+        }
+
+        let uploaded = env
+            .bucket(self.bucket)?
+            .resume_multipart_upload(record.key, record.r2_upload_id)?
+            .upload_part(part, body)
+            .await?;
+        let record = self
+            .call::<UploadRecord>(
+                env,
+                id,
+                "part",
+                Some(&PartUploaded {
+                    part,
+                    etag: uploaded.etag(),
+                    size,
+                }),
+            )
+            .await?;
+        Ok(record.progress())
+    }
+
+    async fn record(&self, env: &Env, id: &str) -> Result<UploadRecord, LeptosCloudflareError> {
+        self.call(env, id, "state", None::<&()>).await
+    }
+
+    /// Sends `action` to the Durable Object of the upload `id`.
+    async fn call<T: serde::de::DeserializeOwned>(
+        &self,
+        env: &Env,
+        id: &str,
+        action: &str,
+        body: Option<&impl Serialize>,
+    ) -> Result<T, LeptosCloudflareError> {
+        let stub = env
+            .durable_object(self.tracker)?
+            .id_from_name(id)?
+            .get_stub()?;
+        let mut init = worker::RequestInit::new();
+        init.with_method(worker::Method::Post);
+        if let Some(body) = body {
+            init.with_body(Some(JsValue::from_str(
+                &serde_json::to_string(body).map_err(worker::Error::from)?,
+            )));
+        }
+        let request = Request::new_with_init(&format!("https://upload/{action}"), &init)?;
+
+        let mut response = stub.fetch_with_request(request).await?;
+        if response.status_code() == 404 {
+            return Err(LeptosCloudflareError::NotFound);
+        }
+        Ok(response.json().await?)
+    }
+
+    /// The progress of the upload `id` as server-sent events, sent whenever it changes.
+    fn events(self, env: Env, id: String) -> worker::Result<Response> {
+        let events =
+            futures::stream::unfold((None::<UploadProgress>, false), move |(last, done)| {
+                let env = env.clone();
+                let id = id.clone();
+                async move {
+                    if done {
+                        return None;
+                    }
+                    loop {
+                        if last.is_some() {
+                            worker::Delay::from(POLL_INTERVAL).await;
+                        }
+                        let progress = match self.progress(&env, &id).await {
+                            Ok(progress) => progress,
+                            Err(err) => {
+                                let event = format!("event: error\ndata: {err}\n\n");
+                                return Some((Ok(event.into_bytes()), (last, true)));
+                            }
+                        };
+                        if last.as_ref() == Some(&progress) {
+                            continue;
+                        }
+                        let done = progress.status != UploadStatus::InProgress;
+                        let event = match serde_json::to_string(&progress) {
+                            Ok(json) => format!("data: {json}\n\n"),
+                            Err(err) => return Some((Err(err.to_string()), (last, true))),
+                        };
+                        return Some((Ok(event.into_bytes()), (Some(progress), done)));
+                    }
+                }
+            });
+
+        let mut response = Response::from_stream(events)?;
+        response
+            .headers_mut()
+            .set("Content-Type", "text/event-stream")?;
+        response.headers_mut().set("Cache-Control", "no-store")?;
+        Ok(response)
+    }
+}
+
+/// Keeps the characters of `filename` that are safe in keys and URLs.
+fn sanitize_filename(filename: &str) -> String {
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    match name.trim_start_matches('.') {
+        "" => "file".to_string(),
+        name => name.to_string(),
+    }
+}
+
+pub trait UploadRoutes {
+    /// Mounts `PUT {base_path}/:id/:part` to send a part, `GET {base_path}/:id` for the progress and
+    /// `GET {base_path}/:id/events` for the progress as server-sent events.
+    fn upload_routes(self, uploads: Uploads) -> Self;
+}
+
+impl<'a, D: 'static> UploadRoutes for worker::Router<'a, D> {
+    fn upload_routes(self, uploads: Uploads) -> Self {
+        self.api_route(
+            worker::Method::Put,
+            &format!("{}/:id/:part", uploads.base_path),
+            move |req: ApiRequest<PartPath>| async move {
+                uploads
+                    .upload_part(&req.env, &req.path.id, req.path.part, req.parts.body)
+                    .await
+                    .map(Json)
+            },
+        )
+        .api_route(
+            worker::Method::Get,
+            &format!("{}/:id", uploads.base_path),
+            move |req: ApiRequest<UploadPath>| async move {
+                uploads.progress(&req.env, &req.path.id).await.map(Json)
+            },
+        )
+        .api_route(
+            worker::Method::Get,
+            &format!("{}/:id/events", uploads.base_path),
+            move |req: ApiRequest<UploadPath>| async move { uploads.events(req.env, req.path.id) },
+        )
+    }
+}
+
+/// The Durable Object class behind [Uploads]. Each upload is a separate instance.
+#[durable_object]
+pub struct UploadDurableObject {
+    state: State,
+}
+
+#[durable_object]
+impl DurableObject for UploadDurableObject {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> worker::Result<Response> {
+        let mut storage = self.state.storage();
+        let current = storage.get::<UploadRecord>("upload").await.ok();
+
+        let updated = match (req.path().as_str(), current) {
+            ("/init", _) => req.json::<UploadRecord>().await?,
+            ("/state", Some(record)) => return Response::from_json(&record),
+            ("/part", Some(mut record)) => {
+                let part = req.json::<PartUploaded>().await?;
+                record.uploaded.insert(part.part, (part.etag, part.size));
+                record
+            }
+            ("/completed", Some(mut record)) => {
+                record.status = UploadStatus::Completed;
+                record
+            }
+            ("/aborted", Some(mut record)) => {
+                record.status = UploadStatus::Aborted;
+                record
+            }
+            _ => return Response::error("Not found", 404),
+        };
+        storage.put("upload", &updated).await?;
+        Response::from_json(&updated)
+    }
+}
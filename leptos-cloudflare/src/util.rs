@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use crate::memo_cache::{memo_cache, MemoCache};
+use crate::LeptosCloudflareError;
+
+/// Compares two byte strings in time that only depends on their lengths, for checking secrets.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter()
+        .zip(b.iter())
+        .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+/// The start of the current one-minute window of each client and the requests it sent in it. Kept
+/// in a [MemoCache] with a TTL of a minute, so that clients with ever new addresses can't grow it.
+pub(crate) type ClientWindows = MemoCache<String, (f64, u32)>;
+
+/// Windows for the 10,000 most recent clients.
+pub(crate) fn client_windows() -> ClientWindows {
+    memo_cache(10_000).ttl(Duration::from_secs(60))
+}
+
+/// Counts a request of `client` in `windows`, returning whether it is within `per_minute`. Limits
+/// the unauthenticated routes that browsers report to.
+pub(crate) fn allow_per_minute(windows: &ClientWindows, client: &str, per_minute: u32) -> bool {
+    let now = js_sys::Date::now();
+    let (started_at, count) = windows
+        .get(&client.to_string())
+        .filter(|(started_at, _)| now - started_at < 60_000.0)
+        .unwrap_or((now, 0));
+    windows.insert(client.to_string(), (started_at, count + 1));
+    count < per_minute
+}
+
+/// The value of the cookie `name` sent with the request, if any.
+pub(crate) fn cookie(headers: &worker::Headers, name: &str) -> Option<String> {
+    let header = headers.get("Cookie").ok().flatten()?;
+    header.split(';').find_map(|pair| {
+        let (key, value) = pair.trim().split_once('=')?;
+        (key == name).then(|| value.to_string())
+    })
+}
+
+/// Checks that the request carries `Authorization: Bearer <token>` with the value of the secret `token_secret`.
+pub(crate) fn authorize_bearer(
+    env: &worker::Env,
+    headers: &worker::Headers,
+    token_secret: &str,
+) -> Result<(), LeptosCloudflareError> {
+    let token = env.secret(token_secret)?.to_string();
+    let provided = headers
+        .get("Authorization")?
+        .and_then(|header| header.strip_prefix("Bearer ").map(str::to_string))
+        .ok_or(LeptosCloudflareError::Unauthorized)?;
+
+    if constant_time_eq(provided.as_bytes(), token.as_bytes()) {
+        Ok(())
+    } else {
+        Err(LeptosCloudflareError::Unauthorized)
+    }
+}
+
+/// `len` cryptographically secure random bytes, from `crypto.getRandomValues`.
+#[cfg(any(
+    feature = "api-keys",
+    feature = "magic-link",
+    feature = "passwords",
+    feature = "seal",
+    feature = "totp",
+    feature = "uploads"
+))]
+pub(crate) fn random_bytes(len: usize) -> worker::Result<Vec<u8>> {
+    use wasm_bindgen::JsCast;
+
+    let crypto = js_sys::Reflect::get(&js_sys::global(), &"crypto".into())?;
+    let fill: js_sys::Function =
+        js_sys::Reflect::get(&crypto, &"getRandomValues".into())?.unchecked_into();
+    let bytes = js_sys::Uint8Array::new_with_length(len as u32);
+    fill.call1(&crypto, &bytes)?;
+    Ok(bytes.to_vec())
+}
+
+/// Calls `crypto.subtle[method](...args)` and awaits the result.
+#[cfg(any(feature = "passwords", feature = "seal"))]
+pub(crate) async fn subtle(
+    method: &str,
+    args: js_sys::Array,
+) -> worker::Result<wasm_bindgen::JsValue> {
+    use wasm_bindgen::JsCast;
+
+    let crypto = js_sys::Reflect::get(&js_sys::global(), &"crypto".into())?;
+    let subtle = js_sys::Reflect::get(&crypto, &"subtle".into())?;
+    let function: js_sys::Function =
+        js_sys::Reflect::get(&subtle, &method.into())?.unchecked_into();
+    let promise = function.apply(&subtle, &args)?;
+    Ok(wasm_bindgen_futures::JsFuture::from(js_sys::Promise::from(promise)).await?)
+}
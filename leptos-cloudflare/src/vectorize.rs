@@ -0,0 +1,306 @@
+//! Semantic search with [Vectorize](https://developers.cloudflare.com/vectorize/) indexes.
+//!
+//! `worker` has no bindings for Vectorize, so [VectorizeIndex] calls the JavaScript binding, converting
+//! vectors and their metadata with serde:
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize, Clone)]
+//! pub struct PostMeta {
+//!     pub slug: String,
+//!     pub lang: String,
+//! }
+//!
+//! #[server(SearchPosts, "/api")]
+//! pub async fn search_posts(cx: Scope, query: String) -> Result<Vec<String>, ServerFnError> {
+//!     let embedding = embed(cx, &query).await?;
+//!     let index = use_vectorize(cx, "POSTS_INDEX")?;
+//!     let matches = index
+//!         .query::<PostMeta>(&embedding, &VectorQuery::new(5).filter(VectorFilter::new().eq("lang", "en")))
+//!         .await?;
+//!     Ok(matches.into_iter().filter_map(|m| m.metadata).map(|meta| meta.slug).collect())
+//! }
+//!
+//! index.upsert(&[Vector::new("hello-world", embedding).metadata(meta)]).await?;
+//! ```
+//!
+//! ```toml
+//! [[vectorize]]
+//! binding = "POSTS_INDEX"
+//! index_name = "posts"
+//! ```
+//!
+//! Filters only apply to metadata fields with a metadata index, created with
+//! `wrangler vectorize create-metadata-index`. Writes are applied asynchronously, so an upserted
+//! vector may take a few seconds to be returned by queries.
+
+use leptos::Scope;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use worker::Env;
+
+use crate::{use_env, LeptosCloudflareError};
+
+/// The index bound as `binding`, from the bindings of the current request.
+pub fn use_vectorize(cx: Scope, binding: &str) -> Result<VectorizeIndex, LeptosCloudflareError> {
+    Ok(VectorizeIndex::from_env(&use_env(cx)?, binding)?)
+}
+
+/// A vector with its metadata, of type `M`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Vector<M = serde_json::Value> {
+    pub id: String,
+    pub values: Vec<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<M>,
+}
+
+impl<M> Vector<M> {
+    pub fn new(id: impl Into<String>, values: Vec<f32>) -> Self {
+        Self {
+            id: id.into(),
+            values,
+            namespace: None,
+            metadata: None,
+        }
+    }
+
+    /// Puts the vector in `namespace`, which queries can be limited to.
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    pub fn metadata(mut self, metadata: M) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+}
+
+/// A filter on the metadata of the vectors, e.g. `VectorFilter::new().eq("lang", "en").gte("year", 2020)`.
+/// Conditions on several fields must all match.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct VectorFilter(serde_json::Map<String, serde_json::Value>);
+
+impl VectorFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn eq(self, field: &str, value: impl Into<serde_json::Value>) -> Self {
+        self.condition(field, "$eq", value.into())
+    }
+
+    pub fn ne(self, field: &str, value: impl Into<serde_json::Value>) -> Self {
+        self.condition(field, "$ne", value.into())
+    }
+
+    /// The field is one of `values`.
+    pub fn one_of<V: Into<serde_json::Value>>(
+        self,
+        field: &str,
+        values: impl IntoIterator<Item = V>,
+    ) -> Self {
+        let values = values.into_iter().map(Into::into).collect();
+        self.condition(field, "$in", serde_json::Value::Array(values))
+    }
+
+    /// The field is none of `values`.
+    pub fn none_of<V: Into<serde_json::Value>>(
+        self,
+        field: &str,
+        values: impl IntoIterator<Item = V>,
+    ) -> Self {
+        let values = values.into_iter().map(Into::into).collect();
+        self.condition(field, "$nin", serde_json::Value::Array(values))
+    }
+
+    pub fn lt(self, field: &str, value: impl Into<serde_json::Value>) -> Self {
+        self.condition(field, "$lt", value.into())
+    }
+
+    pub fn lte(self, field: &str, value: impl Into<serde_json::Value>) -> Self {
+        self.condition(field, "$lte", value.into())
+    }
+
+    pub fn gt(self, field: &str, value: impl Into<serde_json::Value>) -> Self {
+        self.condition(field, "$gt", value.into())
+    }
+
+    pub fn gte(self, field: &str, value: impl Into<serde_json::Value>) -> Self {
+        self.condition(field, "$gte", value.into())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn condition(mut self, field: &str, operator: &str, value: serde_json::Value) -> Self {
+        let conditions = self
+            .0
+            .entry(field.to_string())
+            .or_insert_with(|| serde_json::Value::Object(Default::default()));
+        if let serde_json::Value::Object(conditions) = conditions {
+            conditions.insert(operator.to_string(), value);
+        }
+        self
+    }
+}
+
+/// The options of [VectorizeIndex::query].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VectorQuery {
+    top_k: u32,
+    return_values: bool,
+    return_metadata: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace: Option<String>,
+    #[serde(skip_serializing_if = "VectorFilter::is_empty")]
+    filter: VectorFilter,
+}
+
+impl VectorQuery {
+    /// Returns the `top_k` closest vectors with their metadata.
+    pub fn new(top_k: u32) -> Self {
+        Self {
+            top_k,
+            return_values: false,
+            return_metadata: true,
+            namespace: None,
+            filter: VectorFilter::new(),
+        }
+    }
+
+    /// Also returns the values of the vectors.
+    pub fn with_values(mut self) -> Self {
+        self.return_values = true;
+        self
+    }
+
+    /// Doesn't return the metadata, which makes queries with a large `top_k` faster.
+    pub fn without_metadata(mut self) -> Self {
+        self.return_metadata = false;
+        self
+    }
+
+    /// Only searches the vectors in `namespace`.
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    pub fn filter(mut self, filter: VectorFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+}
+
+/// A vector returned by [VectorizeIndex::query], closest first.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct VectorMatch<M = serde_json::Value> {
+    pub id: String,
+    /// The similarity to the query, by the metric of the index.
+    pub score: f64,
+    #[serde(default)]
+    pub values: Option<Vec<f32>>,
+    #[serde(default)]
+    pub metadata: Option<M>,
+}
+
+/// What a write changed. Writes are applied asynchronously, after they return.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VectorMutation {
+    #[serde(default)]
+    pub ids: Vec<String>,
+    #[serde(default)]
+    pub count: u64,
+    #[serde(default)]
+    pub mutation_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct QueryResult<M> {
+    matches: Vec<VectorMatch<M>>,
+}
+
+/// A Vectorize index.
+#[derive(Clone)]
+pub struct VectorizeIndex(JsValue);
+
+impl VectorizeIndex {
+    /// The index bound as `binding` in `env`.
+    pub fn from_env(env: &Env, binding: &str) -> worker::Result<Self> {
+        let index = js_sys::Reflect::get(env, &JsValue::from_str(binding))?;
+        if index.is_undefined() {
+            return Err(worker::Error::RustError(format!(
+                "no Vectorize index is bound as {binding}"
+            )));
+        }
+        Ok(Self(index))
+    }
+
+    /// The vectors closest to `vector`.
+    pub async fn query<M: DeserializeOwned>(
+        &self,
+        vector: &[f32],
+        query: &VectorQuery,
+    ) -> worker::Result<Vec<VectorMatch<M>>> {
+        let result: QueryResult<M> = self
+            .call("query", &[to_js(&vector)?, to_js(query)?])
+            .await?;
+        Ok(result.matches)
+    }
+
+    /// Inserts `vectors`, replacing those with the same ID.
+    pub async fn upsert<M: Serialize>(
+        &self,
+        vectors: &[Vector<M>],
+    ) -> worker::Result<VectorMutation> {
+        self.call("upsert", &[to_js(&vectors)?]).await
+    }
+
+    /// Inserts `vectors`, keeping those with the same ID.
+    pub async fn insert<M: Serialize>(
+        &self,
+        vectors: &[Vector<M>],
+    ) -> worker::Result<VectorMutation> {
+        self.call("insert", &[to_js(&vectors)?]).await
+    }
+
+    /// The vectors with the IDs `ids` that exist.
+    pub async fn get_by_ids<M: DeserializeOwned>(
+        &self,
+        ids: &[&str],
+    ) -> worker::Result<Vec<Vector<M>>> {
+        self.call("getByIds", &[to_js(&ids)?]).await
+    }
+
+    pub async fn delete_by_ids(&self, ids: &[&str]) -> worker::Result<VectorMutation> {
+        self.call("deleteByIds", &[to_js(&ids)?]).await
+    }
+
+    async fn call<T: DeserializeOwned>(&self, method: &str, args: &[JsValue]) -> worker::Result<T> {
+        let function: js_sys::Function =
+            js_sys::Reflect::get(&self.0, &JsValue::from_str(method))?.unchecked_into();
+        let promise = function.apply(&self.0, &args.iter().collect::<js_sys::Array>())?;
+        let result = JsFuture::from(js_sys::Promise::from(promise)).await?;
+        from_js(&result)
+    }
+}
+
+// Vectors and metadata go through JSON, which the binding accepts and returns as plain objects
+fn to_js<T: Serialize + ?Sized>(value: &T) -> worker::Result<JsValue> {
+    Ok(js_sys::JSON::parse(&serde_json::to_string(value)?)?)
+}
+
+fn from_js<T: DeserializeOwned>(value: &JsValue) -> worker::Result<T> {
+    let json = js_sys::JSON::stringify(value)?
+        .as_string()
+        .unwrap_or_default();
+    Ok(serde_json::from_str(&json)?)
+}
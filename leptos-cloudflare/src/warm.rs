@@ -0,0 +1,220 @@
+//! Re-rendering popular routes into the cache on a schedule.
+//!
+//! A page cached in a [TaggedCache] is slow again for the first visitor after it expires, which shows
+//! up in the tail latency of busy routes. A [WarmList] renders its routes through the same router as
+//! the fetch handler and stores them in the cache before that happens, and runs prefetch hooks first
+//! so that upstream data the pages read (e.g. through [get_or_load](crate::kv_cache::get_or_load)) is
+//! fresh as well. It runs from a cron trigger:
+//!
+//! ```ignore
+//! fn warm_list() -> WarmList {
+//!     WarmList::new("https://example.com", Duration::from_secs(600))
+//!         .route("/")
+//!         .route("/blog")
+//!         .routes_from_kv("CONFIG", "warm-routes")
+//!         .prefetch("posts", |env| async move { refresh_posts(&env).await })
+//! }
+//!
+//! // with `crons = ["*/5 * * * *"]`, so pages are re-rendered well before their 10 minutes expire
+//! CronRouter::new().named("warm", "*/5 * * * *", |cron| async move {
+//!     let cache = TaggedCache::new(CacheStore::Kv("PAGES"), "CACHE_INDEX");
+//!     let report = warm_list().warm(&cron.env, router_data()?, routes(), &cache).await?;
+//!     tracing::info!("warmed {} pages", report.warmed.len());
+//!     Ok(())
+//! })
+//! ```
+//!
+//! [routes_from_kv](WarmList::routes_from_kv) reads more paths from a JSON array in KV, so the list can
+//! follow the traffic without a deployment. Pages are rendered without cookies, as for a first-time
+//! visitor, and responses that set cookies or aren't public are not cached. With
+//! [CacheStore::CacheApi](crate::CacheStore::CacheApi), only the data center running the cron trigger
+//! is warmed.
+
+use std::future::Future;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::future::LocalBoxFuture;
+use futures::StreamExt;
+use leptos::{IntoView, Scope};
+use leptos_router::RouteListing;
+use serde::Serialize;
+use worker::Env;
+
+use crate::{LeptosCloudflareError, LeptosRoutes, TaggedCache, WorkerRouterData};
+
+type PrefetchHook = Rc<dyn Fn(Env) -> LocalBoxFuture<'static, Result<(), LeptosCloudflareError>>>;
+
+/// Routes to keep warm in a cache, and data to prefetch for them.
+#[derive(Clone)]
+pub struct WarmList {
+    origin: String,
+    ttl: Duration,
+    routes: Vec<String>,
+    kv_routes: Option<(&'static str, &'static str)>,
+    prefetch: Vec<(String, PrefetchHook)>,
+    concurrency: usize,
+}
+
+/// What a run of [WarmList::warm] did.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WarmReport {
+    /// The hooks that prefetched their data.
+    pub prefetched: Vec<String>,
+    /// The paths rendered into the cache.
+    pub warmed: Vec<String>,
+    /// The paths rendered but not cached, with the reason.
+    pub skipped: Vec<(String, String)>,
+    /// The hooks and paths that failed, with the error.
+    pub failed: Vec<(String, String)>,
+}
+
+impl WarmList {
+    /// Renders routes as requested from `origin`, e.g. `https://example.com`, and caches them for `ttl`.
+    pub fn new(origin: &str, ttl: Duration) -> Self {
+        Self {
+            origin: origin.trim_end_matches('/').to_string(),
+            ttl,
+            routes: Vec::new(),
+            kv_routes: None,
+            prefetch: Vec::new(),
+            concurrency: 4,
+        }
+    }
+
+    /// Adds a path, with its query string if the page depends on it.
+    pub fn route(mut self, path: &str) -> Self {
+        self.routes.push(path.to_string());
+        self
+    }
+
+    /// Also warms the paths in the JSON array stored under `key` in the KV namespace bound as `binding`.
+    pub fn routes_from_kv(mut self, binding: &'static str, key: &'static str) -> Self {
+        self.kv_routes = Some((binding, key));
+        self
+    }
+
+    /// Runs `hook` before rendering, named `name` in the report.
+    pub fn prefetch<F, Fut>(mut self, name: &str, hook: F) -> Self
+    where
+        F: Fn(Env) -> Fut + 'static,
+        Fut: Future<Output = Result<(), LeptosCloudflareError>> + 'static,
+    {
+        self.prefetch
+            .push((name.to_string(), Rc::new(move |env| Box::pin(hook(env)))));
+        self
+    }
+
+    /// How many pages are rendered at the same time, 4 by default.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Runs the prefetch hooks, then renders every route with the router of `data` and `routes` and
+    /// stores the result in `cache`. Failures of single hooks or pages are reported rather than
+    /// stopping the run.
+    pub async fn warm<IV, AppFn>(
+        &self,
+        env: &Env,
+        data: WorkerRouterData<IV, AppFn>,
+        routes: Vec<RouteListing>,
+        cache: &TaggedCache,
+    ) -> Result<WarmReport, LeptosCloudflareError>
+    where
+        IV: IntoView + 'static,
+        AppFn: Fn(Scope) -> IV + Clone + 'static,
+    {
+        let mut report = WarmReport::default();
+
+        for (name, hook) in &self.prefetch {
+            match hook(env.clone()).await {
+                Ok(()) => report.prefetched.push(name.clone()),
+                Err(err) => report.failed.push((name.clone(), err.to_string())),
+            }
+        }
+
+        let mut paths = self.routes.clone();
+        if let Some((binding, key)) = self.kv_routes {
+            let from_kv = env
+                .kv(binding)?
+                .get(key)
+                .json::<Vec<String>>()
+                .await
+                .map_err(worker::Error::from)?;
+            paths.extend(from_kv.unwrap_or_default());
+        }
+        paths.sort();
+        paths.dedup();
+
+        let mut outcomes = futures::stream::iter(paths)
+            .map(|path| {
+                let data = data.clone();
+                let routes = routes.clone();
+                async move {
+                    let outcome = self.warm_route(env, data, routes, cache, &path).await;
+                    (path, outcome)
+                }
+            })
+            .buffer_unordered(self.concurrency);
+
+        while let Some((path, outcome)) = outcomes.next().await {
+            match outcome {
+                Ok(None) => report.warmed.push(path),
+                Ok(Some(reason)) => report.skipped.push((path, reason)),
+                Err(err) => report.failed.push((path, err.to_string())),
+            }
+        }
+
+        for (name, err) in &report.failed {
+            tracing::warn!("failed to warm {name}: {err}");
+        }
+        Ok(report)
+    }
+
+    /// Renders and caches `path`, returning why it wasn't cached if it wasn't.
+    async fn warm_route<IV, AppFn>(
+        &self,
+        env: &Env,
+        data: WorkerRouterData<IV, AppFn>,
+        routes: Vec<RouteListing>,
+        cache: &TaggedCache,
+        path: &str,
+    ) -> worker::Result<Option<String>>
+    where
+        IV: IntoView + 'static,
+        AppFn: Fn(Scope) -> IV + Clone + 'static,
+    {
+        let url = format!("{}{path}", self.origin);
+        let headers = worker::Headers::new();
+        headers.set("Accept", "text/html")?;
+        headers.set("User-Agent", "leptos-cloudflare-warm")?;
+        let mut init = worker::RequestInit::new();
+        init.with_method(worker::Method::Get).with_headers(headers);
+        let req = worker::Request::new_with_init(&url, &init)?;
+
+        let response = data
+            .into_router()
+            .leptos_routes(routes)
+            .run(req, env.clone())
+            .await?;
+
+        if response.status_code() != 200 {
+            return Ok(Some(format!("status {}", response.status_code())));
+        }
+        if response.headers().has("Set-Cookie")? {
+            return Ok(Some("sets cookies".to_string()));
+        }
+        let cache_control = response
+            .headers()
+            .get("Cache-Control")?
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if cache_control.contains("private") || cache_control.contains("no-store") {
+            return Ok(Some(format!("Cache-Control: {cache_control}")));
+        }
+
+        cache.put(env, &url, response, self.ttl).await?;
+        Ok(None)
+    }
+}
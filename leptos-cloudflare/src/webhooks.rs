@@ -0,0 +1,162 @@
+//! Signature verification for incoming webhooks.
+//!
+//! Every check runs against the raw request body from [RequestParts](crate::RequestParts), so the
+//! payload must not be parsed (and re-serialized) before it is verified. Comparisons of signatures are
+//! constant-time.
+//!
+//! ```ignore
+//! async fn github_webhook(req: ApiRequest) -> Result<worker::Response, LeptosCloudflareError> {
+//!     let secret = req.env.secret("GITHUB_WEBHOOK_SECRET")?.to_string();
+//!     webhooks::verify_github(secret.as_bytes(), &req.parts)?;
+//!     // the body can be trusted from here on
+//! }
+//! ```
+
+use std::time::Duration;
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::{LeptosCloudflareError, RequestParts};
+
+/// Signatures older than this are rejected by the timestamped schemes (Stripe, Slack).
+pub const DEFAULT_TOLERANCE: Duration = Duration::from_secs(300);
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum WebhookError {
+    #[error("Missing {0} header")]
+    MissingHeader(&'static str),
+    #[error("Malformed {0} header")]
+    MalformedHeader(&'static str),
+    #[error("Invalid webhook signature")]
+    InvalidSignature,
+    #[error("Webhook timestamp is outside of the tolerance")]
+    Expired,
+}
+
+impl From<WebhookError> for LeptosCloudflareError {
+    fn from(err: WebhookError) -> Self {
+        match err {
+            WebhookError::InvalidSignature | WebhookError::Expired => {
+                LeptosCloudflareError::Unauthorized
+            }
+            err => LeptosCloudflareError::BadRequest(err.to_string()),
+        }
+    }
+}
+
+/// Checks an HMAC-SHA256 `signature` of `message` in constant time.
+pub fn verify_hmac_sha256(secret: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(message);
+    mac.verify_slice(signature).is_ok()
+}
+
+/// Checks an Ed25519 `signature` of `message` made with the key pair of `public_key`.
+pub fn verify_ed25519(public_key: &[u8; 32], message: &[u8], signature: &[u8]) -> bool {
+    let Ok(key) = VerifyingKey::from_bytes(public_key) else {
+        return false;
+    };
+    let Ok(signature) = Signature::from_slice(signature) else {
+        return false;
+    };
+    key.verify(message, &signature).is_ok()
+}
+
+/// Verifies a GitHub webhook, signed in the `X-Hub-Signature-256: sha256=<hex>` header.
+pub fn verify_github(secret: &[u8], req: &RequestParts) -> Result<(), WebhookError> {
+    const HEADER: &str = "X-Hub-Signature-256";
+
+    let header = header(req, HEADER)?;
+    let signature = header
+        .strip_prefix("sha256=")
+        .and_then(|signature| hex::decode(signature).ok())
+        .ok_or(WebhookError::MalformedHeader(HEADER))?;
+
+    if verify_hmac_sha256(secret, &req.body, &signature) {
+        Ok(())
+    } else {
+        Err(WebhookError::InvalidSignature)
+    }
+}
+
+/// Verifies a Stripe webhook, signed in the `Stripe-Signature: t=<timestamp>,v1=<hex>` header over
+/// `<timestamp>.<body>`. Any of several `v1` signatures may match, which happens while a secret is rolled.
+pub fn verify_stripe(
+    secret: &[u8],
+    req: &RequestParts,
+    tolerance: Duration,
+) -> Result<(), WebhookError> {
+    const HEADER: &str = "Stripe-Signature";
+
+    let header = header(req, HEADER)?;
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for (key, value) in header.split(',').filter_map(|pair| pair.split_once('=')) {
+        match key.trim() {
+            "t" => timestamp = value.parse::<u64>().ok(),
+            "v1" => signatures.extend(hex::decode(value).ok()),
+            _ => {}
+        }
+    }
+    let timestamp = timestamp.ok_or(WebhookError::MalformedHeader(HEADER))?;
+    check_timestamp(timestamp, tolerance)?;
+
+    let signed_payload = [timestamp.to_string().as_bytes(), b".", &req.body].concat();
+    if signatures
+        .iter()
+        .any(|signature| verify_hmac_sha256(secret, &signed_payload, signature))
+    {
+        Ok(())
+    } else {
+        Err(WebhookError::InvalidSignature)
+    }
+}
+
+/// Verifies a Slack request, signed in the `X-Slack-Signature: v0=<hex>` header over
+/// `v0:<X-Slack-Request-Timestamp>:<body>`.
+pub fn verify_slack(
+    secret: &[u8],
+    req: &RequestParts,
+    tolerance: Duration,
+) -> Result<(), WebhookError> {
+    const SIGNATURE_HEADER: &str = "X-Slack-Signature";
+    const TIMESTAMP_HEADER: &str = "X-Slack-Request-Timestamp";
+
+    let timestamp = header(req, TIMESTAMP_HEADER)?
+        .parse::<u64>()
+        .map_err(|_| WebhookError::MalformedHeader(TIMESTAMP_HEADER))?;
+    check_timestamp(timestamp, tolerance)?;
+
+    let signature = header(req, SIGNATURE_HEADER)?
+        .strip_prefix("v0=")
+        .and_then(|signature| hex::decode(signature).ok())
+        .ok_or(WebhookError::MalformedHeader(SIGNATURE_HEADER))?;
+
+    let base_string = [format!("v0:{timestamp}:").as_bytes(), &req.body].concat();
+    if verify_hmac_sha256(secret, &base_string, &signature) {
+        Ok(())
+    } else {
+        Err(WebhookError::InvalidSignature)
+    }
+}
+
+fn header(req: &RequestParts, name: &'static str) -> Result<String, WebhookError> {
+    req.headers
+        .get(name)
+        .ok()
+        .flatten()
+        .ok_or(WebhookError::MissingHeader(name))
+}
+
+/// `timestamp` is in seconds since the epoch.
+fn check_timestamp(timestamp: u64, tolerance: Duration) -> Result<(), WebhookError> {
+    let now = worker::Date::now().as_millis() / 1000;
+    if now.abs_diff(timestamp) > tolerance.as_secs() {
+        Err(WebhookError::Expired)
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+//! WebSocket routes next to the Leptos app.
+//!
+//! [websocket_route](WebSocketRoutes::websocket_route) accepts the `Upgrade: websocket` handshake of
+//! `GET` requests to a path, answers them with the client end of a `WebSocketPair`, and runs the
+//! handler with the server end, which is already accepted:
+//!
+//! ```ignore
+//! async fn chat(session: WebSocketSession) -> Result<(), LeptosCloudflareError> {
+//!     let room = &session.params["room"];
+//!     let user = use_dep::<Session>(session.cx)?.user;
+//!     let mut events = session.socket.events()?;
+//!     while let Some(event) = events.next().await {
+//!         if let WebsocketEvent::Message(message) = event? {
+//!             if let Some(text) = message.text() {
+//!                 session.socket.send_with_str(format!("{user} in {room}: {text}"))?;
+//!             }
+//!         }
+//!     }
+//!     Ok(())
+//! }
+//!
+//! router
+//!     .leptos_routes(routes)
+//!     .websocket_route("/ws/:room", chat)
+//! ```
+//!
+//! The handler runs in a Leptos runtime with the contexts server functions get from the request:
+//! [RequestParts](crate::RequestParts), the bindings for [use_env](crate::use_env),
+//! [use_wait_until](crate::use_wait_until), [use_dep](crate::use_dep) and the registered
+//! [isolate states](crate::IsolateState). The runtime is disposed when the handler returns, which it
+//! should once the socket is closed. Requests without the handshake are answered with
+//! `426 Upgrade Required`.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::rc::Rc;
+
+use futures::future::LocalBoxFuture;
+use http::StatusCode;
+use leptos::{create_runtime, provide_context, raw_scope_and_disposer, IntoView, Scope};
+
+use crate::api::{decode_params, RouteRegistry};
+use crate::{
+    app_env, background, di, generate_request_parts, isolate, request_headers,
+    LeptosCloudflareError, WorkerRouterData,
+};
+
+/// An accepted WebSocket and the request that opened it.
+pub struct WebSocketSession {
+    /// The scope of the runtime the handler runs in.
+    pub cx: Scope,
+    /// The server end of the pair, already accepted.
+    pub socket: worker::WebSocket,
+    /// The decoded `:param` and `*wildcard` values of the route.
+    pub params: HashMap<String, String>,
+}
+
+type BoxedWebSocketHandler =
+    Rc<dyn Fn(WebSocketSession) -> LocalBoxFuture<'static, Result<(), LeptosCloudflareError>>>;
+
+thread_local! {
+    static WEBSOCKET_ROUTES: RouteRegistry<BoxedWebSocketHandler> = RouteRegistry::new();
+}
+
+pub trait WebSocketRoutes {
+    /// Registers `handler` for WebSocket handshakes to `path`, which uses the same `:param` and
+    /// `*wildcard` syntax as the worker router.
+    fn websocket_route<H, Fut>(self, path: &str, handler: H) -> Self
+    where
+        H: Fn(WebSocketSession) -> Fut + 'static,
+        Fut: Future<Output = Result<(), LeptosCloudflareError>> + 'static;
+}
+
+impl<'a, IV, AppFn> WebSocketRoutes for worker::Router<'a, WorkerRouterData<IV, AppFn>>
+where
+    IV: IntoView + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+{
+    fn websocket_route<H, Fut>(self, path: &str, handler: H) -> Self
+    where
+        H: Fn(WebSocketSession) -> Fut + 'static,
+        Fut: Future<Output = Result<(), LeptosCloudflareError>> + 'static,
+    {
+        let handler: BoxedWebSocketHandler = Rc::new(move |session| Box::pin(handler(session)));
+        WEBSOCKET_ROUTES.with(|routes| routes.register(worker::Method::Get, path, handler));
+
+        self.get_async(path, dispatch_websocket::<IV, AppFn>)
+    }
+}
+
+/// Whether `req` asks to upgrade to a WebSocket.
+fn is_upgrade(req: &worker::Request) -> bool {
+    req.headers()
+        .get("Upgrade")
+        .ok()
+        .flatten()
+        .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+}
+
+async fn dispatch_websocket<IV, AppFn>(
+    mut req: worker::Request,
+    ctx: worker::RouteContext<WorkerRouterData<IV, AppFn>>,
+) -> worker::Result<worker::Response>
+where
+    IV: IntoView + 'static,
+    AppFn: Fn(leptos::Scope) -> IV + Clone + 'static,
+{
+    let path = req.path();
+    let matched = WEBSOCKET_ROUTES.with(|routes| routes.find(&worker::Method::Get, &path));
+    let Some((handler, params)) = matched else {
+        return ctx
+            .data
+            .error_renderer
+            .render_for(&req, StatusCode::NOT_FOUND, "Not found");
+    };
+    let params = match decode_params(params) {
+        Ok(params) => params,
+        Err(err) => {
+            return ctx.data.error_renderer.render_for(
+                &req,
+                StatusCode::BAD_REQUEST,
+                &format!("Invalid path parameters: {err}"),
+            )
+        }
+    };
+    if !is_upgrade(&req) {
+        return ctx.data.error_renderer.render_for(
+            &req,
+            StatusCode::UPGRADE_REQUIRED,
+            "Expected a WebSocket handshake",
+        );
+    }
+
+    let req_parts = generate_request_parts(&mut req).await?;
+    let pair = worker::WebSocketPair::new()?;
+    pair.server.accept()?;
+
+    let runtime = create_runtime();
+    let (cx, disposer) = raw_scope_and_disposer(runtime);
+    provide_context(cx, ctx.data.request_headers.apply(&req_parts));
+    provide_context(cx, request_headers::FullRequestParts(req_parts.clone()));
+    isolate::provide_isolate_states(cx, &ctx.data.isolate_states);
+    provide_context(cx, app_env::RequestEnv(ctx.env.clone()));
+    provide_context(cx, background::WaitUntil(ctx.data.context.clone()));
+    provide_context(
+        cx,
+        di::DepContainer::new(ctx.data.deps.clone(), ctx.env.clone(), req_parts),
+    );
+
+    let session = WebSocketSession {
+        cx,
+        socket: pair.server,
+        params,
+    };
+    // The socket outlives the response, so the handler is driven on its own
+    wasm_bindgen_futures::spawn_local(async move {
+        if let Err(err) = handler(session).await {
+            tracing::error!("WebSocket handler for {path} failed: {err}");
+        }
+        disposer.dispose();
+        runtime.dispose();
+    });
+
+    worker::Response::from_websocket(pair.client)
+}
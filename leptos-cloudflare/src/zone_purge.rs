@@ -0,0 +1,116 @@
+//! Purging Cloudflare's CDN cache for the zone through the Cloudflare API.
+//!
+//! Responses cached by the zone (e.g. because of their `Cache-Control` header) outlive anything the worker
+//! caches itself. [ZonePurge] removes them by URL or by `Cache-Tag`, and a [TaggedCache](crate::TaggedCache)
+//! given one with [with_zone_purge](crate::TaggedCache::with_zone_purge) purges the zone cache for every URL it purges itself:
+//!
+//! ```ignore
+//! let zone = ZonePurge::new("023e105f4ecef8ad9ca31a8372d0c353", "CF_PURGE_TOKEN");
+//! let cache = TaggedCache::new(CacheStore::Kv("PAGES"), "CACHE_INDEX").with_zone_purge(zone.clone());
+//!
+//! zone.purge_urls(&env, &["https://example.com/pricing"]).await?;
+//! ```
+//!
+//! The API token needs the `Zone.Cache Purge` permission.
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+use worker::Env;
+
+use crate::LeptosCloudflareError;
+
+const CLOUDFLARE_API: &str = "https://api.cloudflare.com/client/v4";
+/// The API accepts at most this many URLs or tags per call.
+const MAX_PER_CALL: usize = 30;
+
+#[derive(Debug, Clone)]
+pub struct ZonePurge {
+    pub zone_id: String,
+    /// Name of the secret with the API token.
+    pub token_secret: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum PurgeBody<'a> {
+    Files(&'a [String]),
+    Tags(&'a [String]),
+}
+
+#[derive(Deserialize)]
+struct ApiResponse {
+    success: bool,
+    #[serde(default)]
+    errors: Vec<ApiError>,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    message: String,
+}
+
+impl ZonePurge {
+    pub fn new(zone_id: &str, token_secret: &'static str) -> Self {
+        Self {
+            zone_id: zone_id.to_string(),
+            token_secret,
+        }
+    }
+
+    /// Purges the cached responses for `urls`, which must be absolute.
+    pub async fn purge_urls(
+        &self,
+        env: &Env,
+        urls: &[String],
+    ) -> Result<(), LeptosCloudflareError> {
+        for chunk in urls.chunks(MAX_PER_CALL) {
+            self.purge(env, PurgeBody::Files(chunk)).await?;
+        }
+        Ok(())
+    }
+
+    /// Purges the cached responses carrying any of `tags` in their `Cache-Tag` header.
+    pub async fn purge_tags(
+        &self,
+        env: &Env,
+        tags: &[String],
+    ) -> Result<(), LeptosCloudflareError> {
+        for chunk in tags.chunks(MAX_PER_CALL) {
+            self.purge(env, PurgeBody::Tags(chunk)).await?;
+        }
+        Ok(())
+    }
+
+    async fn purge(&self, env: &Env, body: PurgeBody<'_>) -> Result<(), LeptosCloudflareError> {
+        let token = env.secret(self.token_secret)?.to_string();
+        let body = serde_json::to_string(&body).map_err(worker::Error::from)?;
+
+        let mut headers = worker::Headers::new();
+        headers.set("Authorization", &format!("Bearer {token}"))?;
+        headers.set("Content-Type", "application/json")?;
+
+        let mut init = worker::RequestInit::new();
+        init.with_method(worker::Method::Post)
+            .with_headers(headers)
+            .with_body(Some(JsValue::from_str(&body)));
+
+        let request = worker::Request::new_with_init(
+            &format!("{CLOUDFLARE_API}/zones/{}/purge_cache", self.zone_id),
+            &init,
+        )?;
+        let mut response = worker::Fetch::Request(request).send().await?;
+        let status = response.status_code();
+        let result = response.json::<ApiResponse>().await.ok();
+
+        match result {
+            Some(result) if result.success => Ok(()),
+            result => {
+                let message = result
+                    .and_then(|result| result.errors.into_iter().next())
+                    .map(|err| err.message)
+                    .unwrap_or_else(|| format!("Cloudflare API responded with {status}"));
+                Err(LeptosCloudflareError::Internal(message))
+            }
+        }
+    }
+}